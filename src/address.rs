@@ -0,0 +1,415 @@
+use std::fmt;
+use std::str::FromStr;
+use bech32::{segwit, Fe32};
+use serde_json::json;
+use crate::helpers::base58::{base58_decode, base58_encode_checksum, base58_invalid_chars, base58_locate_error};
+use crate::helpers::bech32::{bech32_checksum_variant, bech32_locate_errors, bech32_values, Bech32Variant};
+use crate::helpers::hash256::hash256;
+use crate::helpers::out_type::OutputType;
+use crate::network::Network;
+use crate::script::Script;
+
+/// What an address pays to.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum Payload {
+    PubkeyHash(Vec<u8>),
+    ScriptHash(Vec<u8>),
+    WitnessProgram { version: u8, program: Vec<u8> },
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum AddressError {
+    InvalidBase58,
+    InvalidChecksum,
+    InvalidLength(usize),
+    UnknownVersion(u8),
+    InvalidBech32(String),
+    UnknownHrp(String),
+    InvalidWitnessVersion(u8),
+    InvalidProgramLength(usize),
+    WrongNetwork { expected: Network, found: Network },
+    MixedCase,
+    InvalidCharacters,
+    InvalidSeparator,
+    TooLong(usize),
+    Bech32Checksum,
+    WrongChecksumVariant { version: u8, variant: Bech32Variant },
+    InvalidPadding,
+}
+impl fmt::Display for AddressError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AddressError::InvalidBase58 => write!(f, "not a base58 or bech32 address"),
+            AddressError::InvalidChecksum => write!(f, "invalid base58 checksum"),
+            AddressError::InvalidLength(length) => write!(f, "invalid base58 payload length {}", length),
+            AddressError::UnknownVersion(version) => write!(f, "unknown address version {:02x}", version),
+            AddressError::InvalidBech32(error) => write!(f, "invalid bech32 address: {}", error),
+            AddressError::UnknownHrp(hrp) => write!(f, "unknown address prefix {}", hrp),
+            AddressError::InvalidWitnessVersion(version) => write!(f, "invalid witness version {}", version),
+            AddressError::InvalidProgramLength(length) => write!(f, "invalid witness program length {}", length),
+            AddressError::WrongNetwork { expected, found } => write!(f, "{} address, expected {}", found, expected),
+            AddressError::MixedCase => write!(f, "mixed upper and lower case"),
+            AddressError::InvalidCharacters => write!(f, "invalid characters"),
+            AddressError::InvalidSeparator => write!(f, "missing or misplaced bech32 separator"),
+            AddressError::TooLong(length) => write!(f, "{} characters, bech32 addresses have at most 90", length),
+            AddressError::Bech32Checksum => write!(f, "invalid bech32 checksum"),
+            AddressError::WrongChecksumVariant { version, variant } => {
+                write!(f, "witness version {} must use a {} checksum, not {}", version, Bech32Variant::for_witness_version(*version), variant)
+            }
+            AddressError::InvalidPadding => write!(f, "invalid padding of the witness program"),
+        }
+    }
+}
+impl AddressError {
+    pub fn get_json(&self) -> serde_json::Value {
+        let error = format!("{:?}", self);
+        json!({
+            "error": error.split([' ', '(', '{']).next(),
+            "message": self.to_string(),
+        })
+    }
+}
+impl std::error::Error for AddressError {}
+
+/// Why an address is invalid and the positions of the characters to check.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct AddressDiagnostic {
+    pub error: AddressError,
+    pub positions: Vec<usize>,
+}
+impl AddressDiagnostic {
+    fn new(error: AddressError, positions: Vec<usize>) -> Self {
+        Self { error, positions }
+    }
+    pub fn get_json(&self) -> serde_json::Value {
+        let mut json = self.error.get_json();
+        json["positions"] = json!(self.positions);
+        json
+    }
+}
+impl From<AddressError> for AddressDiagnostic {
+    fn from(error: AddressError) -> Self {
+        AddressDiagnostic::new(error, vec![])
+    }
+}
+
+/// A base58check (P2PKH, P2SH) or bech32/bech32m (segwit v0 to v16) address.
+///
+/// Test chains share their base58 versions, and all but regtest their bech32
+/// prefix, so those addresses report testnet3: use `is_valid_for_network` to
+/// check an address against a given chain.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Address {
+    network: Network,
+    payload: Payload,
+}
+impl Address {
+    pub fn new(network: Network, payload: Payload) -> Result<Self, AddressError> {
+        if let Payload::WitnessProgram { version, program } = &payload {
+            check_witness_program(*version, program)?;
+        }
+        Ok(Address { network, payload })
+    }
+    /// Parses an address of any network.
+    pub fn parse(address: &str) -> Result<Self, AddressError> {
+        match segwit::decode(address) {
+            Ok((hrp, version, program)) => {
+                let network = Network::ALL.into_iter().find(|network| network.hrp() == hrp)
+                    .ok_or(AddressError::UnknownHrp(hrp.to_string()))?;
+                Address::new(network, Payload::WitnessProgram { version: version.to_u8(), program })
+            }
+            // no base58 address starts with the prefix of a network and the bech32 separator
+            Err(error) if has_network_hrp(address) => Err(AddressError::InvalidBech32(error.to_string())),
+            Err(_) => Address::parse_base58(address),
+        }
+    }
+    /// Parses an address, failing when it isn't for `network`.
+    pub fn parse_for_network(address: &str, network: Network) -> Result<Self, AddressError> {
+        let address = Address::parse(address)?;
+        match address.is_valid_for_network(network) {
+            true => Ok(Address { network, ..address }),
+            false => Err(AddressError::WrongNetwork { expected: network, found: address.network }),
+        }
+    }
+    /// Parses an address of `network` the way `parse_for_network` does, but on
+    /// failure tells where the address is wrong: the characters outside the
+    /// alphabet, the one or two a bech32 checksum points to, or the one a base58
+    /// checksum is fixed by changing.
+    pub fn validate(address: &str, network: Network) -> Result<Self, AddressDiagnostic> {
+        let bech32 = has_network_hrp(address) || (!base58_invalid_chars(address.as_bytes()).is_empty() && address.rfind('1').is_some_and(|sep| sep > 0));
+        match bech32 {
+            true => Address::validate_bech32(address, network),
+            false => Address::validate_base58(address, network),
+        }
+    }
+    fn validate_bech32(address: &str, network: Network) -> Result<Self, AddressDiagnostic> {
+        let upper: Vec<usize> = address.char_indices().filter(|(_, c)| c.is_ascii_uppercase()).map(|(i, _)| i).collect();
+        let lower: Vec<usize> = address.char_indices().filter(|(_, c)| c.is_ascii_lowercase()).map(|(i, _)| i).collect();
+        if !upper.is_empty() && !lower.is_empty() {
+            // the case used least is the wrong one
+            let positions = if upper.len() <= lower.len() { upper } else { lower };
+            return Err(AddressDiagnostic::new(AddressError::MixedCase, positions));
+        }
+        if address.len() > 90 {
+            return Err(AddressError::TooLong(address.len()).into());
+        }
+        let address = address.to_lowercase();
+        // the data part holds at least the witness version and the checksum
+        let sep = match address.rfind('1') {
+            Some(sep) if sep > 0 && sep + 8 <= address.len() => sep,
+            sep => return Err(AddressDiagnostic::new(AddressError::InvalidSeparator, sep.into_iter().collect())),
+        };
+        let (hrp, data) = (&address[..sep], &address[sep + 1..]);
+        let values = bech32_values(data).map_err(|positions| {
+            AddressDiagnostic::new(AddressError::InvalidCharacters, positions.iter().map(|i| sep + 1 + i).collect())
+        })?;
+        if hrp != network.hrp().to_lowercase() {
+            let hrp_positions = (0..sep).collect();
+            return match Network::ALL.into_iter().find(|network| network.hrp().to_lowercase() == hrp) {
+                Some(found) => Err(AddressDiagnostic::new(AddressError::WrongNetwork { expected: network, found }, hrp_positions)),
+                None => Err(AddressDiagnostic::new(AddressError::UnknownHrp(hrp.to_string()), hrp_positions)),
+            };
+        }
+        let version = values[0];
+        let expected = Bech32Variant::for_witness_version(version);
+        match bech32_checksum_variant(hrp, &values) {
+            Some(variant) if variant == expected => {}
+            Some(variant) => return Err(AddressError::WrongChecksumVariant { version, variant }.into()),
+            None => {
+                // typos are looked for against the checksum the version calls for first
+                let other = match expected {
+                    Bech32Variant::Bech32 => Bech32Variant::Bech32m,
+                    Bech32Variant::Bech32m => Bech32Variant::Bech32,
+                };
+                let positions = bech32_locate_errors(hrp, &values, expected)
+                    .or_else(|| bech32_locate_errors(hrp, &values, other))
+                    .unwrap_or_default();
+                return Err(AddressDiagnostic::new(AddressError::Bech32Checksum, positions.iter().map(|i| sep + 1 + i).collect()));
+            }
+        }
+        if version > 16 {
+            return Err(AddressDiagnostic::new(AddressError::InvalidWitnessVersion(version), vec![sep + 1]));
+        }
+        // the program in 8 bit bytes, less than 5 bits of zero padding are left
+        let bits: Vec<bool> = values[1..values.len() - 6].iter().flat_map(|value| (0..5).rev().map(move |i| (value >> i) & 1 == 1)).collect();
+        let padding = &bits[bits.len() - bits.len() % 8..];
+        if padding.len() >= 5 || padding.contains(&true) {
+            return Err(AddressError::InvalidPadding.into());
+        }
+        let program = bits.chunks_exact(8).map(|byte| byte.iter().fold(0u8, |acc, bit| (acc << 1) | *bit as u8)).collect();
+        Ok(Address::new(network, Payload::WitnessProgram { version, program })?)
+    }
+    fn validate_base58(address: &str, network: Network) -> Result<Self, AddressDiagnostic> {
+        let invalid = base58_invalid_chars(address.as_bytes());
+        if !invalid.is_empty() {
+            return Err(AddressDiagnostic::new(AddressError::InvalidCharacters, invalid));
+        }
+        match Address::parse_for_network(address, network) {
+            Err(AddressError::InvalidChecksum) => {
+                let positions = base58_locate_error(address.as_bytes()).into_iter().collect();
+                Err(AddressDiagnostic::new(AddressError::InvalidChecksum, positions))
+            }
+            // the version is in the first character
+            Err(error @ (AddressError::WrongNetwork { .. } | AddressError::UnknownVersion(_))) => Err(AddressDiagnostic::new(error, vec![0])),
+            result => Ok(result?),
+        }
+    }
+    fn parse_base58(address: &str) -> Result<Self, AddressError> {
+        let combined = base58_decode(address.as_bytes()).ok_or(AddressError::InvalidBase58)?;
+        if combined.len() < 4 {
+            return Err(AddressError::InvalidLength(combined.len()));
+        }
+        let (payload, checksum) = combined.split_at(combined.len() - 4);
+        if hash256(payload)[..4] != *checksum {
+            return Err(AddressError::InvalidChecksum);
+        }
+        if payload.len() != 21 {
+            return Err(AddressError::InvalidLength(payload.len()));
+        }
+        let (version, hash) = (payload[0], payload[1..].to_vec());
+        for network in Network::ALL {
+            if version == network.p2pkh_version() {
+                return Ok(Address { network, payload: Payload::PubkeyHash(hash) });
+            }
+            if version == network.p2sh_version() {
+                return Ok(Address { network, payload: Payload::ScriptHash(hash) });
+            }
+        }
+        Err(AddressError::UnknownVersion(version))
+    }
+    /// The address paid by an output script, None for scripts without one.
+    pub fn from_script(script: &Script, network: Network) -> Option<Self> {
+        let payload = if script.is_p2pkh_script_pubkey() {
+            Payload::PubkeyHash(script.cmds[2].data()?.clone())
+        } else if script.is_p2sh_script_pubkey() {
+            Payload::ScriptHash(script.cmds[1].data()?.clone())
+        } else {
+            let (version, program) = script.witness_program()?;
+            Payload::WitnessProgram { version, program }
+        };
+        Address::new(network, payload).ok()
+    }
+    pub fn script_pubkey(&self) -> Script {
+        match &self.payload {
+            Payload::PubkeyHash(hash) => Script::p2pkh_script(hash.clone()),
+            Payload::ScriptHash(hash) => Script::p2sh_script(hash.clone()),
+            Payload::WitnessProgram { version, program } => Script::witness_program_script(*version, program.clone()),
+        }
+    }
+    pub fn network(&self) -> Network {
+        self.network
+    }
+    pub fn payload(&self) -> &Payload {
+        &self.payload
+    }
+    pub fn is_valid_for_network(&self, network: Network) -> bool {
+        match self.payload {
+            Payload::WitnessProgram { .. } => self.network.hrp() == network.hrp(),
+            _ => self.network.p2pkh_version() == network.p2pkh_version(),
+        }
+    }
+    pub fn address_type(&self) -> OutputType {
+        match &self.payload {
+            Payload::PubkeyHash(_) => OutputType::P2pkh,
+            Payload::ScriptHash(_) => OutputType::P2sh,
+            Payload::WitnessProgram { version: 0, program } if program.len() == 20 => OutputType::P2wpkh,
+            Payload::WitnessProgram { version: 0, .. } => OutputType::P2wsh,
+            Payload::WitnessProgram { version: 1, program } if program.len() == 32 => OutputType::P2tr,
+            Payload::WitnessProgram { .. } => OutputType::Unknown,
+        }
+    }
+    pub fn get_json(&self) -> serde_json::Value {
+        let script_pubkey = self.script_pubkey();
+        let (witness_version, witness_program) = match &self.payload {
+            Payload::WitnessProgram { version, program } => (json!(version), json!(hex::encode(program))),
+            _ => (json!(null), json!(null)),
+        };
+        json!({
+            "address": self.to_string(),
+            "network": self.network.to_string(),
+            "type": self.address_type().to_string(),
+            "script_pubkey": hex::encode(script_pubkey.raw_serialize()),
+            "script_asm": script_pubkey.to_string().trim_end(),
+            "witness_version": witness_version,
+            "witness_program": witness_program,
+        })
+    }
+}
+fn has_network_hrp(address: &str) -> bool {
+    let address = address.to_lowercase();
+    Network::ALL.iter().any(|network| address.starts_with(&format!("{}1", network.hrp().to_lowercase())))
+}
+// BIP141 program lengths, v0 programs are key or script hashes
+fn check_witness_program(version: u8, program: &[u8]) -> Result<(), AddressError> {
+    if version > 16 {
+        return Err(AddressError::InvalidWitnessVersion(version));
+    }
+    let valid = match version {
+        0 => program.len() == 20 || program.len() == 32,
+        _ => (2..=40).contains(&program.len()),
+    };
+    match valid {
+        true => Ok(()),
+        false => Err(AddressError::InvalidProgramLength(program.len())),
+    }
+}
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (version, hash) = match &self.payload {
+            Payload::PubkeyHash(hash) => (self.network.p2pkh_version(), hash),
+            Payload::ScriptHash(hash) => (self.network.p2sh_version(), hash),
+            Payload::WitnessProgram { version, program } => {
+                // the program was checked when the address was made
+                let version = Fe32::try_from(*version).map_err(|_| fmt::Error)?;
+                let address = segwit::encode(self.network.hrp(), version, program).map_err(|_| fmt::Error)?;
+                return write!(f, "{}", address);
+            }
+        };
+        let address = base58_encode_checksum([vec![version], hash.clone()].concat());
+        write!(f, "{}", String::from_utf8_lossy(&address))
+    }
+}
+impl FromStr for Address {
+    type Err = AddressError;
+    fn from_str(address: &str) -> Result<Self, Self::Err> {
+        Address::parse(address)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_parse() {
+        let addresses = [
+            ("1BenRpVUFK65JFWcQSuHnJKzc4M8ZP8Eqa", Network::Mainnet, "p2pkh", "76a91474d691da1574e6b3c192ecfb52cc8984ee7b6c5688ac"),
+            ("2N3u1R6uwQfuobCqbCgBkpsgBxvr1tZpe7B", Network::Testnet3, "p2sh", "a91474d691da1574e6b3c192ecfb52cc8984ee7b6c5687"),
+            ("bc1qvrtwrt8qqt7catejz59l2ly963xpdct2j370g3", Network::Mainnet, "v0_p2wpkh", "001460d6e1ace002fd8eaf32150bf57c85d44c16e16a"),
+            ("bcrt1qvrtwrt8qqt7catejz59l2ly963xpdct267u3yt", Network::Regtest, "v0_p2wpkh", "001460d6e1ace002fd8eaf32150bf57c85d44c16e16a"),
+            // BIP350 test vectors
+            ("tb1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3q0sl5k7", Network::Testnet3, "p2wsh", "00201863143c14c5166804bd19203356da136c985678cd4d27a1b8c6329604903262"),
+            ("bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqzk5jj0", Network::Mainnet, "v1_p2tr", "512079be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798"),
+            ("BC1SW50QGDZ25J", Network::Mainnet, "unknown", "6002751e"),
+            ("bc1zw508d6qejxtdg4y5r3zarvaryvaxxpcs", Network::Mainnet, "unknown", "5210751e76e8199196d454941c45d1b3a323"),
+        ];
+        for (text, network, address_type, script_pubkey) in addresses {
+            let address = Address::parse(text).unwrap();
+            assert_eq!(address.network(), network);
+            assert_eq!(address.address_type().to_string(), address_type);
+            assert_eq!(hex::encode(address.script_pubkey().raw_serialize()), script_pubkey);
+            // bech32 addresses are written back in lowercase
+            let expected = match address.payload() {
+                Payload::WitnessProgram { .. } => text.to_lowercase(),
+                _ => text.to_string(),
+            };
+            assert_eq!(address.to_string(), expected);
+            assert_eq!(Address::from_script(&address.script_pubkey(), network), Some(address));
+        }
+        assert!(Address::parse("tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx").unwrap().is_valid_for_network(Network::Signet));
+        assert_eq!(
+            Address::parse_for_network("1BenRpVUFK65JFWcQSuHnJKzc4M8ZP8Eqa", Network::Testnet4),
+            Err(AddressError::WrongNetwork { expected: Network::Testnet4, found: Network::Mainnet }),
+        );
+    }
+    #[test]
+    fn test_parse_invalid() {
+        assert_eq!(Address::parse("1BenRpVUFK65JFWcQSuHnJKzc4M8ZP8Eqb"), Err(AddressError::InvalidChecksum));
+        assert_eq!(Address::parse("1BenRpVUFK65JFWcQSuHnJKzc4M8ZP8Eq0"), Err(AddressError::InvalidBase58));
+        // a valid base58check payload of the wrong length
+        assert_eq!(Address::parse("wdA2ffYs5cudrdkhFm5Ym94AuLvavacapuDBL2CAcvqYPkcvi"), Err(AddressError::InvalidLength(32)));
+        assert_eq!(Address::parse("tc1qw508d6qejxtdg4y5r3zarvary0c5xw7kg3g4ty"), Err(AddressError::UnknownHrp("tc".to_string())));
+        // BIP350 invalid addresses: bech32 checksum on v1, bech32m on v0, bad program length
+        for address in [
+            "bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqh2y7hd",
+            "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kemeawh",
+            "bc1pw5dgrnzv",
+            "BC1QR508D6QEJXTDG4Y5R3ZARVARYV98GJ9P",
+        ] {
+            assert!(matches!(Address::parse(address), Err(AddressError::InvalidBech32(_))), "{}", address);
+        }
+        assert_eq!(Address::new(Network::Mainnet, Payload::WitnessProgram { version: 0, program: vec![0; 21] }), Err(AddressError::InvalidProgramLength(21)));
+        assert_eq!(AddressError::InvalidLength(32).get_json()["error"], "InvalidLength");
+    }
+    #[test]
+    fn test_validate() {
+        let diagnostic = |address: &str, network: Network| Address::validate(address, network).unwrap_err();
+        assert_eq!(Address::validate("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4", Network::Mainnet), Address::parse_for_network("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4", Network::Mainnet).map_err(AddressDiagnostic::from));
+        assert!(Address::validate("BC1SW50QGDZ25J", Network::Mainnet).is_ok());
+        assert!(Address::validate("2N3u1R6uwQfuobCqbCgBkpsgBxvr1tZpe7B", Network::Testnet4).is_ok());
+        // two typos in the program
+        assert_eq!(diagnostic("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4".replace("w508", "w5l8").replace("ary0", "ary9").as_str(), Network::Mainnet), AddressDiagnostic::new(AddressError::Bech32Checksum, vec![6, 29]));
+        assert_eq!(diagnostic("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kemeawh", Network::Mainnet).error, AddressError::WrongChecksumVariant { version: 0, variant: Bech32Variant::Bech32m });
+        assert_eq!(diagnostic("bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqh2y7hd", Network::Mainnet).error, AddressError::WrongChecksumVariant { version: 1, variant: Bech32Variant::Bech32 });
+        assert_eq!(diagnostic("bc1qw508d6qejxtdg4y5r3zaRvary0c5xw7kv8f3t4", Network::Mainnet), AddressDiagnostic::new(AddressError::MixedCase, vec![24]));
+        assert_eq!(diagnostic("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4", Network::Testnet4), AddressDiagnostic::new(AddressError::WrongNetwork { expected: Network::Testnet4, found: Network::Mainnet }, vec![0, 1]));
+        assert_eq!(diagnostic("bd1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4", Network::Mainnet).error, AddressError::UnknownHrp("bd".to_string()));
+        assert_eq!(diagnostic("bc1qw5o8d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4", Network::Mainnet), AddressDiagnostic::new(AddressError::InvalidCharacters, vec![6]));
+        assert_eq!(diagnostic("BC1QR508D6QEJXTDG4Y5R3ZARVARYV98GJ9P", Network::Mainnet).error, AddressError::InvalidProgramLength(16));
+        assert_eq!(diagnostic("bc1gmk9yu", Network::Mainnet).error, AddressError::InvalidSeparator);
+        // base58
+        assert_eq!(diagnostic("1BenRpVUFK65JFWcQSuHnJKzc4M9ZP8Eqa", Network::Mainnet), AddressDiagnostic::new(AddressError::InvalidChecksum, vec![27]));
+        assert_eq!(diagnostic("1BenRpVUFK65JFWcQSuHnJKzc4M8ZP8EqO", Network::Mainnet), AddressDiagnostic::new(AddressError::InvalidCharacters, vec![33]));
+        assert_eq!(diagnostic("1BenRpVUFK65JFWcQSuHnJKzc4M8ZP8Eqa", Network::Signet).positions, vec![0]);
+        assert_eq!(diagnostic("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t5", Network::Mainnet).get_json()["positions"], json!([41]));
+    }
+}
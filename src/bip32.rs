@@ -0,0 +1,602 @@
+use std::fmt;
+use std::str::FromStr;
+use num::{BigUint, Zero};
+use serde_json::json;
+use crate::address::{Address, Payload};
+use crate::helpers::base58::{base58_decode_checksum, base58_encode_checksum};
+use crate::helpers::endianness::int_to_big_endian;
+use crate::helpers::hash160::hash160;
+use crate::helpers::hmac_sha512::hmac_sha512;
+use crate::network::Network;
+use crate::point::Point;
+use crate::private_key::PrivateKey;
+use crate::secp256k1::Secp256k1;
+
+/// Child numbers from here up derive hardened keys, written 0' or 0h in paths.
+pub const HARDENED: u32 = 0x8000_0000;
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum Bip32Error {
+    InvalidSeed(usize),
+    InvalidBase58,
+    InvalidLength(usize),
+    UnknownVersion([u8; 4]),
+    ExpectedPrivate,
+    ExpectedPublic,
+    InvalidKey,
+    InvalidParent,
+    MaxDepth,
+    HardenedFromPublic(u32),
+    InvalidChild(u32),
+    InvalidPath(String),
+}
+impl fmt::Display for Bip32Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Bip32Error::InvalidSeed(length) => write!(f, "seed of {} bytes, it must be 16 to 64", length),
+            Bip32Error::InvalidBase58 => write!(f, "invalid base58 or checksum"),
+            Bip32Error::InvalidLength(length) => write!(f, "extended keys are 78 bytes, not {}", length),
+            Bip32Error::UnknownVersion(version) => write!(f, "unknown version {}", hex::encode(version)),
+            Bip32Error::ExpectedPrivate => write!(f, "public key where a private key was expected"),
+            Bip32Error::ExpectedPublic => write!(f, "private key where a public key was expected"),
+            Bip32Error::InvalidKey => write!(f, "invalid key data"),
+            Bip32Error::InvalidParent => write!(f, "master key with a parent fingerprint or child number"),
+            Bip32Error::MaxDepth => write!(f, "keys can't be derived below depth 255"),
+            Bip32Error::HardenedFromPublic(index) => write!(f, "hardened child {}' can't be derived from a public key", index - HARDENED),
+            Bip32Error::InvalidChild(index) => write!(f, "child {} is invalid, use the next index", index),
+            Bip32Error::InvalidPath(path) => write!(f, "invalid derivation path {}", path),
+        }
+    }
+}
+impl Bip32Error {
+    pub fn get_json(&self) -> serde_json::Value {
+        let error = format!("{:?}", self);
+        json!({
+            "error": error.split([' ', '(', '{']).next(),
+            "message": self.to_string(),
+        })
+    }
+}
+impl std::error::Error for Bip32Error {}
+
+/// Outputs of the keys below an extended key, announced by the SLIP-132 version
+/// bytes: xpub/tpub for p2pkh, ypub/upub for p2sh-p2wpkh and zpub/vpub for p2wpkh.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub enum ScriptType {
+    #[default]
+    P2pkh,
+    P2shP2wpkh,
+    P2wpkh,
+}
+impl ScriptType {
+    pub const ALL: [ScriptType; 3] = [ScriptType::P2pkh, ScriptType::P2shP2wpkh, ScriptType::P2wpkh];
+
+    /// Private and public version bytes.
+    pub fn versions(&self, network: Network) -> ([u8; 4], [u8; 4]) {
+        let (private, public): (u32, u32) = match (self, network.is_testnet()) {
+            (ScriptType::P2pkh, false) => (0x0488ade4, 0x0488b21e),
+            (ScriptType::P2pkh, true) => (0x04358394, 0x043587cf),
+            (ScriptType::P2shP2wpkh, false) => (0x049d7878, 0x049d7cb2),
+            (ScriptType::P2shP2wpkh, true) => (0x044a4e28, 0x044a5262),
+            (ScriptType::P2wpkh, false) => (0x04b2430c, 0x04b24746),
+            (ScriptType::P2wpkh, true) => (0x045f18bc, 0x045f1cf6),
+        };
+        (private.to_be_bytes(), public.to_be_bytes())
+    }
+}
+
+/// Child numbers from the master key down, hardened ones include HARDENED.
+#[derive(Debug, Clone, Eq, PartialEq, Default)]
+pub struct DerivationPath(pub Vec<u32>);
+impl DerivationPath {
+    pub fn child(&self, index: u32) -> Self {
+        let mut path = self.0.clone();
+        path.push(index);
+        DerivationPath(path)
+    }
+}
+impl fmt::Display for DerivationPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "m")?;
+        for index in &self.0 {
+            match *index >= HARDENED {
+                true => write!(f, "/{}'", index - HARDENED)?,
+                false => write!(f, "/{}", index)?,
+            }
+        }
+        Ok(())
+    }
+}
+/// Parses paths like m/84'/0'/0'/0/5, hardened indexes can also end in h or H.
+impl FromStr for DerivationPath {
+    type Err = Bip32Error;
+    fn from_str(path: &str) -> Result<Self, Self::Err> {
+        let invalid = || Bip32Error::InvalidPath(path.to_string());
+        let mut parts = path.split('/');
+        if parts.next() != Some("m") {
+            return Err(invalid());
+        }
+        parts.map(|part| {
+            let (number, hardened) = match part.strip_suffix(['\'', 'h', 'H']) {
+                Some(number) => (number, HARDENED),
+                None => (part, 0),
+            };
+            if number.is_empty() || !number.bytes().all(|c| c.is_ascii_digit()) {
+                return Err(invalid());
+            }
+            match number.parse::<u32>() {
+                Ok(index) if index < HARDENED => Ok(index + hardened),
+                _ => Err(invalid()),
+            }
+        }).collect::<Result<Vec<u32>, Bip32Error>>().map(DerivationPath)
+    }
+}
+
+// version, depth, parent fingerprint, child number, chain code and key in base58 with checksum
+fn encode(version: [u8; 4], depth: u8, parent_fingerprint: [u8; 4], child_number: u32, chain_code: &[u8; 32], key: &[u8]) -> String {
+    let mut data = version.to_vec();
+    data.push(depth);
+    data.extend(parent_fingerprint);
+    data.extend(child_number.to_be_bytes());
+    data.extend(chain_code);
+    data.extend(key);
+    String::from_utf8(base58_encode_checksum(data)).unwrap()
+}
+struct Decoded {
+    network: Network,
+    script_type: ScriptType,
+    is_private: bool,
+    depth: u8,
+    parent_fingerprint: [u8; 4],
+    child_number: u32,
+    chain_code: [u8; 32],
+    key: Vec<u8>,
+}
+// testnet versions decode to testnet3, with_network picks another test chain
+fn decode(key: &str) -> Result<Decoded, Bip32Error> {
+    let data = base58_decode_checksum(key.as_bytes()).ok_or(Bip32Error::InvalidBase58)?;
+    if data.len() != 78 {
+        return Err(Bip32Error::InvalidLength(data.len()));
+    }
+    let version: [u8; 4] = data[..4].try_into().unwrap();
+    let (network, script_type, is_private) = ScriptType::ALL.into_iter()
+        .flat_map(|script_type| [Network::Mainnet, Network::Testnet3].map(|network| (network, script_type)))
+        .find_map(|(network, script_type)| match script_type.versions(network) {
+            (private, _) if private == version => Some((network, script_type, true)),
+            (_, public) if public == version => Some((network, script_type, false)),
+            _ => None,
+        })
+        .ok_or(Bip32Error::UnknownVersion(version))?;
+    let decoded = Decoded {
+        network,
+        script_type,
+        is_private,
+        depth: data[4],
+        parent_fingerprint: data[5..9].try_into().unwrap(),
+        child_number: u32::from_be_bytes(data[9..13].try_into().unwrap()),
+        chain_code: data[13..45].try_into().unwrap(),
+        key: data[45..].to_vec(),
+    };
+    if decoded.depth == 0 && (decoded.parent_fingerprint != [0; 4] || decoded.child_number != 0) {
+        return Err(Bip32Error::InvalidParent);
+    }
+    Ok(decoded)
+}
+// splits HMAC-SHA512 output into the key tweak, valid below n, and the chain code
+fn split_hmac(key: &[u8], data: &[u8], index: u32) -> Result<(BigUint, [u8; 32]), Bip32Error> {
+    let i = hmac_sha512(key, data);
+    let tweak = BigUint::from_bytes_be(&i[..32]);
+    if tweak >= Secp256k1::new().n {
+        return Err(Bip32Error::InvalidChild(index));
+    }
+    Ok((tweak, i[32..].try_into().unwrap()))
+}
+
+/// BIP32 extended private key: a secret plus the chain code to derive children,
+/// with the position in the tree it was derived at.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ExtendedPrivateKey {
+    network: Network,
+    script_type: ScriptType,
+    depth: u8,
+    parent_fingerprint: [u8; 4],
+    child_number: u32,
+    chain_code: [u8; 32],
+    private_key: PrivateKey,
+}
+impl ExtendedPrivateKey {
+    pub fn new_master(seed: &[u8], network: Network) -> Result<Self, Bip32Error> {
+        if !(16..=64).contains(&seed.len()) {
+            return Err(Bip32Error::InvalidSeed(seed.len()));
+        }
+        let (secret, chain_code) = split_hmac(b"Bitcoin seed", seed, 0).map_err(|_| Bip32Error::InvalidSeed(seed.len()))?;
+        if secret.is_zero() {
+            return Err(Bip32Error::InvalidSeed(seed.len()));
+        }
+        Ok(ExtendedPrivateKey {
+            network,
+            script_type: ScriptType::default(),
+            depth: 0,
+            parent_fingerprint: [0; 4],
+            child_number: 0,
+            chain_code,
+            private_key: PrivateKey::new(&secret),
+        })
+    }
+    pub fn parse(key: &str) -> Result<Self, Bip32Error> {
+        let decoded = decode(key)?;
+        if !decoded.is_private {
+            return Err(Bip32Error::ExpectedPrivate);
+        }
+        let secret = BigUint::from_bytes_be(&decoded.key[1..]);
+        if decoded.key[0] != 0 || secret.is_zero() || secret >= Secp256k1::new().n {
+            return Err(Bip32Error::InvalidKey);
+        }
+        Ok(ExtendedPrivateKey {
+            network: decoded.network,
+            script_type: decoded.script_type,
+            depth: decoded.depth,
+            parent_fingerprint: decoded.parent_fingerprint,
+            child_number: decoded.child_number,
+            chain_code: decoded.chain_code,
+            private_key: PrivateKey::new(&secret),
+        })
+    }
+    pub fn with_network(mut self, network: Network) -> Self {
+        self.network = network;
+        self
+    }
+    pub fn with_script_type(mut self, script_type: ScriptType) -> Self {
+        self.script_type = script_type;
+        self
+    }
+    /// CKDpriv, children from HARDENED up hash the secret instead of the public key.
+    pub fn derive_child(&self, index: u32) -> Result<Self, Bip32Error> {
+        let depth = self.depth.checked_add(1).ok_or(Bip32Error::MaxDepth)?;
+        let mut data = match index >= HARDENED {
+            true => [vec![0], int_to_big_endian(self.private_key.secret(), 32)].concat(),
+            false => self.private_key.point().sec(true),
+        };
+        data.extend(index.to_be_bytes());
+        let (tweak, chain_code) = split_hmac(&self.chain_code, &data, index)?;
+        let secret = (tweak + self.private_key.secret()) % Secp256k1::new().n;
+        if secret.is_zero() {
+            return Err(Bip32Error::InvalidChild(index));
+        }
+        Ok(ExtendedPrivateKey {
+            network: self.network,
+            script_type: self.script_type,
+            depth,
+            parent_fingerprint: self.fingerprint(),
+            child_number: index,
+            chain_code,
+            private_key: PrivateKey::new(&secret),
+        })
+    }
+    /// Derives every index of the path below this key.
+    pub fn derive_path(&self, path: &DerivationPath) -> Result<Self, Bip32Error> {
+        path.0.iter().try_fold(self.clone(), |key, index| key.derive_child(*index))
+    }
+    /// N(), the same position in the tree without the secret.
+    pub fn to_public(&self) -> ExtendedPublicKey {
+        ExtendedPublicKey {
+            network: self.network,
+            script_type: self.script_type,
+            depth: self.depth,
+            parent_fingerprint: self.parent_fingerprint,
+            child_number: self.child_number,
+            chain_code: self.chain_code,
+            public_key: self.private_key.point(),
+        }
+    }
+    pub fn identifier(&self) -> [u8; 20] {
+        hash160(&self.private_key.point().sec(true))
+    }
+    pub fn fingerprint(&self) -> [u8; 4] {
+        self.identifier()[..4].try_into().unwrap()
+    }
+    pub fn network(&self) -> Network {
+        self.network
+    }
+    pub fn script_type(&self) -> ScriptType {
+        self.script_type
+    }
+    pub fn depth(&self) -> u8 {
+        self.depth
+    }
+    pub fn parent_fingerprint(&self) -> [u8; 4] {
+        self.parent_fingerprint
+    }
+    pub fn child_number(&self) -> u32 {
+        self.child_number
+    }
+    pub fn chain_code(&self) -> [u8; 32] {
+        self.chain_code
+    }
+    pub fn private_key(&self) -> &PrivateKey {
+        &self.private_key
+    }
+    pub fn get_json(&self) -> serde_json::Value {
+        let mut json = self.to_public().get_json();
+        json["xprv"] = json!(self.to_string());
+        json
+    }
+}
+impl fmt::Display for ExtendedPrivateKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (version, _) = self.script_type.versions(self.network);
+        let key = [vec![0], int_to_big_endian(self.private_key.secret(), 32)].concat();
+        write!(f, "{}", encode(version, self.depth, self.parent_fingerprint, self.child_number, &self.chain_code, &key))
+    }
+}
+impl FromStr for ExtendedPrivateKey {
+    type Err = Bip32Error;
+    fn from_str(key: &str) -> Result<Self, Self::Err> {
+        ExtendedPrivateKey::parse(key)
+    }
+}
+
+/// BIP32 extended public key, derives the non hardened children of its private key.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ExtendedPublicKey {
+    network: Network,
+    script_type: ScriptType,
+    depth: u8,
+    parent_fingerprint: [u8; 4],
+    child_number: u32,
+    chain_code: [u8; 32],
+    public_key: Point,
+}
+impl ExtendedPublicKey {
+    pub fn parse(key: &str) -> Result<Self, Bip32Error> {
+        let decoded = decode(key)?;
+        if decoded.is_private {
+            return Err(Bip32Error::ExpectedPublic);
+        }
+        let public_key = Point::parse(&decoded.key).map_err(|_| Bip32Error::InvalidKey)?;
+        Ok(ExtendedPublicKey {
+            network: decoded.network,
+            script_type: decoded.script_type,
+            depth: decoded.depth,
+            parent_fingerprint: decoded.parent_fingerprint,
+            child_number: decoded.child_number,
+            chain_code: decoded.chain_code,
+            public_key,
+        })
+    }
+    pub fn with_network(mut self, network: Network) -> Self {
+        self.network = network;
+        self
+    }
+    pub fn with_script_type(mut self, script_type: ScriptType) -> Self {
+        self.script_type = script_type;
+        self
+    }
+    /// CKDpub, the child's public key is the parent's plus the tweak times G.
+    pub fn derive_child(&self, index: u32) -> Result<Self, Bip32Error> {
+        if index >= HARDENED {
+            return Err(Bip32Error::HardenedFromPublic(index));
+        }
+        let depth = self.depth.checked_add(1).ok_or(Bip32Error::MaxDepth)?;
+        let mut data = self.public_key.sec(true);
+        data.extend(index.to_be_bytes());
+        let (tweak, chain_code) = split_hmac(&self.chain_code, &data, index)?;
+        if tweak.is_zero() {
+            return Err(Bip32Error::InvalidChild(index));
+        }
+        let public_key = PrivateKey::new(&tweak).point() + self.public_key.clone();
+        if public_key.x().is_none() {
+            return Err(Bip32Error::InvalidChild(index));
+        }
+        Ok(ExtendedPublicKey {
+            network: self.network,
+            script_type: self.script_type,
+            depth,
+            parent_fingerprint: self.fingerprint(),
+            child_number: index,
+            chain_code,
+            public_key,
+        })
+    }
+    /// Derives every index of the path below this key, none of them hardened.
+    pub fn derive_path(&self, path: &DerivationPath) -> Result<Self, Bip32Error> {
+        path.0.iter().try_fold(self.clone(), |key, index| key.derive_child(*index))
+    }
+    pub fn identifier(&self) -> [u8; 20] {
+        hash160(&self.public_key.sec(true))
+    }
+    pub fn fingerprint(&self) -> [u8; 4] {
+        self.identifier()[..4].try_into().unwrap()
+    }
+    /// Address of the key for its script type.
+    pub fn address(&self) -> Address {
+        let h160 = self.identifier().to_vec();
+        let payload = match self.script_type {
+            ScriptType::P2pkh => Payload::PubkeyHash(h160),
+            ScriptType::P2shP2wpkh => Payload::ScriptHash(hash160(&[vec![0x00, 0x14], h160].concat()).to_vec()),
+            ScriptType::P2wpkh => Payload::WitnessProgram { version: 0, program: h160 },
+        };
+        Address::new(self.network, payload).expect("hash160 payloads are valid")
+    }
+    pub fn network(&self) -> Network {
+        self.network
+    }
+    pub fn script_type(&self) -> ScriptType {
+        self.script_type
+    }
+    pub fn depth(&self) -> u8 {
+        self.depth
+    }
+    pub fn parent_fingerprint(&self) -> [u8; 4] {
+        self.parent_fingerprint
+    }
+    pub fn child_number(&self) -> u32 {
+        self.child_number
+    }
+    pub fn chain_code(&self) -> [u8; 32] {
+        self.chain_code
+    }
+    pub fn public_key(&self) -> &Point {
+        &self.public_key
+    }
+    pub fn get_json(&self) -> serde_json::Value {
+        json!({
+            "xpub": self.to_string(),
+            "network": self.network.to_string(),
+            "depth": self.depth,
+            "fingerprint": hex::encode(self.fingerprint()),
+            "parent_fingerprint": hex::encode(self.parent_fingerprint),
+            "child_number": DerivationPath(vec![self.child_number]).to_string().trim_start_matches("m/"),
+            "chain_code": hex::encode(self.chain_code),
+            "public_key": hex::encode(self.public_key.sec(true)),
+            "address": self.address().to_string(),
+        })
+    }
+}
+impl fmt::Display for ExtendedPublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (_, version) = self.script_type.versions(self.network);
+        write!(f, "{}", encode(version, self.depth, self.parent_fingerprint, self.child_number, &self.chain_code, &self.public_key.sec(true)))
+    }
+}
+impl FromStr for ExtendedPublicKey {
+    type Err = Bip32Error;
+    fn from_str(key: &str) -> Result<Self, Self::Err> {
+        ExtendedPublicKey::parse(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check_chain(seed: &str, chain: &[(&str, &str, &str)]) {
+        let mut key = ExtendedPrivateKey::new_master(&hex::decode(seed).unwrap(), Network::Mainnet).unwrap();
+        let mut path = DerivationPath::default();
+        for (i, (step, xpub, xprv)) in chain.iter().enumerate() {
+            if i > 0 {
+                let index: DerivationPath = format!("m/{}", step).parse().unwrap();
+                let index = index.0[0];
+                let child = key.derive_child(index).unwrap();
+                if index < HARDENED {
+                    assert_eq!(key.to_public().derive_child(index).unwrap(), child.to_public());
+                } else {
+                    assert_eq!(key.to_public().derive_child(index), Err(Bip32Error::HardenedFromPublic(index)));
+                }
+                assert_eq!(child.parent_fingerprint(), key.fingerprint());
+                path = path.child(index);
+                key = child;
+            }
+            assert_eq!(key.depth() as usize, path.0.len());
+            assert_eq!(key.to_string(), *xprv);
+            assert_eq!(key.to_public().to_string(), *xpub);
+            assert_eq!(ExtendedPrivateKey::parse(xprv).unwrap(), key);
+            assert_eq!(ExtendedPublicKey::parse(xpub).unwrap(), key.to_public());
+        }
+    }
+    #[test]
+    fn test_vector_1() {
+        check_chain("000102030405060708090a0b0c0d0e0f", &[
+            ("m", "xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8",
+             "xprv9s21ZrQH143K3QTDL4LXw2F7HEK3wJUD2nW2nRk4stbPy6cq3jPPqjiChkVvvNKmPGJxWUtg6LnF5kejMRNNU3TGtRBeJgk33yuGBxrMPHi"),
+            ("0'", "xpub68Gmy5EdvgibQVfPdqkBBCHxA5htiqg55crXYuXoQRKfDBFA1WEjWgP6LHhwBZeNK1VTsfTFUHCdrfp1bgwQ9xv5ski8PX9rL2dZXvgGDnw",
+             "xprv9uHRZZhk6KAJC1avXpDAp4MDc3sQKNxDiPvvkX8Br5ngLNv1TxvUxt4cV1rGL5hj6KCesnDYUhd7oWgT11eZG7XnxHrnYeSvkzY7d2bhkJ7"),
+            ("1", "xpub6ASuArnXKPbfEwhqN6e3mwBcDTgzisQN1wXN9BJcM47sSikHjJf3UFHKkNAWbWMiGj7Wf5uMash7SyYq527Hqck2AxYysAA7xmALppuCkwQ",
+             "xprv9wTYmMFdV23N2TdNG573QoEsfRrWKQgWeibmLntzniatZvR9BmLnvSxqu53Kw1UmYPxLgboyZQaXwTCg8MSY3H2EU4pWcQDnRnrVA1xe8fs"),
+            ("2H", "xpub6D4BDPcP2GT577Vvch3R8wDkScZWzQzMMUm3PWbmWvVJrZwQY4VUNgqFJPMM3No2dFDFGTsxxpG5uJh7n7epu4trkrX7x7DogT5Uv6fcLW5",
+             "xprv9z4pot5VBttmtdRTWfWQmoH1taj2axGVzFqSb8C9xaxKymcFzXBDptWmT7FwuEzG3ryjH4ktypQSAewRiNMjANTtpgP4mLTj34bhnZX7UiM"),
+            ("2", "xpub6FHa3pjLCk84BayeJxFW2SP4XRrFd1JYnxeLeU8EqN3vDfZmbqBqaGJAyiLjTAwm6ZLRQUMv1ZACTj37sR62cfN7fe5JnJ7dh8zL4fiyLHV",
+             "xprvA2JDeKCSNNZky6uBCviVfJSKyQ1mDYahRjijr5idH2WwLsEd4Hsb2Tyh8RfQMuPh7f7RtyzTtdrbdqqsunu5Mm3wDvUAKRHSC34sJ7in334"),
+            ("1000000000", "xpub6H1LXWLaKsWFhvm6RVpEL9P4KfRZSW7abD2ttkWP3SSQvnyA8FSVqNTEcYFgJS2UaFcxupHiYkro49S8yGasTvXEYBVPamhGW6cFJodrTHy",
+             "xprvA41z7zogVVwxVSgdKUHDy1SKmdb533PjDz7J6N6mV6uS3ze1ai8FHa8kmHScGpWmj4WggLyQjgPie1rFSruoUihUZREPSL39UNdE3BBDu76"),
+        ]);
+    }
+    #[test]
+    fn test_vector_2() {
+        check_chain("fffcf9f6f3f0edeae7e4e1dedbd8d5d2cfccc9c6c3c0bdbab7b4b1aeaba8a5a29f9c999693908d8a8784817e7b7875726f6c696663605d5a5754514e4b484542", &[
+            ("m", "xpub661MyMwAqRbcFW31YEwpkMuc5THy2PSt5bDMsktWQcFF8syAmRUapSCGu8ED9W6oDMSgv6Zz8idoc4a6mr8BDzTJY47LJhkJ8UB7WEGuduB",
+             "xprv9s21ZrQH143K31xYSDQpPDxsXRTUcvj2iNHm5NUtrGiGG5e2DtALGdso3pGz6ssrdK4PFmM8NSpSBHNqPqm55Qn3LqFtT2emdEXVYsCzC2U"),
+            ("0", "xpub69H7F5d8KSRgmmdJg2KhpAK8SR3DjMwAdkxj3ZuxV27CprR9LgpeyGmXUbC6wb7ERfvrnKZjXoUmmDznezpbZb7ap6r1D3tgFxHmwMkQTPH",
+             "xprv9vHkqa6EV4sPZHYqZznhT2NPtPCjKuDKGY38FBWLvgaDx45zo9WQRUT3dKYnjwih2yJD9mkrocEZXo1ex8G81dwSM1fwqWpWkeS3v86pgKt"),
+            ("2147483647'", "xpub6ASAVgeehLbnwdqV6UKMHVzgqAG8Gr6riv3Fxxpj8ksbH9ebxaEyBLZ85ySDhKiLDBrQSARLq1uNRts8RuJiHjaDMBU4Zn9h8LZNnBC5y4a",
+             "xprv9wSp6B7kry3Vj9m1zSnLvN3xH8RdsPP1Mh7fAaR7aRLcQMKTR2vidYEeEg2mUCTAwCd6vnxVrcjfy2kRgVsFawNzmjuHc2YmYRmagcEPdU9"),
+            ("1", "xpub6DF8uhdarytz3FWdA8TvFSvvAh8dP3283MY7p2V4SeE2wyWmG5mg5EwVvmdMVCQcoNJxGoWaU9DCWh89LojfZ537wTfunKau47EL2dhHKon",
+             "xprv9zFnWC6h2cLgpmSA46vutJzBcfJ8yaJGg8cX1e5StJh45BBciYTRXSd25UEPVuesF9yog62tGAQtHjXajPPdbRCHuWS6T8XA2ECKADdw4Ef"),
+            ("2147483646'", "xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL",
+             "xprvA1RpRA33e1JQ7ifknakTFpgNXPmW2YvmhqLQYMmrj4xJXXWYpDPS3xz7iAxn8L39njGVyuoseXzU6rcxFLJ8HFsTjSyQbLYnMpCqE2VbFWc"),
+            ("2", "xpub6FnCn6nSzZAw5Tw7cgR9bi15UV96gLZhjDstkXXxvCLsUXBGXPdSnLFbdpq8p9HmGsApME5hQTZ3emM2rnY5agb9rXpVGyy3bdW6EEgAtqt",
+             "xprvA2nrNbFZABcdryreWet9Ea4LvTJcGsqrMzxHx98MMrotbir7yrKCEXw7nadnHM8Dq38EGfSh6dqA9QWTyefMLEcBYJUuekgW4BYPJcr9E7j"),
+        ]);
+    }
+    #[test]
+    fn test_derivation_path() {
+        let path: DerivationPath = "m/84'/0'/0'/0/5".parse().unwrap();
+        assert_eq!(path.0, vec![HARDENED + 84, HARDENED, HARDENED, 0, 5]);
+        assert_eq!(path.to_string(), "m/84'/0'/0'/0/5");
+        assert_eq!("m/0h/1H/2".parse::<DerivationPath>().unwrap().to_string(), "m/0'/1'/2");
+        assert_eq!("m".parse::<DerivationPath>(), Ok(DerivationPath::default()));
+        for invalid in ["", "84'/0'", "m/", "m//1", "m/2147483648", "m/-1", "m/+1", "m/1''", "m/x", "M/1"] {
+            assert_eq!(invalid.parse::<DerivationPath>(), Err(Bip32Error::InvalidPath(invalid.to_string())));
+        }
+
+        let seed = hex::decode("000102030405060708090a0b0c0d0e0f").unwrap();
+        let master = ExtendedPrivateKey::new_master(&seed, Network::Mainnet).unwrap();
+        let account = master.derive_path(&"m/0'/1".parse().unwrap()).unwrap();
+        assert_eq!(account.to_string(), "xprv9wTYmMFdV23N2TdNG573QoEsfRrWKQgWeibmLntzniatZvR9BmLnvSxqu53Kw1UmYPxLgboyZQaXwTCg8MSY3H2EU4pWcQDnRnrVA1xe8fs");
+        assert_eq!(master.to_public().derive_path(&"m/0'".parse().unwrap()), Err(Bip32Error::HardenedFromPublic(HARDENED)));
+
+        // addresses of m/0'/1 for each script type
+        let xpub = account.to_public();
+        assert_eq!(xpub.address().to_string(), "1JQheacLPdM5ySCkrZkV66G2ApAXe1mqLj");
+        assert_eq!(xpub.clone().with_script_type(ScriptType::P2shP2wpkh).address().to_string(), "3DymAvEWH38HuzHZ3VwLus673bNZnYwNXu");
+        assert_eq!(xpub.clone().with_script_type(ScriptType::P2wpkh).address().to_string(), "bc1qhm6697d9d2224vfyt8mj4kw03ncec7a7fdafvt");
+        assert_eq!(xpub.with_script_type(ScriptType::P2wpkh).with_network(Network::Testnet4).address().to_string(), "tb1qhm6697d9d2224vfyt8mj4kw03ncec7a7rtx6hc");
+    }
+    #[test]
+    fn test_versions() {
+        let seed = hex::decode("000102030405060708090a0b0c0d0e0f").unwrap();
+        let master = ExtendedPrivateKey::new_master(&seed, Network::Mainnet).unwrap();
+        let keys = [
+            (ScriptType::P2shP2wpkh, Network::Mainnet, "yprvABrGsX5C9jantheLAR8A97LcTCTVsvThwu2FZpdxFtyH2CS4JPYxToNLixTWvGygnuRmFxVEZ18ny3GJ57nPGH8skkt4tbZXKhxuaUFh6jt",
+             "ypub6QqdH2c5z7967BioGSfAWFHM1EHzHPBZK7wrND3ZpEWFtzmCqvsD1bgpaE6pSAPkiSKhkuWPCJV6mZTSNMd2tK8xYTcJ48585pZecmSUzWp"),
+            (ScriptType::P2wpkh, Network::Mainnet, "zprvAWgYBBk7JR8GjzqSzmunMCS7dAbwpYTCs1YUMDXqduMA5JFHZ3iX5s2UkAR6vBdcCYYa1S5o1fVLrKsrnpCQ4WpUd6aVUWP1bS2Yy5DoaKv",
+             "zpub6jftahH18ngZxUuv6oSniLNrBCSSE1B4EEU59bwTCEt8x6aS6b2mdfLxbS4QS53g85SWWP6wexqeer516433gYpZQoJie2tcMYdJ1SYYYAL"),
+            (ScriptType::P2pkh, Network::Testnet3, "tprv8ZgxMBicQKsPeDgjzdC36fs6bMjGApWDNLR9erAXMs5skhMv36j9MV5ecvfavji5khqjWaWSFhN3YcCUUdiKH6isR4Pwy3U5y5egddBr16m",
+             "tpubD6NzVbkrYhZ4XgiXtGrdW5XDAPFCL9h7we1vwNCpn8tGbBcgfVYjXyhWo4E1xkh56hjod1RhGjxbaTLV3X4FyWuejifB9jusQ46QzG87VKp"),
+        ];
+        for (script_type, network, xprv, xpub) in keys {
+            let key = master.clone().with_script_type(script_type).with_network(network);
+            assert_eq!(key.to_string(), xprv);
+            assert_eq!(key.to_public().to_string(), xpub);
+            assert_eq!(ExtendedPrivateKey::parse(xprv).unwrap(), key);
+            assert_eq!(ExtendedPublicKey::parse(xpub).unwrap().script_type(), script_type);
+        }
+        for script_type in ScriptType::ALL {
+            let key = master.clone().with_script_type(script_type).with_network(Network::Testnet3);
+            assert_eq!(ExtendedPrivateKey::parse(&key.to_string()).unwrap(), key);
+        }
+    }
+    #[test]
+    fn test_parse_invalid() {
+        let xprv = "xprv9s21ZrQH143K3QTDL4LXw2F7HEK3wJUD2nW2nRk4stbPy6cq3jPPqjiChkVvvNKmPGJxWUtg6LnF5kejMRNNU3TGtRBeJgk33yuGBxrMPHi";
+        let xpub = "xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8";
+        let modify = |key: &str, edit: &dyn Fn(&mut Vec<u8>)| {
+            let mut data = base58_decode_checksum(key.as_bytes()).unwrap();
+            edit(&mut data);
+            String::from_utf8(base58_encode_checksum(data)).unwrap()
+        };
+        assert_eq!(ExtendedPrivateKey::parse(&xprv.replace('Q', "R")), Err(Bip32Error::InvalidBase58));
+        assert_eq!(ExtendedPrivateKey::parse(&modify(xprv, &|data| data.push(0))), Err(Bip32Error::InvalidLength(79)));
+        assert_eq!(ExtendedPrivateKey::parse(&modify(xprv, &|data| data[3] = 0)), Err(Bip32Error::UnknownVersion([0x04, 0x88, 0xad, 0x00])));
+        assert_eq!(ExtendedPrivateKey::parse(xpub), Err(Bip32Error::ExpectedPrivate));
+        assert_eq!(ExtendedPublicKey::parse(xprv), Err(Bip32Error::ExpectedPublic));
+        assert_eq!(ExtendedPrivateKey::parse(&modify(xprv, &|data| data[8] = 1)), Err(Bip32Error::InvalidParent));
+        assert_eq!(ExtendedPrivateKey::parse(&modify(xprv, &|data| data[12] = 1)), Err(Bip32Error::InvalidParent));
+        assert_eq!(ExtendedPrivateKey::parse(&modify(xprv, &|data| data[45] = 1)), Err(Bip32Error::InvalidKey));
+        assert_eq!(ExtendedPrivateKey::parse(&modify(xprv, &|data| data[46..].fill(0))), Err(Bip32Error::InvalidKey));
+        assert_eq!(ExtendedPrivateKey::parse(&modify(xprv, &|data| data[46..].fill(0xff))), Err(Bip32Error::InvalidKey));
+        assert_eq!(ExtendedPublicKey::parse(&modify(xpub, &|data| data[45] = 0x04)), Err(Bip32Error::InvalidKey));
+        assert_eq!(ExtendedPrivateKey::new_master(&[0; 15], Network::Mainnet), Err(Bip32Error::InvalidSeed(15)));
+        assert_eq!(Bip32Error::HardenedFromPublic(HARDENED + 1).get_json()["error"], "HardenedFromPublic");
+    }
+}
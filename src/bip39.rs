@@ -0,0 +1,234 @@
+use std::fmt;
+use std::str::FromStr;
+use lazy_static::lazy_static;
+use pbkdf2::pbkdf2_hmac;
+use serde_json::json;
+use sha2::{Digest, Sha256, Sha512};
+use unicode_normalization::UnicodeNormalization;
+use crate::bip32::{Bip32Error, ExtendedPrivateKey};
+use crate::network::Network;
+
+lazy_static! {
+    /// The 2048 BIP39 English words, sorted, unique in their first four letters.
+    static ref ENGLISH: Vec<&'static str> = include_str!("bip39_english.txt").lines().collect();
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum Bip39Error {
+    InvalidEntropyLength(usize),
+    InvalidWordCount(usize),
+    UnknownWord { position: usize, word: String, suggestion: Option<&'static str> },
+    InvalidChecksum,
+}
+impl fmt::Display for Bip39Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Bip39Error::InvalidEntropyLength(length) => write!(f, "entropy of {} bytes, it must be 16 to 32 in steps of 4", length),
+            Bip39Error::InvalidWordCount(count) => write!(f, "{} words, mnemonics have 12, 15, 18, 21 or 24", count),
+            Bip39Error::UnknownWord { position, word, suggestion: Some(suggestion) } => {
+                write!(f, "word {} '{}' is not in the wordlist, did you mean '{}'?", position + 1, word, suggestion)
+            }
+            Bip39Error::UnknownWord { position, word, suggestion: None } => write!(f, "word {} '{}' is not in the wordlist", position + 1, word),
+            Bip39Error::InvalidChecksum => write!(f, "invalid checksum, a word is wrong or out of order"),
+        }
+    }
+}
+impl Bip39Error {
+    pub fn get_json(&self) -> serde_json::Value {
+        let error = format!("{:?}", self);
+        let mut json = json!({
+            "error": error.split([' ', '(', '{']).next(),
+            "message": self.to_string(),
+        });
+        if let Bip39Error::UnknownWord { position, suggestion, .. } = self {
+            json["position"] = json!(position);
+            json["suggestion"] = json!(suggestion);
+        }
+        json
+    }
+}
+impl std::error::Error for Bip39Error {}
+
+// edits turning one word into the other, swapping two neighbouring letters counts as one
+fn edit_distance(a: &str, b: &str) -> usize {
+    let (a, b): (Vec<char>, Vec<char>) = (a.chars().collect(), b.chars().collect());
+    let mut rows = vec![(0..=b.len()).collect::<Vec<usize>>()];
+    for i in 1..=a.len() {
+        let mut row = vec![i; b.len() + 1];
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            row[j] = (rows[i - 1][j] + 1).min(row[j - 1] + 1).min(rows[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                row[j] = row[j].min(rows[i - 2][j - 2] + 1);
+            }
+        }
+        rows.push(row);
+    }
+    rows[a.len()][b.len()]
+}
+
+/// The word a mistyped `word` most likely meant: the only one sharing its first
+/// four letters, else the first of the closest ones at most two edits away.
+pub fn suggest_word(word: &str) -> Option<&'static str> {
+    let word = word.to_lowercase();
+    if let Some(prefix) = word.get(..4) {
+        let mut matches = ENGLISH.iter().filter(|candidate| candidate.starts_with(prefix));
+        if let (Some(candidate), None) = (matches.next(), matches.next()) {
+            return Some(candidate);
+        }
+    }
+    ENGLISH.iter()
+        .map(|candidate| (edit_distance(&word, candidate), *candidate))
+        .filter(|(distance, _)| *distance <= 2)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+/// BIP39 mnemonic: entropy written as English words, the last word carrying a
+/// checksum of the first bits of its sha256.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Mnemonic {
+    entropy: Vec<u8>,
+}
+impl Mnemonic {
+    pub fn from_entropy(entropy: &[u8]) -> Result<Self, Bip39Error> {
+        if !(16..=32).contains(&entropy.len()) || !entropy.len().is_multiple_of(4) {
+            return Err(Bip39Error::InvalidEntropyLength(entropy.len()));
+        }
+        Ok(Mnemonic { entropy: entropy.to_vec() })
+    }
+    /// Takes the words in any case and spacing, unknown words come with a suggestion.
+    pub fn parse(mnemonic: &str) -> Result<Self, Bip39Error> {
+        let words: Vec<String> = mnemonic.nfkd().collect::<String>().to_lowercase().split_whitespace().map(String::from).collect();
+        let indexes = words.iter().enumerate().map(|(position, word)| {
+            ENGLISH.binary_search(&word.as_str()).map_err(|_| Bip39Error::UnknownWord {
+                position,
+                word: word.clone(),
+                suggestion: suggest_word(word),
+            })
+        }).collect::<Result<Vec<usize>, Bip39Error>>()?;
+        if !(12..=24).contains(&indexes.len()) || !indexes.len().is_multiple_of(3) {
+            return Err(Bip39Error::InvalidWordCount(indexes.len()));
+        }
+        // 11 bits per word, one checksum bit for every 32 bits of entropy
+        let bits: Vec<bool> = indexes.iter().flat_map(|index| (0..11).rev().map(move |bit| index >> bit & 1 == 1)).collect();
+        let checksum_length = bits.len() / 33;
+        let entropy: Vec<u8> = bits[..bits.len() - checksum_length].chunks(8)
+            .map(|byte| byte.iter().fold(0u8, |byte, bit| byte << 1 | u8::from(*bit)))
+            .collect();
+        let mnemonic = Mnemonic { entropy };
+        if mnemonic.checksum_bits() != bits[bits.len() - checksum_length..] {
+            return Err(Bip39Error::InvalidChecksum);
+        }
+        Ok(mnemonic)
+    }
+    fn checksum_bits(&self) -> Vec<bool> {
+        let hash = Sha256::digest(&self.entropy);
+        (0..self.entropy.len() / 4).map(|bit| hash[0] >> (7 - bit) & 1 == 1).collect()
+    }
+    pub fn entropy(&self) -> &[u8] {
+        &self.entropy
+    }
+    pub fn words(&self) -> Vec<&'static str> {
+        let bits: Vec<bool> = self.entropy.iter()
+            .flat_map(|byte| (0..8).rev().map(move |bit| byte >> bit & 1 == 1))
+            .chain(self.checksum_bits())
+            .collect();
+        bits.chunks(11).map(|word| ENGLISH[word.iter().fold(0usize, |index, bit| index << 1 | usize::from(*bit))]).collect()
+    }
+    /// PBKDF2-HMAC-SHA512 of the words, salted with "mnemonic" and the passphrase.
+    pub fn to_seed(&self, passphrase: &str) -> [u8; 64] {
+        let salt: String = format!("mnemonic{}", passphrase).nfkd().collect();
+        let mut seed = [0u8; 64];
+        pbkdf2_hmac::<Sha512>(self.to_string().as_bytes(), salt.as_bytes(), 2048, &mut seed);
+        seed
+    }
+    pub fn to_master_key(&self, passphrase: &str, network: Network) -> Result<ExtendedPrivateKey, Bip32Error> {
+        ExtendedPrivateKey::new_master(&self.to_seed(passphrase), network)
+    }
+}
+impl fmt::Display for Mnemonic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.words().join(" "))
+    }
+}
+impl FromStr for Mnemonic {
+    type Err = Bip39Error;
+    fn from_str(mnemonic: &str) -> Result<Self, Self::Err> {
+        Mnemonic::parse(mnemonic)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bip32::ScriptType;
+
+    #[test]
+    fn test_vectors() {
+        // from the BIP39 reference implementation, all with the passphrase TREZOR
+        let vectors = [
+            ("00000000000000000000000000000000", "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about",
+             "c55257c360c07c72029aebc1b53c05ed0362ada38ead3e3e9efa3708e53495531f09a6987599d18264c1e1c92f2cf141630c7a3c4ab7c81b2f001698e7463b04",
+             "xprv9s21ZrQH143K3h3fDYiay8mocZ3afhfULfb5GX8kCBdno77K4HiA15Tg23wpbeF1pLfs1c5SPmYHrEpTuuRhxMwvKDwqdKiGJS9XFKzUsAF"),
+            ("7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f", "legal winner thank year wave sausage worth useful legal winner thank yellow",
+             "2e8905819b8723fe2c1d161860e5ee1830318dbf49a83bd451cfb8440c28bd6fa457fe1296106559a3c80937a1c1069be3a3a5bd381ee6260e8d9739fce1f607",
+             "xprv9s21ZrQH143K2gA81bYFHqU68xz1cX2APaSq5tt6MFSLeXnCKV1RVUJt9FWNTbrrryem4ZckN8k4Ls1H6nwdvDTvnV7zEXs2HgPezuVccsq"),
+            ("80808080808080808080808080808080", "letter advice cage absurd amount doctor acoustic avoid letter advice cage above",
+             "d71de856f81a8acc65e6fc851a38d4d7ec216fd0796d0a6827a3ad6ed5511a30fa280f12eb2e47ed2ac03b5c462a0358d18d69fe4f985ec81778c1b370b652a8",
+             "xprv9s21ZrQH143K2shfP28KM3nr5Ap1SXjz8gc2rAqqMEynmjt6o1qboCDpxckqXavCwdnYds6yBHZGKHv7ef2eTXy461PXUjBFQg6PrwY4Gzq"),
+            ("ffffffffffffffffffffffffffffffff", "zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo wrong",
+             "ac27495480225222079d7be181583751e86f571027b0497b5b5d11218e0a8a13332572917f0f8e5a589620c6f15b11c61dee327651a14c34e18231052e48c069",
+             "xprv9s21ZrQH143K2V4oox4M8Zmhi2Fjx5XK4Lf7GKRvPSgydU3mjZuKGCTg7UPiBUD7ydVPvSLtg9hjp7MQTYsW67rZHAXeccqYqrsx8LcXnyd"),
+            ("0000000000000000000000000000000000000000000000000000000000000000",
+             "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon art",
+             "bda85446c68413707090a52022edd26a1c9462295029f2e60cd7c4f2bbd3097170af7a4d73245cafa9c3cca8d561a7c3de6f5d4a10be8ed2a5e608d68f92fcc8",
+             "xprv9s21ZrQH143K32qBagUJAMU2LsHg3ka7jqMcV98Y7gVeVyNStwYS3U7yVVoDZ4btbRNf4h6ibWpY22iRmXq35qgLs79f312g2kj5539ebPM"),
+            ("9e885d952ad362caeb4efe34a8e91bd2", "ozone drill grab fiber curtain grace pudding thank cruise elder eight picnic",
+             "274ddc525802f7c828d8ef7ddbcdc5304e87ac3535913611fbbfa986d0c9e5476c91689f9c8a54fd55bd38606aa6a8595ad213d4c9c9f9aca3fb217069a41028",
+             "xprv9s21ZrQH143K2oZ9stBYpoaZ2ktHj7jLz7iMqpgg1En8kKFTXJHsjxry1JbKH19YrDTicVwKPehFKTbmaxgVEc5TpHdS1aYhB2s9aFJBeJH"),
+        ];
+        for (entropy, words, seed, xprv) in vectors {
+            let mnemonic = Mnemonic::from_entropy(&hex::decode(entropy).unwrap()).unwrap();
+            assert_eq!(mnemonic.to_string(), words);
+            assert_eq!(Mnemonic::parse(words), Ok(mnemonic.clone()));
+            assert_eq!(hex::encode(mnemonic.to_seed("TREZOR")), seed);
+            assert_eq!(mnemonic.to_master_key("TREZOR", Network::Mainnet).unwrap().to_string(), xprv);
+        }
+    }
+    #[test]
+    fn test_bip84_account() {
+        let mnemonic: Mnemonic = "  Abandon abandon abandon abandon abandon abandon\nabandon abandon abandon abandon abandon ABOUT ".parse().unwrap();
+        let master = mnemonic.to_master_key("", Network::Mainnet).unwrap().with_script_type(ScriptType::P2wpkh);
+        let account = master.derive_path(&"m/84'/0'/0'".parse().unwrap()).unwrap().to_public();
+        assert_eq!(account.to_string(), "zpub6rFR7y4Q2AijBEqTUquhVz398htDFrtymD9xYYfG1m4wAcvPhXNfE3EfH1r1ADqtfSdVCToUG868RvUUkgDKf31mGDtKsAYz2oz2AGutZYs");
+        assert_eq!(account.derive_path(&"m/0/0".parse().unwrap()).unwrap().address().to_string(), "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu");
+        // passphrases are NFKD normalized, é composed or not gives the same seed
+        assert_eq!(mnemonic.to_seed("caf\u{e9}"), mnemonic.to_seed("cafe\u{301}"));
+    }
+    #[test]
+    fn test_parse_invalid() {
+        let about = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
+        assert_eq!(Mnemonic::parse(&about.replace("about", "abandon")), Err(Bip39Error::InvalidChecksum));
+        assert_eq!(Mnemonic::parse(&about.replace(" about", "")), Err(Bip39Error::InvalidWordCount(11)));
+        assert_eq!(Mnemonic::from_entropy(&[0; 20]).unwrap().words().len(), 15);
+        assert_eq!(Mnemonic::from_entropy(&[0; 18]), Err(Bip39Error::InvalidEntropyLength(18)));
+
+        let error = Mnemonic::parse(&about.replacen("abandon", "abnadon", 1)).unwrap_err();
+        assert_eq!(error, Bip39Error::UnknownWord { position: 0, word: "abnadon".to_string(), suggestion: Some("abandon") });
+        assert_eq!(error.get_json()["suggestion"], "abandon");
+        let error = Mnemonic::parse(&about.replace("about", "bitcoin")).unwrap_err();
+        assert_eq!(error, Bip39Error::UnknownWord { position: 11, word: "bitcoin".to_string(), suggestion: None });
+        assert_eq!(error.get_json()["position"], 11);
+    }
+    #[test]
+    fn test_suggest_word() {
+        assert_eq!(suggest_word("abandn"), Some("abandon"));
+        assert_eq!(suggest_word("ZOOO"), Some("zoo"));
+        assert_eq!(suggest_word("lettr"), Some("letter"));
+        assert_eq!(suggest_word("wrnog"), Some("wrong"));
+        assert_eq!(suggest_word("bitcoin"), None);
+        assert_eq!(edit_distance("acount", "account"), 1);
+        assert_eq!(edit_distance("hte", "the"), 1);
+    }
+}
@@ -1,13 +1,15 @@
 use chrono::{Utc, DateTime};
-use std::{io::{Cursor, Read}};
+use std::{io::Cursor};
 use num::{pow, BigUint, ToPrimitive};
 use crate::helpers::block_bits::bits_to_target;
+use crate::network::Network;
 use crate::helpers::endianness::{int_to_little_endian, little_endian_to_int};
 use crate::helpers::hash256::hash256;
 use num::Num;
 use std::net::TcpStream;
 use serde_json::json;
 use crate::helpers::merkle_hash::merkle_root;
+use crate::helpers::decode_error::{read_array, read_bytes, read_exact_array, DecodeError};
 use crate::rpc_api::RpcApi;
 
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -27,9 +29,9 @@ impl Block {
             version, prev_block, merkle_root, timestamp, bits, nonce, tx_hashes: Vec::new(), block_json: json!({}),
         }
     }
-    pub async fn new_from_id(testnet: bool, block_id_str: String) -> Option<Self>  {
+    pub async fn new_from_id(network: Network, block_id_str: String) -> Option<Self>  {
         let block_id = block_id_str.as_str();
-        let api = RpcApi::new(testnet);
+        let api = RpcApi::new(network);
         let block_unwrapped = api.get_block(block_id).await;
 
         match block_unwrapped {
@@ -41,52 +43,29 @@ impl Block {
         self.block_json.clone()
     }
     pub fn parse_tcp(stream: &mut TcpStream) -> Result<Self, std::io::Error> {
-        let mut buffer = [0; 4];
-        stream.read(&mut buffer)?;
-        let version = little_endian_to_int(buffer.as_slice()).to_u32().unwrap();
-        let mut buffer = [0; 32];
-        stream.read(&mut buffer)?;
-        let mut prev_block = buffer.to_vec();
+        let version = u32::from_le_bytes(read_exact_array(stream, 0)?);
+        let mut prev_block = read_exact_array::<32>(stream, 4)?.to_vec();
         prev_block.reverse();
-        let mut buffer = [0; 32];
-        stream.read(&mut buffer)?;
-        let mut merkle_root = buffer.to_vec();
+        let mut merkle_root = read_exact_array::<32>(stream, 36)?.to_vec();
         merkle_root.reverse();
-        let mut buffer = [0; 4];
-        stream.read(&mut buffer)?;
-        let timestamp = little_endian_to_int(buffer.as_slice()).to_u32().unwrap();
-
-        let mut buffer = [0; 4];
-        stream.read(&mut buffer)?;
-        let bits = buffer.to_vec();
-        let mut buffer = [0; 4];
-        stream.read(&mut buffer)?;
-        let nonce = buffer.to_vec();
+        let timestamp = u32::from_le_bytes(read_exact_array(stream, 68)?);
+        let bits = read_exact_array::<4>(stream, 72)?.to_vec();
+        let nonce = read_exact_array::<4>(stream, 76)?.to_vec();
 
         Ok(Block::new(version, prev_block, merkle_root, timestamp, bits, nonce))
     }
-    pub fn parse(stream: &mut Cursor<Vec<u8>>) -> Result<Self, std::io::Error> {
-        let mut buffer = [0; 4];
-        stream.read(&mut buffer)?;
+    pub fn parse(stream: &mut Cursor<Vec<u8>>) -> Result<Self, DecodeError> {
+        let buffer = read_array::<4>(stream)?;
         let version = little_endian_to_int(buffer.as_slice()).to_u32().unwrap();
-        let mut buffer = [0; 32];
-        stream.read(&mut buffer)?;
-        let mut prev_block = buffer.to_vec();
+        let mut prev_block = read_bytes(stream, 32)?;
         prev_block.reverse();
-        let mut buffer = [0; 32];
-        stream.read(&mut buffer)?;
-        let mut merkle_root = buffer.to_vec();
+        let mut merkle_root = read_bytes(stream, 32)?;
         merkle_root.reverse();
-        let mut buffer = [0; 4];
-        stream.read(&mut buffer)?;
+        let buffer = read_array::<4>(stream)?;
         let timestamp = little_endian_to_int(buffer.as_slice()).to_u32().unwrap();
 
-        let mut buffer = [0; 4];
-        stream.read(&mut buffer)?;
-        let bits = buffer.to_vec();
-        let mut buffer = [0; 4];
-        stream.read(&mut buffer)?;
-        let nonce = buffer.to_vec();
+        let bits = read_bytes(stream, 4)?;
+        let nonce = read_bytes(stream, 4)?;
 
         Ok(Block::new(version, prev_block, merkle_root, timestamp, bits, nonce))
     }
@@ -0,0 +1,397 @@
+use std::cmp::Reverse;
+use std::fmt;
+use serde_json::json;
+use crate::helpers::op_codes::OP_0;
+use crate::rpc_models::Unspent;
+use crate::script::{Cmd, Script};
+use crate::tx_builder::Utxo;
+use crate::tx_output::TxOutput;
+
+// branch and bound gives up after this many steps
+const BNB_MAX_TRIES: usize = 100_000;
+// random subsets tried by knapsack
+const KNAPSACK_ITERATIONS: usize = 1000;
+// weight of the outpoint, empty scriptSig and sequence of an input
+const INPUT_BASE_WEIGHT: usize = (32 + 4 + 1 + 4) * 4;
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum CoinSelectionAlgorithm {
+    BranchAndBound,
+    Knapsack,
+    LargestFirst,
+    SingleRandomDraw,
+}
+impl CoinSelectionAlgorithm {
+    pub const ALL: [CoinSelectionAlgorithm; 4] = [
+        CoinSelectionAlgorithm::BranchAndBound,
+        CoinSelectionAlgorithm::Knapsack,
+        CoinSelectionAlgorithm::LargestFirst,
+        CoinSelectionAlgorithm::SingleRandomDraw,
+    ];
+}
+impl fmt::Display for CoinSelectionAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            CoinSelectionAlgorithm::BranchAndBound => "bnb",
+            CoinSelectionAlgorithm::Knapsack => "knapsack",
+            CoinSelectionAlgorithm::LargestFirst => "largest_first",
+            CoinSelectionAlgorithm::SingleRandomDraw => "srd",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// What a spend needs: the amount sent, the fee rate now and the one expected
+/// when the change is spent later, the weight of the tx besides its inputs,
+/// and the change output script.
+#[derive(Debug, Clone)]
+pub struct CoinSelectionParams {
+    target: u64,
+    fee_rate: f64,
+    long_term_fee_rate: f64,
+    base_weight: usize,
+    change_script: Script,
+    seed: u64,
+}
+impl CoinSelectionParams {
+    pub fn new(target: u64, fee_rate: f64) -> Self {
+        Self {
+            target,
+            fee_rate,
+            long_term_fee_rate: 10.0,
+            base_weight: 0,
+            change_script: Script::new(vec![Cmd::Op(OP_0), Cmd::push(vec![0u8; 20])]),
+            seed: 0x853c_49e6_748f_ea9b,
+        }
+    }
+    /// Fee rate in sat/vB expected when the change is spent, 10 by default.
+    pub fn with_long_term_fee_rate(mut self, long_term_fee_rate: f64) -> Self {
+        self.long_term_fee_rate = long_term_fee_rate;
+        self
+    }
+    /// Weight of the version, locktime, counts and recipient outputs.
+    pub fn with_base_weight(mut self, base_weight: usize) -> Self {
+        self.base_weight = base_weight;
+        self
+    }
+    /// The change output script, p2wpkh by default.
+    pub fn with_change_script(mut self, change_script: Script) -> Self {
+        self.change_script = change_script;
+        self
+    }
+    /// Seed of the random choices of knapsack and single random draw.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+    fn fee(&self, weight: usize, fee_rate: f64) -> i64 {
+        (fee_rate * weight.div_ceil(4) as f64).ceil() as i64
+    }
+    // amount the inputs must bring once their own fees are paid
+    fn selection_target(&self) -> i64 {
+        self.target as i64 + self.fee(self.base_weight, self.fee_rate)
+    }
+    fn change_fee(&self) -> i64 {
+        let change = TxOutput::new(0, self.change_script.clone());
+        self.fee(change.serialize().len() * 4, self.fee_rate)
+    }
+    // creating the change output now and spending it later
+    fn cost_of_change(&self) -> i64 {
+        let spend_weight = Utxo::new(vec![], 0, TxOutput::new(0, self.change_script.clone()))
+            .satisfaction_weight()
+            .map_or(INPUT_BASE_WEIGHT, |(weight, _)| INPUT_BASE_WEIGHT + weight);
+        self.change_fee() + self.fee(spend_weight, self.long_term_fee_rate)
+    }
+}
+
+// a spendable utxo with its effective value and the waste of spending it now
+#[derive(Debug, Clone)]
+struct Candidate {
+    index: usize,
+    effective_value: i64,
+    waste: i64,
+}
+fn candidates(utxos: &[Utxo], params: &CoinSelectionParams) -> Vec<Candidate> {
+    let mut candidates: Vec<Candidate> = utxos.iter().enumerate().filter_map(|(index, utxo)| {
+        let effective_value = utxo.effective_value(params.fee_rate)?;
+        let long_term_value = utxo.effective_value(params.long_term_fee_rate)?;
+        // paying more now than later wastes, paying less saves
+        Some(Candidate { index, effective_value, waste: long_term_value - effective_value })
+    }).filter(|candidate| candidate.effective_value > 0).collect();
+    candidates.sort_by_key(|candidate| Reverse(candidate.effective_value));
+    candidates
+}
+
+// xorshift64*, enough to shuffle coins without a dependency
+struct Rng(u64);
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+/// The coins chosen by an algorithm and what spending them costs. `fee`
+/// includes any excess left to the miners when there is no change.
+#[derive(Debug, Clone)]
+pub struct CoinSelection {
+    pub algorithm: CoinSelectionAlgorithm,
+    pub utxos: Vec<Utxo>,
+    pub amount: u64,
+    pub effective_value: i64,
+    pub fee: u64,
+    pub change: u64,
+    pub waste: i64,
+}
+impl CoinSelection {
+    pub fn get_json(&self) -> serde_json::Value {
+        let utxos: Vec<serde_json::Value> = self.utxos.iter().map(|utxo| json!({
+            "prev_tx": hex::encode(&utxo.prev_tx),
+            "prev_index": utxo.prev_index,
+            "amount": utxo.output.amount(),
+        })).collect();
+        json!({
+            "algorithm": self.algorithm.to_string(),
+            "utxos": utxos,
+            "amount": self.amount,
+            "effective_value": self.effective_value,
+            "fee": self.fee,
+            "change": self.change,
+            "waste": self.waste,
+        })
+    }
+}
+
+// the selection of the chosen candidates, with change if the excess pays for it
+fn selection(algorithm: CoinSelectionAlgorithm, utxos: &[Utxo], chosen: &[&Candidate], params: &CoinSelectionParams) -> CoinSelection {
+    let amount: u64 = chosen.iter().map(|candidate| utxos[candidate.index].output.amount()).sum();
+    let effective_value: i64 = chosen.iter().map(|candidate| candidate.effective_value).sum();
+    let excess = effective_value - params.selection_target();
+    let change_amount = excess - params.change_fee();
+    let dust = TxOutput::new(0, params.change_script.clone()).dust_threshold() as i64;
+    // branch and bound looks for changeless solutions only
+    let change = match algorithm != CoinSelectionAlgorithm::BranchAndBound && change_amount >= dust {
+        true => change_amount as u64,
+        false => 0,
+    };
+    let inputs_waste: i64 = chosen.iter().map(|candidate| candidate.waste).sum();
+    let waste = inputs_waste + if change > 0 { params.cost_of_change() } else { excess };
+    CoinSelection {
+        algorithm,
+        utxos: chosen.iter().map(|candidate| utxos[candidate.index].clone()).collect(),
+        amount,
+        effective_value,
+        fee: amount - params.target - change,
+        change,
+        waste,
+    }
+}
+
+// depth first search for the changeless input set of least waste, with an
+// effective value between the target and the target plus the cost of change
+#[allow(clippy::too_many_arguments)]
+fn bnb(pool: &[Candidate], index: usize, value: i64, waste: i64, remaining: i64, target: i64, upper: i64, selected: &mut Vec<usize>, best: &mut Option<(i64, Vec<usize>)>, tries: &mut usize) {
+    *tries += 1;
+    if *tries > BNB_MAX_TRIES || value > upper || value + remaining < target {
+        return;
+    }
+    // while inputs waste, more of them can't beat the best found
+    if best.as_ref().is_some_and(|(best_waste, _)| waste > *best_waste && pool.first().is_some_and(|candidate| candidate.waste > 0)) {
+        return;
+    }
+    if value >= target {
+        let total_waste = waste + value - target;
+        if best.as_ref().is_none_or(|(best_waste, _)| total_waste <= *best_waste) {
+            *best = Some((total_waste, selected.clone()));
+        }
+        return;
+    }
+    if index == pool.len() {
+        return;
+    }
+    let candidate = &pool[index];
+    selected.push(index);
+    bnb(pool, index + 1, value + candidate.effective_value, waste + candidate.waste, remaining - candidate.effective_value, target, upper, selected, best, tries);
+    selected.pop();
+    bnb(pool, index + 1, value, waste, remaining - candidate.effective_value, target, upper, selected, best, tries);
+}
+// the smallest total at least `target` over random subsets, as Bitcoin Core's
+// ApproximateBestSubset does
+fn approximate_best_subset(pool: &[&Candidate], target: i64, rng: &mut Rng) -> Option<Vec<usize>> {
+    let total: i64 = pool.iter().map(|candidate| candidate.effective_value).sum();
+    let mut best: Option<(i64, Vec<usize>)> = match total >= target {
+        true => Some((total, (0..pool.len()).collect())),
+        false => return None,
+    };
+    for _ in 0..KNAPSACK_ITERATIONS {
+        let mut value = 0;
+        let mut included = vec![false; pool.len()];
+        // the second pass adds the coins skipped by the first
+        'passes: for pass in 0..2 {
+            for (index, candidate) in pool.iter().enumerate() {
+                let include = match pass {
+                    0 => rng.next() & 1 == 1,
+                    _ => !included[index],
+                };
+                if !include {
+                    continue;
+                }
+                value += candidate.effective_value;
+                included[index] = true;
+                if value >= target {
+                    if best.as_ref().is_some_and(|(best_value, _)| value < *best_value) {
+                        best = Some((value, (0..pool.len()).filter(|index| included[*index]).collect()));
+                    }
+                    // try without the last coin for a smaller total
+                    value -= candidate.effective_value;
+                    included[index] = false;
+                    if pass == 1 {
+                        break 'passes;
+                    }
+                }
+            }
+        }
+    }
+    best.map(|(_, indices)| indices)
+}
+
+/// Chooses among `utxos` with one algorithm, None when they can't pay the
+/// target. Utxos whose spending weight isn't known or which cost more to
+/// spend than they are worth are left out.
+pub fn select_coins(utxos: &[Utxo], params: &CoinSelectionParams, algorithm: CoinSelectionAlgorithm) -> Option<CoinSelection> {
+    let pool = candidates(utxos, params);
+    let target = params.selection_target();
+    let total: i64 = pool.iter().map(|candidate| candidate.effective_value).sum();
+    if total < target {
+        return None;
+    }
+    let mut rng = Rng(params.seed.max(1));
+    let chosen: Vec<&Candidate> = match algorithm {
+        CoinSelectionAlgorithm::BranchAndBound => {
+            let mut best = None;
+            bnb(&pool, 0, 0, 0, total, target, target + params.cost_of_change(), &mut vec![], &mut best, &mut 0);
+            let (_, indices) = best?;
+            indices.iter().map(|index| &pool[*index]).collect()
+        }
+        CoinSelectionAlgorithm::Knapsack => {
+            let target_with_change = target + params.cost_of_change();
+            if let Some(exact) = pool.iter().find(|candidate| candidate.effective_value == target) {
+                vec![exact]
+            } else {
+                // coins smaller than needed get combined, unless the smallest larger one does better
+                let (smaller, larger): (Vec<&Candidate>, Vec<&Candidate>) = pool.iter().partition(|candidate| candidate.effective_value < target_with_change);
+                let lowest_larger = larger.last().copied();
+                let subset = approximate_best_subset(&smaller, target_with_change, &mut rng)
+                    .or_else(|| approximate_best_subset(&smaller, target, &mut rng))
+                    .map(|indices| indices.iter().map(|index| smaller[*index]).collect::<Vec<&Candidate>>());
+                let subset_value = |subset: &Vec<&Candidate>| subset.iter().map(|candidate| candidate.effective_value).sum::<i64>();
+                match (subset, lowest_larger) {
+                    (Some(subset), Some(larger)) if larger.effective_value <= subset_value(&subset) => vec![larger],
+                    (Some(subset), _) => subset,
+                    (None, Some(larger)) => vec![larger],
+                    (None, None) => return None,
+                }
+            }
+        }
+        CoinSelectionAlgorithm::LargestFirst => {
+            let count = pool.iter().scan(0, |value, candidate| {
+                let reached = *value >= target;
+                *value += candidate.effective_value;
+                match reached {
+                    true => None,
+                    false => Some(()),
+                }
+            }).count();
+            pool.iter().take(count).collect()
+        }
+        CoinSelectionAlgorithm::SingleRandomDraw => {
+            let mut order: Vec<&Candidate> = pool.iter().collect();
+            for index in (1..order.len()).rev() {
+                order.swap(index, rng.below(index + 1));
+            }
+            // draw until there is enough for a change output, or everything is taken
+            let target_with_change = target + params.cost_of_change();
+            let mut value = 0;
+            let count = order.iter().take_while(|candidate| {
+                let draw = value < target_with_change;
+                value += candidate.effective_value;
+                draw
+            }).count();
+            order.truncate(count);
+            order
+        }
+    };
+    Some(selection(algorithm, utxos, &chosen, params))
+}
+/// The selection of least waste among all the algorithms.
+pub fn select_coins_best(utxos: &[Utxo], params: &CoinSelectionParams) -> Option<CoinSelection> {
+    CoinSelectionAlgorithm::ALL.iter()
+        .filter_map(|algorithm| select_coins(utxos, params, *algorithm))
+        .min_by_key(|selection| selection.waste)
+}
+/// A utxo of the wallet as listed by `RpcApi::list_unspent`.
+pub fn utxo_from_unspent(unspent: &Unspent) -> Option<Utxo> {
+    let prev_tx = hex::decode(&unspent.txid).ok()?;
+    let script_pubkey = Script::parse_raw(&hex::decode(&unspent.script_pub_key).ok()?).ok()?;
+    let amount = (unspent.amount * 100_000_000.0).round() as u64;
+    Some(Utxo::new(prev_tx, u32::try_from(unspent.vout).ok()?, TxOutput::new(amount, script_pubkey)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utxos(amounts: &[u64]) -> Vec<Utxo> {
+        amounts.iter().enumerate().map(|(index, amount)| {
+            let script_pubkey = Script::new(vec![Cmd::Op(OP_0), Cmd::push(vec![index as u8; 20])]);
+            Utxo::new(vec![index as u8; 32], 0, TxOutput::new(*amount, script_pubkey))
+        }).collect()
+    }
+    #[test]
+    fn test_effective_value() {
+        let utxos = utxos(&[10_000, 500]);
+        // p2wpkh inputs are 68 vbytes, at 10 sat/vB the 500 sats coin isn't worth spending
+        let params = CoinSelectionParams::new(5_000, 10.0).with_long_term_fee_rate(5.0);
+        let pool = candidates(&utxos, &params);
+        assert_eq!(pool.len(), 1);
+        assert_eq!((pool[0].effective_value, pool[0].waste), (9_320, 340));
+        // cheaper now than later is a saving
+        let params = CoinSelectionParams::new(5_000, 2.0).with_long_term_fee_rate(5.0);
+        assert_eq!(candidates(&utxos, &params)[1].waste, -204);
+    }
+    #[test]
+    fn test_bnb() {
+        let utxos = utxos(&[100_000, 60_000, 40_000, 25_000]);
+        // at 1 sat/vB each input costs 68 sats: 60_000 + 40_000 covers it without change
+        let params = CoinSelectionParams::new(100_000 - 136, 1.0).with_long_term_fee_rate(1.0);
+        let selection = select_coins(&utxos, &params, CoinSelectionAlgorithm::BranchAndBound).unwrap();
+        assert_eq!(selection.utxos.iter().map(|utxo| utxo.output.amount()).collect::<Vec<u64>>(), vec![60_000, 40_000]);
+        assert_eq!((selection.change, selection.fee, selection.waste), (0, 136, 0));
+        // nothing adds up close enough
+        let params = CoinSelectionParams::new(50_000, 1.0);
+        assert!(select_coins(&utxos, &params, CoinSelectionAlgorithm::BranchAndBound).is_none());
+    }
+    #[test]
+    fn test_select_coins() {
+        let utxos = utxos(&[100_000, 60_000, 40_000, 25_000]);
+        let params = CoinSelectionParams::new(120_000, 5.0).with_base_weight(4 * 50);
+        let selection = select_coins(&utxos, &params, CoinSelectionAlgorithm::LargestFirst).unwrap();
+        assert_eq!(selection.utxos.len(), 2);
+        // amount = target + change + fee
+        assert_eq!(selection.amount, 120_000 + selection.change + selection.fee);
+        assert!(selection.change > 0);
+        for algorithm in [CoinSelectionAlgorithm::Knapsack, CoinSelectionAlgorithm::SingleRandomDraw] {
+            let selection = select_coins(&utxos, &params, algorithm).unwrap();
+            assert!(selection.effective_value >= params.selection_target());
+            assert_eq!(selection.amount, 120_000 + selection.change + selection.fee);
+        }
+        let best = select_coins_best(&utxos, &params).unwrap();
+        assert!(CoinSelectionAlgorithm::ALL.iter().filter_map(|algorithm| select_coins(&utxos, &params, *algorithm)).all(|selection| selection.waste >= best.waste));
+        assert!(select_coins(&utxos, &CoinSelectionParams::new(300_000, 5.0), CoinSelectionAlgorithm::Knapsack).is_none());
+    }
+}
@@ -44,22 +44,57 @@ pub fn base58_encode_checksum(val: Vec<u8>) -> Vec<u8> {
     result.extend_from_slice(&hash[0..4]);
     base58_encode(result.to_vec())
 }
-pub fn decode_base58(val: Vec<u8>) -> Vec<u8> {
+/// The payload of a base58check string without its version byte, such as the
+/// hash160 of an address. None when the string isn't valid base58check.
+pub fn decode_base58(val: &[u8]) -> Option<Vec<u8>> {
+    base58_decode_checksum(val)?.get(1..).map(|payload| payload.to_vec())
+}
+/// The bytes of a base58 string, None when a character isn't base58.
+pub fn base58_decode(val: &[u8]) -> Option<Vec<u8>> {
     let mut num = BigUint::from(0u32);
     for c in val {
         num *= BigUint::from(58u8);
-        let index = BASE58_ALPHABET.iter().position(|&r| r == c).unwrap();
-        num += BigUint::from(index);
+        num += BigUint::from(BASE58_ALPHABET.iter().position(|r| r == c)?);
+    }
+    // each leading '1' is a leading zero byte
+    let leading_zeros = val.iter().take_while(|c| **c == BASE58_ALPHABET[0]).count();
+    let mut combined = vec![0u8; leading_zeros];
+    if num > BigUint::from(0u32) {
+        combined.extend(num.to_bytes_be());
     }
-    let combined = num.to_bytes_be();
-    let checksum = combined.as_slice()[combined.len() - 4..].to_vec();
-    let rest = combined.as_slice()[..combined.len() - 4].to_vec();
-    let hash = hash256(rest.as_slice());
-    if hash[..4] != checksum {
-        panic!("decode_base58 checksum mismatch");
+    Some(combined)
+}
+/// The payload of a base58check string, version byte included. None when a
+/// character isn't base58 or the checksum doesn't match.
+pub fn base58_decode_checksum(val: &[u8]) -> Option<Vec<u8>> {
+    let combined = base58_decode(val)?;
+    if combined.len() < 4 {
+        return None;
+    }
+    let (rest, checksum) = combined.split_at(combined.len() - 4);
+    match hash256(rest)[..4] == *checksum {
+        true => Some(rest.to_vec()),
+        false => None,
+    }
+}
+/// Positions of the characters that aren't base58.
+pub fn base58_invalid_chars(val: &[u8]) -> Vec<usize> {
+    val.iter().enumerate().filter(|(_, c)| !BASE58_ALPHABET.contains(c)).map(|(i, _)| i).collect()
+}
+/// Position of the character to change to fix the checksum of a base58check
+/// string, None unless exactly one position does it.
+pub fn base58_locate_error(val: &[u8]) -> Option<usize> {
+    let mut positions = (0..val.len()).filter(|position| {
+        BASE58_ALPHABET.iter().filter(|c| **c != val[*position]).any(|c| {
+            let mut candidate = val.to_vec();
+            candidate[*position] = *c;
+            base58_decode_checksum(&candidate).is_some()
+        })
+    });
+    match (positions.next(), positions.next()) {
+        (Some(position), None) => Some(position),
+        _ => None,
     }
-    let result = combined[1..combined.len() - 4].to_vec();
-    result
 }
 #[cfg(test)]
 mod tests {
@@ -101,7 +136,10 @@ mod tests {
     fn decode_58() {
 
         let addr = "mnrVtF8DWjMu839VW3rBfgYaAfKk8983Xf".as_bytes().to_vec();
-        let h160 = hex::encode(decode_base58(addr.clone()));
+        let h160 = hex::encode(decode_base58(&addr).unwrap());
+        assert_eq!(decode_base58(b"mnrVtF8DWjMu839VW3rBfgYaAfKk8983Xg"), None);
+        assert_eq!(decode_base58(b"0nrVtF8DWjMu839VW3rBfgYaAfKk8983Xf"), None);
+        assert_eq!(decode_base58(b"1"), None);
 
         let want = "507b27411ccf7f16f10297de6cef3f291623eddf";
         assert_eq!(want, h160);
@@ -115,4 +153,14 @@ mod tests {
         let got = base58_encode_checksum(buffer);
         assert_eq!(addr, got);
     }
+    #[test]
+    fn decode_58_checksum() {
+        let payload = base58_decode_checksum(b"1BenRpVUFK65JFWcQSuHnJKzc4M8ZP8Eqa").unwrap();
+        assert_eq!(hex::encode(payload), "0074d691da1574e6b3c192ecfb52cc8984ee7b6c56");
+        assert_eq!(base58_decode_checksum(b"1BenRpVUFK65JFWcQSuHnJKzc4M8ZP8Eqb"), None);
+        assert_eq!(base58_decode_checksum(b"1BenRpVUFK65JFWcQSuHnJKzc4M8ZP8Eq0"), None);
+        assert_eq!(base58_invalid_chars(b"1BenRpVUFK65JFWcQSuHnJKzc4M8ZP8Eq0"), vec![33]);
+        assert_eq!(base58_locate_error(b"1BenRpVUFK65JFWcQSuHnJKzc4M8ZP8Eqa"), None);
+        assert_eq!(base58_locate_error(b"1BenRpVUFK65JFWcQSuHnJKzc4M9ZP8Eqa"), Some(27));
+    }
 }
\ No newline at end of file
@@ -1,15 +1,113 @@
-use bech32::{hrp, segwit, Hrp, Bech32m};
+use std::collections::HashMap;
+use std::fmt;
+use bech32::segwit;
+use crate::network::Network;
 
 pub enum SegwitVersion {
     version_0,
     version_1,
 }
-pub fn bech32_segwit_encode(hash: Vec<u8>, segwit_version: SegwitVersion) -> String {
+pub fn bech32_segwit_encode(hash: Vec<u8>, segwit_version: SegwitVersion, network: Network) -> String {
     let version = match segwit_version {
         SegwitVersion::version_0 => segwit::VERSION_0,
         SegwitVersion::version_1 => segwit::VERSION_1,
     };
-    segwit::encode(hrp::BC, version, &hash).expect("valid witness version and program")
+    segwit::encode(network.hrp(), version, &hash).expect("valid witness version and program")
+}
+
+const CHARSET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+const GENERATOR: [u32; 5] = [0x3b6a57b2, 0x26508e6d, 0x1ea119fa, 0x3d4233dd, 0x2a1462b3];
+
+/// The two bech32 checksums, BIP350 replaced bech32 with bech32m for witness versions 1 and up.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Bech32Variant {
+    Bech32,
+    Bech32m,
+}
+impl Bech32Variant {
+    pub fn for_witness_version(version: u8) -> Self {
+        match version {
+            0 => Bech32Variant::Bech32,
+            _ => Bech32Variant::Bech32m,
+        }
+    }
+    fn constant(&self) -> u32 {
+        match self {
+            Bech32Variant::Bech32 => 1,
+            Bech32Variant::Bech32m => 0x2bc830a3,
+        }
+    }
+}
+impl fmt::Display for Bech32Variant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Bech32Variant::Bech32 => write!(f, "bech32"),
+            Bech32Variant::Bech32m => write!(f, "bech32m"),
+        }
+    }
+}
+fn polymod_step(c: u32, value: u8) -> u32 {
+    let top = c >> 25;
+    let mut c = ((c & 0x1ffffff) << 5) ^ value as u32;
+    for (i, generator) in GENERATOR.iter().enumerate() {
+        if (top >> i) & 1 == 1 {
+            c ^= generator;
+        }
+    }
+    c
+}
+// checksum residue of the data part, 0 when it ends with a valid checksum of `variant`
+fn residue(hrp: &str, values: &[u8], variant: Bech32Variant) -> u32 {
+    let hrp = hrp.to_lowercase();
+    let expanded = hrp.bytes().map(|c| c >> 5).chain([0]).chain(hrp.bytes().map(|c| c & 31));
+    expanded.chain(values.iter().copied()).fold(1, polymod_step) ^ variant.constant()
+}
+/// The 5 bit values of a data part, or the positions of the characters outside the charset.
+pub fn bech32_values(data: &str) -> Result<Vec<u8>, Vec<usize>> {
+    let data = data.to_lowercase();
+    let values: Vec<Option<u8>> = data.bytes().map(|c| CHARSET.iter().position(|x| *x == c).map(|value| value as u8)).collect();
+    let invalid: Vec<usize> = values.iter().enumerate().filter(|(_, value)| value.is_none()).map(|(i, _)| i).collect();
+    match invalid.is_empty() {
+        true => Ok(values.into_iter().flatten().collect()),
+        false => Err(invalid),
+    }
+}
+/// Which checksum, if any, the data part (checksum included) ends with.
+pub fn bech32_checksum_variant(hrp: &str, values: &[u8]) -> Option<Bech32Variant> {
+    [Bech32Variant::Bech32, Bech32Variant::Bech32m].into_iter().find(|variant| residue(hrp, values, *variant) == 0)
+}
+/// Positions in the data part of up to two substitutions that give it a valid
+/// `variant` checksum, None when it takes more. The checksum is linear, the
+/// residue of a corrupted string is the residue of its errors alone: it is looked
+/// up among the residues of every single error, then of every pair.
+pub fn bech32_locate_errors(hrp: &str, values: &[u8], variant: Bech32Variant) -> Option<Vec<usize>> {
+    let target = residue(hrp, values, variant);
+    if target == 0 {
+        return Some(vec![]);
+    }
+    // residue of the error `e` at each position, from the last one back
+    let mut errors: HashMap<u32, (usize, u8)> = HashMap::new();
+    let mut residues: Vec<u32> = (1..32).collect();
+    for distance in 0..values.len() {
+        for (e, residue) in (1..32u8).zip(&residues) {
+            errors.insert(*residue, (values.len() - 1 - distance, e));
+        }
+        residues = residues.into_iter().map(|residue| polymod_step(residue, 0)).collect();
+    }
+    if let Some((position, _)) = errors.get(&target) {
+        return Some(vec![*position]);
+    }
+    for (residue, (position, _)) in &errors {
+        match errors.get(&(target ^ residue)) {
+            Some((other, _)) if other != position => {
+                let mut positions = vec![*position, *other];
+                positions.sort();
+                return Some(positions);
+            }
+            _ => {}
+        }
+    }
+    None
 }
 #[cfg(test)]
 mod tests {
@@ -18,21 +116,25 @@ mod tests {
     const TAP_ADDR: &str = "bc1p4w46h2at4w46h2at4w46h2at4w46h2at5kreae";
 
     use super::*;
+    use bech32::{hrp, Hrp, Bech32m};
     #[test]
     fn debug_bech32() {
         // p2wpkh
         let data = vec![96, 214, 225, 172, 224, 2, 253, 142, 175, 50, 21, 11, 245, 124, 133, 212, 76, 22, 225, 106];
-        let address = bech32_segwit_encode(data, SegwitVersion::version_0);
+        let address = bech32_segwit_encode(data.clone(), SegwitVersion::version_0, Network::Mainnet);
         assert_eq!(address, "bc1qvrtwrt8qqt7catejz59l2ly963xpdct2j370g3".to_string());
+        // test chains use their own prefix
+        assert_eq!(bech32_segwit_encode(data.clone(), SegwitVersion::version_0, Network::Testnet4), "tb1qvrtwrt8qqt7catejz59l2ly963xpdct2ch9unz".to_string());
+        assert_eq!(bech32_segwit_encode(data, SegwitVersion::version_0, Network::Regtest), "bcrt1qvrtwrt8qqt7catejz59l2ly963xpdct267u3yt".to_string());
 
         // p2tr
         let data2 = vec![113, 5, 116, 56, 145, 120, 21, 46, 95, 188, 82, 85, 138, 130, 24, 236, 26, 223, 230, 132, 219, 139, 191, 67, 0, 165, 177, 165, 44, 246, 32, 172];
-        let address2 = bech32_segwit_encode(data2, SegwitVersion::version_1);
+        let address2 = bech32_segwit_encode(data2, SegwitVersion::version_1, Network::Mainnet);
         assert_eq!(address2, "bc1pwyzhgwy30q2juhau2f2c4qscasddle5ymw9m7scq5kc62t8kyzkqyz059k".to_string());
 
         // p2sh
         let data3 = vec![101, 249, 26, 83, 203, 113, 32, 5, 125, 179, 211, 120, 189, 15, 125, 148, 65, 103, 212, 58, 125, 203, 255, 21, 214, 175, 196, 130, 63, 29, 62, 211];
-        let address3 = bech32_segwit_encode(data3, SegwitVersion::version_0);
+        let address3 = bech32_segwit_encode(data3, SegwitVersion::version_0, Network::Mainnet);
         assert_eq!(address3, "bc1qvhu3557twysq2ldn6dut6rmaj3qk04p60h9l79wk4lzgy0ca8mfsnffz65".to_string());
 
     }
@@ -84,4 +186,25 @@ mod tests {
         // Do something with the encoded data.
         let _ = taproot.byte_iter();
     }
-}
\ No newline at end of file
+    #[test]
+    fn test_locate_errors() {
+        let address = "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4";
+        let (hrp, data) = address.split_at(2);
+        let values = bech32_values(&data[1..]).unwrap();
+        assert_eq!(bech32_checksum_variant(hrp, &values), Some(Bech32Variant::Bech32));
+        assert_eq!(bech32_locate_errors(hrp, &values, Bech32Variant::Bech32), Some(vec![]));
+        // one and two substitutions are found where they are
+        for positions in [vec![5], vec![0], vec![38], vec![3, 17], vec![10, 11]] {
+            let mut typo = values.clone();
+            for position in &positions {
+                typo[*position] ^= 0x0b;
+            }
+            assert_eq!(bech32_checksum_variant(hrp, &typo), None);
+            assert_eq!(bech32_locate_errors(hrp, &typo, Bech32Variant::Bech32), Some(positions));
+        }
+        assert_eq!(bech32_values("qp1zb"), Err(vec![2, 4]));
+        // a bech32m checksum on a v0 program
+        let values = bech32_values("qw508d6qejxtdg4y5r3zarvary0c5xw7kemeawh").unwrap();
+        assert_eq!(bech32_checksum_variant("bc", &values), Some(Bech32Variant::Bech32m));
+    }
+}
@@ -0,0 +1,20 @@
+use std::future::Future;
+use std::pin::pin;
+use std::task::{Context, Poll, Waker};
+
+/// Runs a future that never waits on I/O to completion on the current thread.
+/// Verification and signing are only async to fetch prevouts, with a provider
+/// that already has them they complete on the first poll.
+///
+/// # Panics
+///
+/// Panics if the future is pending after the first poll, there is no reactor
+/// to wake it up.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    let mut context = Context::from_waker(Waker::noop());
+    match future.as_mut().poll(&mut context) {
+        Poll::Ready(output) => output,
+        Poll::Pending => panic!("block_on polled a future waiting on I/O"),
+    }
+}
@@ -0,0 +1,94 @@
+use std::fmt;
+use std::io::{Cursor, Read};
+use serde_json::json;
+
+/// Why bytes couldn't be decoded, with the offset in the data where it happened.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum DecodeError {
+    UnexpectedEof { offset: usize },
+    NonMinimalVarint { offset: usize },
+    OversizedVector { offset: usize, length: u64 },
+    TrailingBytes { offset: usize },
+    BadSegwitFlag { offset: usize, flag: u8 },
+}
+impl DecodeError {
+    pub fn offset(&self) -> usize {
+        match self {
+            DecodeError::UnexpectedEof { offset }
+            | DecodeError::NonMinimalVarint { offset }
+            | DecodeError::OversizedVector { offset, .. }
+            | DecodeError::TrailingBytes { offset }
+            | DecodeError::BadSegwitFlag { offset, .. } => *offset,
+        }
+    }
+    pub fn get_json(&self) -> serde_json::Value {
+        let error = format!("{:?}", self);
+        json!({
+            "error": error.split([' ', '{']).next(),
+            "message": self.to_string(),
+            "offset": self.offset(),
+        })
+    }
+}
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::UnexpectedEof { offset } => write!(f, "data ends unexpectedly at byte {}", offset),
+            DecodeError::NonMinimalVarint { offset } => write!(f, "varint at byte {} is not minimally encoded", offset),
+            DecodeError::OversizedVector { offset, length } => write!(f, "length {} at byte {} is larger than the data left", length, offset),
+            DecodeError::TrailingBytes { offset } => write!(f, "unexpected data after byte {}", offset),
+            DecodeError::BadSegwitFlag { offset, flag } => write!(f, "invalid segwit flag {:02x} at byte {}", flag, offset),
+        }
+    }
+}
+impl std::error::Error for DecodeError {}
+impl From<DecodeError> for std::io::Error {
+    fn from(error: DecodeError) -> Self {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, error)
+    }
+}
+
+pub fn read_bytes(stream: &mut Cursor<Vec<u8>>, length: usize) -> Result<Vec<u8>, DecodeError> {
+    let offset = stream.position() as usize;
+    if length > stream.get_ref().len().saturating_sub(offset) {
+        return Err(DecodeError::UnexpectedEof { offset: stream.get_ref().len().max(offset) });
+    }
+    let mut bytes = vec![0u8; length];
+    stream.read_exact(&mut bytes).map_err(|_| DecodeError::UnexpectedEof { offset })?;
+    Ok(bytes)
+}
+pub fn read_array<const N: usize>(stream: &mut Cursor<Vec<u8>>) -> Result<[u8; N], DecodeError> {
+    Ok(read_bytes(stream, N)?.try_into().unwrap())
+}
+/// Reads N bytes from a stream such as a socket, `offset` being the number of
+/// bytes already read. Running out of data is an UnexpectedEof.
+pub fn read_exact_array<const N: usize>(reader: &mut impl Read, offset: usize) -> Result<[u8; N], std::io::Error> {
+    let mut bytes = [0u8; N];
+    reader.read_exact(&mut bytes).map_err(|error| match error.kind() {
+        std::io::ErrorKind::UnexpectedEof => DecodeError::UnexpectedEof { offset }.into(),
+        _ => error,
+    })?;
+    Ok(bytes)
+}
+/// Fails with TrailingBytes unless all the data has been read.
+pub fn check_end(stream: &Cursor<Vec<u8>>) -> Result<(), DecodeError> {
+    match stream.position() as usize >= stream.get_ref().len() {
+        true => Ok(()),
+        false => Err(DecodeError::TrailingBytes { offset: stream.position() as usize }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_read_bytes() {
+        let mut stream = Cursor::new(vec![1, 2, 3]);
+        assert_eq!(read_array::<2>(&mut stream), Ok([1, 2]));
+        assert_eq!(check_end(&stream), Err(DecodeError::TrailingBytes { offset: 2 }));
+        assert_eq!(read_bytes(&mut stream, 2), Err(DecodeError::UnexpectedEof { offset: 3 }));
+        let error = read_exact_array::<4>(&mut &[1u8, 2][..], 6).unwrap_err();
+        assert_eq!(error.into_inner().unwrap().downcast::<DecodeError>().ok().map(|error| *error), Some(DecodeError::UnexpectedEof { offset: 6 }));
+        assert_eq!(DecodeError::BadSegwitFlag { offset: 5, flag: 2 }.get_json()["error"], "BadSegwitFlag");
+    }
+}
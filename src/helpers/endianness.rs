@@ -21,6 +21,13 @@ pub fn int_to_little_endian(n: BigUint, length: u32) -> Vec<u8> {
         panic!("Unsupported little_endian length");
     }
 }
+/// Big endian bytes of `n`, left padded with zeros to `length`.
+pub fn int_to_big_endian(n: &BigUint, length: usize) -> Vec<u8> {
+    let bytes = n.to_bytes_be();
+    let mut result = vec![0u8; length.saturating_sub(bytes.len())];
+    result.extend(bytes);
+    result
+}
 
 #[cfg(test)]
 mod tests {
@@ -55,4 +62,9 @@ mod tests {
         println!("{:?}", res.as_slice());
         assert_eq!(res, expect);
     }
+    #[test]
+    fn int_to_be() {
+        assert_eq!(int_to_big_endian(&BigUint::from(0x0102u32), 4), vec![0, 0, 1, 2]);
+        assert_eq!(int_to_big_endian(&BigUint::from(0u32), 2), vec![0, 0]);
+    }
 }
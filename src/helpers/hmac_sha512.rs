@@ -0,0 +1,11 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha512;
+
+pub fn hmac_sha512(key: &[u8], data: &[u8]) -> [u8; 64] {
+    let mut mac = Hmac::<Sha512>::new_from_slice(key).expect("hmac takes keys of any length");
+    mac.update(data);
+    let result = mac.finalize().into_bytes();
+    let mut hash: [u8; 64] = [0; 64];
+    hash.copy_from_slice(&result);
+    hash
+}
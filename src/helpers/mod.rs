@@ -1,6 +1,7 @@
 pub mod base58;
 pub mod hash256;
 pub mod hash160;
+pub mod hmac_sha512;
 pub mod endianness;
 pub mod varint;
 pub mod op_codes;
@@ -11,4 +12,11 @@ pub mod merkle_hash;
 pub mod out_type;
 pub mod verify_input_res;
 pub mod bech32;
+pub mod block_on;
 
+pub mod script_error;
+pub mod decode_error;
+pub mod script_trace;
+pub mod verify_flags;
+pub mod tx_context;
+pub mod taproot;
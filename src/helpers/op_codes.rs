@@ -6,6 +6,11 @@ use ripemd::{Ripemd160};
 use crate::point::Point;
 // use crate::helpers::hex;
 use crate::signature::Signature;
+use crate::helpers::script_error::ScriptErrorKind;
+use crate::helpers::verify_flags::VerifyFlags;
+use crate::helpers::tx_context::TxContext;
+use crate::helpers::sig_hash::{SIGHASH_ALL, SIGHASH_ANYONECANPAY, SIGHASH_DEFAULT, SIGHASH_SINGLE};
+use crate::secp256k1;
 
 pub fn is_op(cmd: &Vec<u8>) -> bool {
     if cmd.len() == 1 {
@@ -28,7 +33,8 @@ pub fn op_code_names() -> HashMap<u8, &'static str> {
     op_code_names.insert(OP_PUSHDATA1, "OP_PUSHDATA1");
     op_code_names.insert(OP_PUSHDATA2, "OP_PUSHDATA2");
     op_code_names.insert(OP_PUSHDATA4, "OP_PUSHDATA4");
-    op_code_names.insert(OP_1NEGATE, "OP_NEGATE");
+    op_code_names.insert(OP_1NEGATE, "OP_1NEGATE");
+    op_code_names.insert(OP_RESERVED, "OP_RESERVED");
     op_code_names.insert(OP_1, "OP_1");
     op_code_names.insert(OP_2, "OP_2");
     op_code_names.insert(OP_3, "OP_3");
@@ -46,8 +52,11 @@ pub fn op_code_names() -> HashMap<u8, &'static str> {
     op_code_names.insert(OP_15, "OP_15");
     op_code_names.insert(OP_16, "OP_16");
     op_code_names.insert(OP_NOP, "OP_NOP");
+    op_code_names.insert(OP_VER, "OP_VER");
     op_code_names.insert(OP_IF, "OP_IF");
     op_code_names.insert(OP_NOTIF, "OP_NOTIF");
+    op_code_names.insert(OP_VERIF, "OP_VERIF");
+    op_code_names.insert(OP_VERNOTIF, "OP_VERNOTIF");
     op_code_names.insert(OP_ELSE, "OP_ELSE");
     op_code_names.insert(OP_ENDIF, "OP_ENDIF");
     op_code_names.insert(OP_VERIFY, "OP_VERIFY");
@@ -71,11 +80,23 @@ pub fn op_code_names() -> HashMap<u8, &'static str> {
     op_code_names.insert(OP_ROT, "OP_ROT");
     op_code_names.insert(OP_SWAP, "OP_SWAP");
     op_code_names.insert(OP_TUCK, "OP_TUCK");
+    op_code_names.insert(OP_CAT, "OP_CAT");
+    op_code_names.insert(OP_SUBSTR, "OP_SUBSTR");
+    op_code_names.insert(OP_LEFT, "OP_LEFT");
+    op_code_names.insert(OP_RIGHT, "OP_RIGHT");
     op_code_names.insert(OP_SIZE, "OP_SIZE");
+    op_code_names.insert(OP_INVERT, "OP_INVERT");
+    op_code_names.insert(OP_AND, "OP_AND");
+    op_code_names.insert(OP_OR, "OP_OR");
+    op_code_names.insert(OP_XOR, "OP_XOR");
     op_code_names.insert(OP_EQUAL, "OP_EQUAL");
     op_code_names.insert(OP_EQUALVERIFY, "OP_EQUALVERIFY");
+    op_code_names.insert(OP_RESERVED1, "OP_RESERVED1");
+    op_code_names.insert(OP_RESERVED2, "OP_RESERVED2");
     op_code_names.insert(OP_1ADD, "OP_1ADD");
     op_code_names.insert(OP_1SUB, "OP_1SUB");
+    op_code_names.insert(OP_2MUL, "OP_2MUL");
+    op_code_names.insert(OP_2DIV, "OP_2DIV");
     op_code_names.insert(OP_NEGATE, "OP_NEGATE");
     op_code_names.insert(OP_ABS, "OP_ABS");
     op_code_names.insert(OP_NOT, "OP_NOT");
@@ -83,6 +104,10 @@ pub fn op_code_names() -> HashMap<u8, &'static str> {
     op_code_names.insert(OP_ADD, "OP_ADD");
     op_code_names.insert(OP_SUB, "OP_SUB");
     op_code_names.insert(OP_MUL, "OP_MUL");
+    op_code_names.insert(OP_DIV, "OP_DIV");
+    op_code_names.insert(OP_MOD, "OP_MOD");
+    op_code_names.insert(OP_LSHIFT, "OP_LSHIFT");
+    op_code_names.insert(OP_RSHIFT, "OP_RSHIFT");
     op_code_names.insert(OP_BOOLAND, "OP_BOOLAND");
     op_code_names.insert(OP_BOOLOR, "OP_BOOLOR");
     op_code_names.insert(OP_NUMEQUAL, "OP_NUMEQUAL");
@@ -90,6 +115,7 @@ pub fn op_code_names() -> HashMap<u8, &'static str> {
     op_code_names.insert(OP_NUMNOTEQUAL, "OP_NUMNOTEQUAL");
     op_code_names.insert(OP_LESSTHAN, "OP_LESSTHAN");
     op_code_names.insert(OP_GREATERTHAN, "OP_GREATERTHAN");
+    op_code_names.insert(OP_LESSTHANOREQUAL, "OP_LESSTHANOREQUAL");
     op_code_names.insert(OP_GREATERTHANOREQUAL, "OP_GREATERTHANOREQUAL");
     op_code_names.insert(OP_MIN, "OP_MIN");
     op_code_names.insert(OP_MAX, "OP_MAX");
@@ -103,15 +129,42 @@ pub fn op_code_names() -> HashMap<u8, &'static str> {
     op_code_names.insert(OP_CHECKSIG, "OP_CHECKSIG");
     op_code_names.insert(OP_CHECKSIGVERIFY, "OP_CHECKSIGVERIFY");
     op_code_names.insert(OP_CHECKMULTISIG, "OP_CHECKMULTISIG");
+    op_code_names.insert(OP_CHECKMULTISIGVERIFY, "OP_CHECKMULTISIGVERIFY");
+    op_code_names.insert(OP_NOOP1, "OP_NOP1");
+    op_code_names.insert(OP_CHECKLOCKTIMEVERIFY, "OP_CHECKLOCKTIMEVERIFY");
     op_code_names.insert(OP_CHECKSEQUENCEVERIFY, "OP_CHECKSEQUENCEVERIFY");
+    op_code_names.insert(OP_NOP4, "OP_NOP4");
+    op_code_names.insert(OP_NOP5, "OP_NOP5");
+    op_code_names.insert(OP_NOP6, "OP_NOP6");
+    op_code_names.insert(OP_NOP7, "OP_NOP7");
+    op_code_names.insert(OP_NOP8, "OP_NOP8");
+    op_code_names.insert(OP_NOP9, "OP_NOP9");
+    op_code_names.insert(OP_NOP10, "OP_NOP10");
+    op_code_names.insert(OP_CHECKSIGADD, "OP_CHECKSIGADD");
 
     op_code_names
 }
+pub fn op_code_name(op_code: u8) -> String {
+    match op_code_names().get(&op_code) {
+        Some(name) => name.to_string(),
+        None => format!("OP_UNKNOWN_{:#04x}", op_code),
+    }
+}
+/// Reverse of `op_code_name`, the OP_ prefix is optional.
+pub fn op_code_from_name(name: &str) -> Option<u8> {
+    let name = name.to_uppercase();
+    let name = if name.starts_with("OP_") { name } else { format!("OP_{}", name) };
+    if let Some(hex) = name.strip_prefix("OP_UNKNOWN_0X") {
+        return u8::from_str_radix(hex, 16).ok();
+    }
+    op_code_names().into_iter().find(|(_, op_name)| *op_name == name).map(|(op_code, _)| op_code)
+}
 pub const OP_0: u8 = 0;
 pub const OP_PUSHDATA1: u8 = 76;
 pub const OP_PUSHDATA2: u8 = 77;
 pub const OP_PUSHDATA4: u8 = 78;
 pub const OP_1NEGATE: u8 = 79;
+pub const OP_RESERVED: u8 = 80;
 pub const OP_1: u8 = 81;
 pub const OP_2: u8 = 82;
 pub const OP_3: u8 = 83;
@@ -129,8 +182,11 @@ pub const OP_14: u8 = 94;
 pub const OP_15: u8 = 95;
 pub const OP_16: u8 = 96;
 pub const OP_NOP: u8 = 97;
+pub const OP_VER: u8 = 98;
 pub const OP_IF: u8 = 99;
 pub const OP_NOTIF: u8 = 100;
+pub const OP_VERIF: u8 = 101;
+pub const OP_VERNOTIF: u8 = 102;
 pub const OP_ELSE: u8 = 103;
 pub const OP_ENDIF: u8 = 104;
 pub const OP_VERIFY: u8 = 105;
@@ -154,11 +210,23 @@ pub const OP_ROLL: u8 = 122;
 pub const OP_ROT: u8 = 123;
 pub const OP_SWAP: u8 = 124;
 pub const OP_TUCK: u8 = 125;
+pub const OP_CAT: u8 = 126;
+pub const OP_SUBSTR: u8 = 127;
+pub const OP_LEFT: u8 = 128;
+pub const OP_RIGHT: u8 = 129;
 pub const OP_SIZE: u8 = 130;
+pub const OP_INVERT: u8 = 131;
+pub const OP_AND: u8 = 132;
+pub const OP_OR: u8 = 133;
+pub const OP_XOR: u8 = 134;
 pub const OP_EQUAL: u8 = 135;
 pub const OP_EQUALVERIFY: u8 = 136;
+pub const OP_RESERVED1: u8 = 137;
+pub const OP_RESERVED2: u8 = 138;
 pub const OP_1ADD: u8 = 139;
 pub const OP_1SUB: u8 = 140;
+pub const OP_2MUL: u8 = 141;
+pub const OP_2DIV: u8 = 142;
 pub const OP_NEGATE: u8 = 143;
 pub const OP_ABS: u8 = 144;
 pub const OP_NOT: u8 = 145;
@@ -166,6 +234,10 @@ pub const OP_0NOTEQUAL: u8 = 146;
 pub const OP_ADD: u8 = 147;
 pub const OP_SUB: u8 = 148;
 pub const OP_MUL: u8 = 149;
+pub const OP_DIV: u8 = 150;
+pub const OP_MOD: u8 = 151;
+pub const OP_LSHIFT: u8 = 152;
+pub const OP_RSHIFT: u8 = 153;
 pub const OP_BOOLAND: u8 = 154;
 pub const OP_BOOLOR: u8 = 155;
 pub const OP_NUMEQUAL: u8 = 156;
@@ -191,8 +263,29 @@ pub const OP_CHECKMULTISIGVERIFY: u8 = 175;
 pub const OP_NOOP1: u8 = 176;
 pub const OP_CHECKLOCKTIMEVERIFY: u8 = 177;
 pub const OP_CHECKSEQUENCEVERIFY: u8 = 178;
+pub const OP_NOP4: u8 = 179;
+pub const OP_NOP5: u8 = 180;
+pub const OP_NOP6: u8 = 181;
+pub const OP_NOP7: u8 = 182;
+pub const OP_NOP8: u8 = 183;
+pub const OP_NOP9: u8 = 184;
+pub const OP_NOP10: u8 = 185;
+// tapscript only (BIP342)
+pub const OP_CHECKSIGADD: u8 = 186;
+
+/// Opcodes that make a tapscript succeed as soon as it is decoded (BIP342 OP_SUCCESSx).
+pub fn is_op_success(op_code: u8) -> bool {
+    matches!(op_code, 80 | 98 | 126..=129 | 131..=134 | 137..=138 | 141..=142 | 149..=153 | 187..=254)
+}
+
+// locktimes below are block heights, above unix timestamps
+pub const LOCKTIME_THRESHOLD: i64 = 500_000_000;
+pub const SEQUENCE_FINAL: u32 = 0xffffffff;
+pub const SEQUENCE_LOCKTIME_DISABLE_FLAG: u32 = 1 << 31;
+pub const SEQUENCE_LOCKTIME_TYPE_FLAG: u32 = 1 << 22;
+pub const SEQUENCE_LOCKTIME_MASK: u32 = 0x0000ffff;
 
-pub fn encode_num(num: i32) -> Vec<u8> {
+pub fn encode_num(num: i64) -> Vec<u8> {
     if num == 0 {
         return vec![];
     }
@@ -217,20 +310,20 @@ pub fn encode_num(num: i32) -> Vec<u8> {
     result
 }
 
-pub fn decode_num(element: &[u8]) -> i32 {
+pub fn decode_num(element: &[u8]) -> i64 {
     if element.is_empty() {
         return 0;
     }
     let big_endian = element.iter().rev().cloned().collect::<Vec<_>>();
     let negative = big_endian[0] & 0x80 != 0;
     let mut result = if negative {
-        (big_endian[0] & 0x7f) as i32
+        (big_endian[0] & 0x7f) as i64
     } else {
-        big_endian[0] as i32
+        big_endian[0] as i64
     };
     for &c in &big_endian[1..] {
         result <<= 8;
-        result += c as i32;
+        result += c as i64;
     }
     if negative {
         -result
@@ -239,699 +332,481 @@ pub fn decode_num(element: &[u8]) -> i32 {
     }
 }
 
-pub fn op_0(stack: &mut Vec<Vec<u8>>) -> bool {
+// any non zero byte is true, except the "negative zero" 0x80 in the last byte
+pub fn cast_to_bool(element: &[u8]) -> bool {
+    for (i, byte) in element.iter().enumerate() {
+        if *byte != 0 {
+            return !(i == element.len() - 1 && *byte == 0x80);
+        }
+    }
+    false
+}
+
+fn pop(stack: &mut Vec<Vec<u8>>) -> Result<Vec<u8>, ScriptErrorKind> {
+    stack.pop().ok_or(ScriptErrorKind::StackUnderflow)
+}
+// numeric operands are limited to 4 bytes, results may overflow that
+fn pop_num(stack: &mut Vec<Vec<u8>>) -> Result<i64, ScriptErrorKind> {
+    let element = pop(stack)?;
+    if element.len() > 4 {
+        return Err(ScriptErrorKind::InvalidNumber);
+    }
+    Ok(decode_num(&element))
+}
+// no leading zero bytes, a zero byte is only allowed to carry the sign bit
+pub fn is_minimal_num(element: &[u8]) -> bool {
+    match element.last() {
+        None => true,
+        Some(last) if last & 0x7f != 0 => true,
+        Some(_) => element.len() > 1 && element[element.len() - 2] & 0x80 != 0,
+    }
+}
+/// How many elements on top of the stack an opcode reads as numbers.
+pub fn numeric_operands(op_code: u8) -> usize {
+    match op_code {
+        OP_PICK | OP_ROLL | OP_1ADD | OP_1SUB | OP_NEGATE | OP_ABS | OP_NOT | OP_0NOTEQUAL
+        | OP_CHECKMULTISIG | OP_CHECKMULTISIGVERIFY | OP_CHECKLOCKTIMEVERIFY | OP_CHECKSEQUENCEVERIFY => 1,
+        OP_ADD..=OP_MAX => 2,
+        OP_WITHIN => 3,
+        _ => 0,
+    }
+}
+fn require(stack: &[Vec<u8>], n: usize) -> Result<(), ScriptErrorKind> {
+    if stack.len() < n {
+        return Err(ScriptErrorKind::StackUnderflow);
+    }
+    Ok(())
+}
+
+pub fn op_0(stack: &mut Vec<Vec<u8>>) -> Result<(), ScriptErrorKind> {
     stack.push(encode_num(0));
-    true
+    Ok(())
 }
-pub fn op_1negate(stack: &mut Vec<Vec<u8>>) -> bool {
+pub fn op_1negate(stack: &mut Vec<Vec<u8>>) -> Result<(), ScriptErrorKind> {
     stack.push(encode_num(-1));
-    true
+    Ok(())
 }
-pub fn op_1(stack: &mut Vec<Vec<u8>>) -> bool {
+pub fn op_1(stack: &mut Vec<Vec<u8>>) -> Result<(), ScriptErrorKind> {
     stack.push(encode_num(1));
-    true
+    Ok(())
 }
-pub fn op_2(stack: &mut Vec<Vec<u8>>) -> bool {
+pub fn op_2(stack: &mut Vec<Vec<u8>>) -> Result<(), ScriptErrorKind> {
     stack.push(encode_num(2));
-    true
+    Ok(())
 }
-pub fn op_3(stack: &mut Vec<Vec<u8>>) -> bool {
+pub fn op_3(stack: &mut Vec<Vec<u8>>) -> Result<(), ScriptErrorKind> {
     stack.push(encode_num(3));
-    true
+    Ok(())
 }
-pub fn op_4(stack: &mut Vec<Vec<u8>>) -> bool {
+pub fn op_4(stack: &mut Vec<Vec<u8>>) -> Result<(), ScriptErrorKind> {
     stack.push(encode_num(4));
-    true
+    Ok(())
 }
-pub fn op_5(stack: &mut Vec<Vec<u8>>) -> bool {
+pub fn op_5(stack: &mut Vec<Vec<u8>>) -> Result<(), ScriptErrorKind> {
     stack.push(encode_num(5));
-    true
+    Ok(())
 }
-pub fn op_6(stack: &mut Vec<Vec<u8>>) -> bool {
+pub fn op_6(stack: &mut Vec<Vec<u8>>) -> Result<(), ScriptErrorKind> {
     stack.push(encode_num(6));
-    true
+    Ok(())
 }
-pub fn op_7(stack: &mut Vec<Vec<u8>>) -> bool {
+pub fn op_7(stack: &mut Vec<Vec<u8>>) -> Result<(), ScriptErrorKind> {
     stack.push(encode_num(7));
-    true
+    Ok(())
 }
-pub fn op_8(stack: &mut Vec<Vec<u8>>) -> bool {
+pub fn op_8(stack: &mut Vec<Vec<u8>>) -> Result<(), ScriptErrorKind> {
     stack.push(encode_num(8));
-    true
+    Ok(())
 }
-pub fn op_9(stack: &mut Vec<Vec<u8>>) -> bool {
+pub fn op_9(stack: &mut Vec<Vec<u8>>) -> Result<(), ScriptErrorKind> {
     stack.push(encode_num(9));
-    true
+    Ok(())
 }
-pub fn op_10(stack: &mut Vec<Vec<u8>>) -> bool {
+pub fn op_10(stack: &mut Vec<Vec<u8>>) -> Result<(), ScriptErrorKind> {
     stack.push(encode_num(10));
-    true
+    Ok(())
 }
-pub fn op_11(stack: &mut Vec<Vec<u8>>) -> bool {
+pub fn op_11(stack: &mut Vec<Vec<u8>>) -> Result<(), ScriptErrorKind> {
     stack.push(encode_num(11));
-    true
+    Ok(())
 }
-pub fn op_12(stack: &mut Vec<Vec<u8>>) -> bool {
+pub fn op_12(stack: &mut Vec<Vec<u8>>) -> Result<(), ScriptErrorKind> {
     stack.push(encode_num(12));
-    true
+    Ok(())
 }
-pub fn op_13(stack: &mut Vec<Vec<u8>>) -> bool {
+pub fn op_13(stack: &mut Vec<Vec<u8>>) -> Result<(), ScriptErrorKind> {
     stack.push(encode_num(13));
-    true
+    Ok(())
 }
-pub fn op_14(stack: &mut Vec<Vec<u8>>) -> bool {
+pub fn op_14(stack: &mut Vec<Vec<u8>>) -> Result<(), ScriptErrorKind> {
     stack.push(encode_num(14));
-    true
+    Ok(())
 }
-pub fn op_15(stack: &mut Vec<Vec<u8>>) -> bool {
+pub fn op_15(stack: &mut Vec<Vec<u8>>) -> Result<(), ScriptErrorKind> {
     stack.push(encode_num(15));
-    true
+    Ok(())
 }
-pub fn op_16(stack: &mut Vec<Vec<u8>>) -> bool {
+pub fn op_16(stack: &mut Vec<Vec<u8>>) -> Result<(), ScriptErrorKind> {
     stack.push(encode_num(16));
-    true
-}
-pub fn op_nop(_stack: &mut Vec<Vec<u8>>) -> bool {
-    true
-}
-pub fn op_if(stack: &mut Vec<Vec<u8>>, items: &mut Vec<u8>) -> bool {
-    if stack.len() < 1 {
-        return false;
-    }
-
-    let mut true_items = Vec::new();
-    let mut false_items = Vec::new();
-    let mut current_array = &mut true_items;
-    let mut found = false;
-    let mut num_endifs_needed = 1;
-
-    while !items.is_empty() {
-        let item = items.remove(0);
-        match item {
-            99 | 100 => {
-                num_endifs_needed += 1;
-                current_array.push(item);
-            }
-            103 if num_endifs_needed == 1 => {
-                current_array = &mut false_items;
-            }
-            104 => {
-                if num_endifs_needed == 1 {
-                    found = true;
-                    break;
-                } else {
-                    num_endifs_needed -= 1;
-                    current_array.push(item);
-                }
-            }
-            _ => {
-                current_array.push(item);
-            }
+    Ok(())
+}
+pub fn op_nop(_stack: &mut Vec<Vec<u8>>) -> Result<(), ScriptErrorKind> {
+    Ok(())
+}
+pub fn op_if(stack: &mut Vec<Vec<u8>>, exec_stack: &mut Vec<bool>) -> Result<(), ScriptErrorKind> {
+    // inside a branch not taken the condition is not consumed,
+    // the new branch is just marked as not executed
+    let mut value = false;
+    if !exec_stack.contains(&false) {
+        let element = stack.pop().ok_or(ScriptErrorKind::UnbalancedConditional)?;
+        value = cast_to_bool(&element);
+    }
+    exec_stack.push(value);
+    Ok(())
+}
+pub fn op_notif(stack: &mut Vec<Vec<u8>>, exec_stack: &mut Vec<bool>) -> Result<(), ScriptErrorKind> {
+    let mut value = false;
+    if !exec_stack.contains(&false) {
+        let element = stack.pop().ok_or(ScriptErrorKind::UnbalancedConditional)?;
+        value = !cast_to_bool(&element);
+    }
+    exec_stack.push(value);
+    Ok(())
+}
+pub fn op_else(exec_stack: &mut [bool]) -> Result<(), ScriptErrorKind> {
+    match exec_stack.last_mut() {
+        Some(value) => {
+            *value = !*value;
+            Ok(())
         }
+        None => Err(ScriptErrorKind::UnbalancedConditional),
     }
-
-    if !found {
-        return false;
-    }
-
-    let element = stack.pop().unwrap();
-    if decode_num(&element) == 0 {
-        items.splice(0..0, false_items.into_iter());
-    } else {
-        items.splice(0..0, true_items.into_iter());
-    }
-
-    true
 }
-pub fn op_notif(stack: &mut Vec<Vec<u8>>, items: &mut Vec<u8>) -> bool {
-    if stack.len() < 1 {
-        return false;
-    }
-
-    let mut true_items = Vec::new();
-    let mut false_items = Vec::new();
-    let mut current_array = &mut true_items;
-    let mut found = false;
-    let mut num_endifs_needed = 1;
-
-    while !items.is_empty() {
-        let item = items.remove(0);
-        match item {
-            99 | 100 => {
-                num_endifs_needed += 1;
-                current_array.push(item);
-            }
-            103 if num_endifs_needed == 1 => {
-                current_array = &mut false_items;
-            }
-            104 => {
-                if num_endifs_needed == 1 {
-                    found = true;
-                    break;
-                } else {
-                    num_endifs_needed -= 1;
-                    current_array.push(item);
-                }
-            }
-            _ => {
-                current_array.push(item);
-            }
-        }
+pub fn op_endif(exec_stack: &mut Vec<bool>) -> Result<(), ScriptErrorKind> {
+    match exec_stack.pop() {
+        Some(_) => Ok(()),
+        None => Err(ScriptErrorKind::UnbalancedConditional),
     }
-
-    if !found {
-        return false;
-    }
-
-    let element = stack.pop().unwrap();
-    if decode_num(&element) == 0 {
-        items.splice(0..0, true_items.into_iter());
-    } else {
-        items.splice(0..0, false_items.into_iter());
-    }
-
-    true
-}
-pub fn op_else(stack: &mut Vec<Vec<u8>>, items: &mut Vec<u8>) -> bool {
-    println!("{}", stack.len());
-    println!("{:?}", items);
-    panic!("op_else not implemented")
-}
-pub fn op_endif(stack: &mut Vec<Vec<u8>>, items: &mut Vec<u8>) -> bool {
-    println!("{}", stack.len());
-    println!("{:?}", items);
-    panic!("op_endif not implemented")
 }
-pub fn op_verify(stack: &mut Vec<Vec<u8>>) -> bool {
-    if stack.len() < 1 {
-        return false;
+pub fn op_verify(stack: &mut Vec<Vec<u8>>) -> Result<(), ScriptErrorKind> {
+    let element = pop(stack)?;
+    if !cast_to_bool(&element) {
+        return Err(ScriptErrorKind::VerifyFailed);
     }
-
-    let element = stack.pop().unwrap();
-    if decode_num(&element) == 0 {
-        return false;
-    }
-
-    true
+    Ok(())
 }
-pub fn op_return(_stack: &mut Vec<Vec<u8>>) -> bool {
-    return false;
+pub fn op_return(_stack: &mut Vec<Vec<u8>>) -> Result<(), ScriptErrorKind> {
+    Err(ScriptErrorKind::OpReturn)
 }
-pub fn op_toaltstack(stack: &mut Vec<Vec<u8>>, altstack: &mut Vec<Vec<u8>>) -> bool {
-    if stack.is_empty() {
-        return false;
-    }
-    let item = stack.pop().unwrap();
+pub fn op_toaltstack(stack: &mut Vec<Vec<u8>>, altstack: &mut Vec<Vec<u8>>) -> Result<(), ScriptErrorKind> {
+    let item = pop(stack)?;
     altstack.push(item);
-    true
+    Ok(())
 }
-pub fn op_fromaltstack(stack: &mut Vec<Vec<u8>>, altstack: &mut Vec<Vec<u8>>) -> bool {
-    if altstack.is_empty() {
-        return false;
-    }
-    let item = altstack.pop().unwrap();
+pub fn op_fromaltstack(stack: &mut Vec<Vec<u8>>, altstack: &mut Vec<Vec<u8>>) -> Result<(), ScriptErrorKind> {
+    let item = altstack.pop().ok_or(ScriptErrorKind::AltStackUnderflow)?;
     stack.push(item);
-    true
+    Ok(())
 }
-pub fn op_2drop(stack: &mut Vec<Vec<u8>>) -> bool {
-    if stack.len() < 2 {
-        return false;
-    }
+pub fn op_2drop(stack: &mut Vec<Vec<u8>>) -> Result<(), ScriptErrorKind> {
+    require(stack, 2)?;
     stack.pop();
     stack.pop();
-    true
+    Ok(())
 }
-pub fn op_2dup(stack: &mut Vec<Vec<u8>>) -> bool {
-    if stack.len() < 2 {
-        return false;
-    }
+pub fn op_2dup(stack: &mut Vec<Vec<u8>>) -> Result<(), ScriptErrorKind> {
+    require(stack, 2)?;
     let item1 = stack[stack.len() - 2].clone();
     let item2 = stack[stack.len() - 1].clone();
     stack.push(item1);
     stack.push(item2);
-    true
+    Ok(())
 }
-pub fn op_3dup(stack: &mut Vec<Vec<u8>>) -> bool {
-    if stack.len() < 3 {
-        return false;
-    }
+pub fn op_3dup(stack: &mut Vec<Vec<u8>>) -> Result<(), ScriptErrorKind> {
+    require(stack, 3)?;
     let item1 = stack[stack.len() - 3].clone();
     let item2 = stack[stack.len() - 2].clone();
     let item3 = stack[stack.len() - 1].clone();
     stack.push(item1);
     stack.push(item2);
     stack.push(item3);
-    true
+    Ok(())
 }
-pub fn op_2over(stack: &mut Vec<Vec<u8>>) -> bool {
-    if stack.len() < 4 {
-        return false;
-    }
+pub fn op_2over(stack: &mut Vec<Vec<u8>>) -> Result<(), ScriptErrorKind> {
+    require(stack, 4)?;
     let item1 = stack[stack.len() - 4].clone();
     let item2 = stack[stack.len() - 3].clone();
     stack.push(item1);
     stack.push(item2);
-    true
+    Ok(())
 }
-pub fn op_2rot(stack: &mut Vec<Vec<u8>>) -> bool {
-    if stack.len() < 6 {
-        return false;
-    }
-    let item1 = stack.pop().unwrap();
-    let item2 = stack.pop().unwrap();
-    let item3 = stack.pop().unwrap();
-    let item4 = stack.pop().unwrap();
-    let item5 = stack.pop().unwrap();
-    let item6 = stack.pop().unwrap();
-    stack.push(item3);
-    stack.push(item4);
+// x1 x2 x3 x4 x5 x6 -> x3 x4 x5 x6 x1 x2
+pub fn op_2rot(stack: &mut Vec<Vec<u8>>) -> Result<(), ScriptErrorKind> {
+    require(stack, 6)?;
+    let item1 = stack.remove(stack.len() - 6);
+    let item2 = stack.remove(stack.len() - 5);
     stack.push(item1);
     stack.push(item2);
-    stack.push(item5);
-    stack.push(item6);
-    true
+    Ok(())
 }
-pub fn op_2swap(stack: &mut Vec<Vec<u8>>) -> bool {
-    if stack.len() < 4 {
-        return false;
-    }
-    let item1 = stack.pop().unwrap();
-    let item2 = stack.pop().unwrap();
-    let item3 = stack.pop().unwrap();
-    let item4 = stack.pop().unwrap();
-    stack.push(item3);
-    stack.push(item4);
+// x1 x2 x3 x4 -> x3 x4 x1 x2
+pub fn op_2swap(stack: &mut Vec<Vec<u8>>) -> Result<(), ScriptErrorKind> {
+    require(stack, 4)?;
+    let item1 = stack.remove(stack.len() - 4);
+    let item2 = stack.remove(stack.len() - 3);
     stack.push(item1);
     stack.push(item2);
-    true
+    Ok(())
 }
-pub fn op_ifdup(stack: &mut Vec<Vec<u8>>) -> bool {
-    if stack.len() < 1 {
-        return false;
-    }
+pub fn op_ifdup(stack: &mut Vec<Vec<u8>>) -> Result<(), ScriptErrorKind> {
+    require(stack, 1)?;
     let item = stack[stack.len() - 1].clone();
-    if decode_num(&item) != 0 {
+    if cast_to_bool(&item) {
         stack.push(item);
     }
-    true
+    Ok(())
 }
-pub fn op_depth(stack: &mut Vec<Vec<u8>>) -> bool {
-    let depth = stack.len() as i32;
+pub fn op_depth(stack: &mut Vec<Vec<u8>>) -> Result<(), ScriptErrorKind> {
+    let depth = stack.len() as i64;
     stack.push(encode_num(depth));
-    true
+    Ok(())
 }
-pub fn op_drop(stack: &mut Vec<Vec<u8>>) -> bool {
-    if stack.is_empty() {
-        return false;
-    }
-    stack.pop();
-    true
+pub fn op_drop(stack: &mut Vec<Vec<u8>>) -> Result<(), ScriptErrorKind> {
+    pop(stack)?;
+    Ok(())
 }
-pub fn op_dup(stack: &mut Vec<Vec<u8>>) -> bool {
-    if stack.is_empty() {
-        println!("op_dup called with empty stack");
-        return false;
-    }
+pub fn op_dup(stack: &mut Vec<Vec<u8>>) -> Result<(), ScriptErrorKind> {
+    require(stack, 1)?;
     let item = stack[stack.len() - 1].clone();
     stack.push(item);
-    true
+    Ok(())
 }
-pub fn op_nip(stack: &mut Vec<Vec<u8>>) -> bool {
-    if stack.len() < 2 {
-        return false;
-    }
+pub fn op_nip(stack: &mut Vec<Vec<u8>>) -> Result<(), ScriptErrorKind> {
+    require(stack, 2)?;
     stack.remove(stack.len() - 2);
-    true
+    Ok(())
 }
-pub fn op_over(stack: &mut Vec<Vec<u8>>) -> bool {
-    if stack.len() < 2 {
-        return false;
-    }
+pub fn op_over(stack: &mut Vec<Vec<u8>>) -> Result<(), ScriptErrorKind> {
+    require(stack, 2)?;
     let item = stack[stack.len() - 2].clone();
     stack.push(item);
-    true
+    Ok(())
 }
-pub fn op_pick(stack: &mut Vec<Vec<u8>>) -> bool {
-    if stack.len() < 1 {
-        return false;
-    }
-    let item = stack.pop().unwrap();
-    let n = decode_num(&item) as usize;
-    if stack.len() < n {
-        return false;
+// n is counted from the top, 0 OP_PICK is OP_DUP
+pub fn op_pick(stack: &mut Vec<Vec<u8>>) -> Result<(), ScriptErrorKind> {
+    let n = pop_num(stack)?;
+    if n < 0 || n as usize >= stack.len() {
+        return Err(ScriptErrorKind::StackUnderflow);
     }
-    let item = stack[stack.len() - n].clone();
+    let item = stack[stack.len() - 1 - n as usize].clone();
     stack.push(item);
-    true
+    Ok(())
 }
-pub fn op_roll(stack: &mut Vec<Vec<u8>>) -> bool {
-    if stack.len() < 1 {
-        return false;
-    }
-    let item = stack.pop().unwrap();
-    let n = decode_num(&item) as usize;
-    if stack.len() < n {
-        return false;
+pub fn op_roll(stack: &mut Vec<Vec<u8>>) -> Result<(), ScriptErrorKind> {
+    let n = pop_num(stack)?;
+    if n < 0 || n as usize >= stack.len() {
+        return Err(ScriptErrorKind::StackUnderflow);
     }
-    let item = stack.remove(stack.len() - n);
+    let item = stack.remove(stack.len() - 1 - n as usize);
     stack.push(item);
-    true
+    Ok(())
 }
-pub fn op_rot(stack: &mut Vec<Vec<u8>>) -> bool {
-    if stack.len() < 3 {
-        return false;
-    }
+pub fn op_rot(stack: &mut Vec<Vec<u8>>) -> Result<(), ScriptErrorKind> {
+    require(stack, 3)?;
     let item1 = stack.pop().unwrap();
     let item2 = stack.pop().unwrap();
     let item3 = stack.pop().unwrap();
     stack.push(item2);
     stack.push(item1);
     stack.push(item3);
-    true
+    Ok(())
 }
-pub fn op_swap(stack: &mut Vec<Vec<u8>>) -> bool {
-    if stack.len() < 2 {
-        return false;
-    }
+pub fn op_swap(stack: &mut Vec<Vec<u8>>) -> Result<(), ScriptErrorKind> {
+    require(stack, 2)?;
     let item1 = stack.pop().unwrap();
     let item2 = stack.pop().unwrap();
     stack.push(item1);
     stack.push(item2);
-    true
+    Ok(())
 }
-pub fn op_tuck(stack: &mut Vec<Vec<u8>>) -> bool {
-    if stack.len() < 2 {
-        return false;
-    }
+pub fn op_tuck(stack: &mut Vec<Vec<u8>>) -> Result<(), ScriptErrorKind> {
+    require(stack, 2)?;
     let item1 = stack.pop().unwrap();
     let item2 = stack.pop().unwrap();
     stack.push(item1.clone());
     stack.push(item2);
     stack.push(item1);
-    true
+    Ok(())
 }
-pub fn op_size(stack: &mut Vec<Vec<u8>>) -> bool {
-    if stack.is_empty() {
-        return false;
-    }
-    let item = stack.pop().unwrap();
-    let size = item.len() as i32;
+// the element is left on the stack, its size is pushed on top
+pub fn op_size(stack: &mut Vec<Vec<u8>>) -> Result<(), ScriptErrorKind> {
+    require(stack, 1)?;
+    let size = stack[stack.len() - 1].len() as i64;
     stack.push(encode_num(size));
-    true
+    Ok(())
 }
-pub fn op_equal(stack: &mut Vec<Vec<u8>>) -> bool {
-    if stack.len() < 2 {
-        return false;
-    }
+pub fn op_equal(stack: &mut Vec<Vec<u8>>) -> Result<(), ScriptErrorKind> {
+    require(stack, 2)?;
     let item1 = stack.pop().unwrap();
     let item2 = stack.pop().unwrap();
     let result = if item1 == item2 { 1 } else { 0 };
     stack.push(encode_num(result));
-    true
+    Ok(())
 }
-pub fn op_equalverify(stack: &mut Vec<Vec<u8>>) -> bool {
-    if !op_equal(stack) {
-        return false;
-    }
-    if stack.is_empty() {
-        return false;
-    }
-    let item = stack.pop().unwrap();
-    if decode_num(&item) == 0 {
-        return false;
-    }
-    true
+pub fn op_equalverify(stack: &mut Vec<Vec<u8>>) -> Result<(), ScriptErrorKind> {
+    op_equal(stack)?;
+    op_verify(stack).map_err(|_| ScriptErrorKind::EqualVerifyFailed)
 }
-pub fn op_1add(stack: &mut Vec<Vec<u8>>) -> bool {
-    if stack.is_empty() {
-        return false;
-    }
-    let item = stack.pop().unwrap();
-    let num = decode_num(&item);
+pub fn op_1add(stack: &mut Vec<Vec<u8>>) -> Result<(), ScriptErrorKind> {
+    let num = pop_num(stack)?;
     stack.push(encode_num(num + 1));
-    true
+    Ok(())
 }
-pub fn op_1sub(stack: &mut Vec<Vec<u8>>) -> bool {
-    if stack.is_empty() {
-        return false;
-    }
-    let item = stack.pop().unwrap();
-    let num = decode_num(&item);
+pub fn op_1sub(stack: &mut Vec<Vec<u8>>) -> Result<(), ScriptErrorKind> {
+    let num = pop_num(stack)?;
     stack.push(encode_num(num - 1));
-    true
+    Ok(())
 }
-pub fn op_negate(stack: &mut Vec<Vec<u8>>) -> bool {
-    if stack.is_empty() {
-        return false;
-    }
-    let item = stack.pop().unwrap();
-    let num = decode_num(&item);
+pub fn op_negate(stack: &mut Vec<Vec<u8>>) -> Result<(), ScriptErrorKind> {
+    let num = pop_num(stack)?;
     stack.push(encode_num(-num));
-    true
+    Ok(())
 }
-pub fn op_abs(stack: &mut Vec<Vec<u8>>) -> bool {
-    if stack.is_empty() {
-        return false;
-    }
-    let item = stack.pop().unwrap();
-    let num = decode_num(&item);
+pub fn op_abs(stack: &mut Vec<Vec<u8>>) -> Result<(), ScriptErrorKind> {
+    let num = pop_num(stack)?;
     stack.push(encode_num(num.abs()));
-    true
+    Ok(())
 }
-pub fn op_not(stack: &mut Vec<Vec<u8>>) -> bool {
-    if stack.is_empty() {
-        return false;
-    }
-    let item = stack.pop().unwrap();
-    let num = decode_num(&item);
+pub fn op_not(stack: &mut Vec<Vec<u8>>) -> Result<(), ScriptErrorKind> {
+    let num = pop_num(stack)?;
     let result = if num == 0 { 1 } else { 0 };
     stack.push(encode_num(result));
-    true
+    Ok(())
 }
-pub fn op_0notequal(stack: &mut Vec<Vec<u8>>) -> bool {
-    if stack.is_empty() {
-        return false;
-    }
-    let item = stack.pop().unwrap();
-    let num = decode_num(&item);
+pub fn op_0notequal(stack: &mut Vec<Vec<u8>>) -> Result<(), ScriptErrorKind> {
+    let num = pop_num(stack)?;
     let result = if num == 0 { 0 } else { 1 };
     stack.push(encode_num(result));
-    true
-}
-pub fn op_add(stack: &mut Vec<Vec<u8>>) -> bool {
-    if stack.len() < 2 {
-        return false;
-    }
-    let item1 = stack.pop().unwrap();
-    let item2 = stack.pop().unwrap();
-    let num1 = decode_num(&item1);
-    let num2 = decode_num(&item2);
-    stack.push(encode_num(num1 + num2));
-    true
-}
-pub fn op_sub(stack: &mut Vec<Vec<u8>>) -> bool {
-    if stack.len() < 2 {
-        return false;
-    }
-    let item1 = stack.pop().unwrap();
-    let item2 = stack.pop().unwrap();
-    let num1 = decode_num(&item1);
-    let num2 = decode_num(&item2);
-    stack.push(encode_num(num1 - num2));
-    true
-}
-pub fn op_mul(stack: &mut Vec<Vec<u8>>) -> bool {
-    if stack.len() < 2 {
-        return false;
-    }
-    let item1 = decode_num(stack.pop().unwrap().as_slice());
-    let item2 = decode_num(stack.pop().unwrap().as_slice());
-    stack.push(encode_num(item1 * item2));
-    true
-}
-pub fn op_booland(stack: &mut Vec<Vec<u8>>) -> bool {
-    if stack.len() < 2 {
-        return false;
-    }
-    let item1 = stack.pop().unwrap();
-    let item2 = stack.pop().unwrap();
-    let num1 = decode_num(&item1);
-    let num2 = decode_num(&item2);
-    let result = if num1 != 0 && num2 != 0 { 1 } else { 0 };
+    Ok(())
+}
+// pops b then a, the operands of the binary operators are in script order: a b OP
+fn pop_two_nums(stack: &mut Vec<Vec<u8>>) -> Result<(i64, i64), ScriptErrorKind> {
+    require(stack, 2)?;
+    let b = pop_num(stack)?;
+    let a = pop_num(stack)?;
+    Ok((a, b))
+}
+pub fn op_add(stack: &mut Vec<Vec<u8>>) -> Result<(), ScriptErrorKind> {
+    let (a, b) = pop_two_nums(stack)?;
+    stack.push(encode_num(a + b));
+    Ok(())
+}
+pub fn op_sub(stack: &mut Vec<Vec<u8>>) -> Result<(), ScriptErrorKind> {
+    let (a, b) = pop_two_nums(stack)?;
+    stack.push(encode_num(a - b));
+    Ok(())
+}
+pub fn op_mul(stack: &mut Vec<Vec<u8>>) -> Result<(), ScriptErrorKind> {
+    let (a, b) = pop_two_nums(stack)?;
+    stack.push(encode_num(a * b));
+    Ok(())
+}
+pub fn op_booland(stack: &mut Vec<Vec<u8>>) -> Result<(), ScriptErrorKind> {
+    let (a, b) = pop_two_nums(stack)?;
+    let result = if a != 0 && b != 0 { 1 } else { 0 };
     stack.push(encode_num(result));
-    true
+    Ok(())
 }
-pub fn op_boolor(stack: &mut Vec<Vec<u8>>) -> bool {
-    if stack.len() < 2 {
-        return false;
-    }
-    let item1 = stack.pop().unwrap();
-    let item2 = stack.pop().unwrap();
-    let num1 = decode_num(&item1);
-    let num2 = decode_num(&item2);
-    let result = if num1 != 0 || num2 != 0 { 1 } else { 0 };
+pub fn op_boolor(stack: &mut Vec<Vec<u8>>) -> Result<(), ScriptErrorKind> {
+    let (a, b) = pop_two_nums(stack)?;
+    let result = if a != 0 || b != 0 { 1 } else { 0 };
     stack.push(encode_num(result));
-    true
+    Ok(())
 }
-pub fn op_numequal(stack: &mut Vec<Vec<u8>>) -> bool {
-    if stack.len() < 2 {
-        return false;
-    }
-    let item1 = stack.pop().unwrap();
-    let item2 = stack.pop().unwrap();
-    let num1 = decode_num(&item1);
-    let num2 = decode_num(&item2);
-    let result = if num1 == num2 { 1 } else { 0 };
+pub fn op_numequal(stack: &mut Vec<Vec<u8>>) -> Result<(), ScriptErrorKind> {
+    let (a, b) = pop_two_nums(stack)?;
+    let result = if a == b { 1 } else { 0 };
     stack.push(encode_num(result));
-    true
+    Ok(())
 }
-pub fn op_numequalverify(stack: &mut Vec<Vec<u8>>) -> bool {
-    if !op_numequal(stack) {
-        return false;
-    }
-    if stack.is_empty() {
-        return false;
-    }
-    let item = stack.pop().unwrap();
-    if decode_num(&item) == 0 {
-        return false;
-    }
-    true
+pub fn op_numequalverify(stack: &mut Vec<Vec<u8>>) -> Result<(), ScriptErrorKind> {
+    op_numequal(stack)?;
+    op_verify(stack).map_err(|_| ScriptErrorKind::NumEqualVerifyFailed)
 }
-pub fn op_numnotequal(stack: &mut Vec<Vec<u8>>) -> bool {
-    if stack.len() < 2 {
-        return false;
-    }
-    let item1 = stack.pop().unwrap();
-    let item2 = stack.pop().unwrap();
-    let num1 = decode_num(&item1);
-    let num2 = decode_num(&item2);
-    let result = if num1 != num2 { 1 } else { 0 };
+pub fn op_numnotequal(stack: &mut Vec<Vec<u8>>) -> Result<(), ScriptErrorKind> {
+    let (a, b) = pop_two_nums(stack)?;
+    let result = if a != b { 1 } else { 0 };
     stack.push(encode_num(result));
-    true
+    Ok(())
 }
-pub fn op_lessthan(stack: &mut Vec<Vec<u8>>) -> bool {
-    if stack.len() < 2 {
-        return false;
-    }
-    let item1 = stack.pop().unwrap();
-    let item2 = stack.pop().unwrap();
-    let num1 = decode_num(&item1);
-    let num2 = decode_num(&item2);
-    let result = if num1 < num2 { 1 } else { 0 };
+pub fn op_lessthan(stack: &mut Vec<Vec<u8>>) -> Result<(), ScriptErrorKind> {
+    let (a, b) = pop_two_nums(stack)?;
+    let result = if a < b { 1 } else { 0 };
     stack.push(encode_num(result));
-    true
+    Ok(())
 }
-pub fn op_greaterthan(stack: &mut Vec<Vec<u8>>) -> bool {
-    if stack.len() < 2 {
-        return false;
-    }
-    let item1 = stack.pop().unwrap();
-    let item2 = stack.pop().unwrap();
-    let num1 = decode_num(&item1);
-    let num2 = decode_num(&item2);
-    let result = if num1 > num2 { 1 } else { 0 };
+pub fn op_greaterthan(stack: &mut Vec<Vec<u8>>) -> Result<(), ScriptErrorKind> {
+    let (a, b) = pop_two_nums(stack)?;
+    let result = if a > b { 1 } else { 0 };
     stack.push(encode_num(result));
-    true
+    Ok(())
 }
-pub fn op_lessthanorequal(stack: &mut Vec<Vec<u8>>) -> bool {
-    if stack.len() < 2 {
-        return false;
-    }
-    let item1 = stack.pop().unwrap();
-    let item2 = stack.pop().unwrap();
-    let num1 = decode_num(&item1);
-    let num2 = decode_num(&item2);
-    let result = if num1 <= num2 { 1 } else { 0 };
+pub fn op_lessthanorequal(stack: &mut Vec<Vec<u8>>) -> Result<(), ScriptErrorKind> {
+    let (a, b) = pop_two_nums(stack)?;
+    let result = if a <= b { 1 } else { 0 };
     stack.push(encode_num(result));
-    true
+    Ok(())
 }
-pub fn op_greaterthanorequal(stack: &mut Vec<Vec<u8>>) -> bool {
-    if stack.len() < 2 {
-        return false;
-    }
-    let item1 = stack.pop().unwrap();
-    let item2 = stack.pop().unwrap();
-    let num1 = decode_num(&item1);
-    let num2 = decode_num(&item2);
-    let result = if num1 >= num2 { 1 } else { 0 };
+pub fn op_greaterthanorequal(stack: &mut Vec<Vec<u8>>) -> Result<(), ScriptErrorKind> {
+    let (a, b) = pop_two_nums(stack)?;
+    let result = if a >= b { 1 } else { 0 };
     stack.push(encode_num(result));
-    true
-}
-pub fn op_min(stack: &mut Vec<Vec<u8>>) -> bool {
-    if stack.len() < 2 {
-        return false;
-    }
-    let item1 = stack.pop().unwrap();
-    let item2 = stack.pop().unwrap();
-    let num1 = decode_num(&item1);
-    let num2 = decode_num(&item2);
-    let result = if num1 < num2 { num1 } else { num2 };
-    stack.push(encode_num(result));
-    true
-}
-pub fn op_max(stack: &mut Vec<Vec<u8>>) -> bool {
-    if stack.len() < 2 {
-        return false;
-    }
-    let item1 = stack.pop().unwrap();
-    let item2 = stack.pop().unwrap();
-    let num1 = decode_num(&item1);
-    let num2 = decode_num(&item2);
-    let result = if num1 > num2 { num1 } else { num2 };
-    stack.push(encode_num(result));
-    true
-}
-pub fn op_within(stack: &mut Vec<Vec<u8>>) -> bool {
-    if stack.len() < 3 {
-        return false;
-    }
-    let item1 = stack.pop().unwrap();
-    let item2 = stack.pop().unwrap();
-    let item3 = stack.pop().unwrap();
-    let num1 = decode_num(&item1);
-    let num2 = decode_num(&item2);
-    let num3 = decode_num(&item3);
-    let result = if num2 <= num1 && num1 < num3 { 1 } else { 0 };
+    Ok(())
+}
+pub fn op_min(stack: &mut Vec<Vec<u8>>) -> Result<(), ScriptErrorKind> {
+    let (a, b) = pop_two_nums(stack)?;
+    stack.push(encode_num(a.min(b)));
+    Ok(())
+}
+pub fn op_max(stack: &mut Vec<Vec<u8>>) -> Result<(), ScriptErrorKind> {
+    let (a, b) = pop_two_nums(stack)?;
+    stack.push(encode_num(a.max(b)));
+    Ok(())
+}
+// x min max -> min <= x < max
+pub fn op_within(stack: &mut Vec<Vec<u8>>) -> Result<(), ScriptErrorKind> {
+    require(stack, 3)?;
+    let max = pop_num(stack)?;
+    let min = pop_num(stack)?;
+    let x = pop_num(stack)?;
+    let result = if min <= x && x < max { 1 } else { 0 };
     stack.push(encode_num(result));
-    true
+    Ok(())
 }
-pub fn op_ripemd160(stack: &mut Vec<Vec<u8>>) -> bool {
-    if stack.is_empty() {
-        return false;
-    }
-    let item = stack.pop().unwrap();
+pub fn op_ripemd160(stack: &mut Vec<Vec<u8>>) -> Result<(), ScriptErrorKind> {
+    let item = pop(stack)?;
     let hash = Ripemd160::digest(&item);
     stack.push(hash.to_vec());
-    true
+    Ok(())
 }
-pub fn op_sha1(stack: &mut Vec<Vec<u8>>) -> bool {
-    if stack.is_empty() {
-        return false;
-    }
-    let element = stack.pop().unwrap();
+pub fn op_sha1(stack: &mut Vec<Vec<u8>>) -> Result<(), ScriptErrorKind> {
+    let element = pop(stack)?;
     let mut hasher = Sha1::new();
     hasher.update(&element);
     let result = hasher.finalize();
     stack.push(result.to_vec());
-    true
+    Ok(())
 }
-pub fn op_sha256(stack: &mut Vec<Vec<u8>>) -> bool {
-    if stack.is_empty() {
-        return false;
-    }
-    let element = stack.pop().unwrap();
+pub fn op_sha256(stack: &mut Vec<Vec<u8>>) -> Result<(), ScriptErrorKind> {
+    let element = pop(stack)?;
     let mut hasher = Sha256::new();
     hasher.update(&element);
     let result = hasher.finalize();
     stack.push(result.to_vec());
-    true
+    Ok(())
 }
-pub fn op_hash160(stack: &mut Vec<Vec<u8>>) -> bool {
-    if stack.is_empty() {
-        return false;
-    }
-    let element = stack.pop().unwrap();
+pub fn op_hash160(stack: &mut Vec<Vec<u8>>) -> Result<(), ScriptErrorKind> {
+    let element = pop(stack)?;
     let mut hasher = Sha256::new();
     hasher.update(&element);
     let result = hasher.finalize();
@@ -939,13 +814,10 @@ pub fn op_hash160(stack: &mut Vec<Vec<u8>>) -> bool {
     hasher.update(&result);
     let result = hasher.finalize();
     stack.push(result.to_vec());
-    true
+    Ok(())
 }
-pub fn op_hash256(stack: &mut Vec<Vec<u8>>) -> bool {
-    if stack.is_empty() {
-        return false;
-    }
-    let element = stack.pop().unwrap();
+pub fn op_hash256(stack: &mut Vec<Vec<u8>>) -> Result<(), ScriptErrorKind> {
+    let element = pop(stack)?;
     let mut hasher = Sha256::new();
     hasher.update(&element);
     let result = hasher.finalize();
@@ -953,151 +825,331 @@ pub fn op_hash256(stack: &mut Vec<Vec<u8>>) -> bool {
     hasher.update(&result);
     let result = hasher.finalize();
     stack.push(result.to_vec());
-    true
+    Ok(())
 }
-pub fn op_codeseparator(stack: &mut Vec<Vec<u8>>) -> bool {
-    println!("{}", stack.len());
-    panic!("op_codeseparator not implemented")
+// only moves the start of the signed script code, tracked by the interpreter
+pub fn op_codeseparator(_stack: &mut Vec<Vec<u8>>) -> Result<(), ScriptErrorKind> {
+    Ok(())
 }
-pub fn op_checksig(stack: &mut Vec<Vec<u8>>, z: &BigUint) -> bool {
-    if stack.len() < 2 {
+// BIP66 strict DER: 0x30 [total-length] 0x02 [R-length] [R] 0x02 [S-length] [S] [sighash]
+pub fn is_valid_signature_encoding(sig: &[u8]) -> bool {
+    if sig.len() < 9 || sig.len() > 73 {
+        return false;
+    }
+    if sig[0] != 0x30 || sig[1] as usize != sig.len() - 3 {
+        return false;
+    }
+    let len_r = sig[3] as usize;
+    if 5 + len_r >= sig.len() {
         return false;
     }
+    let len_s = sig[5 + len_r] as usize;
+    if len_r + len_s + 7 != sig.len() {
+        return false;
+    }
+    // R and S are positive integers without unnecessary leading zero bytes
+    if sig[2] != 0x02 || len_r == 0 || sig[4] & 0x80 != 0 {
+        return false;
+    }
+    if len_r > 1 && sig[4] == 0x00 && sig[5] & 0x80 == 0 {
+        return false;
+    }
+    if sig[len_r + 4] != 0x02 || len_s == 0 || sig[len_r + 6] & 0x80 != 0 {
+        return false;
+    }
+    if len_s > 1 && sig[len_r + 6] == 0x00 && sig[len_r + 7] & 0x80 == 0 {
+        return false;
+    }
+    true
+}
+fn check_signature_encoding(sig: &[u8], flags: VerifyFlags) -> Result<(), ScriptErrorKind> {
+    let strict = VerifyFlags::DERSIG | VerifyFlags::LOW_S | VerifyFlags::STRICTENC;
+    if flags.bits() & strict.bits() != 0 && !is_valid_signature_encoding(sig) {
+        return Err(ScriptErrorKind::SigInvalid);
+    }
+    if flags.contains(VerifyFlags::LOW_S) {
+        let signature = Signature::parse(&sig[..sig.len() - 1].to_vec()).map_err(|_| ScriptErrorKind::SigInvalid)?;
+        let n = secp256k1::Secp256k1::new().n;
+        if signature.s() > &(n / 2u32) {
+            return Err(ScriptErrorKind::SigHighS);
+        }
+    }
+    if flags.contains(VerifyFlags::STRICTENC) {
+        let hash_type = sig[sig.len() - 1] & !SIGHASH_ANYONECANPAY;
+        if !(SIGHASH_ALL..=SIGHASH_SINGLE).contains(&hash_type) {
+            return Err(ScriptErrorKind::SigHashType);
+        }
+    }
+    Ok(())
+}
+fn check_pubkey_encoding(sec: &[u8], flags: VerifyFlags) -> Result<(), ScriptErrorKind> {
+    if flags.contains(VerifyFlags::STRICTENC) {
+        let valid = match sec.first() {
+            Some(0x02 | 0x03) => sec.len() == 33,
+            Some(0x04) => sec.len() == 65,
+            _ => false,
+        };
+        if !valid {
+            return Err(ScriptErrorKind::PubKeyType);
+        }
+    }
+    // only set for witness v0 scripts
+    if flags.contains(VerifyFlags::WITNESS_PUBKEYTYPE) && !(sec.len() == 33 && matches!(sec[0], 0x02 | 0x03)) {
+        return Err(ScriptErrorKind::WitnessPubKeyType);
+    }
+    Ok(())
+}
+/// Computes the digest a signature commits to, given the signature (its last
+/// byte is the hash type) and all the signatures of the operation, which legacy
+/// scripts remove from the signed script code.
+pub type SigHash<'a> = dyn Fn(&[u8], &[Vec<u8>]) -> BigUint + 'a;
+
+// an empty signature or an unparsable pubkey or signature make the check fail,
+// the encoding rules enabled by the flags fail the whole script
+fn check_sig(sec: &[u8], der: &[u8], sig_hash: &SigHash, signatures: &[Vec<u8>], flags: VerifyFlags) -> Result<bool, ScriptErrorKind> {
+    // the pubkey encoding is checked even without a signature
+    if der.is_empty() {
+        check_pubkey_encoding(sec, flags)?;
+        return Ok(false);
+    }
+    check_signature_encoding(der, flags)?;
+    check_pubkey_encoding(sec, flags)?;
+    let z = sig_hash(der, signatures);
+    // take off the last byte of the signature as that's the hash_type
+    let der = der[..der.len() - 1].to_vec();
+    let signature = match Signature::parse(&der) {
+        Ok(signature) => signature,
+        Err(_) => return Ok(false),
+    };
+    match Point::parse(sec) {
+        Ok(point) => Ok(point.verify(&z, &signature)),
+        Err(_) => Ok(false),
+    }
+}
+/// BIP341 signature check: 64 bytes signing with SIGHASH_DEFAULT, or 65 with the
+/// hash type last. `sig_hash` gives the digest of a hash type, None if not defined.
+pub fn check_schnorr_sig(pubkey: &[u8], sig: &[u8], sig_hash: &dyn Fn(u8) -> Option<[u8; 32]>) -> Result<(), ScriptErrorKind> {
+    let (sig, hash_type) = match sig.len() {
+        64 => (sig, SIGHASH_DEFAULT),
+        // the default hash type can't be explicit
+        65 if sig[64] != SIGHASH_DEFAULT => (&sig[..64], sig[64]),
+        65 => return Err(ScriptErrorKind::SchnorrSigHashType),
+        _ => return Err(ScriptErrorKind::SchnorrSigSize),
+    };
+    let msg = sig_hash(hash_type).ok_or(ScriptErrorKind::SchnorrSigHashType)?;
+    match Point::lift_x(pubkey) {
+        Ok(point) if point.verify_schnorr(&msg, sig) => Ok(()),
+        _ => Err(ScriptErrorKind::SchnorrSig),
+    }
+}
+// every non-empty tapscript signature consumes this much of the input's validation weight
+pub const VALIDATION_WEIGHT_PER_SIGOP_PASSED: i64 = 50;
+
+// BIP342: an empty signature makes the check fail, any other must be valid.
+// 32 byte keys are BIP340 keys, other lengths are kept for upgrades and pass
+fn check_tapscript_sig(pubkey: &[u8], sig: &[u8], sig_hash: &dyn Fn(u8) -> Option<[u8; 32]>, validation_weight: &mut i64, flags: VerifyFlags) -> Result<bool, ScriptErrorKind> {
+    let success = !sig.is_empty();
+    if success {
+        *validation_weight -= VALIDATION_WEIGHT_PER_SIGOP_PASSED;
+        if *validation_weight < 0 {
+            return Err(ScriptErrorKind::TapscriptValidationWeight);
+        }
+    }
+    match pubkey.len() {
+        0 => Err(ScriptErrorKind::PubKeyType),
+        32 if success => check_schnorr_sig(pubkey, sig, sig_hash).map(|_| true),
+        32 => Ok(false),
+        _ if flags.contains(VerifyFlags::DISCOURAGE_UPGRADABLE_PUBKEYTYPE) => Err(ScriptErrorKind::DiscourageUpgradablePubKeyType),
+        _ => Ok(success),
+    }
+}
+pub fn op_checksig_tapscript(stack: &mut Vec<Vec<u8>>, sig_hash: &dyn Fn(u8) -> Option<[u8; 32]>, validation_weight: &mut i64, flags: VerifyFlags) -> Result<(), ScriptErrorKind> {
+    require(stack, 2)?;
+    let pubkey = stack.pop().unwrap();
+    let sig = stack.pop().unwrap();
+    let result = if check_tapscript_sig(&pubkey, &sig, sig_hash, validation_weight, flags)? { 1 } else { 0 };
+    stack.push(encode_num(result));
+    Ok(())
+}
+pub fn op_checksigverify_tapscript(stack: &mut Vec<Vec<u8>>, sig_hash: &dyn Fn(u8) -> Option<[u8; 32]>, validation_weight: &mut i64, flags: VerifyFlags) -> Result<(), ScriptErrorKind> {
+    op_checksig_tapscript(stack, sig_hash, validation_weight, flags)?;
+    op_verify(stack).map_err(|_| ScriptErrorKind::CheckSigVerifyFailed)
+}
+// <sig> <n> <pubkey> OP_CHECKSIGADD leaves n + 1 for a valid signature, n for an empty one
+pub fn op_checksigadd(stack: &mut Vec<Vec<u8>>, sig_hash: &dyn Fn(u8) -> Option<[u8; 32]>, validation_weight: &mut i64, flags: VerifyFlags) -> Result<(), ScriptErrorKind> {
+    require(stack, 3)?;
+    let pubkey = stack.pop().unwrap();
+    let n = pop_num(stack)?;
+    let sig = stack.pop().unwrap();
+    let result = if check_tapscript_sig(&pubkey, &sig, sig_hash, validation_weight, flags)? { n + 1 } else { n };
+    stack.push(encode_num(result));
+    Ok(())
+}
+pub fn op_checksig(stack: &mut Vec<Vec<u8>>, sig_hash: &SigHash, flags: VerifyFlags) -> Result<(), ScriptErrorKind> {
+    require(stack, 2)?;
     // the top element of the stack is the SEC pubkey
     // the top element is the last added
     // sec is the last added, pop takes the last!
     let sec = stack.pop().unwrap();
     // the next element of the stack is the DER signature
-    let mut der = stack.pop().unwrap();
-
-    // take off the last byte of the signature as that's the hash_type
-    der.pop();
+    let der = stack.pop().unwrap();
 
-    let point1 = Point::parse(&sec);
-    let point2 = Signature::parse(&der);
-
-    if point1.verify(z, &point2.unwrap()) == true {
-        stack.push(encode_num(1));
-    } else {
-        stack.push(encode_num(0));
+    let valid = check_sig(&sec, &der, sig_hash, std::slice::from_ref(&der), flags)?;
+    if !valid && flags.contains(VerifyFlags::NULLFAIL) && !der.is_empty() {
+        return Err(ScriptErrorKind::SigNullFail);
     }
-    true
+    stack.push(encode_num(if valid { 1 } else { 0 }));
+    Ok(())
 }
-pub fn op_checksigverify(stack: &mut Vec<Vec<u8>>, z: &BigUint) -> bool {
-    op_checksig(stack, z) && op_verify(stack)
+pub fn op_checksigverify(stack: &mut Vec<Vec<u8>>, sig_hash: &SigHash, flags: VerifyFlags) -> Result<(), ScriptErrorKind> {
+    op_checksig(stack, sig_hash, flags)?;
+    op_verify(stack).map_err(|_| ScriptErrorKind::CheckSigVerifyFailed)
 }
-pub fn op_checkmultisig(stack: &mut Vec<Vec<u8>>, z: &BigUint) -> bool {
-    if stack.len() < 1 {
-        return false;
-    }
+pub fn op_checkmultisig(stack: &mut Vec<Vec<u8>>, sig_hash: &SigHash, flags: VerifyFlags) -> Result<(), ScriptErrorKind> {
     // m of n : m signatures oof n pub keys
-    let n = decode_num(&stack.pop().unwrap()) as usize;
-    if stack.len() < (n + 1) {
-        return false;
+    let n = pop_num(stack)?;
+    if !(0..=20).contains(&n) {
+        return Err(ScriptErrorKind::PubKeyCount);
     }
+    require(stack, n as usize + 1)?;
     let mut sec_pubkeys: Vec<Vec<u8>> = vec![];
     for _ in 0..n {
         sec_pubkeys.push(stack.pop().unwrap())
     }
 
-    let m = decode_num(&stack.pop().unwrap()) as usize;
-    if stack.len() < (m + 1) {
-        return false;
+    let m = pop_num(stack)?;
+    if m < 0 || m > n {
+        return Err(ScriptErrorKind::SigCount);
     }
+    // one extra element for the OP_CHECKMULTISIG bug
+    require(stack, m as usize + 1)?;
     let mut der_signatures: Vec<Vec<u8>> = vec![];
     for _ in 0..m {
-        let mut der_signature = stack.pop().unwrap();
-        der_signature.pop(); // der signature is assumed to be signed with SIGHASH_ALL
-        der_signatures.push(der_signature);
-    }
-
-    // OP_CHECKMULTISIG bug
-    stack.pop();
-
-    let mut points: Vec<Point> = vec![];
-    let mut sigs: Vec<Signature> = vec![];
-
-    for sec_pubkey in sec_pubkeys {
-        points.push(Point::parse(sec_pubkey.as_slice()));
-    }
-    for der in der_signatures {
-        sigs.push(Signature::parse(&der).unwrap());
+        der_signatures.push(stack.pop().unwrap());
     }
 
-    for sig in sigs {
-        // if we have no more points, signatures are no good
-        if points.len() == 0 {
-            return false;
+    // signatures must be in the same order as the pubkeys, each pubkey is tried
+    // once and the check stops when too few pubkeys are left
+    let (mut sig_index, mut key_index) = (0, 0);
+    let mut valid = true;
+    while valid && sig_index < der_signatures.len() {
+        if check_sig(&sec_pubkeys[key_index], &der_signatures[sig_index], sig_hash, &der_signatures, flags)? {
+            sig_index += 1;
         }
-        while points.len() > 0 {
-            let point = points.remove(0);
-            if point.verify(z, &sig) {
-                break
-            } else { println!("not verfied"); }
+        key_index += 1;
+        if der_signatures.len() - sig_index > sec_pubkeys.len() - key_index {
+            valid = false;
         }
     }
-    stack.push(encode_num(1));
-    true
-}
-pub fn op_checkmultisigverify(stack: &mut Vec<Vec<u8>>, z: &BigUint) -> bool {
-    op_checkmultisig(stack, z) && op_verify(stack)
-}
-pub fn op_noop1(_stack: &mut Vec<Vec<u8>>) -> bool {
-    panic!("op_noop1 not implemented")
-}
-pub fn op_checklocktimeverify(stack: &mut Vec<Vec<u8>>, locktime: u32, sequence: u32) -> bool {
-    if sequence == 0xffffffff {
-        return false;
-    }
-    if stack.len() < 1 {
-        return false;
-    }
-    let element = decode_num(stack.last().unwrap());
-    if element < 0 {
-        return false;
+    if !valid && flags.contains(VerifyFlags::NULLFAIL) && der_signatures.iter().any(|der| !der.is_empty()) {
+        return Err(ScriptErrorKind::SigNullFail);
     }
-    if element < 500_000_000 && locktime > 500_000_000 {
-        return false;
-    }
-    if locktime < element as u32 {
-        return false;
-    }
-    true
-}
-pub fn op_checksequenceverify(stack: &mut Vec<Vec<u8>>, version: u32, sequence: u32) -> bool {
-    if sequence & (1 << 31) == (1 << 31) {
-        return false;
-    }
-    if stack.len() < 1 {
-        return false;
-    }
-    let element = decode_num(stack.last().unwrap());
-    if element < 0 {
-        return false;
-    }
-    if element as u32 & (1 << 31) == (1 << 31) {
-        if version < 2 {
-            return false;
-        } else if sequence & (1 << 31) == (1 << 31) {
-            return false;
-        } else if element as u32 & (1 << 22) != sequence & (1 << 22) {
-            return false;
-        } else if element as u32 & 0xffff > sequence & 0xffff {
-            return false;
-        }
+
+    // OP_CHECKMULTISIG bug
+    let dummy = stack.pop().unwrap();
+    if flags.contains(VerifyFlags::NULLDUMMY) && !dummy.is_empty() {
+        return Err(ScriptErrorKind::SigNullDummy);
     }
-    true
+    let result = if valid { 1 } else { 0 };
+    stack.push(encode_num(result));
+    Ok(())
+}
+/// The signatures an OP_CHECKMULTISIG would check on this stack, None when its
+/// counts are not valid or the stack is too small.
+pub fn multisig_signatures(stack: &[Vec<u8>]) -> Option<Vec<Vec<u8>>> {
+    let mut stack = stack.to_vec();
+    let n = pop_num(&mut stack).ok()?;
+    if !(0..=20).contains(&n) || stack.len() < n as usize + 1 {
+        return None;
+    }
+    stack.truncate(stack.len() - n as usize);
+    let m = pop_num(&mut stack).ok()?;
+    if m < 0 || m > n || stack.len() < m as usize + 1 {
+        return None;
+    }
+    Some(stack.split_off(stack.len() - m as usize))
+}
+pub fn op_checkmultisigverify(stack: &mut Vec<Vec<u8>>, sig_hash: &SigHash, flags: VerifyFlags) -> Result<(), ScriptErrorKind> {
+    op_checkmultisig(stack, sig_hash, flags)?;
+    op_verify(stack).map_err(|_| ScriptErrorKind::CheckMultiSigVerifyFailed)
+}
+pub fn op_noop1(_stack: &mut Vec<Vec<u8>>) -> Result<(), ScriptErrorKind> {
+    Ok(())
+}
+// lock times are read as 5 byte numbers, they can use the full u32 range
+fn top_locktime(stack: &[Vec<u8>]) -> Result<i64, ScriptErrorKind> {
+    let element = stack.last().ok_or(ScriptErrorKind::StackUnderflow)?;
+    if element.len() > 5 {
+        return Err(ScriptErrorKind::InvalidNumber);
+    }
+    let locktime = decode_num(element);
+    if locktime < 0 {
+        return Err(ScriptErrorKind::NegativeLocktime);
+    }
+    Ok(locktime)
+}
+// BIP65, the element is left on the stack
+pub fn op_checklocktimeverify(stack: &[Vec<u8>], context: Option<&TxContext>) -> Result<(), ScriptErrorKind> {
+    let locktime = top_locktime(stack)?;
+    let context = context.ok_or(ScriptErrorKind::UnsatisfiedLocktime)?;
+    let tx_locktime = context.locktime() as i64;
+    // both block heights or both timestamps
+    if (locktime < LOCKTIME_THRESHOLD) != (tx_locktime < LOCKTIME_THRESHOLD) {
+        return Err(ScriptErrorKind::UnsatisfiedLocktime);
+    }
+    if locktime > tx_locktime {
+        return Err(ScriptErrorKind::UnsatisfiedLocktime);
+    }
+    // a final input disables the transaction locktime
+    if context.sequence() == SEQUENCE_FINAL {
+        return Err(ScriptErrorKind::UnsatisfiedLocktime);
+    }
+    Ok(())
+}
+// BIP112 with the BIP68 relative lock encoding, the element is left on the stack
+pub fn op_checksequenceverify(stack: &[Vec<u8>], context: Option<&TxContext>) -> Result<(), ScriptErrorKind> {
+    let sequence = top_locktime(stack)?;
+    // with the disable flag set the opcode behaves as a NOP
+    if sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG as i64 != 0 {
+        return Ok(());
+    }
+    let context = context.ok_or(ScriptErrorKind::UnsatisfiedLocktime)?;
+    let tx_sequence = context.sequence();
+    // relative lock times need version 2 and are not enforced on inputs with the disable flag
+    if context.version() < 2 || tx_sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG != 0 {
+        return Err(ScriptErrorKind::UnsatisfiedLocktime);
+    }
+    let mask = SEQUENCE_LOCKTIME_TYPE_FLAG | SEQUENCE_LOCKTIME_MASK;
+    let sequence = sequence as u32 & mask;
+    let tx_sequence = tx_sequence & mask;
+    // both blocks or both 512 second units
+    if (sequence & SEQUENCE_LOCKTIME_TYPE_FLAG) != (tx_sequence & SEQUENCE_LOCKTIME_TYPE_FLAG) {
+        return Err(ScriptErrorKind::UnsatisfiedLocktime);
+    }
+    if sequence > tx_sequence {
+        return Err(ScriptErrorKind::UnsatisfiedLocktime);
+    }
+    Ok(())
 }
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::script::Script;
+    use crate::tx::Tx;
+    use crate::network::Network;
+    use crate::tx_input::TxInput;
+    use crate::tx_output::TxOutput;
     use num::Num;
     #[test]
     fn test_op_hash160() {
         let mut stack: Vec<Vec<u8>> = vec![b"hello world".to_vec()];
-        assert_eq!(op_hash160(&mut stack), true);
+        assert!(op_hash160(&mut stack).is_ok());
         assert_eq!(hex::encode(stack[0].clone()), "d7d5ee7824ff93f94c3055af9382c86c68b5ca92");
     }
+    // signatures checked against a digest fixed in advance
+    fn fixed(z: &BigUint) -> impl Fn(&[u8], &[Vec<u8>]) -> BigUint + '_ {
+        move |_, _| z.clone()
+    }
     #[test]
     fn test_op_checksig() {
 
@@ -1105,7 +1157,7 @@ mod tests {
         let sec = hex::decode("04887387e452b8eacc4acfde10d9aaf7f6d9a0f975aabb10d006e4da568744d06c61de6d95231cd89026e286df3b6ae4a894a3378e393e93a0f45b666329a0ae34").unwrap();
         let sig = hex::decode("3045022000eff69ef2b1bd93a66ed5219add4fb51e11a840f404876325a1e8ffe0529a2c022100c7207fee197d27c618aea621406f6bf5ef6fca38681d82b2f06fddbdce6feab601").unwrap();
         let mut stack: Vec<Vec<u8>> = vec![sig, sec];
-        assert_eq!(op_checksig(&mut stack, &z), true);
+        assert!(op_checksig(&mut stack, &fixed(&z), VerifyFlags::CONSENSUS).is_ok());
         assert_eq!(decode_num(stack[0].as_slice()), 1);
     }
     #[test]
@@ -1123,7 +1175,178 @@ mod tests {
         stack.push(sec1);
         stack.push(sec2);
         stack.push(b"\x02".to_vec());
-        assert_eq!(op_checkmultisig(&mut stack, &z), true);
+        assert!(op_checkmultisig(&mut stack, &fixed(&z), VerifyFlags::CONSENSUS).is_ok());
         assert_eq!(decode_num(stack[0].as_slice()), 1);
     }
-}
\ No newline at end of file
+    #[test]
+    fn test_op_checkmultisig_wrong_order() {
+        let z = BigUint::from_str_radix("e71bfa115715d6fd33796948126f40a8cdd39f187e4afb03896795189fe1423c", 16).unwrap();
+        let sig1 = hex::decode("3045022100dc92655fe37036f47756db8102e0d7d5e28b3beb83a8fef4f5dc0559bddfb94e02205a36d4e4e6c7fcd16658c50783e00c341609977aed3ad00937bf4ee942a8993701").unwrap();
+        let sig2 = hex::decode("3045022100da6bee3c93766232079a01639d07fa869598749729ae323eab8eef53577d611b02207bef15429dcadce2121ea07f233115c6f09034c0be68db99980b9a6c5e75402201").unwrap();
+        let sec1 = hex::decode("022626e955ea6ea6d98850c994f9107b036b1334f18ca8830bfff1295d21cfdb70").unwrap();
+        let sec2 = hex::decode("03b287eaf122eea69030a0e9feed096bed8045c8b98bec453e1ffac7fbdbd4bb71").unwrap();
+        let mut stack: Vec<Vec<u8>> = vec![b"".to_vec(), sig2, sig1, b"\x02".to_vec(), sec1, sec2, b"\x02".to_vec()];
+        assert!(op_checkmultisig(&mut stack, &fixed(&z), VerifyFlags::CONSENSUS).is_ok());
+        assert_eq!(stack, vec![encode_num(0)]);
+
+        let mut stack: Vec<Vec<u8>> = vec![b"\x03".to_vec(), b"\x15".to_vec()];
+        assert_eq!(op_checkmultisig(&mut stack, &fixed(&z), VerifyFlags::CONSENSUS), Err(ScriptErrorKind::PubKeyCount));
+        let mut stack: Vec<Vec<u8>> = vec![vec![0x30, 0x01], b"\x01".to_vec(), vec![0x02; 33], b"\x01".to_vec()];
+        assert_eq!(op_checkmultisig(&mut stack, &fixed(&z), VerifyFlags::CONSENSUS), Err(ScriptErrorKind::StackUnderflow));
+    }
+    #[test]
+    fn test_op_checksig_errors() {
+        let z = BigUint::from(1u32);
+        let sec = hex::decode("022626e955ea6ea6d98850c994f9107b036b1334f18ca8830bfff1295d21cfdb70").unwrap();
+        // an empty signature just fails the check
+        let mut stack: Vec<Vec<u8>> = vec![vec![], sec.clone()];
+        assert!(op_checksig(&mut stack, &fixed(&z), VerifyFlags::CONSENSUS).is_ok());
+        assert_eq!(stack, vec![encode_num(0)]);
+        // a signature that is not DER fails the script
+        let mut stack: Vec<Vec<u8>> = vec![vec![0x31, 0x00, 0x01], sec];
+        assert_eq!(op_checksig(&mut stack, &fixed(&z), VerifyFlags::CONSENSUS), Err(ScriptErrorKind::SigInvalid));
+        let mut stack: Vec<Vec<u8>> = vec![vec![]];
+        assert_eq!(op_checksig(&mut stack, &fixed(&z), VerifyFlags::CONSENSUS), Err(ScriptErrorKind::StackUnderflow));
+    }
+    #[test]
+    fn test_operand_order() {
+        // 5 3 OP_SUB -> 2
+        let mut stack = vec![encode_num(5), encode_num(3)];
+        assert!(op_sub(&mut stack).is_ok());
+        assert_eq!(stack, vec![encode_num(2)]);
+        // 2 3 OP_LESSTHAN -> 1
+        let mut stack = vec![encode_num(2), encode_num(3)];
+        assert!(op_lessthan(&mut stack).is_ok());
+        assert_eq!(stack, vec![encode_num(1)]);
+        // 3 2 5 OP_WITHIN -> 1
+        let mut stack = vec![encode_num(3), encode_num(2), encode_num(5)];
+        assert!(op_within(&mut stack).is_ok());
+        assert_eq!(stack, vec![encode_num(1)]);
+        // a b c 0 OP_PICK -> a b c c, a b c 2 OP_ROLL -> b c a
+        let mut stack = vec![vec![1], vec![2], vec![3], encode_num(0)];
+        assert!(op_pick(&mut stack).is_ok());
+        assert_eq!(stack, vec![vec![1], vec![2], vec![3], vec![3]]);
+        let mut stack = vec![vec![1], vec![2], vec![3], encode_num(2)];
+        assert!(op_roll(&mut stack).is_ok());
+        assert_eq!(stack, vec![vec![2], vec![3], vec![1]]);
+        let mut stack = vec![vec![1], encode_num(1)];
+        assert_eq!(op_pick(&mut stack), Err(ScriptErrorKind::StackUnderflow));
+        // x1 x2 x3 x4 OP_2SWAP -> x3 x4 x1 x2
+        let mut stack = vec![vec![1], vec![2], vec![3], vec![4]];
+        assert!(op_2swap(&mut stack).is_ok());
+        assert_eq!(stack, vec![vec![3], vec![4], vec![1], vec![2]]);
+    }
+    #[test]
+    fn test_number_overflow() {
+        let mut stack = vec![vec![0xff; 5], encode_num(1)];
+        assert_eq!(op_add(&mut stack), Err(ScriptErrorKind::InvalidNumber));
+        // 4 byte operands may produce a 5 byte result
+        let mut stack = vec![encode_num(0x7fffffff), encode_num(0x7fffffff)];
+        assert!(op_add(&mut stack).is_ok());
+        assert_eq!(decode_num(&stack[0]), 0xfffffffe);
+    }
+    #[test]
+    fn test_signature_flags() {
+        let z = BigUint::from_str_radix("7c076ff316692a3d7eb3c3bb0f8b1488cf72e1afcd929e29307032997a838a3d", 16).unwrap();
+        let sec = hex::decode("04887387e452b8eacc4acfde10d9aaf7f6d9a0f975aabb10d006e4da568744d06c61de6d95231cd89026e286df3b6ae4a894a3378e393e93a0f45b666329a0ae34").unwrap();
+        let sig = hex::decode("3045022000eff69ef2b1bd93a66ed5219add4fb51e11a840f404876325a1e8ffe0529a2c022100c7207fee197d27c618aea621406f6bf5ef6fca38681d82b2f06fddbdce6feab601").unwrap();
+        assert!(is_valid_signature_encoding(&sig));
+        // S is in the upper half of the curve order
+        let mut stack: Vec<Vec<u8>> = vec![sig.clone(), sec.clone()];
+        assert_eq!(op_checksig(&mut stack, &fixed(&z), VerifyFlags::LOW_S), Err(ScriptErrorKind::SigHighS));
+        // undefined hash type
+        let mut bad_hash_type = sig.clone();
+        *bad_hash_type.last_mut().unwrap() = 0x05;
+        let mut stack: Vec<Vec<u8>> = vec![bad_hash_type.clone(), sec.clone()];
+        assert!(op_checksig(&mut stack, &fixed(&z), VerifyFlags::CONSENSUS).is_ok());
+        let mut stack: Vec<Vec<u8>> = vec![bad_hash_type, sec.clone()];
+        assert_eq!(op_checksig(&mut stack, &fixed(&z), VerifyFlags::STRICTENC), Err(ScriptErrorKind::SigHashType));
+        // hybrid pubkey
+        let mut hybrid = sec.clone();
+        hybrid[0] = 0x06;
+        let mut stack: Vec<Vec<u8>> = vec![sig.clone(), hybrid];
+        assert_eq!(op_checksig(&mut stack, &fixed(&z), VerifyFlags::STRICTENC), Err(ScriptErrorKind::PubKeyType));
+        // even when there is no signature to check
+        let mut stack: Vec<Vec<u8>> = vec![vec![], vec![0x01, 0x02, 0x03, 0x04, 0x05]];
+        assert_eq!(op_checksig(&mut stack, &fixed(&z), VerifyFlags::STRICTENC), Err(ScriptErrorKind::PubKeyType));
+        // a padded R is not strict DER, without DERSIG the check just fails
+        let mut padded = hex::decode("3046022100").unwrap();
+        padded.extend(&sig[4..]);
+        assert!(!is_valid_signature_encoding(&padded));
+        let mut stack: Vec<Vec<u8>> = vec![padded.clone(), sec.clone()];
+        assert_eq!(op_checksig(&mut stack, &fixed(&z), VerifyFlags::DERSIG), Err(ScriptErrorKind::SigInvalid));
+        let mut stack: Vec<Vec<u8>> = vec![vec![0x30, 0x01], sec];
+        assert!(op_checksig(&mut stack, &fixed(&z), VerifyFlags::NONE).is_ok());
+        assert_eq!(stack, vec![encode_num(0)]);
+    }
+    #[test]
+    fn test_nulldummy() {
+        let z = BigUint::from(1u32);
+        let mut stack: Vec<Vec<u8>> = vec![vec![0x01], encode_num(0), encode_num(0)];
+        assert_eq!(op_checkmultisig(&mut stack, &fixed(&z), VerifyFlags::NULLDUMMY), Err(ScriptErrorKind::SigNullDummy));
+        let mut stack: Vec<Vec<u8>> = vec![vec![0x01], encode_num(0), encode_num(0)];
+        assert!(op_checkmultisig(&mut stack, &fixed(&z), VerifyFlags::NONE).is_ok());
+        assert_eq!(stack, vec![encode_num(1)]);
+    }
+    #[test]
+    fn test_is_minimal_num() {
+        assert!(is_minimal_num(&[]));
+        assert!(is_minimal_num(&encode_num(-1)));
+        assert!(is_minimal_num(&encode_num(0x80)));
+        assert!(!is_minimal_num(&[0x00]));
+        assert!(!is_minimal_num(&[0x01, 0x00]));
+        assert!(!is_minimal_num(&[0x80]));
+    }
+    fn tx_context(tx: &Tx) -> TxContext<'_> {
+        TxContext::new(tx, 0, TxOutput::new(0, Script::new(vec![])))
+    }
+    fn spending_tx(version: u32, locktime: u32, sequence: u32) -> Tx {
+        let tx_in = TxInput::new(vec![0u8; 32], 0, Script::new(vec![]), sequence);
+        Tx::new(version, vec![tx_in], vec![], locktime, Network::Mainnet, false)
+    }
+    #[test]
+    fn test_op_checklocktimeverify() {
+        let tx = spending_tx(1, 500, 0xfffffffe);
+        let context = tx_context(&tx);
+        assert!(op_checklocktimeverify(&[encode_num(500)], Some(&context)).is_ok());
+        assert_eq!(op_checklocktimeverify(&[encode_num(501)], Some(&context)), Err(ScriptErrorKind::UnsatisfiedLocktime));
+        assert_eq!(op_checklocktimeverify(&[encode_num(-1)], Some(&context)), Err(ScriptErrorKind::NegativeLocktime));
+        assert_eq!(op_checklocktimeverify(&[], Some(&context)), Err(ScriptErrorKind::StackUnderflow));
+        assert_eq!(op_checklocktimeverify(&[encode_num(500)], None), Err(ScriptErrorKind::UnsatisfiedLocktime));
+        // a timestamp can't be satisfied by a block height
+        assert_eq!(op_checklocktimeverify(&[encode_num(LOCKTIME_THRESHOLD)], Some(&context)), Err(ScriptErrorKind::UnsatisfiedLocktime));
+        // 5 byte locktimes are allowed
+        let tx = spending_tx(1, 0xffffffff, 0);
+        let context = tx_context(&tx);
+        assert!(op_checklocktimeverify(&[encode_num(0xffffffff)], Some(&context)).is_ok());
+        // a final input disables the locktime
+        let tx = spending_tx(1, 500, SEQUENCE_FINAL);
+        let context = tx_context(&tx);
+        assert_eq!(op_checklocktimeverify(&[encode_num(100)], Some(&context)), Err(ScriptErrorKind::UnsatisfiedLocktime));
+    }
+    #[test]
+    fn test_op_checksequenceverify() {
+        let tx = spending_tx(2, 0, 10);
+        let context = tx_context(&tx);
+        assert!(op_checksequenceverify(&[encode_num(10)], Some(&context)).is_ok());
+        assert_eq!(op_checksequenceverify(&[encode_num(11)], Some(&context)), Err(ScriptErrorKind::UnsatisfiedLocktime));
+        assert_eq!(op_checksequenceverify(&[encode_num(-1)], Some(&context)), Err(ScriptErrorKind::NegativeLocktime));
+        // blocks against 512 second units
+        let time_lock = (SEQUENCE_LOCKTIME_TYPE_FLAG | 1) as i64;
+        assert_eq!(op_checksequenceverify(&[encode_num(time_lock)], Some(&context)), Err(ScriptErrorKind::UnsatisfiedLocktime));
+        // bits outside the mask are ignored
+        assert!(op_checksequenceverify(&[encode_num(1 << 16 | 10)], Some(&context)).is_ok());
+        // the disable flag in the script makes it a NOP, even without a context
+        let disabled = SEQUENCE_LOCKTIME_DISABLE_FLAG as i64;
+        assert!(op_checksequenceverify(&[encode_num(disabled)], None).is_ok());
+        assert_eq!(op_checksequenceverify(&[encode_num(10)], None), Err(ScriptErrorKind::UnsatisfiedLocktime));
+        // relative locks need version 2
+        let tx = spending_tx(1, 0, 10);
+        let context = tx_context(&tx);
+        assert_eq!(op_checksequenceverify(&[encode_num(10)], Some(&context)), Err(ScriptErrorKind::UnsatisfiedLocktime));
+        // and an input without the disable flag
+        let tx = spending_tx(2, 0, SEQUENCE_LOCKTIME_DISABLE_FLAG | 10);
+        let context = tx_context(&tx);
+        assert_eq!(op_checksequenceverify(&[encode_num(10)], Some(&context)), Err(ScriptErrorKind::UnsatisfiedLocktime));
+    }
+}
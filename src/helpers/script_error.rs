@@ -0,0 +1,199 @@
+use std::fmt;
+use serde_json::json;
+use crate::helpers::verify_flags::VerifyFlags;
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum ScriptErrorKind {
+    EvalFalse,
+    OpReturn,
+    BadOpcode,
+    DisabledOpcode,
+    PushSize,
+    ScriptSize,
+    OpCount,
+    StackSize,
+    InvalidNumber,
+    NonMinimalNumber,
+    StackUnderflow,
+    AltStackUnderflow,
+    UnbalancedConditional,
+    VerifyFailed,
+    EqualVerifyFailed,
+    NumEqualVerifyFailed,
+    CheckSigVerifyFailed,
+    CheckMultiSigVerifyFailed,
+    SigInvalid,
+    SigHighS,
+    SigHashType,
+    SigNullDummy,
+    SigPushOnly,
+    SigNullFail,
+    SigFindAndDelete,
+    PubKeyType,
+    WitnessPubKeyType,
+    MinimalData,
+    CleanStack,
+    MinimalIf,
+    OpCodeSeparator,
+    DiscourageUpgradableNops,
+    DiscourageUpgradableWitnessProgram,
+    DiscourageUpgradableTaprootVersion,
+    DiscourageOpSuccess,
+    DiscourageUpgradablePubKeyType,
+    SigCount,
+    PubKeyCount,
+    NegativeLocktime,
+    UnsatisfiedLocktime,
+    WitnessProgramWrongLength,
+    WitnessProgramMismatch,
+    WitnessProgramWitnessEmpty,
+    WitnessMalleated,
+    WitnessMalleatedP2sh,
+    WitnessUnexpected,
+    SchnorrSigSize,
+    SchnorrSigHashType,
+    SchnorrSig,
+    TaprootWrongControlSize,
+    TapscriptValidationWeight,
+    TapscriptCheckMultiSig,
+    TapscriptMinimalIf,
+    UnknownPrevout,
+}
+impl ScriptErrorKind {
+    /// The verification flag that enables the rule this error breaks, if any.
+    pub fn flag(&self) -> Option<VerifyFlags> {
+        match self {
+            ScriptErrorKind::SigInvalid => Some(VerifyFlags::DERSIG),
+            ScriptErrorKind::SigHighS => Some(VerifyFlags::LOW_S),
+            ScriptErrorKind::SigHashType | ScriptErrorKind::PubKeyType => Some(VerifyFlags::STRICTENC),
+            ScriptErrorKind::SigNullDummy => Some(VerifyFlags::NULLDUMMY),
+            ScriptErrorKind::SigPushOnly => Some(VerifyFlags::P2SH),
+            ScriptErrorKind::SigNullFail => Some(VerifyFlags::NULLFAIL),
+            ScriptErrorKind::SigFindAndDelete | ScriptErrorKind::OpCodeSeparator => Some(VerifyFlags::CONST_SCRIPTCODE),
+            ScriptErrorKind::WitnessPubKeyType => Some(VerifyFlags::WITNESS_PUBKEYTYPE),
+            ScriptErrorKind::MinimalData | ScriptErrorKind::NonMinimalNumber => Some(VerifyFlags::MINIMALDATA),
+            ScriptErrorKind::CleanStack => Some(VerifyFlags::CLEANSTACK),
+            ScriptErrorKind::MinimalIf => Some(VerifyFlags::MINIMALIF),
+            ScriptErrorKind::DiscourageUpgradableNops => Some(VerifyFlags::DISCOURAGE_UPGRADABLE_NOPS),
+            ScriptErrorKind::DiscourageUpgradableWitnessProgram => Some(VerifyFlags::DISCOURAGE_UPGRADABLE_WITNESS_PROGRAM),
+            ScriptErrorKind::DiscourageUpgradableTaprootVersion => Some(VerifyFlags::DISCOURAGE_UPGRADABLE_TAPROOT_VERSION),
+            ScriptErrorKind::DiscourageOpSuccess => Some(VerifyFlags::DISCOURAGE_OP_SUCCESS),
+            ScriptErrorKind::DiscourageUpgradablePubKeyType => Some(VerifyFlags::DISCOURAGE_UPGRADABLE_PUBKEYTYPE),
+            ScriptErrorKind::WitnessProgramWrongLength
+            | ScriptErrorKind::WitnessProgramMismatch
+            | ScriptErrorKind::WitnessProgramWitnessEmpty
+            | ScriptErrorKind::WitnessMalleated
+            | ScriptErrorKind::WitnessMalleatedP2sh
+            | ScriptErrorKind::WitnessUnexpected => Some(VerifyFlags::WITNESS),
+            ScriptErrorKind::SchnorrSigSize
+            | ScriptErrorKind::SchnorrSigHashType
+            | ScriptErrorKind::SchnorrSig
+            | ScriptErrorKind::TaprootWrongControlSize
+            | ScriptErrorKind::TapscriptValidationWeight
+            | ScriptErrorKind::TapscriptCheckMultiSig
+            | ScriptErrorKind::TapscriptMinimalIf => Some(VerifyFlags::TAPROOT),
+            _ => None,
+        }
+    }
+}
+impl fmt::Display for ScriptErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let message = match self {
+            ScriptErrorKind::EvalFalse => "script evaluated without error but finished with a false/empty top stack element",
+            ScriptErrorKind::OpReturn => "OP_RETURN was encountered",
+            ScriptErrorKind::BadOpcode => "opcode missing or not understood",
+            ScriptErrorKind::DisabledOpcode => "attempted to use a disabled opcode",
+            ScriptErrorKind::PushSize => "push value size limit exceeded",
+            ScriptErrorKind::ScriptSize => "script is too big",
+            ScriptErrorKind::OpCount => "operation limit exceeded",
+            ScriptErrorKind::StackSize => "stack size limit exceeded",
+            ScriptErrorKind::InvalidNumber => "script number overflow",
+            ScriptErrorKind::NonMinimalNumber => "script number not minimally encoded",
+            ScriptErrorKind::StackUnderflow => "operation not valid with the current stack size",
+            ScriptErrorKind::AltStackUnderflow => "operation not valid with the current altstack size",
+            ScriptErrorKind::UnbalancedConditional => "invalid OP_IF construction",
+            ScriptErrorKind::VerifyFailed => "script failed an OP_VERIFY operation",
+            ScriptErrorKind::EqualVerifyFailed => "script failed an OP_EQUALVERIFY operation",
+            ScriptErrorKind::NumEqualVerifyFailed => "script failed an OP_NUMEQUALVERIFY operation",
+            ScriptErrorKind::CheckSigVerifyFailed => "script failed an OP_CHECKSIGVERIFY operation",
+            ScriptErrorKind::CheckMultiSigVerifyFailed => "script failed an OP_CHECKMULTISIGVERIFY operation",
+            ScriptErrorKind::SigInvalid => "signature is not a valid DER encoding",
+            ScriptErrorKind::SigHighS => "non-canonical signature: S value is unnecessarily high",
+            ScriptErrorKind::SigHashType => "signature hash type missing or not understood",
+            ScriptErrorKind::SigNullDummy => "dummy OP_CHECKMULTISIG argument must be zero",
+            ScriptErrorKind::SigPushOnly => "only push operators allowed in signatures",
+            ScriptErrorKind::SigNullFail => "signature must be zero for failed CHECK(MULTI)SIG operation",
+            ScriptErrorKind::SigFindAndDelete => "signature is found in scriptCode",
+            ScriptErrorKind::PubKeyType => "public key is neither compressed or uncompressed",
+            ScriptErrorKind::WitnessPubKeyType => "using non-compressed keys in segwit",
+            ScriptErrorKind::MinimalData => "data push or number larger than necessary",
+            ScriptErrorKind::CleanStack => "stack size must be exactly one after execution",
+            ScriptErrorKind::MinimalIf => "OP_IF/NOTIF argument must be minimal",
+            ScriptErrorKind::OpCodeSeparator => "using OP_CODESEPARATOR in non-witness script",
+            ScriptErrorKind::DiscourageUpgradableNops => "NOPx reserved for soft-fork upgrades",
+            ScriptErrorKind::DiscourageUpgradableWitnessProgram => "witness version reserved for soft-fork upgrades",
+            ScriptErrorKind::DiscourageUpgradableTaprootVersion => "taproot version reserved for soft-fork upgrades",
+            ScriptErrorKind::DiscourageOpSuccess => "OP_SUCCESSx reserved for soft-fork upgrades",
+            ScriptErrorKind::DiscourageUpgradablePubKeyType => "public key version reserved for soft-fork upgrades",
+            ScriptErrorKind::SigCount => "signature count negative or greater than pubkey count",
+            ScriptErrorKind::PubKeyCount => "pubkey count negative or limit exceeded",
+            ScriptErrorKind::NegativeLocktime => "negative locktime",
+            ScriptErrorKind::UnsatisfiedLocktime => "locktime requirement not satisfied",
+            ScriptErrorKind::WitnessProgramWrongLength => "witness program has incorrect length",
+            ScriptErrorKind::WitnessProgramMismatch => "witness program hash mismatch",
+            ScriptErrorKind::WitnessProgramWitnessEmpty => "witness program was passed an empty witness",
+            ScriptErrorKind::WitnessMalleated => "witness requires empty scriptSig",
+            ScriptErrorKind::WitnessMalleatedP2sh => "witness requires only-redeemscript scriptSig",
+            ScriptErrorKind::WitnessUnexpected => "witness provided for non-witness script",
+            ScriptErrorKind::SchnorrSigSize => "invalid Schnorr signature size",
+            ScriptErrorKind::SchnorrSigHashType => "invalid Schnorr signature hash type",
+            ScriptErrorKind::SchnorrSig => "invalid Schnorr signature",
+            ScriptErrorKind::TaprootWrongControlSize => "invalid Taproot control block size",
+            ScriptErrorKind::TapscriptValidationWeight => "too much signature validation relative to witness weight",
+            ScriptErrorKind::TapscriptCheckMultiSig => "OP_CHECKMULTISIG(VERIFY) is not available in tapscript",
+            ScriptErrorKind::TapscriptMinimalIf => "OP_IF/NOTIF argument must be minimal in tapscript",
+            ScriptErrorKind::UnknownPrevout => "the output spent by the input is not known",
+        };
+        write!(f, "{}", message)
+    }
+}
+
+/// Why a script failed, and at which command. `op_index` counts the commands
+/// executed in order: scriptSig, scriptPubKey, then any redeem or witness script.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct ScriptError {
+    kind: ScriptErrorKind,
+    op_index: Option<usize>,
+}
+impl ScriptError {
+    pub fn new(kind: ScriptErrorKind, op_index: Option<usize>) -> Self {
+        Self { kind, op_index }
+    }
+    pub fn kind(&self) -> ScriptErrorKind {
+        self.kind
+    }
+    pub fn op_index(&self) -> Option<usize> {
+        self.op_index
+    }
+    pub fn get_json(&self) -> serde_json::Value {
+        json!({
+            "error": format!("{:?}", self.kind),
+            "message": self.kind.to_string(),
+            "op_index": self.op_index,
+            "flag": self.kind.flag().map(|flag| flag.to_string()),
+        })
+    }
+}
+impl From<ScriptErrorKind> for ScriptError {
+    fn from(kind: ScriptErrorKind) -> Self {
+        ScriptError::new(kind, None)
+    }
+}
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.op_index {
+            Some(op_index) => write!(f, "{} (op {})", self.kind, op_index),
+            None => write!(f, "{}", self.kind),
+        }
+    }
+}
@@ -0,0 +1,73 @@
+use std::fmt;
+use serde_json::json;
+use crate::helpers::script_error::ScriptError;
+
+/// Which script a traced command comes from.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum TracePhase {
+    Script,
+    RedeemScript,
+    WitnessProgram,
+    WitnessScript,
+    Tapscript,
+}
+impl fmt::Display for TracePhase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TracePhase::Script => write!(f, "script"),
+            TracePhase::RedeemScript => write!(f, "redeem_script"),
+            TracePhase::WitnessProgram => write!(f, "witness_program"),
+            TracePhase::WitnessScript => write!(f, "witness_script"),
+            TracePhase::Tapscript => write!(f, "tapscript"),
+        }
+    }
+}
+
+/// Interpreter state right after one command, `executed` is false for
+/// commands skipped inside a branch not taken.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct TraceStep {
+    pub op_index: usize,
+    pub phase: TracePhase,
+    pub op: String,
+    pub data: Option<Vec<u8>>,
+    pub executed: bool,
+    pub stack: Vec<Vec<u8>>,
+    pub altstack: Vec<Vec<u8>>,
+    pub exec_stack: Vec<bool>,
+}
+impl TraceStep {
+    pub fn get_json(&self) -> serde_json::Value {
+        json!({
+            "op_index": self.op_index,
+            "phase": self.phase.to_string(),
+            "op": self.op,
+            "data": self.data.as_ref().map(hex::encode),
+            "executed": self.executed,
+            "stack": self.stack.iter().map(hex::encode).collect::<Vec<String>>(),
+            "altstack": self.altstack.iter().map(hex::encode).collect::<Vec<String>>(),
+            "exec_stack": self.exec_stack,
+        })
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct ScriptTrace {
+    pub steps: Vec<TraceStep>,
+    pub result: Result<(), ScriptError>,
+}
+impl ScriptTrace {
+    pub fn new(steps: Vec<TraceStep>, result: Result<(), ScriptError>) -> Self {
+        Self { steps, result }
+    }
+    pub fn get_json(&self) -> serde_json::Value {
+        let mut trace_json = json!({
+            "is_valid": self.result.is_ok(),
+            "steps": self.steps.iter().map(|step| step.get_json()).collect::<Vec<serde_json::Value>>(),
+        });
+        if let Err(error) = &self.result {
+            trace_json["script_error"] = error.get_json();
+        }
+        trace_json
+    }
+}
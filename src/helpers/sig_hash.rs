@@ -1,3 +1,6 @@
+// taproot signatures without a hash type byte, signing like SIGHASH_ALL (BIP341)
+pub const SIGHASH_DEFAULT: u8 = 0;
 pub const SIGHASH_ALL: u8 = 1;
 pub const SIGHASH_NONE: u8 = 2;
 pub const SIGHASH_SINGLE: u8 = 3;
+pub const SIGHASH_ANYONECANPAY: u8 = 0x80;
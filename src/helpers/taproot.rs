@@ -0,0 +1,91 @@
+use num::BigUint;
+use sha2::{Digest, Sha256};
+use crate::field_element::FieldElement;
+use crate::helpers::varint::encode_varint;
+use crate::point::Point;
+use crate::secp256k1::Secp256k1;
+
+/// First byte of the annex, an optional last witness element of taproot spends.
+pub const ANNEX_TAG: u8 = 0x50;
+
+/// BIP340 tagged hash: sha256(sha256(tag) || sha256(tag) || msg).
+pub fn tagged_hash(tag: &str, msg: &[u8]) -> [u8; 32] {
+    let tag_hash = Sha256::digest(tag.as_bytes());
+    let mut hasher = Sha256::new();
+    hasher.update(tag_hash);
+    hasher.update(tag_hash);
+    hasher.update(msg);
+    let mut result: [u8; 32] = [0; 32];
+    result.copy_from_slice(&hasher.finalize());
+    result
+}
+
+/// Leaf version of BIP342 tapscripts.
+pub const TAPROOT_LEAF_TAPSCRIPT: u8 = 0xc0;
+// a control block is the leaf version and parity byte, the internal key,
+// and up to 128 hashes of the merkle path
+const TAPROOT_CONTROL_BASE_SIZE: usize = 33;
+const TAPROOT_CONTROL_NODE_SIZE: usize = 32;
+const TAPROOT_CONTROL_MAX_NODE_COUNT: usize = 128;
+
+pub fn is_valid_control_block_size(control_block: &[u8]) -> bool {
+    control_block.len() >= TAPROOT_CONTROL_BASE_SIZE
+        && control_block.len() <= TAPROOT_CONTROL_BASE_SIZE + TAPROOT_CONTROL_NODE_SIZE * TAPROOT_CONTROL_MAX_NODE_COUNT
+        && (control_block.len() - TAPROOT_CONTROL_BASE_SIZE).is_multiple_of(TAPROOT_CONTROL_NODE_SIZE)
+}
+pub fn tap_leaf_hash(leaf_version: u8, script: &[u8]) -> [u8; 32] {
+    let mut leaf = vec![leaf_version];
+    leaf.extend(encode_varint(script.len() as u64).unwrap());
+    leaf.extend(script);
+    tagged_hash("TapLeaf", &leaf)
+}
+/// Hash of two merkle tree nodes, in lexicographic order.
+pub fn tap_branch_hash(a: &[u8], b: &[u8]) -> [u8; 32] {
+    let (first, second) = if a < b { (a, b) } else { (b, a) };
+    tagged_hash("TapBranch", &[first, second].concat())
+}
+/// The output key committing to the x-only `internal_key` and the script tree
+/// `merkle_root`, None if the key is not on the curve or the tweak is not a valid scalar.
+pub fn tweak_public_key(internal_key: &[u8], merkle_root: Option<&[u8]>) -> Option<Point> {
+    let s256 = Secp256k1::new();
+    let internal_key = Point::lift_x(internal_key).ok()?;
+    let mut data = internal_key.xonly();
+    data.extend(merkle_root.unwrap_or_default());
+    let tweak = BigUint::from_bytes_be(&tagged_hash("TapTweak", &data));
+    if tweak >= s256.n {
+        return None;
+    }
+    let g = Point::new_secp256k1(&Some(FieldElement::new(&s256.gx, &s256.p)), &Some(FieldElement::new(&s256.gy, &s256.p)));
+    Some(internal_key + g * tweak)
+}
+/// Whether the control block proves that the output key commits to the leaf (BIP341).
+pub fn verify_taproot_commitment(output_key: &[u8], control_block: &[u8], leaf_hash: &[u8; 32]) -> bool {
+    let merkle_root = control_block[TAPROOT_CONTROL_BASE_SIZE..]
+        .chunks(TAPROOT_CONTROL_NODE_SIZE)
+        .fold(*leaf_hash, |node, sibling| tap_branch_hash(&node, sibling));
+    match tweak_public_key(&control_block[1..TAPROOT_CONTROL_BASE_SIZE], Some(&merkle_root)) {
+        // the parity of the output key is the low bit of the first byte
+        Some(point) => point.xonly() == output_key && point.has_even_y() == (control_block[0] & 1 == 0),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_tweak_public_key() {
+        // BIP341 wallet test vector, key path only
+        let internal_key = hex::decode("d6889cb081036e0faefa3a35157ad71086b123b2b144b649798b494c300a961d").unwrap();
+        let output_key = tweak_public_key(&internal_key, None).unwrap();
+        assert_eq!(hex::encode(output_key.xonly()), "53a1f6e454df1aa2776a2814a721372d6258050de330b3c6d10ee8f4e0dda343");
+    }
+    #[test]
+    fn test_control_block_size() {
+        assert!(is_valid_control_block_size(&[0xc0; 33]));
+        assert!(is_valid_control_block_size(&[0xc0; 33 + 32 * 128]));
+        assert!(!is_valid_control_block_size(&[0xc0; 32]));
+        assert!(!is_valid_control_block_size(&[0xc0; 34]));
+        assert!(!is_valid_control_block_size(&[0xc0; 33 + 32 * 129]));
+    }
+}
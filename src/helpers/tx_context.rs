@@ -0,0 +1,46 @@
+use num::BigUint;
+use crate::script::Script;
+use crate::tx::Tx;
+use crate::tx_output::TxOutput;
+
+/// The spending transaction, input and spent output a script is evaluated for,
+/// needed by the signature checks, OP_CHECKLOCKTIMEVERIFY and OP_CHECKSEQUENCEVERIFY.
+#[derive(Debug, Clone)]
+pub struct TxContext<'a> {
+    pub tx: &'a Tx,
+    pub input_index: usize,
+    pub prev_output: TxOutput,
+    // the outputs spent by all the inputs, taproot signatures commit to them
+    pub spent_outputs: Option<Vec<TxOutput>>,
+}
+impl<'a> TxContext<'a> {
+    pub fn new(tx: &'a Tx, input_index: usize, prev_output: TxOutput) -> Self {
+        Self { tx, input_index, prev_output, spent_outputs: None }
+    }
+    pub fn with_spent_outputs(mut self, spent_outputs: Vec<TxOutput>) -> Self {
+        self.spent_outputs = Some(spent_outputs);
+        self
+    }
+    pub fn version(&self) -> u32 {
+        self.tx.version()
+    }
+    pub fn locktime(&self) -> u32 {
+        self.tx.locktime()
+    }
+    pub fn sequence(&self) -> u32 {
+        self.tx.tx_ins()[self.input_index].sequence()
+    }
+    /// The digest a signature with `hash_type` commits to, BIP143 for witness v0 scripts.
+    pub fn sig_hash(&self, script_code: &Script, hash_type: u8, witness_v0: bool) -> BigUint {
+        match witness_v0 {
+            true => self.tx.sig_hash_segwit(self.input_index, script_code, self.prev_output.amount(), hash_type),
+            false => self.tx.sig_hash_legacy(self.input_index, script_code, hash_type),
+        }
+    }
+    /// The BIP341 digest of a taproot spend, `script_path` is the leaf hash and
+    /// OP_CODESEPARATOR position of a script path spend. None when the spent
+    /// outputs are not known or the hash type is not defined for this input.
+    pub fn sig_hash_taproot(&self, hash_type: u8, annex: Option<&[u8]>, script_path: Option<([u8; 32], u32)>) -> Option<[u8; 32]> {
+        self.tx.sig_hash_taproot(self.input_index, self.spent_outputs.as_ref()?, hash_type, annex, script_path)
+    }
+}
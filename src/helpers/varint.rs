@@ -1,7 +1,8 @@
-use std::io::{Cursor, Read, Write};
-use num::{BigUint, ToPrimitive};
+use std::io::{Cursor, Write};
+use num::BigUint;
 use std::net::TcpStream;
-use crate::helpers::endianness::{int_to_little_endian, little_endian_to_int};
+use crate::helpers::endianness::int_to_little_endian;
+use crate::helpers::decode_error::{read_array, read_exact_array, DecodeError};
 
 pub struct VarInt {
     pub value: u64,
@@ -9,60 +10,48 @@ pub struct VarInt {
 }
 
 pub fn read_varint_tcp(stream: &mut TcpStream) -> Result<VarInt, std::io::Error> {
-    let mut buffer = [0; 1];
-    stream.read(&mut buffer)?;
-    let i = buffer[0];
+    let [i] = read_exact_array::<1>(stream, 0)?;
 
     match i {
         // 0xfd > 2 bytes
-        0xfd => {
-            let mut buffer = [0; 2];
-            stream.read(&mut buffer)?;
-            Ok(VarInt{value: little_endian_to_int(buffer.as_slice()).to_u64().unwrap(), bytes: 2})
-        }
+        0xfd => Ok(VarInt{value: u16::from_le_bytes(read_exact_array(stream, 1)?) as u64, bytes: 2}),
         // 0xfe > 4 bytes
-        0xfe => {
-            let mut buffer = [0; 4];
-            stream.read(&mut buffer)?;
-            Ok(VarInt{value: little_endian_to_int(buffer.as_slice()).to_u64().unwrap(), bytes: 4})
-        }
+        0xfe => Ok(VarInt{value: u32::from_le_bytes(read_exact_array(stream, 1)?) as u64, bytes: 4}),
         // 0xff 8 bytes
-        0xff => {
-            let mut buffer = [0; 8];
-            stream.read(&mut buffer)?;
-            Ok(VarInt{value: little_endian_to_int(buffer.as_slice()).to_u64().unwrap(), bytes: 8})
-        }
+        0xff => Ok(VarInt{value: u64::from_le_bytes(read_exact_array(stream, 1)?), bytes: 8}),
         // the integer
         _ => Ok(VarInt{value: u64::from(i), bytes: 1}),
     }
 }
-pub fn read_varint(stream: &mut Cursor<Vec<u8>>) -> Result<VarInt, std::io::Error> {
-    let mut buffer = [0; 1];
-    stream.read(&mut buffer)?;
-    let i = buffer[0];
-
-    match i {
+/// Reads a minimally encoded varint, as nodes require.
+pub fn read_varint(stream: &mut Cursor<Vec<u8>>) -> Result<VarInt, DecodeError> {
+    let offset = stream.position() as usize;
+    let [i] = read_array::<1>(stream)?;
+    // the smallest value each prefix may encode
+    let (value, bytes, min) = match i {
         // 0xfd > 2 bytes
-        0xfd => {
-            let mut buffer = [0; 2];
-            stream.read(&mut buffer)?;
-            Ok(VarInt{value: little_endian_to_int(buffer.as_slice()).to_u64().unwrap(), bytes: 2})
-        }
+        0xfd => (u16::from_le_bytes(read_array(stream)?) as u64, 2, 0xfd),
         // 0xfe > 4 bytes
-        0xfe => {
-            let mut buffer = [0; 4];
-            stream.read(&mut buffer)?;
-            Ok(VarInt{value: little_endian_to_int(buffer.as_slice()).to_u64().unwrap(), bytes: 4})
-        }
+        0xfe => (u32::from_le_bytes(read_array(stream)?) as u64, 4, 0x10000),
         // 0xff 8 bytes
-        0xff => {
-            let mut buffer = [0; 8];
-            stream.read(&mut buffer)?;
-            Ok(VarInt{value: little_endian_to_int(buffer.as_slice()).to_u64().unwrap(), bytes: 8})
-        }
+        0xff => (u64::from_le_bytes(read_array(stream)?), 8, 0x100000000),
         // the integer
-        _ => Ok(VarInt{value: u64::from(i), bytes: 1}),
+        _ => return Ok(VarInt{value: u64::from(i), bytes: 1}),
+    };
+    if value < min {
+        return Err(DecodeError::NonMinimalVarint { offset });
+    }
+    Ok(VarInt{value, bytes})
+}
+/// A varint counting the items or bytes that follow. Each takes at least a
+/// byte, so a count larger than the data left can't be right.
+pub fn read_length(stream: &mut Cursor<Vec<u8>>) -> Result<VarInt, DecodeError> {
+    let offset = stream.position() as usize;
+    let length = read_varint(stream)?;
+    if length.value > (stream.get_ref().len() as u64).saturating_sub(stream.position()) {
+        return Err(DecodeError::OversizedVector { offset, length: length.value });
     }
+    Ok(length)
 }
 pub fn encode_varint(i: u64) -> Result<Vec<u8>, std::io::Error> {
     let mut buffer = Vec::new();
@@ -96,11 +85,11 @@ mod tests {
     fn varint_1() {
         let test_cases = vec![
             (vec![0x01], 1),
-            (vec![0xfd, 0x02, 0x00], 2),
-            (vec![0xfe, 0x03, 0x00, 0x00, 0x00], 3),
+            (vec![0xfd, 0xfd, 0x00], 0xfd),
+            (vec![0xfe, 0x00, 0x00, 0x01, 0x00], 0x10000),
             (
-                vec![0xff, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
-                4,
+                vec![0xff, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00],
+                0x100000000,
             ),
             (
                 vec![0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff],
@@ -113,6 +102,20 @@ mod tests {
             let result = read_varint(&mut cursor).unwrap();
             assert_eq!(result.value, expected_output);
         }
+        // values that fit a shorter encoding are rejected
+        let non_minimal = vec![
+            vec![0xfd, 0x02, 0x00],
+            vec![0xfe, 0x03, 0x00, 0x00, 0x00],
+            vec![0xff, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+        ];
+        for input in non_minimal {
+            let mut cursor = Cursor::new(input);
+            assert_eq!(read_varint(&mut cursor).err(), Some(DecodeError::NonMinimalVarint { offset: 0 }));
+        }
+        let mut cursor = Cursor::new(vec![0xfd, 0x02]);
+        assert_eq!(read_varint(&mut cursor).err(), Some(DecodeError::UnexpectedEof { offset: 2 }));
+        let mut cursor = Cursor::new(vec![0x03, 0x00, 0x00]);
+        assert_eq!(read_length(&mut cursor).err(), Some(DecodeError::OversizedVector { offset: 0, length: 3 }));
     }
     #[test]
     fn varint_2() {
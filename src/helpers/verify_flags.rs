@@ -0,0 +1,153 @@
+use std::fmt;
+use std::ops::{BitOr, BitOrAssign};
+
+/// Script verification flags, same bits as Bitcoin Core's SCRIPT_VERIFY_*.
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Default)]
+pub struct VerifyFlags(u32);
+
+impl VerifyFlags {
+    pub const NONE: VerifyFlags = VerifyFlags(0);
+    // evaluate P2SH redeem scripts (BIP16)
+    pub const P2SH: VerifyFlags = VerifyFlags(1 << 0);
+    // defined hash types and compressed/uncompressed pubkeys only
+    pub const STRICTENC: VerifyFlags = VerifyFlags(1 << 1);
+    // strict DER signatures (BIP66)
+    pub const DERSIG: VerifyFlags = VerifyFlags(1 << 2);
+    // S in the lower half of the curve order (BIP146)
+    pub const LOW_S: VerifyFlags = VerifyFlags(1 << 3);
+    // the OP_CHECKMULTISIG dummy element must be empty (BIP147)
+    pub const NULLDUMMY: VerifyFlags = VerifyFlags(1 << 4);
+    // scriptSigs only push data
+    pub const SIGPUSHONLY: VerifyFlags = VerifyFlags(1 << 5);
+    // pushes and numbers use their shortest encoding
+    pub const MINIMALDATA: VerifyFlags = VerifyFlags(1 << 6);
+    // the NOPs reserved for soft forks fail instead of doing nothing
+    pub const DISCOURAGE_UPGRADABLE_NOPS: VerifyFlags = VerifyFlags(1 << 7);
+    // exactly one element is left on the stack
+    pub const CLEANSTACK: VerifyFlags = VerifyFlags(1 << 8);
+    // enforce OP_CHECKLOCKTIMEVERIFY (BIP65)
+    pub const CHECKLOCKTIMEVERIFY: VerifyFlags = VerifyFlags(1 << 9);
+    // enforce OP_CHECKSEQUENCEVERIFY (BIP112)
+    pub const CHECKSEQUENCEVERIFY: VerifyFlags = VerifyFlags(1 << 10);
+    // evaluate witness programs (BIP141)
+    pub const WITNESS: VerifyFlags = VerifyFlags(1 << 11);
+    // witness versions reserved for soft forks fail instead of succeeding
+    pub const DISCOURAGE_UPGRADABLE_WITNESS_PROGRAM: VerifyFlags = VerifyFlags(1 << 12);
+    // OP_IF/OP_NOTIF arguments of witness v0 scripts are empty or 0x01
+    pub const MINIMALIF: VerifyFlags = VerifyFlags(1 << 13);
+    // a failed signature check must have an empty signature (BIP146)
+    pub const NULLFAIL: VerifyFlags = VerifyFlags(1 << 14);
+    // witness v0 scripts only take compressed pubkeys
+    pub const WITNESS_PUBKEYTYPE: VerifyFlags = VerifyFlags(1 << 15);
+    // legacy scripts can't use OP_CODESEPARATOR or contain their signatures
+    pub const CONST_SCRIPTCODE: VerifyFlags = VerifyFlags(1 << 16);
+    // evaluate witness v1 programs (BIP341, BIP342)
+    pub const TAPROOT: VerifyFlags = VerifyFlags(1 << 17);
+    // taproot leaf versions reserved for soft forks fail instead of succeeding
+    pub const DISCOURAGE_UPGRADABLE_TAPROOT_VERSION: VerifyFlags = VerifyFlags(1 << 18);
+    // OP_SUCCESSx fail a tapscript instead of making it succeed
+    pub const DISCOURAGE_OP_SUCCESS: VerifyFlags = VerifyFlags(1 << 19);
+    // tapscript pubkeys of unknown types fail instead of succeeding
+    pub const DISCOURAGE_UPGRADABLE_PUBKEYTYPE: VerifyFlags = VerifyFlags(1 << 20);
+
+    /// Rules every block must follow.
+    pub const CONSENSUS: VerifyFlags = VerifyFlags(
+        Self::P2SH.0 | Self::DERSIG.0 | Self::NULLDUMMY.0 | Self::CHECKLOCKTIMEVERIFY.0
+            | Self::CHECKSEQUENCEVERIFY.0 | Self::WITNESS.0 | Self::TAPROOT.0
+    );
+    /// Rules Bitcoin Core applies to relay transactions.
+    pub const STANDARD: VerifyFlags = VerifyFlags(
+        Self::CONSENSUS.0 | Self::STRICTENC.0 | Self::LOW_S.0 | Self::MINIMALDATA.0 | Self::DISCOURAGE_UPGRADABLE_NOPS.0
+            | Self::CLEANSTACK.0 | Self::DISCOURAGE_UPGRADABLE_WITNESS_PROGRAM.0 | Self::MINIMALIF.0 | Self::NULLFAIL.0
+            | Self::WITNESS_PUBKEYTYPE.0 | Self::CONST_SCRIPTCODE.0 | Self::DISCOURAGE_UPGRADABLE_TAPROOT_VERSION.0
+            | Self::DISCOURAGE_OP_SUCCESS.0 | Self::DISCOURAGE_UPGRADABLE_PUBKEYTYPE.0
+    );
+    /// Every flag, the standard ones and SIGPUSHONLY.
+    pub const ALL: VerifyFlags = VerifyFlags(Self::STANDARD.0 | Self::SIGPUSHONLY.0);
+
+    const NAMES: [(VerifyFlags, &'static str); 21] = [
+        (Self::P2SH, "P2SH"),
+        (Self::STRICTENC, "STRICTENC"),
+        (Self::DERSIG, "DERSIG"),
+        (Self::LOW_S, "LOW_S"),
+        (Self::NULLDUMMY, "NULLDUMMY"),
+        (Self::SIGPUSHONLY, "SIGPUSHONLY"),
+        (Self::MINIMALDATA, "MINIMALDATA"),
+        (Self::DISCOURAGE_UPGRADABLE_NOPS, "DISCOURAGE_UPGRADABLE_NOPS"),
+        (Self::CLEANSTACK, "CLEANSTACK"),
+        (Self::CHECKLOCKTIMEVERIFY, "CHECKLOCKTIMEVERIFY"),
+        (Self::CHECKSEQUENCEVERIFY, "CHECKSEQUENCEVERIFY"),
+        (Self::WITNESS, "WITNESS"),
+        (Self::DISCOURAGE_UPGRADABLE_WITNESS_PROGRAM, "DISCOURAGE_UPGRADABLE_WITNESS_PROGRAM"),
+        (Self::MINIMALIF, "MINIMALIF"),
+        (Self::NULLFAIL, "NULLFAIL"),
+        (Self::WITNESS_PUBKEYTYPE, "WITNESS_PUBKEYTYPE"),
+        (Self::CONST_SCRIPTCODE, "CONST_SCRIPTCODE"),
+        (Self::TAPROOT, "TAPROOT"),
+        (Self::DISCOURAGE_UPGRADABLE_TAPROOT_VERSION, "DISCOURAGE_UPGRADABLE_TAPROOT_VERSION"),
+        (Self::DISCOURAGE_OP_SUCCESS, "DISCOURAGE_OP_SUCCESS"),
+        (Self::DISCOURAGE_UPGRADABLE_PUBKEYTYPE, "DISCOURAGE_UPGRADABLE_PUBKEYTYPE"),
+    ];
+
+    pub fn bits(&self) -> u32 {
+        self.0
+    }
+    pub fn contains(&self, other: VerifyFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+    pub fn without(&self, other: VerifyFlags) -> VerifyFlags {
+        VerifyFlags(self.0 & !other.0)
+    }
+    pub fn names(&self) -> Vec<&'static str> {
+        Self::NAMES.iter().filter(|(flag, _)| self.contains(*flag)).map(|(_, name)| *name).collect()
+    }
+    /// Parses a comma separated list like "P2SH,WITNESS", "" and "NONE" are no flags.
+    /// None if a name is not known.
+    pub fn from_names(names: &str) -> Option<VerifyFlags> {
+        let mut flags = VerifyFlags::NONE;
+        for name in names.split(',').map(str::trim).filter(|name| !name.is_empty() && *name != "NONE") {
+            let (flag, _) = Self::NAMES.iter().find(|(_, flag_name)| *flag_name == name)?;
+            flags |= *flag;
+        }
+        Some(flags)
+    }
+}
+impl BitOr for VerifyFlags {
+    type Output = Self;
+    fn bitor(self, other: Self) -> Self {
+        VerifyFlags(self.0 | other.0)
+    }
+}
+impl BitOrAssign for VerifyFlags {
+    fn bitor_assign(&mut self, other: Self) {
+        self.0 |= other.0;
+    }
+}
+impl fmt::Display for VerifyFlags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.names().join(","))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_flags() {
+        let flags = VerifyFlags::P2SH | VerifyFlags::WITNESS;
+        assert!(flags.contains(VerifyFlags::P2SH));
+        assert!(!flags.contains(VerifyFlags::DERSIG));
+        assert!(VerifyFlags::STANDARD.contains(VerifyFlags::CONSENSUS));
+        assert_eq!(flags.to_string(), "P2SH,WITNESS");
+        assert_eq!(VerifyFlags::NONE.to_string(), "");
+        assert_eq!(VerifyFlags::from_names("P2SH, WITNESS"), Some(flags));
+        assert_eq!(VerifyFlags::from_names("NONE"), Some(VerifyFlags::NONE));
+        assert_eq!(VerifyFlags::from_names(""), Some(VerifyFlags::NONE));
+        assert_eq!(VerifyFlags::from_names("P2SH,SIGPUSHONLY"), Some(VerifyFlags::P2SH | VerifyFlags::SIGPUSHONLY));
+        assert_eq!(VerifyFlags::from_names("P2SH,NOT_A_FLAG"), None);
+        let policy = VerifyFlags::STANDARD.without(VerifyFlags::CONSENSUS);
+        assert!(policy.contains(VerifyFlags::STRICTENC | VerifyFlags::NULLFAIL) && !policy.contains(VerifyFlags::P2SH));
+        assert_eq!(VerifyFlags::ALL.without(VerifyFlags::STANDARD).to_string(), "SIGPUSHONLY");
+        assert_eq!(VerifyFlags::from_names(&VerifyFlags::ALL.to_string()), Some(VerifyFlags::ALL));
+    }
+}
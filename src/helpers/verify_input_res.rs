@@ -1,13 +1,21 @@
 use crate::script::Script;
+use crate::helpers::script_error::ScriptError;
+use crate::helpers::script_trace::ScriptTrace;
 
 #[derive(Debug, Eq, PartialEq, Clone)]
 pub struct VerifyInputRes {
     pub is_valid: bool,
     pub script_pubkey: Option<Script>,
-    pub redeem_script: Option<Script> // to find p2wpkh nested in p2sh
+    pub redeem_script: Option<Script>, // to find p2wpkh nested in p2sh
+    pub error: Option<ScriptError>, // why the input is invalid
+    pub trace: Option<ScriptTrace>,
 }
 impl VerifyInputRes {
-    pub fn new(is_valid: bool, script_pubkey: Option<Script>, redeem_script: Option<Script>) -> Self {
-        Self { is_valid, script_pubkey, redeem_script }
+    pub fn new(result: Result<(), ScriptError>, script_pubkey: Option<Script>, redeem_script: Option<Script>) -> Self {
+        Self { is_valid: result.is_ok(), script_pubkey, redeem_script, error: result.err(), trace: None }
     }
-}
\ No newline at end of file
+    pub fn with_trace(mut self, trace: ScriptTrace) -> Self {
+        self.trace = Some(trace);
+        self
+    }
+}
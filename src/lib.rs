@@ -3,10 +3,25 @@ extern crate core;
 use serde::Serialize;
 use serde_json::json;
 use wasm_bindgen::prelude::*;
+use num::BigUint;
+use std::io::Cursor;
 use crate::tx::Tx;
+use crate::network::Network;
+use crate::tx_output::TxOutput;
+use crate::psbt::Psbt;
+use crate::tx_builder::Utxo;
+use crate::coin_selection::{select_coins, select_coins_best, utxo_from_unspent, CoinSelectionAlgorithm, CoinSelectionParams};
+use chrono::Utc;
+use crate::script::Script;
+use crate::address::Address;
+use crate::bip32::{Bip32Error, DerivationPath, ExtendedPrivateKey, ExtendedPublicKey};
+use crate::bip39::Mnemonic;
+use crate::helpers::script_trace::ScriptTrace;
+use crate::helpers::verify_flags::VerifyFlags;
 
 use crate::block::Block;
 use crate::rpc_api::RpcApi;
+use crate::prevout_provider::{MemoryPrevoutProvider, PrevoutProvider, RpcPrevoutProvider};
 use crate::rpc_models::RpcBlockchaininfoResult;
 use crate::utils::set_panic_hook;
 
@@ -21,13 +36,21 @@ pub mod helpers;
 pub mod tx;
 pub mod tx_input;
 pub mod tx_output;
+pub mod psbt;
+pub mod tx_builder;
+pub mod coin_selection;
 pub mod script;
 pub mod rpc_api;
+pub mod prevout_provider;
 pub mod block;
 pub mod merkle_tree;
 pub mod merkle_block;
 pub mod env;
 pub mod rpc_models;
+pub mod network;
+pub mod address;
+pub mod bip32;
+pub mod bip39;
 
 extern crate console_error_panic_hook;
 extern crate wasm_log;
@@ -37,27 +60,231 @@ pub fn init_app() {
     set_panic_hook();
     wasm_log::init(wasm_log::Config::default());
 }
+// the exports take the network by name: "mainnet", "testnet3", "testnet4", "signet"
+// or "regtest", an unknown name gives the same empty result as a failed request
 #[wasm_bindgen]
-pub async fn get_tx_json(testnet: bool, tx_id: String) -> String {
+pub async fn get_tx_json(network: String, tx_id: String) -> String {
+    let Ok(network) = network.parse::<Network>() else {
+        return "".to_string();
+    };
 
-    let mut tx = Tx::new_from_id(testnet, tx_id.clone()).await;
+    let mut tx = Tx::new_from_id(network, tx_id.clone()).await;
     match tx {
         Some(tx) => tx.tx_json.to_string(),
         None => "".to_string()
     }
 }
 #[wasm_bindgen]
-pub async fn get_block_json(testnet: bool, block_id: String) -> String {
+pub async fn get_input_trace_json(network: String, tx_id: String, input_index: usize) -> String {
+    let Ok(network) = network.parse::<Network>() else {
+        return "".to_string();
+    };
+    let api = RpcApi::new(network);
+    let res_wrapped = api.get_tx(&tx_id).await;
+    match res_wrapped {
+        Ok(mut tx) => {
+            if input_index >= tx.tx_ins().len() || tx.is_coinbase() {
+                return "".to_string();
+            }
+            // taproot inputs need the outputs spent by all the inputs
+            let provider = RpcPrevoutProvider::new(network);
+            provider.prefetch(&tx).await;
+            let res = tx.trace_input(input_index, VerifyFlags::CONSENSUS, &provider).await;
+            match (res.trace, res.error) {
+                (Some(trace), _) => trace.get_json().to_string(),
+                // the input scripts could not be evaluated at all
+                (None, Some(error)) => ScriptTrace::new(vec![], Err(error)).get_json().to_string(),
+                (None, None) => "".to_string(),
+            }
+        }
+        Err(_) => {
+            "".to_string()
+        }
+    }
+}
+/// Decodes and verifies a raw tx without a node, `spent_outputs_json` lists the
+/// outputs spent by its inputs in order: [{"amount": 1000, "script_pubkey": "0014.."}].
+/// A tx that can't be decoded gives {"error": {error, message, offset}}.
+#[wasm_bindgen]
+pub async fn get_raw_tx_json(network: String, raw_tx: String, spent_outputs_json: String) -> String {
+    let Ok(network) = network.parse::<Network>() else {
+        return "".to_string();
+    };
+    let Ok(raw_tx) = hex::decode(raw_tx.trim()) else {
+        return "".to_string();
+    };
+    let tx = match Tx::from_bytes(&raw_tx, network) {
+        Ok(tx) => tx,
+        Err(error) => return json!({"error": error.get_json()}).to_string(),
+    };
+    let Some(spent_outputs) = spent_outputs_from_json(&spent_outputs_json) else {
+        return "".to_string();
+    };
+    let provider = MemoryPrevoutProvider::for_tx(&tx, spent_outputs);
+    tx.with_verification_json(&provider).await.tx_json.to_string()
+}
+fn spent_outputs_from_json(json: &str) -> Option<Vec<TxOutput>> {
+    let outputs: Vec<serde_json::Value> = serde_json::from_str(json).ok()?;
+    outputs.iter().map(|output| {
+        let amount = output["amount"].as_u64()?;
+        let script_pubkey = Script::parse_raw(&hex::decode(output["script_pubkey"].as_str()?).ok()?).ok()?;
+        Some(TxOutput::new(amount, script_pubkey))
+    }).collect()
+}
+/// The network, type and output script of a base58 or bech32 address, or
+/// {"error": {error, message}} when it isn't valid.
+#[wasm_bindgen]
+pub fn get_address_json(address: String) -> String {
+    match Address::parse(address.trim()) {
+        Ok(address) => address.get_json().to_string(),
+        Err(error) => json!({"error": error.get_json()}).to_string(),
+    }
+}
+/// Checks an address typed for `network`: {"is_valid": true, "address": {..}} or
+/// {"is_valid": false, "error": {error, message, positions}}, the positions of
+/// the characters to look at when it was mistyped.
+#[wasm_bindgen]
+pub fn validate_address(network: String, address: String) -> String {
+    let Ok(network) = network.parse::<Network>() else {
+        return "".to_string();
+    };
+    match Address::validate(address.trim(), network) {
+        Ok(address) => json!({"is_valid": true, "address": address.get_json()}).to_string(),
+        Err(diagnostic) => json!({"is_valid": false, "error": diagnostic.get_json()}).to_string(),
+    }
+}
+/// An xprv or xpub, or their SLIP-132 and testnet variants, derived down `path`,
+/// like m/0/5 (m being the key given), or {"error": {error, message}}.
+#[wasm_bindgen]
+pub fn get_extended_key_json(key: String, path: String) -> String {
+    let key = key.trim();
+    let derived = path.trim().parse::<DerivationPath>().and_then(|path| match ExtendedPrivateKey::parse(key) {
+        Err(Bip32Error::ExpectedPrivate) => ExtendedPublicKey::parse(key)?.derive_path(&path).map(|key| key.get_json()),
+        key => key?.derive_path(&path).map(|key| key.get_json()),
+    });
+    match derived {
+        Ok(json) => json.to_string(),
+        Err(error) => json!({"error": error.get_json()}).to_string(),
+    }
+}
+/// A BIP39 mnemonic with its entropy, seed and the BIP32 master key of the seed, or
+/// {"error": {error, message}}, a mistyped word also giving its position and a suggestion.
+#[wasm_bindgen]
+pub fn get_mnemonic_json(network: String, mnemonic: String, passphrase: String) -> String {
+    let Ok(network) = network.parse::<Network>() else {
+        return "".to_string();
+    };
+    let mnemonic = match Mnemonic::parse(&mnemonic) {
+        Ok(mnemonic) => mnemonic,
+        Err(error) => return json!({"error": error.get_json()}).to_string(),
+    };
+    let seed = mnemonic.to_seed(&passphrase);
+    match ExtendedPrivateKey::new_master(&seed, network) {
+        Ok(master_key) => json!({
+            "words": mnemonic.words(),
+            "entropy": hex::encode(mnemonic.entropy()),
+            "seed": hex::encode(seed),
+            "master_key": master_key.get_json(),
+        }).to_string(),
+        Err(error) => json!({"error": error.get_json()}).to_string(),
+    }
+}
+/// A PSBT given in base64 or hex, with the signatures each input has and what it is missing,
+/// or {"error": {error, message}} when it can't be read.
+#[wasm_bindgen]
+pub fn get_psbt_json(network: String, psbt: String) -> String {
+    let Ok(network) = network.parse::<Network>() else {
+        return "".to_string();
+    };
+    match psbt_from_str(&psbt, network) {
+        Ok(psbt) => psbt.get_json().to_string(),
+        Err(error) => json!({"error": psbt::error_json(&error)}).to_string(),
+    }
+}
+/// Merges the PSBTs of a JSON array of base64 or hex PSBTs, returned in base64.
+#[wasm_bindgen]
+pub fn combine_psbts(network: String, psbts_json: String) -> String {
+    let Ok(network) = network.parse::<Network>() else {
+        return "".to_string();
+    };
+    let Ok(psbts) = serde_json::from_str::<Vec<String>>(&psbts_json) else {
+        return "".to_string();
+    };
+    let Ok(mut psbts) = psbts.iter().map(|psbt| psbt_from_str(psbt, network)).collect::<Result<Vec<Psbt>, _>>() else {
+        return "".to_string();
+    };
+    if psbts.is_empty() {
+        return "".to_string();
+    }
+    let mut combined = psbts.remove(0);
+    for psbt in psbts {
+        if combined.combine(&psbt).is_err() {
+            return "".to_string();
+        }
+    }
+    combined.to_base64()
+}
+/// Finalizes the inputs of a PSBT, with the signed tx hex once all of them are,
+/// or {"error": {error, message}} when it can't be read.
+#[wasm_bindgen]
+pub fn finalize_psbt(network: String, psbt: String) -> String {
+    let Ok(network) = network.parse::<Network>() else {
+        return "".to_string();
+    };
+    let mut psbt = match psbt_from_str(&psbt, network) {
+        Ok(psbt) => psbt,
+        Err(error) => return json!({"error": psbt::error_json(&error)}).to_string(),
+    };
+    let is_complete = psbt.finalize();
+    let tx_hex = psbt.extract().ok().map(|tx| hex::encode(tx.serialize(false)));
+    json!({
+        "psbt": psbt.to_base64(),
+        "is_complete": is_complete,
+        "hex": tx_hex,
+    }).to_string()
+}
+fn psbt_from_str(psbt: &str, network: Network) -> Result<Psbt, std::io::Error> {
+    match hex::decode(psbt.trim()) {
+        Ok(bytes) => Psbt::parse(&bytes, network),
+        Err(_) => Psbt::from_base64(psbt, network),
+    }
+}
+#[wasm_bindgen]
+pub fn get_script_asm_json(script_asm: String) -> String {
+    match Script::from_asm(&script_asm) {
+        Ok(script) => {
+            // there is no spending transaction, signatures are checked against z = 0
+            let trace = script.trace(&BigUint::from(0u32), &None, VerifyFlags::CONSENSUS, None);
+            json!({
+                "hex": hex::encode(script.raw_serialize()),
+                "asm": script.to_string().trim_end(),
+                "script": script.get_json(),
+                "trace": trace.get_json(),
+            }).to_string()
+        }
+        Err(_) => {
+            "".to_string()
+        }
+    }
+}
+#[wasm_bindgen]
+pub async fn get_block_json(network: String, block_id: String) -> String {
+    let Ok(network) = network.parse::<Network>() else {
+        return "".to_string();
+    };
 
-    let mut block = Block::new_from_id(testnet, block_id.clone()).await;
+    let mut block = Block::new_from_id(network, block_id.clone()).await;
     match block {
         Some(block) => block.block_json.to_string(),
         None => "".to_string()
     }
 }
 #[wasm_bindgen]
-pub async fn get_block_txs_json(testnet: bool, block_id: String) -> String {
-    let api = RpcApi::new(testnet);
+pub async fn get_block_txs_json(network: String, block_id: String) -> String {
+    let Ok(network) = network.parse::<Network>() else {
+        return "".to_string();
+    };
+    let api = RpcApi::new(network);
     let res_wrapped = api.get_block_2(&block_id).await;
 
     match res_wrapped {
@@ -65,7 +292,13 @@ pub async fn get_block_txs_json(testnet: bool, block_id: String) -> String {
             let txs = block_res.result.tx;
             let mut txs_json = Vec::new();
             for tx in txs {
-                let mut tx_json = json!({});
+                // the fee is in BTC, missing for the coinbase
+                let fee = tx.fee.map(|fee| (fee * 100_000_000.0).round() as i64);
+                let raw_tx = tx.hex.as_ref().and_then(|hex| hex::decode(hex).ok());
+                let mut tx_json = match raw_tx.and_then(|raw_tx| Tx::parse(&mut Cursor::new(raw_tx), network).ok()) {
+                    Some(tx) => tx.size_json(fee),
+                    None => json!({}),
+                };
                 tx_json["txid"] = json!(tx.txid);
                 tx_json["fee"] = json!(tx.fee);
                 let mut vin_count = 0;
@@ -90,8 +323,11 @@ pub async fn get_block_txs_json(testnet: bool, block_id: String) -> String {
     }
 }
 #[wasm_bindgen]
-pub async fn get_block_count(testnet: bool) -> u32 {
-    let api = RpcApi::new(testnet);
+pub async fn get_block_count(network: String) -> u32 {
+    let Ok(network) = network.parse::<Network>() else {
+        return 0;
+    };
+    let api = RpcApi::new(network);
     let res_wrapped = api.get_block_count().await;
     match res_wrapped {
         Ok(res) => {res.result},
@@ -99,8 +335,11 @@ pub async fn get_block_count(testnet: bool) -> u32 {
     }
 }
 #[wasm_bindgen]
-pub async fn get_blockchain_info(testnet: bool) -> String {
-    let api = RpcApi::new(testnet);
+pub async fn get_blockchain_info(network: String) -> String {
+    let Ok(network) = network.parse::<Network>() else {
+        return "".to_string();
+    };
+    let api = RpcApi::new(network);
     let res_wrapped = api.get_blockchain_info().await;
     match res_wrapped {
         Ok(res) => {
@@ -110,8 +349,11 @@ pub async fn get_blockchain_info(testnet: bool) -> String {
     }
 }
 #[wasm_bindgen]
-pub async fn get_mempool_info(testnet: bool) -> String {
-    let api = RpcApi::new(testnet);
+pub async fn get_mempool_info(network: String) -> String {
+    let Ok(network) = network.parse::<Network>() else {
+        return "".to_string();
+    };
+    let api = RpcApi::new(network);
     let res_wrapped = api.get_mempool_info().await;
     match res_wrapped {
         Ok(res) => {
@@ -121,8 +363,11 @@ pub async fn get_mempool_info(testnet: bool) -> String {
     }
 }
 #[wasm_bindgen]
-pub async fn get_mining_info(testnet: bool) -> String {
-    let api = RpcApi::new(testnet);
+pub async fn get_mining_info(network: String) -> String {
+    let Ok(network) = network.parse::<Network>() else {
+        return "".to_string();
+    };
+    let api = RpcApi::new(network);
     let res_wrapped = api.get_mining_info().await;
     match res_wrapped {
         Ok(res) => {
@@ -132,8 +377,11 @@ pub async fn get_mining_info(testnet: bool) -> String {
     }
 }
 #[wasm_bindgen]
-pub async fn get_nettotals(testnet: bool) -> String {
-    let api = RpcApi::new(testnet);
+pub async fn get_nettotals(network: String) -> String {
+    let Ok(network) = network.parse::<Network>() else {
+        return "".to_string();
+    };
+    let api = RpcApi::new(network);
     let res_wrapped = api.get_nettotals().await;
     match res_wrapped {
         Ok(res) => {
@@ -143,8 +391,11 @@ pub async fn get_nettotals(testnet: bool) -> String {
     }
 }
 #[wasm_bindgen]
-pub async fn get_network_info(testnet: bool) -> String {
-    let api = RpcApi::new(testnet);
+pub async fn get_network_info(network: String) -> String {
+    let Ok(network) = network.parse::<Network>() else {
+        return "".to_string();
+    };
+    let api = RpcApi::new(network);
     let res_wrapped = api.get_network_info().await;
     match res_wrapped {
         Ok(res) => {
@@ -155,8 +406,11 @@ pub async fn get_network_info(testnet: bool) -> String {
 }
 
 #[wasm_bindgen]
-pub async fn get_wallet_info(testnet: bool) -> String {
-    let api = RpcApi::new(testnet);
+pub async fn get_wallet_info(network: String) -> String {
+    let Ok(network) = network.parse::<Network>() else {
+        return "".to_string();
+    };
+    let api = RpcApi::new(network);
     let res_wrapped = api.get_wallet_info().await;
     match res_wrapped {
         Ok(res) => {
@@ -168,8 +422,11 @@ pub async fn get_wallet_info(testnet: bool) -> String {
     }
 }
 #[wasm_bindgen]
-pub async fn list_transactions(testnet: bool) -> String {
-    let api = RpcApi::new(testnet);
+pub async fn list_transactions(network: String) -> String {
+    let Ok(network) = network.parse::<Network>() else {
+        return "".to_string();
+    };
+    let api = RpcApi::new(network);
     let res_wrapped = api.list_transactions().await;
     match res_wrapped {
         Ok(res) => {
@@ -181,8 +438,11 @@ pub async fn list_transactions(testnet: bool) -> String {
     }
 }
 #[wasm_bindgen]
-pub async fn list_unspent(testnet: bool) -> String {
-    let api = RpcApi::new(testnet);
+pub async fn list_unspent(network: String) -> String {
+    let Ok(network) = network.parse::<Network>() else {
+        return "".to_string();
+    };
+    let api = RpcApi::new(network);
     let res_wrapped = api.list_unspent().await;
     match res_wrapped {
         Ok(res) => {
@@ -192,4 +452,33 @@ pub async fn list_unspent(testnet: bool) -> String {
             "".to_string()
         }
     }
-}
\ No newline at end of file
+}
+
+/// Which wallet coins each coin selection algorithm would spend to send `amount`
+/// sats to `address` at `fee_rate` sat/vB, with the selection of least waste.
+#[wasm_bindgen]
+pub async fn get_coin_selection_json(network: String, address: String, amount: u64, fee_rate: f64) -> String {
+    let Ok(network) = network.parse::<Network>() else {
+        return "".to_string();
+    };
+    let Some(script_pubkey) = Script::from_address(&address, network) else {
+        return "".to_string();
+    };
+    // version, locktime, counts and the recipient output, plus the segwit marker and flag
+    let base_weight = Tx::new(2, vec![], vec![TxOutput::new(amount, script_pubkey)], 0, network, false).weight() + 2;
+    let api = RpcApi::new(network);
+    let Ok(res) = api.list_unspent().await else {
+        return "".to_string();
+    };
+    let utxos: Vec<Utxo> = res.result.iter().filter(|unspent| unspent.spendable).filter_map(utxo_from_unspent).collect();
+    let seed = Utc::now().timestamp_nanos_opt().unwrap_or_default() as u64;
+    let params = CoinSelectionParams::new(amount, fee_rate).with_base_weight(base_weight).with_seed(seed);
+    let selections: Vec<serde_json::Value> = CoinSelectionAlgorithm::ALL.iter()
+        .filter_map(|algorithm| select_coins(&utxos, &params, *algorithm))
+        .map(|selection| selection.get_json())
+        .collect();
+    json!({
+        "selections": selections,
+        "best": select_coins_best(&utxos, &params).map(|selection| selection.get_json()),
+    }).to_string()
+}
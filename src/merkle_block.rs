@@ -1,8 +1,9 @@
-use std::io::{Cursor, Read};
+use std::io::Cursor;
 use crate::helpers::endianness::little_endian_to_int;
 use num::{ToPrimitive};
 use crate::helpers::merkle_hash::bytes_to_bit_field;
-use crate::helpers::varint::read_varint;
+use crate::helpers::varint::read_length;
+use crate::helpers::decode_error::{read_array, read_bytes, DecodeError};
 use crate::merkle_tree::MerkleTree;
 
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -18,46 +19,28 @@ pub struct MerkleBlock {
     flags: Vec<u8>,
 }
 impl MerkleBlock {
-    pub fn parse(stream: &mut Cursor<Vec<u8>>) -> Result<Self, std::io::Error> {
-        let mut buffer = [0; 4];
-        stream.read(&mut buffer)?;
+    pub fn parse(stream: &mut Cursor<Vec<u8>>) -> Result<Self, DecodeError> {
+        let buffer = read_array::<4>(stream)?;
         let version = little_endian_to_int(buffer.as_slice()).to_u32().unwrap();
-        let mut buffer = [0; 32];
-        stream.read(&mut buffer)?;
-        let mut prev_block = buffer.to_vec();
+        let mut prev_block = read_bytes(stream, 32)?;
         prev_block.reverse();
-        let mut buffer = [0; 32];
-        stream.read(&mut buffer)?;
-        let mut merkle_root = buffer.to_vec();
+        let mut merkle_root = read_bytes(stream, 32)?;
         merkle_root.reverse();
-        let mut buffer = [0; 4];
-        stream.read(&mut buffer)?;
+        let buffer = read_array::<4>(stream)?;
         let timestamp = little_endian_to_int(buffer.as_slice()).to_u32().unwrap();
-        let mut buffer = [0; 4];
-        stream.read(&mut buffer)?;
-        let bits = buffer.to_vec();
-        let mut buffer = [0; 4];
-        stream.read(&mut buffer)?;
-        let nonce = buffer.to_vec();
-        let mut buffer = [0; 4];
-        stream.read(&mut buffer)?;
+        let bits = read_bytes(stream, 4)?;
+        let nonce = read_bytes(stream, 4)?;
+        let buffer = read_array::<4>(stream)?;
         let total = little_endian_to_int(buffer.as_slice()).to_u32().unwrap();
         let mut hashes: Vec<Vec<u8>> = vec![];
-        if let Ok(number_of_tx_hashes) = read_varint(stream) {
-            for _ in 0..number_of_tx_hashes.value {
-                let mut buffer = [0; 32];
-                stream.read(&mut buffer)?;
-                let mut hash = buffer.to_vec();
-                hash.reverse();
-                hashes.push(hash);
-            }
-        }
-        let mut flags: Vec<u8> = vec![];
-        if let Ok(lenght_flags_field) = read_varint(stream) {
-            let mut buffer: Vec<u8> = vec![0u8; lenght_flags_field.value as usize];
-            stream.read(&mut buffer)?;
-            flags = buffer.to_vec();
+        let number_of_tx_hashes = read_length(stream)?;
+        for _ in 0..number_of_tx_hashes.value {
+            let mut hash = read_bytes(stream, 32)?;
+            hash.reverse();
+            hashes.push(hash);
         }
+        let lenght_flags_field = read_length(stream)?;
+        let flags = read_bytes(stream, lenght_flags_field.value as usize)?;
         Ok(MerkleBlock { version, prev_block, merkle_root, timestamp, bits, nonce, total, hashes, flags })
     }
     pub fn is_valid(&self) -> bool {
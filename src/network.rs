@@ -0,0 +1,126 @@
+use std::fmt;
+use std::str::FromStr;
+use bech32::{hrp, Hrp};
+
+/// The chain transactions, addresses and nodes belong to, with the constants
+/// that tell the chains apart.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Default)]
+pub enum Network {
+    #[default]
+    Mainnet,
+    Testnet3,
+    Testnet4,
+    Signet,
+    Regtest,
+}
+impl Network {
+    pub const ALL: [Network; 5] = [Network::Mainnet, Network::Testnet3, Network::Testnet4, Network::Signet, Network::Regtest];
+
+    /// Test chains share their address versions and, except regtest, their bech32 prefix.
+    pub fn is_testnet(&self) -> bool {
+        *self != Network::Mainnet
+    }
+    pub fn p2pkh_version(&self) -> u8 {
+        match self.is_testnet() {
+            true => 0x6f,
+            false => 0x00,
+        }
+    }
+    pub fn p2sh_version(&self) -> u8 {
+        match self.is_testnet() {
+            true => 0xc4,
+            false => 0x05,
+        }
+    }
+    pub fn hrp(&self) -> Hrp {
+        match self {
+            Network::Mainnet => hrp::BC,
+            Network::Regtest => hrp::BCRT,
+            _ => hrp::TB,
+        }
+    }
+    /// Start bytes of every p2p message.
+    pub fn magic(&self) -> [u8; 4] {
+        match self {
+            Network::Mainnet => [0xf9, 0xbe, 0xb4, 0xd9],
+            Network::Testnet3 => [0x0b, 0x11, 0x09, 0x07],
+            Network::Testnet4 => [0x1c, 0x16, 0x3f, 0x28],
+            Network::Signet => [0x0a, 0x03, 0xcf, 0x40],
+            Network::Regtest => [0xfa, 0xbf, 0xb5, 0xda],
+        }
+    }
+    /// Hash of the first block, in display order.
+    pub fn genesis_hash(&self) -> &'static str {
+        match self {
+            Network::Mainnet => "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f",
+            Network::Testnet3 => "000000000933ea01ad0ee984209779baaec3ced90fa3f408719526f8d77f4943",
+            Network::Testnet4 => "00000000da84f2bafbbc53dee25a72ae507ff4914b867c565be350b0da8bf043",
+            Network::Signet => "00000008819873e925422c1ff0f99f7cc9bbb232af63a077a480a3633bee1ef6",
+            Network::Regtest => "0f9188f13cb7b2c71f2a335e3a4fc328bf5beb436012afca590b1a11466e2206",
+        }
+    }
+    pub fn default_port(&self) -> u16 {
+        match self {
+            Network::Mainnet => 8333,
+            Network::Testnet3 => 18333,
+            Network::Testnet4 => 48333,
+            Network::Signet => 38333,
+            Network::Regtest => 18444,
+        }
+    }
+    pub fn default_rpc_port(&self) -> u16 {
+        match self {
+            Network::Mainnet => 8332,
+            Network::Testnet3 => 18332,
+            Network::Testnet4 => 48332,
+            Network::Signet => 38332,
+            Network::Regtest => 18443,
+        }
+    }
+    pub fn from_magic(magic: [u8; 4]) -> Option<Network> {
+        Network::ALL.into_iter().find(|network| network.magic() == magic)
+    }
+}
+impl fmt::Display for Network {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Network::Mainnet => "mainnet",
+            Network::Testnet3 => "testnet3",
+            Network::Testnet4 => "testnet4",
+            Network::Signet => "signet",
+            Network::Regtest => "regtest",
+        };
+        write!(f, "{}", name)
+    }
+}
+/// Also takes the chain names nodes report in getblockchaininfo.
+impl FromStr for Network {
+    type Err = String;
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_lowercase().as_str() {
+            "mainnet" | "main" | "bitcoin" => Ok(Network::Mainnet),
+            "testnet3" | "testnet" | "test" => Ok(Network::Testnet3),
+            "testnet4" => Ok(Network::Testnet4),
+            "signet" => Ok(Network::Signet),
+            "regtest" => Ok(Network::Regtest),
+            _ => Err(format!("unknown network {}", name)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_network() {
+        for network in Network::ALL {
+            assert_eq!(network.to_string().parse::<Network>(), Ok(network));
+            assert_eq!(Network::from_magic(network.magic()), Some(network));
+        }
+        assert_eq!("main".parse::<Network>(), Ok(Network::Mainnet));
+        assert_eq!("test".parse::<Network>(), Ok(Network::Testnet3));
+        assert!("testnet5".parse::<Network>().is_err());
+        assert_eq!((Network::Mainnet.p2pkh_version(), Network::Signet.p2sh_version()), (0x00, 0xc4));
+        assert_eq!((Network::Testnet4.hrp(), Network::Regtest.hrp()), (hrp::TB, hrp::BCRT));
+    }
+}
@@ -1,11 +1,15 @@
 use std::ops::{Add, Mul};
 use num::{BigInt, BigUint, Integer};
 use std::{fmt};
+use std::io::{Error, ErrorKind};
 use crate::field_element::FieldElement;
 use crate::secp256k1;
 use crate::signature::Signature;
 use crate::helpers::hash160::hash160;
 use crate::helpers::base58::base58_encode_checksum;
+use crate::network::Network;
+use crate::helpers::endianness::int_to_big_endian;
+use crate::helpers::taproot::tagged_hash;
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct Point {
@@ -74,7 +78,10 @@ impl Point {
         let p = self.clone();
         let total = g * u + p * v;
 
-        total.x.unwrap().num_value() == signature.r().clone()
+        match total.x {
+            Some(x) => x.num_value() == signature.r().clone(),
+            None => false,
+        }
     }
     fn is_inf(&self) -> bool {
         self.x.is_none() && self.y.is_none()
@@ -82,74 +89,135 @@ impl Point {
     pub fn x(&self) -> Option<FieldElement> {
         self.x.clone()
     }
+    pub fn y(&self) -> Option<FieldElement> {
+        self.y.clone()
+    }
+    pub fn has_even_y(&self) -> bool {
+        self.y.as_ref().is_some_and(|y| y.num_value().is_even())
+    }
+    /// The 32 byte x coordinate, how BIP340 encodes public keys.
+    pub fn xonly(&self) -> Vec<u8> {
+        int_to_big_endian(&self.x.clone().unwrap().num_value(), 32)
+    }
+    /// The point with x coordinate `x` and an even y (BIP340 lift_x).
+    pub fn lift_x(x: &[u8]) -> Result<Self, Error> {
+        if x.len() != 32 {
+            return Err(Error::new(ErrorKind::InvalidData, "invalid x-only public key"));
+        }
+        let mut sec = vec![0x02];
+        sec.extend(x);
+        Self::parse(&sec)
+    }
+    /// BIP340 check of a 64 byte signature of `msg`, for the point returned by lift_x.
+    pub fn verify_schnorr(&self, msg: &[u8], sig: &[u8]) -> bool {
+        let s256 = secp256k1::Secp256k1::new();
+        if sig.len() != 64 {
+            return false;
+        }
+        let r = BigUint::from_bytes_be(&sig[..32]);
+        let s = BigUint::from_bytes_be(&sig[32..]);
+        if r >= s256.p || s >= s256.n {
+            return false;
+        }
+        let mut challenge = sig[..32].to_vec();
+        challenge.extend(self.xonly());
+        challenge.extend(msg);
+        let e = BigUint::from_bytes_be(&tagged_hash("BIP0340/challenge", &challenge)) % &s256.n;
+
+        // R = s*G - e*P must have an even y and r as the x coordinate
+        let g = Point::new_secp256k1(
+            &Some(FieldElement::new(&s256.gx, &s256.p)),
+            &Some(FieldElement::new(&s256.gy, &s256.p))
+        );
+        let total = g * s + self.clone() * (&s256.n - e);
+        match &total.x {
+            Some(x) => total.has_even_y() && x.num_value() == r,
+            None => false,
+        }
+    }
     pub fn sec(&self, compressed: bool) -> Vec<u8> {
         let mut sec : Vec<u8> = Vec::new();
         if compressed {
             if self.y.clone().unwrap().num_value() % BigUint::from(2u32) == BigUint::from(0u32) {
                 sec.push(0x02);
-                sec.extend(self.x.clone().unwrap().num_value().to_bytes_be());
+                sec.extend(int_to_big_endian(&self.x.clone().unwrap().num_value(), 32));
                 sec
             } else {
                 sec.push(0x03);
-                sec.extend(self.x.clone().unwrap().num_value().to_bytes_be());
+                sec.extend(int_to_big_endian(&self.x.clone().unwrap().num_value(), 32));
                 sec
             }
         } else {
             sec.push(0x04);
-            sec.extend(self.x.clone().unwrap().num_value().to_bytes_be());
-            sec.extend(self.y.clone().unwrap().num_value().to_bytes_be());
+            sec.extend(int_to_big_endian(&self.x.clone().unwrap().num_value(), 32));
+            sec.extend(int_to_big_endian(&self.y.clone().unwrap().num_value(), 32));
             sec
         }
     }
-    pub fn address(&self, compressed: bool, testnet: bool) -> Vec<u8> {
+    pub fn address(&self, compressed: bool, network: Network) -> Vec<u8> {
         let sec = self.sec(compressed);
 
         let h160 = hash160(&sec.as_slice());
-        let prefix = if testnet { b"\x6f" } else { b"\x00" };
 
-        let mut address = prefix.to_vec();
+        let mut address = vec![network.p2pkh_version()];
         address.extend(h160);
         let to_retrun = base58_encode_checksum(address);
         to_retrun
     }
-    pub fn parse(data: &[u8]) -> Self {
+    pub fn parse(data: &[u8]) -> Result<Self, Error> {
         let s256 = secp256k1::Secp256k1::new();
-        if data[0] == 0x04 { // uncompressed
-            let x = BigUint::from_bytes_be(&data[1..33]);
-            let y = BigUint::from_bytes_be(&data[33..65]);
-
-            return Self::new_secp256k1(
-                &Some(FieldElement::new(&x, &s256.p)),
-                &Some(FieldElement::new(&y, &s256.p)),
-            );
-        }
-
-        let is_even = data[0] == 0x02;
-        let x = BigUint::from_bytes_be(&data[1..]);
-        let x = FieldElement::new(&x, &s256.p);
-
-        // right side of the equation y^2 = x^3 + 7
-        let alpha = x.pow(BigInt::from(3u32)) + FieldElement::new(&s256.b, &s256.p);
-
-        // solve for left side
-        let beta = alpha.sqrt();
-
-        let even_beta = if beta.num_value().is_even() {
-            beta.clone()
-        } else {
-            FieldElement::new(&(&s256.p - beta.clone().num_value()), &s256.p)
-        };
-
-        let odd_beta = if beta.num_value().is_even() {
-            FieldElement::new(&(&s256.p - beta.clone().num_value()), &s256.p)
-        } else {
-            beta.clone()
-        };
-
-        if is_even {
-            Self::new_secp256k1(&Some(x), &Some(even_beta))
-        } else {
-            Self::new_secp256k1(&Some(x), &Some(odd_beta))
+        let invalid = || Error::new(ErrorKind::InvalidData, "invalid SEC public key");
+        let alpha = |x: &FieldElement| x.pow(BigInt::from(3u32)) + FieldElement::new(&s256.b, &s256.p);
+        match (data.first(), data.len()) {
+            (Some(0x04), 65) => { // uncompressed
+                let x = BigUint::from_bytes_be(&data[1..33]);
+                let y = BigUint::from_bytes_be(&data[33..65]);
+                if x >= s256.p || y >= s256.p {
+                    return Err(invalid());
+                }
+                let x = FieldElement::new(&x, &s256.p);
+                let y = FieldElement::new(&y, &s256.p);
+                if y.pow(BigInt::from(2u32)) != alpha(&x) {
+                    return Err(invalid());
+                }
+                Ok(Self::new_secp256k1(&Some(x), &Some(y)))
+            }
+            (Some(0x02 | 0x03), 33) => {
+                let is_even = data[0] == 0x02;
+                let x = BigUint::from_bytes_be(&data[1..]);
+                if x >= s256.p {
+                    return Err(invalid());
+                }
+                let x = FieldElement::new(&x, &s256.p);
+
+                // right side of the equation y^2 = x^3 + 7
+                let alpha = alpha(&x);
+
+                // solve for left side, x is not on the curve when alpha has no square root
+                let beta = alpha.sqrt();
+                if beta.pow(BigInt::from(2u32)) != alpha {
+                    return Err(invalid());
+                }
+
+                let even_beta = if beta.num_value().is_even() {
+                    beta.clone()
+                } else {
+                    FieldElement::new(&(&s256.p - beta.clone().num_value()), &s256.p)
+                };
+
+                let odd_beta = if beta.num_value().is_even() {
+                    FieldElement::new(&(&s256.p - beta.clone().num_value()), &s256.p)
+                } else {
+                    beta.clone()
+                };
+
+                if is_even {
+                    Ok(Self::new_secp256k1(&Some(x), &Some(even_beta)))
+                } else {
+                    Ok(Self::new_secp256k1(&Some(x), &Some(odd_beta)))
+                }
+            }
+            _ => Err(invalid()),
         }
     }
 }
@@ -547,22 +615,22 @@ mod tests {
         let s256 = secp256k1::Secp256k1::new();
         let generator = Point::new_secp256k1(&Some(FieldElement::new(&s256.gx, &s256.p)), &Some(FieldElement::new(&s256.gy, &s256.p)));
         let point = generator.clone() * BigUint::from(5000u32);
-        assert_eq!(Point::parse(&point.sec(false)), point);
+        assert_eq!(Point::parse(&point.sec(false)).unwrap(), point);
         let point = generator.clone() * BigUint::from(2018_u32).pow(5);
-        assert_eq!(Point::parse(&point.sec(false)), point);
+        assert_eq!(Point::parse(&point.sec(false)).unwrap(), point);
         let point = generator.clone() * BigUint::from_str_radix("deadbeef12345", 16).unwrap();
-        assert_eq!(Point::parse(&point.sec(false)), point);
+        assert_eq!(Point::parse(&point.sec(false)).unwrap(), point);
     }
     #[test]
     fn test_sec_5() {
         let s256 = secp256k1::Secp256k1::new();
         let generator = Point::new_secp256k1(&Some(FieldElement::new(&s256.gx, &s256.p)), &Some(FieldElement::new(&s256.gy, &s256.p)));
         let point = generator.clone() * BigUint::from(5001u32);
-        assert_eq!(Point::parse(&point.sec(true)), point);
+        assert_eq!(Point::parse(&point.sec(true)).unwrap(), point);
         let point = generator.clone() * BigUint::from(2019_u32).pow(5);
-        assert_eq!(Point::parse(&point.sec(true)), point);
+        assert_eq!(Point::parse(&point.sec(true)).unwrap(), point);
         let point = generator.clone() * BigUint::from_str_radix("deadbeef54321", 16).unwrap();
-        assert_eq!(Point::parse(&point.sec(true)), point);
+        assert_eq!(Point::parse(&point.sec(true)).unwrap(), point);
     }
     #[test]
     fn test_address_1() {
@@ -571,19 +639,19 @@ mod tests {
         let point = generator.clone() * BigUint::from(5002u32);
 
         assert_eq!(
-            point.address(false, true),
+            point.address(false, Network::Testnet3),
             "mmTPbXQFxboEtNRkwfh6K51jvdtHLxGeMA".as_bytes().to_vec()
         );
 
         let point = generator.clone() * BigUint::from(2020_u32).pow(5);
         assert_eq!(
-            point.address(true, true),
+            point.address(true, Network::Testnet3),
             "mopVkxp8UhXqRYbCYJsbeE1h1fiF64jcoH".as_bytes().to_vec()
         );
 
         let point = generator.clone() * BigUint::from_str_radix("12345deadbeef", 16).unwrap();
         assert_eq!(
-            point.address(true, false),
+            point.address(true, Network::Mainnet),
             "1F1Pn2y6pDb68E5nYJJeba4TLg2U7B6KF1".as_bytes().to_vec()
         );
 
@@ -598,16 +666,16 @@ mod tests {
         let secret2 = 321_u32;
         let secret3 = 4242424242_u32;
         let values = vec![
-            (secret1, true, false, "148dY81A9BmdpMhvYEVznrM45kWN32vSCN"),
-            (secret1, true, true, "mieaqB68xDCtbUBYFoUNcmZNwk74xcBfTP"),
-            (secret2, false, false, "1S6g2xBJSED7Qr9CYZib5f4PYVhHZiVfj"),
-            (secret2, false, true, "mfx3y63A7TfTtXKkv7Y6QzsPFY6QCBCXiP"),
-            (secret3, false, false, "1226JSptcStqn4Yq9aAmNXdwdc2ixuH9nb"),
-            (secret3, false, true, "mgY3bVusRUL6ZB2Ss999CSrGVbdRwVpM8s"),
+            (secret1, true, Network::Mainnet, "148dY81A9BmdpMhvYEVznrM45kWN32vSCN"),
+            (secret1, true, Network::Testnet3, "mieaqB68xDCtbUBYFoUNcmZNwk74xcBfTP"),
+            (secret2, false, Network::Mainnet, "1S6g2xBJSED7Qr9CYZib5f4PYVhHZiVfj"),
+            (secret2, false, Network::Signet, "mfx3y63A7TfTtXKkv7Y6QzsPFY6QCBCXiP"),
+            (secret3, false, Network::Mainnet, "1226JSptcStqn4Yq9aAmNXdwdc2ixuH9nb"),
+            (secret3, false, Network::Regtest, "mgY3bVusRUL6ZB2Ss999CSrGVbdRwVpM8s"),
         ];
-        for (secret, compressed, testnet, address) in values {
+        for (secret, compressed, network, address) in values {
             let point = generator.clone() * BigUint::from(secret);
-            assert_eq!(point.address(compressed, testnet), address.as_bytes().to_vec());
+            assert_eq!(point.address(compressed, network), address.as_bytes().to_vec());
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,120 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use crate::rpc_api::RpcApi;
+use crate::network::Network;
+use crate::tx::Tx;
+use crate::tx_output::TxOutput;
+
+/// Where the outputs spent by transaction inputs come from. `prev_tx` is the
+/// previous tx id as stored in `TxInput::prev_tx`.
+#[allow(async_fn_in_trait)]
+pub trait PrevoutProvider {
+    /// The output `prev_index` of `prev_tx`, None if it can't be found.
+    async fn prevout(&self, prev_tx: &[u8], prev_index: u32) -> Option<TxOutput>;
+    /// Loads the outputs spent by all the inputs of `tx` ahead of time, when the provider can.
+    async fn prefetch(&self, _tx: &Tx) {}
+}
+
+/// Spent outputs supplied by the caller, nothing is fetched.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryPrevoutProvider {
+    prevouts: HashMap<(Vec<u8>, u32), TxOutput>,
+}
+impl MemoryPrevoutProvider {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// The outputs spent by `tx`, in the order of its inputs.
+    pub fn for_tx(tx: &Tx, spent_outputs: Vec<TxOutput>) -> Self {
+        let mut provider = Self::new();
+        for (tx_in, prevout) in tx.tx_ins().iter().zip(spent_outputs) {
+            provider.insert(tx_in.prev_tx(), tx_in.prev_index(), prevout);
+        }
+        provider
+    }
+    pub fn insert(&mut self, prev_tx: Vec<u8>, prev_index: u32, prevout: TxOutput) {
+        self.prevouts.insert((prev_tx, prev_index), prevout);
+    }
+}
+impl PrevoutProvider for MemoryPrevoutProvider {
+    async fn prevout(&self, prev_tx: &[u8], prev_index: u32) -> Option<TxOutput> {
+        self.prevouts.get(&(prev_tx.to_vec(), prev_index)).cloned()
+    }
+}
+
+/// Fetches the previous transactions from the node. Each one is fetched once
+/// per provider, and `prefetch` gets all the parents of a tx in one request.
+#[derive(Debug, Clone)]
+pub struct RpcPrevoutProvider {
+    network: Network,
+    // outputs of the previous txs fetched so far, by tx id
+    cache: RefCell<HashMap<Vec<u8>, Vec<TxOutput>>>,
+}
+impl RpcPrevoutProvider {
+    pub fn new(network: Network) -> Self {
+        Self { network, cache: RefCell::new(HashMap::new()) }
+    }
+    // the parents of `tx` not fetched yet, each once
+    fn missing_parents(&self, tx: &Tx) -> Vec<Vec<u8>> {
+        let cache = self.cache.borrow();
+        let mut parents: Vec<Vec<u8>> = vec![];
+        for tx_in in tx.tx_ins() {
+            if !cache.contains_key(&tx_in.prev_tx()) && !parents.contains(&tx_in.prev_tx()) {
+                parents.push(tx_in.prev_tx());
+            }
+        }
+        parents
+    }
+}
+impl PrevoutProvider for RpcPrevoutProvider {
+    async fn prevout(&self, prev_tx: &[u8], prev_index: u32) -> Option<TxOutput> {
+        if let Some(outputs) = self.cache.borrow().get(prev_tx) {
+            return outputs.get(prev_index as usize).cloned();
+        }
+        let tx = RpcApi::new(self.network).get_tx(&hex::encode(prev_tx)).await.ok()?;
+        let outputs = tx.tx_outs();
+        self.cache.borrow_mut().insert(prev_tx.to_vec(), outputs.clone());
+        outputs.get(prev_index as usize).cloned()
+    }
+    async fn prefetch(&self, tx: &Tx) {
+        let parents = self.missing_parents(tx);
+        if parents.is_empty() {
+            return;
+        }
+        let tx_ids: Vec<String> = parents.iter().map(hex::encode).collect();
+        // on failure the outputs are fetched one by one when needed
+        let Ok(txs) = RpcApi::new(self.network).get_txs(&tx_ids).await else {
+            return;
+        };
+        let mut cache = self.cache.borrow_mut();
+        for (prev_tx, parent) in parents.into_iter().zip(txs) {
+            if let Some(parent) = parent {
+                cache.insert(prev_tx, parent.tx_outs());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::future::Future;
+    use crate::script::Script;
+    use crate::tx_input::TxInput;
+    #[test]
+    fn test_rpc_provider_cache() {
+        let inputs = [1u8, 2, 1, 3].iter().map(|parent| TxInput::new(vec![*parent; 32], 0, Script::new(vec![]), 0xffffffff)).collect();
+        let tx = Tx::new(2, inputs, vec![], 0, Network::Mainnet, false);
+        let provider = RpcPrevoutProvider::new(Network::Mainnet);
+        provider.cache.borrow_mut().insert(vec![3u8; 32], vec![TxOutput::new(1000, Script::new(vec![]))]);
+        // a parent shared by several inputs is fetched once
+        assert_eq!(provider.missing_parents(&tx), vec![vec![1u8; 32], vec![2u8; 32]]);
+        // cached outputs are served without a request
+        let mut prevout = std::pin::pin!(provider.prevout(&[3u8; 32], 0));
+        let mut context = std::task::Context::from_waker(std::task::Waker::noop());
+        match prevout.as_mut().poll(&mut context) {
+            std::task::Poll::Ready(prevout) => assert_eq!(prevout.map(|prevout| prevout.amount()), Some(1000)),
+            std::task::Poll::Pending => panic!("cached prevout was fetched"),
+        }
+    }
+}
@@ -5,6 +5,8 @@ use crate::field_element::FieldElement;
 use crate::point::Point;
 use crate::secp256k1::Secp256k1;
 use rfc6979::consts::U32;
+use crate::helpers::endianness::int_to_big_endian;
+use crate::helpers::taproot::tagged_hash;
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct PrivateKey {
@@ -41,9 +43,40 @@ impl PrivateKey {
         }
         Signature::new(&r, &s)
     }
+    /// BIP340 signature of a 32 byte message, `aux_rand` is mixed into the nonce.
+    pub fn sign_schnorr(&self, msg: &[u8], aux_rand: &[u8; 32]) -> Vec<u8> {
+        let s256 = Secp256k1::new();
+        let n = &s256.n;
+        // the key whose point has an even y
+        let d = if self.public_key.has_even_y() { self.secret.clone() } else { n - &self.secret };
+        let pubkey = self.public_key.xonly();
+
+        let t: Vec<u8> = int_to_big_endian(&d, 32).iter()
+            .zip(tagged_hash("BIP0340/aux", aux_rand))
+            .map(|(d, aux)| d ^ aux)
+            .collect();
+        let mut nonce = t;
+        nonce.extend(&pubkey);
+        nonce.extend(msg);
+        let k = BigUint::from_bytes_be(&tagged_hash("BIP0340/nonce", &nonce)) % n;
+        let generator = Point::new_secp256k1(&Some(FieldElement::new(&s256.gx, &s256.p)), &Some(FieldElement::new(&s256.gy, &s256.p)));
+        let r = generator * k.clone();
+        let k = if r.has_even_y() { k } else { n - k };
+
+        let mut challenge = r.xonly();
+        challenge.extend(&pubkey);
+        challenge.extend(msg);
+        let e = BigUint::from_bytes_be(&tagged_hash("BIP0340/challenge", &challenge)) % n;
+        let mut sig = r.xonly();
+        sig.extend(int_to_big_endian(&((k + e * d) % n), 32));
+        sig
+    }
     pub fn point(&self) -> Point {
         self.public_key.clone()
     }
+    pub fn secret(&self) -> &BigUint {
+        &self.secret
+    }
     pub fn deterministic_k(&self, z: &BigUint) -> BigUint {
         let s256 = Secp256k1::new();
         let n_bytes = s256.n.to_bytes_be();
@@ -95,4 +128,29 @@ mod tests {
 
         assert!(point.verify(&z, &sig));
     }
+    #[test]
+    fn test_sign_schnorr() {
+        // BIP340 test vectors 0 and 1
+        let vectors = [
+            ("03", "0000000000000000000000000000000000000000000000000000000000000000", "0000000000000000000000000000000000000000000000000000000000000000",
+             "F9308A019258C31049344F85F89D5229B531C845836F99B08601F113BCE036F9",
+             "E907831F80848D1069A5371B402410364BDF1C5F8307B0084C55F1CE2DCA821525F66A4A85EA8B71E482A74F382D2CE5EBEEE8FDB2172F477DF4900D310536C0"),
+            ("B7E151628AED2A6ABF7158809CF4F3C762E7160F38B4DA56A784D9045190CFEF", "0000000000000000000000000000000000000000000000000000000000000001", "243F6A8885A308D313198A2E03707344A4093822299F31D0082EFA98EC4E6C89",
+             "DFF1D77F2A671C5F36183726DB2341BE58FEAE1DA2DECED843240F7B502BA659",
+             "6896BD60EEAE296DB48A229FF71DFE071BDE413E6D43F917DC8DCF8C78DE33418906D11AC976ABCCB20B091292BFF4EA897EFCB639EA871CFA95F6DE339E4B0A"),
+        ];
+        for (secret, aux_rand, msg, pubkey, sig) in vectors {
+            let private_key = PrivateKey::new(&BigUint::from_bytes_be(&hex::decode(secret).unwrap()));
+            let aux_rand: [u8; 32] = hex::decode(aux_rand).unwrap().try_into().unwrap();
+            let msg = hex::decode(msg).unwrap();
+            let pubkey = hex::decode(pubkey).unwrap();
+            let mut sig = hex::decode(sig).unwrap();
+            assert_eq!(private_key.point().xonly(), pubkey);
+            assert_eq!(private_key.sign_schnorr(&msg, &aux_rand), sig);
+            let point = Point::lift_x(&pubkey).unwrap();
+            assert!(point.verify_schnorr(&msg, &sig));
+            sig[63] ^= 1;
+            assert!(!point.verify_schnorr(&msg, &sig));
+        }
+    }
 }
@@ -0,0 +1,832 @@
+use std::io::{Cursor, Error, ErrorKind, Read};
+use std::iter::once;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use crate::helpers::hash160::hash160;
+use crate::helpers::decode_error::DecodeError;
+use crate::bip32::DerivationPath;
+use crate::network::Network;
+use crate::helpers::op_codes::{OP_1, OP_16, OP_CHECKMULTISIG};
+use crate::helpers::sig_hash::SIGHASH_ALL;
+use crate::helpers::varint::{encode_varint, read_varint};
+use crate::prevout_provider::PrevoutProvider;
+use crate::private_key::PrivateKey;
+use crate::script::{Cmd, Script};
+use crate::tx::Tx;
+use crate::tx_output::TxOutput;
+
+pub const PSBT_MAGIC: [u8; 5] = *b"psbt\xff";
+
+// global key types
+pub const PSBT_GLOBAL_UNSIGNED_TX: u8 = 0x00;
+pub const PSBT_GLOBAL_XPUB: u8 = 0x01;
+pub const PSBT_GLOBAL_VERSION: u8 = 0xfb;
+// key type of proprietary extensions, in every map
+pub const PSBT_PROPRIETARY: u8 = 0xfc;
+// input key types
+pub const PSBT_IN_NON_WITNESS_UTXO: u8 = 0x00;
+pub const PSBT_IN_WITNESS_UTXO: u8 = 0x01;
+pub const PSBT_IN_PARTIAL_SIG: u8 = 0x02;
+pub const PSBT_IN_SIGHASH_TYPE: u8 = 0x03;
+pub const PSBT_IN_REDEEM_SCRIPT: u8 = 0x04;
+pub const PSBT_IN_WITNESS_SCRIPT: u8 = 0x05;
+pub const PSBT_IN_BIP32_DERIVATION: u8 = 0x06;
+pub const PSBT_IN_FINAL_SCRIPTSIG: u8 = 0x07;
+pub const PSBT_IN_FINAL_SCRIPTWITNESS: u8 = 0x08;
+pub const PSBT_IN_TAP_KEY_SIG: u8 = 0x13;
+pub const PSBT_IN_TAP_SCRIPT_SIG: u8 = 0x14;
+pub const PSBT_IN_TAP_LEAF_SCRIPT: u8 = 0x15;
+pub const PSBT_IN_TAP_BIP32_DERIVATION: u8 = 0x16;
+pub const PSBT_IN_TAP_INTERNAL_KEY: u8 = 0x17;
+pub const PSBT_IN_TAP_MERKLE_ROOT: u8 = 0x18;
+// output key types
+pub const PSBT_OUT_REDEEM_SCRIPT: u8 = 0x00;
+pub const PSBT_OUT_WITNESS_SCRIPT: u8 = 0x01;
+pub const PSBT_OUT_BIP32_DERIVATION: u8 = 0x02;
+pub const PSBT_OUT_TAP_INTERNAL_KEY: u8 = 0x05;
+pub const PSBT_OUT_TAP_TREE: u8 = 0x06;
+pub const PSBT_OUT_TAP_BIP32_DERIVATION: u8 = 0x07;
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message.to_string())
+}
+/// {error, message} of a PSBT that can't be read, with the offset when a
+/// length or the unsigned tx doesn't decode.
+pub fn error_json(error: &Error) -> serde_json::Value {
+    match error.get_ref().and_then(|inner| inner.downcast_ref::<DecodeError>()) {
+        Some(error) => error.get_json(),
+        None => json!({"error": format!("{:?}", error.kind()), "message": error.to_string()}),
+    }
+}
+fn read_bytes(stream: &mut Cursor<Vec<u8>>) -> Result<Vec<u8>, Error> {
+    let length = read_varint(stream)?.value;
+    // a corrupt length can't make us allocate more than what is left
+    if length > stream.get_ref().len() as u64 - stream.position() {
+        return Err(invalid("PSBT field past the end of the data"));
+    }
+    let mut bytes = vec![0u8; length as usize];
+    stream.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+fn write_bytes(result: &mut Vec<u8>, bytes: &[u8]) {
+    result.extend(encode_varint(bytes.len() as u64).unwrap());
+    result.extend(bytes);
+}
+// witness stacks are stored as in the tx: item count, then each item
+fn parse_witness(value: &[u8]) -> Result<Vec<Vec<u8>>, Error> {
+    let mut stream = Cursor::new(value.to_vec());
+    let items = read_varint(&mut stream)?.value;
+    (0..items).map(|_| read_bytes(&mut stream)).collect()
+}
+fn serialize_witness(witness: &[Vec<u8>]) -> Vec<u8> {
+    let mut result = encode_varint(witness.len() as u64).unwrap();
+    for item in witness {
+        write_bytes(&mut result, item);
+    }
+    result
+}
+// value of a BIP32 derivation: master key fingerprint, then the path
+fn derivation_value(fingerprint: [u8; 4], path: &[u32]) -> Vec<u8> {
+    let mut result = fingerprint.to_vec();
+    for index in path {
+        result.extend(index.to_le_bytes());
+    }
+    result
+}
+
+/// The key-value pairs of a PSBT map in their serialized order, unknown keys
+/// included. The first byte of a key is its type, the rest the key data.
+#[derive(Debug, Eq, PartialEq, Clone, Default)]
+pub struct PsbtMap {
+    pairs: Vec<(Vec<u8>, Vec<u8>)>,
+}
+impl PsbtMap {
+    pub fn parse(stream: &mut Cursor<Vec<u8>>) -> Result<Self, Error> {
+        let mut map = PsbtMap::default();
+        loop {
+            // a map must end with its separator, not with the data
+            if stream.position() >= stream.get_ref().len() as u64 {
+                return Err(Error::new(ErrorKind::UnexpectedEof, "PSBT map without separator"));
+            }
+            // an empty key ends the map
+            let key = read_bytes(stream)?;
+            if key.is_empty() {
+                return Ok(map);
+            }
+            if map.pairs.iter().any(|(existing, _)| *existing == key) {
+                return Err(invalid("duplicate PSBT key"));
+            }
+            let value = read_bytes(stream)?;
+            map.pairs.push((key, value));
+        }
+    }
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut result = vec![];
+        for (key, value) in &self.pairs {
+            write_bytes(&mut result, key);
+            write_bytes(&mut result, value);
+        }
+        result.push(0x00);
+        result
+    }
+    pub fn pairs(&self) -> &[(Vec<u8>, Vec<u8>)] {
+        &self.pairs
+    }
+    /// The value of the key made of the type alone.
+    pub fn get(&self, key_type: u8) -> Option<&Vec<u8>> {
+        self.pairs.iter().find(|(key, _)| *key == [key_type]).map(|(_, value)| value)
+    }
+    /// Key data and value of every key of the type.
+    pub fn get_all(&self, key_type: u8) -> Vec<(&[u8], &Vec<u8>)> {
+        self.pairs.iter().filter(|(key, _)| key[0] == key_type).map(|(key, value)| (&key[1..], value)).collect()
+    }
+    /// Sets the value of `key`, keeping its position if it is already there.
+    pub fn insert(&mut self, key: Vec<u8>, value: Vec<u8>) {
+        match self.pairs.iter_mut().find(|(existing, _)| *existing == key) {
+            Some((_, existing)) => *existing = value,
+            None => self.pairs.push((key, value)),
+        }
+    }
+    /// Removes every key of the type.
+    pub fn remove(&mut self, key_type: u8) {
+        self.pairs.retain(|(key, _)| key[0] != key_type);
+    }
+    /// Adds the keys of `other` missing here, values already here are kept.
+    pub fn merge(&mut self, other: &PsbtMap) {
+        for (key, value) in &other.pairs {
+            if !self.pairs.iter().any(|(existing, _)| existing == key) {
+                self.pairs.push((key.clone(), value.clone()));
+            }
+        }
+    }
+}
+
+// which map a key belongs to, key types mean different things in each
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+enum MapKind {
+    Global,
+    Input,
+    Output,
+}
+fn key_type_name(kind: MapKind, key_type: u8) -> &'static str {
+    match (kind, key_type) {
+        (_, PSBT_PROPRIETARY) => "PROPRIETARY",
+        (MapKind::Global, PSBT_GLOBAL_UNSIGNED_TX) => "UNSIGNED_TX",
+        (MapKind::Global, PSBT_GLOBAL_XPUB) => "XPUB",
+        (MapKind::Global, PSBT_GLOBAL_VERSION) => "VERSION",
+        (MapKind::Input, PSBT_IN_NON_WITNESS_UTXO) => "NON_WITNESS_UTXO",
+        (MapKind::Input, PSBT_IN_WITNESS_UTXO) => "WITNESS_UTXO",
+        (MapKind::Input, PSBT_IN_PARTIAL_SIG) => "PARTIAL_SIG",
+        (MapKind::Input, PSBT_IN_SIGHASH_TYPE) => "SIGHASH_TYPE",
+        (MapKind::Input, PSBT_IN_REDEEM_SCRIPT) => "REDEEM_SCRIPT",
+        (MapKind::Input, PSBT_IN_WITNESS_SCRIPT) => "WITNESS_SCRIPT",
+        (MapKind::Input, PSBT_IN_BIP32_DERIVATION) => "BIP32_DERIVATION",
+        (MapKind::Input, PSBT_IN_FINAL_SCRIPTSIG) => "FINAL_SCRIPTSIG",
+        (MapKind::Input, PSBT_IN_FINAL_SCRIPTWITNESS) => "FINAL_SCRIPTWITNESS",
+        (MapKind::Input, PSBT_IN_TAP_KEY_SIG) => "TAP_KEY_SIG",
+        (MapKind::Input, PSBT_IN_TAP_SCRIPT_SIG) => "TAP_SCRIPT_SIG",
+        (MapKind::Input, PSBT_IN_TAP_LEAF_SCRIPT) => "TAP_LEAF_SCRIPT",
+        (MapKind::Input, PSBT_IN_TAP_BIP32_DERIVATION) => "TAP_BIP32_DERIVATION",
+        (MapKind::Input, PSBT_IN_TAP_INTERNAL_KEY) => "TAP_INTERNAL_KEY",
+        (MapKind::Input, PSBT_IN_TAP_MERKLE_ROOT) => "TAP_MERKLE_ROOT",
+        (MapKind::Output, PSBT_OUT_REDEEM_SCRIPT) => "REDEEM_SCRIPT",
+        (MapKind::Output, PSBT_OUT_WITNESS_SCRIPT) => "WITNESS_SCRIPT",
+        (MapKind::Output, PSBT_OUT_BIP32_DERIVATION) => "BIP32_DERIVATION",
+        (MapKind::Output, PSBT_OUT_TAP_INTERNAL_KEY) => "TAP_INTERNAL_KEY",
+        (MapKind::Output, PSBT_OUT_TAP_TREE) => "TAP_TREE",
+        (MapKind::Output, PSBT_OUT_TAP_BIP32_DERIVATION) => "TAP_BIP32_DERIVATION",
+        _ => "UNKNOWN",
+    }
+}
+fn pairs_json(map: &PsbtMap, kind: MapKind) -> serde_json::Value {
+    let pairs: Vec<serde_json::Value> = map.pairs().iter().map(|(key, value)| json!({
+        "type": key_type_name(kind, key[0]),
+        "key": hex::encode(key),
+        "value": hex::encode(value),
+    })).collect();
+    json!(pairs)
+}
+// BIP32 derivations: the pubkey is the key data, the value the master key
+// fingerprint followed by the path as little endian u32s
+fn derivations_json(derivations: Vec<(&[u8], &Vec<u8>)>) -> serde_json::Value {
+    let derivations: Vec<serde_json::Value> = derivations.into_iter().filter(|(_, value)| value.len() >= 4 && value.len().is_multiple_of(4)).map(|(pubkey, value)| {
+        let path = DerivationPath(value[4..].chunks(4).map(|index| u32::from_le_bytes(index.try_into().unwrap())).collect());
+        json!({
+            "pubkey": hex::encode(pubkey),
+            "fingerprint": hex::encode(&value[..4]),
+            "path": path.to_string(),
+        })
+    }).collect();
+    json!(derivations)
+}
+fn script_asm(value: Option<&Vec<u8>>) -> Option<String> {
+    Script::parse_raw(value?).ok().map(|script| script.to_string().trim_end().to_string())
+}
+// m of a bare "m <keys> n OP_CHECKMULTISIG" script, None unless it has the
+// n keys and 1 <= m <= n <= 16
+fn multisig_threshold(script: &Script) -> Option<usize> {
+    let [Cmd::Op(m), keys @ .., Cmd::Op(n), Cmd::Op(OP_CHECKMULTISIG)] = script.cmds.as_slice() else {
+        return None;
+    };
+    if !(OP_1..=OP_16).contains(m) || !(m..=&OP_16).contains(&n) || keys.len() != (n - OP_1 + 1) as usize {
+        return None;
+    }
+    match keys.iter().all(|key| key.data().is_some()) {
+        true => Some((m - OP_1 + 1) as usize),
+        false => None,
+    }
+}
+// signatures for a multisig script in the order of its keys, None while
+// there are fewer than required
+fn multisig_sigs(script: &Script, partial_sigs: &[(Vec<u8>, Vec<u8>)]) -> Option<Vec<Vec<u8>>> {
+    let required = multisig_threshold(script)?;
+    let sigs: Vec<Vec<u8>> = script.cmds[1..script.cmds.len() - 2].iter()
+        .filter_map(|cmd| partial_sigs.iter().find(|(pubkey, _)| Some(pubkey) == cmd.data()))
+        .map(|(_, sig)| sig.clone())
+        .take(required)
+        .collect();
+    match sigs.len() == required {
+        true => Some(sigs),
+        false => None,
+    }
+}
+
+/// A partially signed transaction (BIP174): the unsigned tx with a map of
+/// global data, and one map for each input and output.
+#[derive(Debug, Clone)]
+pub struct Psbt {
+    tx: Tx,
+    network: Network,
+    pub global: PsbtMap,
+    pub inputs: Vec<PsbtMap>,
+    pub outputs: Vec<PsbtMap>,
+}
+impl Psbt {
+    pub fn parse(bytes: &[u8], network: Network) -> Result<Self, Error> {
+        if !bytes.starts_with(&PSBT_MAGIC) {
+            return Err(invalid("missing PSBT magic bytes"));
+        }
+        let mut stream = Cursor::new(bytes.to_vec());
+        stream.set_position(PSBT_MAGIC.len() as u64);
+        let global = PsbtMap::parse(&mut stream)?;
+        let raw_tx = global.get(PSBT_GLOBAL_UNSIGNED_TX).ok_or(invalid("missing PSBT unsigned tx"))?;
+        let tx = Tx::parse(&mut Cursor::new(raw_tx.clone()), network)?;
+        if tx.serialize_legacy() != *raw_tx || tx.tx_ins().iter().any(|tx_in| !tx_in.script_sig.cmds.is_empty()) {
+            return Err(invalid("PSBT tx is not unsigned"));
+        }
+        let inputs = (0..tx.tx_ins().len()).map(|_| PsbtMap::parse(&mut stream)).collect::<Result<Vec<_>, _>>()?;
+        let outputs = (0..tx.tx_outs().len()).map(|_| PsbtMap::parse(&mut stream)).collect::<Result<Vec<_>, _>>()?;
+        if stream.position() != bytes.len() as u64 {
+            return Err(invalid("data after the PSBT"));
+        }
+        Ok(Psbt { tx, network, global, inputs, outputs })
+    }
+    pub fn from_base64(psbt: &str, network: Network) -> Result<Self, Error> {
+        let bytes = BASE64.decode(psbt.trim()).map_err(|_| invalid("invalid base64"))?;
+        Psbt::parse(&bytes, network)
+    }
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut result = PSBT_MAGIC.to_vec();
+        result.extend(self.global.serialize());
+        for map in self.inputs.iter().chain(&self.outputs) {
+            result.extend(map.serialize());
+        }
+        result
+    }
+    pub fn to_base64(&self) -> String {
+        BASE64.encode(self.serialize())
+    }
+    /// The unsigned transaction.
+    pub fn tx(&self) -> &Tx {
+        &self.tx
+    }
+    pub fn version(&self) -> u32 {
+        match self.global.get(PSBT_GLOBAL_VERSION) {
+            Some(version) if version.len() == 4 => u32::from_le_bytes(version[..].try_into().unwrap()),
+            _ => 0,
+        }
+    }
+    /// The output spent by an input, from its witness utxo or its previous tx.
+    pub fn utxo(&self, input_index: usize) -> Option<TxOutput> {
+        let map = &self.inputs[input_index];
+        if let Some(value) = map.get(PSBT_IN_WITNESS_UTXO) {
+            return TxOutput::parse(&mut Cursor::new(value.clone())).ok();
+        }
+        self.non_witness_utxo(input_index)
+    }
+    // the output spent by an input taken from the whole previous tx
+    fn non_witness_utxo(&self, input_index: usize) -> Option<TxOutput> {
+        let tx_in = &self.tx.tx_ins()[input_index];
+        let value = self.inputs[input_index].get(PSBT_IN_NON_WITNESS_UTXO)?;
+        let prev_tx = Tx::parse(&mut Cursor::new(value.clone()), self.network).ok()?;
+        // the previous tx must be the one the input spends
+        if prev_tx.tx_id() != hex::encode(tx_in.prev_tx()) {
+            return None;
+        }
+        prev_tx.tx_outs().get(tx_in.prev_index() as usize).cloned()
+    }
+    /// Pubkey and signature of every partial signature of an input.
+    pub fn partial_sigs(&self, input_index: usize) -> Vec<(Vec<u8>, Vec<u8>)> {
+        self.inputs[input_index].get_all(PSBT_IN_PARTIAL_SIG).into_iter().map(|(pubkey, sig)| (pubkey.to_vec(), sig.clone())).collect()
+    }
+    pub fn sighash_type(&self, input_index: usize) -> Option<u32> {
+        let value = self.inputs[input_index].get(PSBT_IN_SIGHASH_TYPE)?;
+        Some(u32::from_le_bytes(value[..].try_into().ok()?))
+    }
+    pub fn redeem_script(&self, input_index: usize) -> Option<Script> {
+        Script::parse_raw(self.inputs[input_index].get(PSBT_IN_REDEEM_SCRIPT)?).ok()
+    }
+    pub fn witness_script(&self, input_index: usize) -> Option<Script> {
+        Script::parse_raw(self.inputs[input_index].get(PSBT_IN_WITNESS_SCRIPT)?).ok()
+    }
+    pub fn is_finalized(&self, input_index: usize) -> bool {
+        let map = &self.inputs[input_index];
+        map.get(PSBT_IN_FINAL_SCRIPTSIG).is_some() || map.get(PSBT_IN_FINAL_SCRIPTWITNESS).is_some()
+    }
+    /// Sum of the inputs less the outputs, None while a utxo is missing.
+    pub fn fee(&self) -> Option<i64> {
+        let inputs = (0..self.inputs.len()).map(|input_index| self.utxo(input_index).map(|utxo| utxo.amount())).sum::<Option<u64>>()?;
+        let outputs: u64 = self.tx.tx_outs().iter().map(|tx_out| tx_out.amount()).sum();
+        Some(inputs as i64 - outputs as i64)
+    }
+    /// Signatures found and needed by an input, and what is still missing
+    /// before it can be finalized.
+    pub fn input_status(&self, input_index: usize) -> (usize, Option<usize>, Vec<&'static str>) {
+        let map = &self.inputs[input_index];
+        if self.is_finalized(input_index) {
+            return (0, None, vec![]);
+        }
+        let Some(utxo) = self.utxo(input_index) else {
+            return (0, None, vec!["utxo"]);
+        };
+        let mut script = utxo.script_pubkey();
+        if script.is_p2tr_script_pubkey() {
+            let signatures = map.get_all(PSBT_IN_TAP_KEY_SIG).len() + map.get_all(PSBT_IN_TAP_SCRIPT_SIG).len();
+            return match signatures {
+                0 => (0, Some(1), vec!["signatures"]),
+                _ => (signatures, Some(1), vec![]),
+            };
+        }
+        if script.is_p2sh_script_pubkey() {
+            match self.redeem_script(input_index) {
+                Some(redeem_script) => script = redeem_script,
+                None => return (0, None, vec!["redeem_script"]),
+            }
+        }
+        if script.is_p2wsh_script_pubkey() {
+            match self.witness_script(input_index) {
+                Some(witness_script) => script = witness_script,
+                None => return (0, None, vec!["witness_script"]),
+            }
+        }
+        let required = multisig_threshold(&script).unwrap_or(1);
+        let signatures = self.partial_sigs(input_index).len();
+        let missing = if signatures < required { vec!["signatures"] } else { vec![] };
+        (signatures, Some(required), missing)
+    }
+    /// Creator: a PSBT with empty maps for an unsigned transaction.
+    pub fn new(tx: Tx, network: Network) -> Result<Self, Error> {
+        if tx.tx_ins().iter().any(|tx_in| !tx_in.script_sig.cmds.is_empty() || tx_in.witness.as_ref().is_some_and(|witness| !witness.is_empty())) {
+            return Err(invalid("PSBT tx is not unsigned"));
+        }
+        let mut global = PsbtMap::default();
+        global.insert(vec![PSBT_GLOBAL_UNSIGNED_TX], tx.serialize_legacy());
+        let inputs = vec![PsbtMap::default(); tx.tx_ins().len()];
+        let outputs = vec![PsbtMap::default(); tx.tx_outs().len()];
+        Ok(Psbt { tx, network, global, inputs, outputs })
+    }
+    /// Updater: the output spent by an input.
+    pub fn set_witness_utxo(&mut self, input_index: usize, utxo: &TxOutput) {
+        self.inputs[input_index].insert(vec![PSBT_IN_WITNESS_UTXO], utxo.serialize());
+    }
+    /// Updater: the whole tx spent by an input, false if it isn't the one the input spends.
+    pub fn set_non_witness_utxo(&mut self, input_index: usize, prev_tx: &Tx) -> bool {
+        if prev_tx.tx_id() != hex::encode(self.tx.tx_ins()[input_index].prev_tx()) {
+            return false;
+        }
+        self.inputs[input_index].insert(vec![PSBT_IN_NON_WITNESS_UTXO], prev_tx.serialize_legacy());
+        true
+    }
+    /// Updater: the outputs spent by the witness inputs that have none yet, from
+    /// `provider`. A p2sh input needs its redeem script first. Legacy inputs need
+    /// the whole previous tx, given with `set_non_witness_utxo`.
+    pub async fn add_utxos(&mut self, provider: &impl PrevoutProvider) {
+        for (input_index, tx_in) in self.tx.tx_ins().iter().enumerate() {
+            if self.utxo(input_index).is_some() {
+                continue;
+            }
+            let Some(utxo) = tx_in.prev_output(provider).await else {
+                continue;
+            };
+            let script_pubkey = utxo.script_pubkey();
+            let is_witness = match script_pubkey.is_p2sh_script_pubkey() {
+                true => self.redeem_script(input_index).is_some_and(|redeem_script| redeem_script.witness_program().is_some()),
+                false => script_pubkey.witness_program().is_some(),
+            };
+            // a witness utxo alone could lie about the amount a legacy input spends
+            if is_witness {
+                self.set_witness_utxo(input_index, &utxo);
+            }
+        }
+    }
+    pub fn set_redeem_script(&mut self, input_index: usize, redeem_script: &Script) {
+        self.inputs[input_index].insert(vec![PSBT_IN_REDEEM_SCRIPT], redeem_script.raw_serialize());
+    }
+    pub fn set_witness_script(&mut self, input_index: usize, witness_script: &Script) {
+        self.inputs[input_index].insert(vec![PSBT_IN_WITNESS_SCRIPT], witness_script.raw_serialize());
+    }
+    /// Updater: the master key fingerprint and path `pubkey` of an input is derived with.
+    pub fn add_input_derivation(&mut self, input_index: usize, pubkey: &[u8], fingerprint: [u8; 4], path: &[u32]) {
+        self.inputs[input_index].insert([&[PSBT_IN_BIP32_DERIVATION], pubkey].concat(), derivation_value(fingerprint, path));
+    }
+    /// Updater: the master key fingerprint and path `pubkey` of an output is derived with.
+    pub fn add_output_derivation(&mut self, output_index: usize, pubkey: &[u8], fingerprint: [u8; 4], path: &[u32]) {
+        self.outputs[output_index].insert([&[PSBT_OUT_BIP32_DERIVATION], pubkey].concat(), derivation_value(fingerprint, path));
+    }
+    /// Signer: adds the signature of `private_key` to an input it can sign,
+    /// with the sighash type of the input or SIGHASH_ALL. Returns false when
+    /// the key isn't in the input script, the utxo or a script is missing, or
+    /// a legacy input comes without its previous tx.
+    pub fn sign(&mut self, input_index: usize, private_key: &PrivateKey) -> bool {
+        let Some(utxo) = self.utxo(input_index) else {
+            return false;
+        };
+        let hash_type = match self.sighash_type(input_index) {
+            Some(hash_type) => match u8::try_from(hash_type) {
+                Ok(hash_type) => hash_type,
+                Err(_) => return false,
+            },
+            None => SIGHASH_ALL,
+        };
+        let mut script = utxo.script_pubkey();
+        if script.is_p2sh_script_pubkey() {
+            let Some(redeem_script) = self.redeem_script(input_index) else {
+                return false;
+            };
+            if *script.cmds[1].data().unwrap() != hash160(&redeem_script.raw_serialize()).to_vec() {
+                return false;
+            }
+            script = redeem_script;
+        }
+        let (script_code, segwit) = if script.is_p2wpkh_script_pubkey() {
+            (Script::p2pkh_script(script.cmds[1].data().unwrap().clone()), true)
+        } else if script.is_p2wsh_script_pubkey() {
+            let Some(witness_script) = self.witness_script(input_index) else {
+                return false;
+            };
+            if *script.cmds[1].data().unwrap() != Sha256::digest(witness_script.raw_serialize()).to_vec() {
+                return false;
+            }
+            (witness_script, true)
+        } else {
+            (script, false)
+        };
+        // only the whole previous tx proves what a legacy input spends
+        if !segwit && self.non_witness_utxo(input_index).as_ref() != Some(&utxo) {
+            return false;
+        }
+        // the key appears in the script itself or hashed, segwit only takes compressed keys
+        let point = private_key.point();
+        let secs = match segwit {
+            true => vec![point.sec(true)],
+            false => vec![point.sec(true), point.sec(false)],
+        };
+        let Some(sec) = secs.into_iter().find(|sec| {
+            let h160 = hash160(sec).to_vec();
+            script_code.cmds.iter().any(|cmd| cmd.data().is_some_and(|data| data == sec || *data == h160))
+        }) else {
+            return false;
+        };
+        let z = match segwit {
+            true => self.tx.sig_hash_segwit(input_index, &script_code, utxo.amount(), hash_type),
+            false => self.tx.sig_hash_legacy(input_index, &script_code, hash_type),
+        };
+        let mut sig = private_key.sign(&z).der();
+        sig.push(hash_type);
+        self.inputs[input_index].insert([vec![PSBT_IN_PARTIAL_SIG], sec].concat(), sig);
+        true
+    }
+    /// Combiner: adds the data of another PSBT of the same transaction, ours is kept on conflicts.
+    pub fn combine(&mut self, other: &Psbt) -> Result<(), Error> {
+        if self.tx.tx_id() != other.tx.tx_id() {
+            return Err(invalid("PSBTs of different transactions"));
+        }
+        let maps = once(&mut self.global).chain(&mut self.inputs).chain(&mut self.outputs);
+        let other_maps = once(&other.global).chain(&other.inputs).chain(&other.outputs);
+        for (map, other_map) in maps.zip(other_maps) {
+            map.merge(other_map);
+        }
+        Ok(())
+    }
+    /// Finalizer: builds the final scriptSig and witness of an input from its
+    /// partial signatures, for p2pkh, p2wpkh, p2sh-p2wpkh and multisig in
+    /// p2wsh, p2sh-p2wsh or p2sh. The data used to sign is then removed.
+    pub fn finalize_input(&mut self, input_index: usize) -> bool {
+        if self.is_finalized(input_index) {
+            return true;
+        }
+        let Some(utxo) = self.utxo(input_index) else {
+            return false;
+        };
+        let partial_sigs = self.partial_sigs(input_index);
+        let mut script = utxo.script_pubkey();
+        let redeem_script = match script.is_p2sh_script_pubkey() {
+            true => match self.redeem_script(input_index) {
+                Some(redeem_script) => Some(redeem_script),
+                None => return false,
+            },
+            false => None,
+        };
+        if let Some(redeem_script) = &redeem_script {
+            script = redeem_script.clone();
+        }
+        // signature and pubkey for a script locked to a key hash
+        let key_sig = |h160: &Vec<u8>| partial_sigs.iter()
+            .find(|(pubkey, _)| hash160(pubkey).to_vec() == *h160)
+            .map(|(pubkey, sig)| vec![sig.clone(), pubkey.clone()]);
+        let (mut script_sig, witness) = if script.is_p2pkh_script_pubkey() {
+            let Some(items) = key_sig(script.cmds[2].data().unwrap()) else {
+                return false;
+            };
+            (items, None)
+        } else if script.is_p2wpkh_script_pubkey() {
+            let Some(items) = key_sig(script.cmds[1].data().unwrap()) else {
+                return false;
+            };
+            (vec![], Some(items))
+        } else if script.is_p2wsh_script_pubkey() {
+            let Some(witness_script) = self.witness_script(input_index) else {
+                return false;
+            };
+            let Some(sigs) = multisig_sigs(&witness_script, &partial_sigs) else {
+                return false;
+            };
+            // the extra element consumed by OP_CHECKMULTISIG comes first
+            (vec![], Some([vec![vec![]], sigs, vec![witness_script.raw_serialize()]].concat()))
+        } else {
+            let Some(sigs) = multisig_sigs(&script, &partial_sigs) else {
+                return false;
+            };
+            ([vec![vec![]], sigs].concat(), None)
+        };
+        if let Some(redeem_script) = redeem_script {
+            script_sig.push(redeem_script.raw_serialize());
+        }
+        let map = &mut self.inputs[input_index];
+        if !script_sig.is_empty() {
+            let script_sig = Script::new(script_sig.into_iter().map(Cmd::push).collect());
+            map.insert(vec![PSBT_IN_FINAL_SCRIPTSIG], script_sig.raw_serialize());
+        }
+        if let Some(witness) = witness {
+            map.insert(vec![PSBT_IN_FINAL_SCRIPTWITNESS], serialize_witness(&witness));
+        }
+        for key_type in PSBT_IN_PARTIAL_SIG..=PSBT_IN_BIP32_DERIVATION {
+            map.remove(key_type);
+        }
+        true
+    }
+    /// Finalizes every input it can, true when all of them are finalized.
+    pub fn finalize(&mut self) -> bool {
+        let mut finalized = true;
+        for input_index in 0..self.inputs.len() {
+            finalized &= self.finalize_input(input_index);
+        }
+        finalized
+    }
+    /// Extractor: the signed transaction, once every input is finalized.
+    pub fn extract(&self) -> Result<Tx, Error> {
+        let mut inputs = self.tx.tx_ins();
+        let mut segwit = false;
+        for (input_index, tx_in) in inputs.iter_mut().enumerate() {
+            if !self.is_finalized(input_index) {
+                return Err(invalid("PSBT input not finalized"));
+            }
+            let map = &self.inputs[input_index];
+            if let Some(script_sig) = map.get(PSBT_IN_FINAL_SCRIPTSIG) {
+                tx_in.script_sig = Script::parse_raw(script_sig)?;
+            }
+            if let Some(witness) = map.get(PSBT_IN_FINAL_SCRIPTWITNESS) {
+                tx_in.witness = Some(parse_witness(witness)?);
+                segwit = true;
+            }
+        }
+        let tx = Tx::new(self.tx.version(), inputs, self.tx.tx_outs(), self.tx.locktime(), self.network, segwit);
+        // parsed back to come with its json like any other tx
+        Ok(Tx::parse(&mut Cursor::new(tx.serialize(false)), self.network)?)
+    }
+    pub fn get_json(&self) -> serde_json::Value {
+        let network = self.network;
+        let inputs: Vec<serde_json::Value> = (0..self.inputs.len()).map(|input_index| {
+            let map = &self.inputs[input_index];
+            let tx_in = &self.tx.tx_ins()[input_index];
+            let utxo = self.utxo(input_index).map(|utxo| json!({
+                "amount": utxo.amount(),
+                "script_pubkey": hex::encode(utxo.script_pubkey().raw_serialize()),
+                "script_type": utxo.script_pubkey().get_output_type().to_string(),
+                "address": utxo.script_pubkey().get_address(network),
+            }));
+            let partial_sigs: Vec<serde_json::Value> = self.partial_sigs(input_index).iter().map(|(pubkey, sig)| json!({
+                "pubkey": hex::encode(pubkey),
+                "signature": hex::encode(sig),
+            })).collect();
+            let final_script_witness = map.get(PSBT_IN_FINAL_SCRIPTWITNESS)
+                .and_then(|value| parse_witness(value).ok())
+                .map(|witness| witness.iter().map(hex::encode).collect::<Vec<String>>());
+            let (signatures, signatures_required, missing) = self.input_status(input_index);
+            json!({
+                "prev_tx": hex::encode(tx_in.prev_tx()),
+                "prev_index": tx_in.prev_index(),
+                "utxo": utxo,
+                "sighash_type": self.sighash_type(input_index),
+                "partial_sigs": partial_sigs,
+                "redeem_script": script_asm(map.get(PSBT_IN_REDEEM_SCRIPT)),
+                "witness_script": script_asm(map.get(PSBT_IN_WITNESS_SCRIPT)),
+                "bip32_derivation": derivations_json(map.get_all(PSBT_IN_BIP32_DERIVATION)),
+                "final_script_sig": script_asm(map.get(PSBT_IN_FINAL_SCRIPTSIG)),
+                "final_script_witness": final_script_witness,
+                "is_finalized": self.is_finalized(input_index),
+                "is_signed": self.is_finalized(input_index) || (signatures > 0 && missing.is_empty()),
+                "signatures": signatures,
+                "signatures_required": signatures_required,
+                "missing": missing,
+                "pairs": pairs_json(map, MapKind::Input),
+            })
+        }).collect();
+        let outputs: Vec<serde_json::Value> = self.outputs.iter().zip(self.tx.tx_outs()).map(|(map, tx_out)| json!({
+            "amount": tx_out.amount(),
+            "script_pubkey": hex::encode(tx_out.script_pubkey().raw_serialize()),
+            "address": tx_out.script_pubkey().get_address(network),
+            "redeem_script": script_asm(map.get(PSBT_OUT_REDEEM_SCRIPT)),
+            "witness_script": script_asm(map.get(PSBT_OUT_WITNESS_SCRIPT)),
+            "bip32_derivation": derivations_json(map.get_all(PSBT_OUT_BIP32_DERIVATION)),
+            "pairs": pairs_json(map, MapKind::Output),
+        })).collect();
+        json!({
+            "version": self.version(),
+            "tx_id": self.tx.tx_id(),
+            "tx": self.tx.tx_json(),
+            "fee": self.fee(),
+            "is_complete": (0..self.inputs.len()).all(|input_index| self.is_finalized(input_index)),
+            "global": pairs_json(&self.global, MapKind::Global),
+            "inputs": inputs,
+            "outputs": outputs,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use num::BigUint;
+    use crate::helpers::hash256::hash256;
+    use crate::helpers::op_codes::{OP_0, OP_2, OP_3};
+    use crate::helpers::verify_flags::VerifyFlags;
+    use crate::prevout_provider::MemoryPrevoutProvider;
+    use crate::helpers::block_on::block_on;
+    use crate::tx_input::TxInput;
+
+    // a tx spending a p2wpkh output and a p2sh 2-of-3 multisig output
+    fn test_psbt_bytes() -> Vec<u8> {
+        let p2wpkh = Script::new(vec![Cmd::Op(OP_0), Cmd::push(vec![0x11; 20])]);
+        let multisig = Script::new(vec![Cmd::Op(OP_2), Cmd::push(vec![0x02; 33]), Cmd::push(vec![0x03; 33]), Cmd::push(vec![0x02; 33]), Cmd::Op(OP_3), Cmd::Op(OP_CHECKMULTISIG)]);
+        let prev_tx = Tx::new(2, vec![TxInput::new(vec![0x22; 32], 0, Script::new(vec![]), 0xffffffff)], vec![TxOutput::new(70_000, Script::p2sh_script(vec![0x33; 20]))], 0, Network::Mainnet, false);
+        let inputs = vec![
+            TxInput::new(vec![0x44; 32], 1, Script::new(vec![]), 0xfffffffd),
+            TxInput::new(hex::decode(prev_tx.tx_id()).unwrap(), 0, Script::new(vec![]), 0xfffffffd),
+        ];
+        let tx = Tx::new(2, inputs, vec![TxOutput::new(100_000, Script::p2pkh_script(vec![0x55; 20]))], 0, Network::Mainnet, false);
+
+        let mut global = PsbtMap::default();
+        global.insert(vec![PSBT_GLOBAL_UNSIGNED_TX], tx.serialize_legacy());
+        global.insert(vec![0xf0, 0x01], vec![0xab, 0xcd]);
+        let mut input_0 = PsbtMap::default();
+        input_0.insert(vec![PSBT_IN_WITNESS_UTXO], TxOutput::new(40_000, p2wpkh).serialize());
+        input_0.insert([vec![PSBT_IN_PARTIAL_SIG], vec![0x02; 33]].concat(), vec![0x30, 0x01]);
+        input_0.insert([vec![PSBT_IN_BIP32_DERIVATION], vec![0x02; 33]].concat(), [vec![0xde, 0xad, 0xbe, 0xef], 0x8000_0054u32.to_le_bytes().to_vec(), 7u32.to_le_bytes().to_vec()].concat());
+        let mut input_1 = PsbtMap::default();
+        input_1.insert(vec![PSBT_IN_NON_WITNESS_UTXO], prev_tx.serialize_legacy());
+        input_1.insert(vec![PSBT_IN_REDEEM_SCRIPT], multisig.raw_serialize());
+        input_1.insert([vec![PSBT_IN_PARTIAL_SIG], vec![0x03; 33]].concat(), vec![0x30, 0x02]);
+        input_1.insert(vec![0x99], vec![]);
+        let output = PsbtMap::default();
+
+        let mut result = PSBT_MAGIC.to_vec();
+        for map in [global, input_0, input_1, output] {
+            result.extend(map.serialize());
+        }
+        result
+    }
+    #[test]
+    fn test_parse_serialize() {
+        let bytes = test_psbt_bytes();
+        let psbt = Psbt::parse(&bytes, Network::Mainnet).unwrap();
+        assert_eq!(psbt.serialize(), bytes);
+        assert_eq!(Psbt::from_base64(&psbt.to_base64(), Network::Mainnet).unwrap().serialize(), bytes);
+        assert_eq!(psbt.tx().tx_ins().len(), 2);
+        assert_eq!(psbt.global.get_all(0xf0), vec![(&[0x01u8][..], &vec![0xab, 0xcd])]);
+        assert_eq!(psbt.utxo(0).map(|utxo| utxo.amount()), Some(40_000));
+        assert_eq!(psbt.utxo(1).map(|utxo| utxo.amount()), Some(70_000));
+        assert_eq!(psbt.fee(), Some(10_000));
+        // the p2wpkh input has its signature, the multisig one needs another
+        assert_eq!(psbt.input_status(0), (1, Some(1), vec![]));
+        assert_eq!(psbt.input_status(1), (1, Some(2), vec!["signatures"]));
+        let json = psbt.get_json();
+        assert_eq!(json["inputs"][0]["is_signed"], true);
+        assert_eq!(json["inputs"][1]["is_signed"], false);
+        assert_eq!(json["inputs"][0]["bip32_derivation"][0]["path"], "m/84'/7");
+        assert_eq!(json["inputs"][1]["pairs"][3]["type"], "UNKNOWN");
+    }
+    #[test]
+    fn test_parse_invalid() {
+        let bytes = test_psbt_bytes();
+        let error = Psbt::parse(&bytes[1..], Network::Mainnet).unwrap_err();
+        assert_eq!(error_json(&error), json!({"error": "InvalidData", "message": "missing PSBT magic bytes"}));
+        assert!(Psbt::parse(&bytes[..bytes.len() - 1], Network::Mainnet).is_err());
+        assert!(Psbt::parse(&[bytes.clone(), vec![0x00]].concat(), Network::Mainnet).is_err());
+        // the same key twice in the output map
+        let duplicate = [&bytes[..bytes.len() - 1], &[0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00]].concat();
+        assert!(Psbt::parse(&duplicate, Network::Mainnet).is_err());
+    }
+    #[test]
+    fn test_roles() {
+        let keys: Vec<PrivateKey> = (0u8..3).map(|i| PrivateKey::new(&BigUint::from_bytes_be(&hash256(&[b'k', i])))).collect();
+        let secs: Vec<Vec<u8>> = keys.iter().map(|key| key.point().sec(true)).collect();
+        let p2wpkh = |sec: &[u8]| Script::new(vec![Cmd::Op(OP_0), Cmd::push(hash160(sec).to_vec())]);
+        let multisig = Script::new(vec![Cmd::Op(OP_2), Cmd::push(secs[1].clone()), Cmd::push(secs[2].clone()), Cmd::Op(OP_2), Cmd::Op(OP_CHECKMULTISIG)]);
+        let prev_tx = Tx::new(1, vec![TxInput::new(vec![0x01; 32], 0, Script::new(vec![]), 0xffffffff)], vec![TxOutput::new(10_000, Script::p2pkh_script(hash160(&secs[0]).to_vec()))], 0, Network::Mainnet, false);
+        let prev_outputs = vec![
+            prev_tx.tx_outs()[0].clone(),
+            TxOutput::new(20_000, p2wpkh(&secs[0])),
+            TxOutput::new(30_000, Script::p2sh_script(hash160(&p2wpkh(&secs[2]).raw_serialize()).to_vec())),
+            TxOutput::new(40_000, Script::new(vec![Cmd::Op(OP_0), Cmd::push(Sha256::digest(multisig.raw_serialize()).to_vec())])),
+        ];
+        let mut inputs: Vec<TxInput> = (2u8..5).map(|i| TxInput::new(vec![i; 32], 0, Script::new(vec![]), 0xfffffffd)).collect();
+        inputs.insert(0, TxInput::new(hex::decode(prev_tx.tx_id()).unwrap(), 0, Script::new(vec![]), 0xfffffffd));
+        let tx = Tx::new(2, inputs, vec![TxOutput::new(95_000, p2wpkh(&secs[1]))], 0, Network::Mainnet, false);
+
+        // updater
+        let mut psbt = Psbt::new(tx.clone(), Network::Mainnet).unwrap();
+        assert!(psbt.set_non_witness_utxo(0, &prev_tx));
+        assert!(!psbt.set_non_witness_utxo(1, &prev_tx));
+        psbt.set_redeem_script(2, &p2wpkh(&secs[2]));
+        block_on(psbt.add_utxos(&MemoryPrevoutProvider::for_tx(&tx, prev_outputs.clone())));
+        psbt.set_witness_script(3, &multisig);
+        psbt.add_output_derivation(0, &secs[1], [0xde, 0xad, 0xbe, 0xef], &[0x8000_0054, 0x8000_0000, 0x8000_0000, 0, 1]);
+        assert_eq!(psbt.fee(), Some(5_000));
+
+        // a legacy input is only signed with its previous tx
+        let mut legacy = Psbt::new(tx.clone(), Network::Mainnet).unwrap();
+        block_on(legacy.add_utxos(&MemoryPrevoutProvider::for_tx(&tx, prev_outputs.clone())));
+        assert_eq!(legacy.inputs[0].get(PSBT_IN_WITNESS_UTXO), None);
+        assert!(legacy.inputs[1].get(PSBT_IN_WITNESS_UTXO).is_some());
+        legacy.set_witness_utxo(0, &prev_outputs[0]);
+        assert!(!legacy.sign(0, &keys[0]));
+
+        // two signers working on copies
+        let mut other = psbt.clone();
+        assert!(psbt.sign(0, &keys[0]));
+        assert!(psbt.sign(1, &keys[0]));
+        assert!(psbt.sign(3, &keys[1]));
+        assert!(!psbt.sign(2, &keys[0]));
+        assert!(other.sign(2, &keys[2]));
+        assert!(other.sign(3, &keys[2]));
+        assert!(!psbt.finalize_input(3));
+
+        // combiner, finalizer and extractor
+        psbt.combine(&other).unwrap();
+        assert_eq!(psbt.input_status(3), (2, Some(2), vec![]));
+        assert!(psbt.combine(&Psbt::new(prev_tx.clone(), Network::Mainnet).unwrap()).is_err());
+        assert!(psbt.finalize());
+        assert!(psbt.inputs.iter().all(|map| map.get_all(PSBT_IN_PARTIAL_SIG).is_empty()));
+        let psbt = Psbt::from_base64(&psbt.to_base64(), Network::Mainnet).unwrap();
+        let mut signed = psbt.extract().unwrap();
+        assert_eq!(signed.tx_ins()[2].script_sig.cmds, vec![Cmd::push(p2wpkh(&secs[2]).raw_serialize())]);
+        assert_eq!(signed.tx_ins()[3].witness.as_ref().map(|witness| witness.len()), Some(4));
+        let provider = MemoryPrevoutProvider::for_tx(&signed, prev_outputs);
+        for input_index in 0..4 {
+            assert!(block_on(signed.verify_input(input_index, VerifyFlags::STANDARD, &provider)).is_valid);
+        }
+    }
+    #[test]
+    fn test_malformed_multisig() {
+        let sigs = vec![(vec![0x02; 33], vec![0x30; 71])];
+        let key = || Cmd::push(vec![0x02; 33]);
+        assert_eq!(multisig_sigs(&Script::new(vec![Cmd::Op(OP_1), key(), Cmd::Op(OP_1), Cmd::Op(OP_CHECKMULTISIG)]), &sigs), Some(vec![vec![0x30; 71]]));
+        // no keys, fewer keys than n, m above n, and n not a number
+        assert_eq!(multisig_sigs(&Script::new(vec![Cmd::Op(OP_1), Cmd::Op(OP_CHECKMULTISIG)]), &sigs), None);
+        assert_eq!(multisig_sigs(&Script::new(vec![Cmd::Op(OP_1), key(), Cmd::Op(OP_2), Cmd::Op(OP_CHECKMULTISIG)]), &sigs), None);
+        assert_eq!(multisig_sigs(&Script::new(vec![Cmd::Op(OP_2), key(), Cmd::Op(OP_1), Cmd::Op(OP_CHECKMULTISIG)]), &sigs), None);
+        assert_eq!(multisig_sigs(&Script::new(vec![Cmd::Op(OP_1), key(), key(), Cmd::Op(OP_CHECKMULTISIG)]), &sigs), None);
+
+        // a witness script without keys is not finalized
+        let mut psbt = Psbt::parse(&test_psbt_bytes(), Network::Mainnet).unwrap();
+        let witness_script = Script::new(vec![Cmd::Op(OP_1), Cmd::Op(OP_CHECKMULTISIG)]);
+        let p2wsh = Script::new(vec![Cmd::Op(OP_0), Cmd::push(Sha256::digest(witness_script.raw_serialize()).to_vec())]);
+        psbt.inputs[0].insert(vec![PSBT_IN_WITNESS_UTXO], TxOutput::new(10_000, p2wsh).serialize());
+        psbt.set_witness_script(0, &witness_script);
+        psbt.inputs[0].insert([vec![PSBT_IN_PARTIAL_SIG], vec![0x02; 33]].concat(), vec![0x30; 71]);
+        assert!(!psbt.finalize_input(0));
+    }
+}
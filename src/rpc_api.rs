@@ -7,22 +7,23 @@ use num::BigUint;
 use reqwest::Method;
 //use ripemd::digest::core_api::Block;
 use crate::tx::Tx;
+use crate::network::Network;
 use crate::block::Block;
 use serde_json::json;
 use serde::{Deserialize, Serialize};
 use to_binary::BinaryString;
 use crate::env::{API_PASS, API_URL, API_USER};
 use crate::helpers::endianness::int_to_little_endian;
-use crate::rpc_models::{RpcTxResponse, RpcBlock0Response, RpcBlock1Response, RpcBlockCountResponse, RpcBlockchaininfoResponse, RpcBlock2Response, RpcGetmempoolinfoResponse, RpcGetmininginfoResponse, RpcGetnettotalsRsponse, RpcGetnetworkinfoResponse, RpcGetwalletinfoResponse, RpcListtransactionsResponse, RpcListunspentResponse};
+use crate::rpc_models::{RpcTxResponse, RpcRawTxBatchItem, RpcBlock0Response, RpcBlock1Response, RpcBlockCountResponse, RpcBlockchaininfoResponse, RpcBlock2Response, RpcGetmempoolinfoResponse, RpcGetmininginfoResponse, RpcGetnettotalsRsponse, RpcGetnetworkinfoResponse, RpcGetwalletinfoResponse, RpcListtransactionsResponse, RpcListunspentResponse};
 
 pub struct RpcApi {
     api_url: String,
-    testnet: bool,
+    network: Network,
 }
 
 impl RpcApi {
-    pub fn new(testnet: bool) -> Self {
-        RpcApi { api_url: API_URL.lock().unwrap().to_string(), testnet }
+    pub fn new(network: Network) -> Self {
+        RpcApi { api_url: API_URL.lock().unwrap().to_string(), network }
     }
     pub async fn get_tx(&self, tx_id: &str) -> Result<Tx, reqwest::Error> {
 
@@ -55,7 +56,7 @@ impl RpcApi {
                 let k = format!("{}", tid);
 
                 let mut stream = Cursor::new(raw_tx.clone());
-                let mut tx = Tx::parse(&mut stream, self.testnet).unwrap();
+                let mut tx = Tx::parse(&mut stream, self.network).unwrap();
                 let mut tx_json = tx.tx_json();
                 tx_json["hex"] = json!(result.result.hex.clone());
                 tx_json["blockhash"] = json!(result.result.blockhash.clone());
@@ -70,6 +71,39 @@ impl RpcApi {
             }
         }
     }
+    /// Fetches several txs in one batched request, None for the ones the node doesn't know.
+    pub async fn get_txs(&self, tx_ids: &[String]) -> Result<Vec<Option<Tx>>, reqwest::Error> {
+
+        let url = self.api_url.clone();
+        let (user, pass) = (API_USER.lock().unwrap().to_string(), API_PASS.lock().unwrap().to_string());
+
+        let requests: Vec<serde_json::Value> = tx_ids.iter().enumerate().map(|(id, tx_id)| json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": "getrawtransaction",
+            "params": [tx_id, false]
+        })).collect();
+        let json_string = json!(requests).to_string();
+
+        let client = reqwest::Client::new();
+        let response = client
+            .post(url)
+            .basic_auth(user, Some(pass))
+            .body(json_string)
+            .send()
+            .await?
+            .json::<Vec<RpcRawTxBatchItem>>()
+            .await?;
+        // replies may come in any order
+        let mut txs: Vec<Option<Tx>> = vec![None; tx_ids.len()];
+        for item in response {
+            let raw_tx = item.result.and_then(|hex| hex::decode(hex).ok());
+            if let (Some(tx), Some(raw_tx)) = (txs.get_mut(item.id), raw_tx) {
+                *tx = Tx::parse(&mut Cursor::new(raw_tx), self.network).ok();
+            }
+        }
+        Ok(txs)
+    }
     pub async fn get_block(&self, block_id: &str) -> Result<Block, reqwest::Error> {
 
         let url = format!("{}", self.api_url);
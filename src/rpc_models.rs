@@ -11,6 +11,12 @@ pub struct RpcTxResult {
     pub(crate) blocktime: u64,
     pub(crate) confirmations: u64,
 }
+// one reply of a batch of getrawtransaction calls, result is null for an unknown tx
+#[derive(Deserialize)]
+pub struct RpcRawTxBatchItem {
+    pub(crate) id: usize,
+    pub(crate) result: Option<String>,
+}
 #[derive(Deserialize)]
 pub struct RpcBlock0Response {
     pub(crate) result: String,
@@ -53,6 +59,7 @@ pub struct RpcBlock2Result {
 #[derive(Deserialize)]
 pub struct RpcBlock2Tx {
     pub(crate) txid: String,
+    pub(crate) hex: Option<String>,
     pub(crate) fee: Option<f64>,
     pub(crate) vin: Option<Vec<Vin>>,
     pub(crate) vout: Option<Vec<Vout>>
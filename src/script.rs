@@ -1,24 +1,507 @@
 use std::ops::{Add};
 use std::{io::{Cursor, Read, Error}};
-use crate::helpers::varint::{encode_varint, read_varint};
+use crate::helpers::varint::{encode_varint, read_length};
+use crate::helpers::decode_error::{read_array, read_bytes, DecodeError};
 use core::fmt;
 use log::log;
 use num::{BigUint, ToPrimitive};
 use sha2::{Digest, Sha256};
-use crate::helpers::endianness::{int_to_little_endian, little_endian_to_int};
+use crate::helpers::endianness::{int_to_big_endian, int_to_little_endian, little_endian_to_int};
 use crate::helpers::op_codes::*;
 use serde_json::json;
 use crate::helpers::out_type::OutputType;
+use crate::helpers::script_error::{ScriptError, ScriptErrorKind};
+use crate::helpers::script_trace::{ScriptTrace, TracePhase, TraceStep};
+use crate::helpers::verify_flags::VerifyFlags;
+use crate::helpers::tx_context::TxContext;
+use crate::helpers::taproot::{is_valid_control_block_size, tap_leaf_hash, verify_taproot_commitment, ANNEX_TAG, TAPROOT_LEAF_TAPSCRIPT};
 use crate::helpers::address::{h160_to_p2pkh_address, h160_to_p2sh_address};
 use crate::helpers::bech32::{bech32_segwit_encode, SegwitVersion};
+use crate::address::Address;
+use crate::network::Network;
+
+// consensus limits on the size of a single stack element, of a script,
+// of the non-push opcodes in a script and of the stack and altstack together
+const MAX_SCRIPT_ELEMENT_SIZE: usize = 520;
+pub const MAX_SCRIPT_SIZE: usize = 10_000;
+const MAX_OPS_PER_SCRIPT: usize = 201;
+const MAX_STACK_SIZE: usize = 1000;
+// a tapscript starts with this much validation weight on top of its witness size
+const VALIDATION_WEIGHT_OFFSET: i64 = 50;
+
+/// A single script element: either an opcode or a data push. The push keeps the
+/// opcode it was encoded with (1..=75 or OP_PUSHDATA1/2/4) so serialization round-trips.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum Cmd {
+    Op(u8),
+    Push(u8, Vec<u8>),
+}
+impl Cmd {
+    /// Push `data` with the smallest push opcode, OP_0 for an empty element.
+    pub fn push(data: Vec<u8>) -> Self {
+        match data.len() {
+            0 => Cmd::Op(OP_0),
+            len @ 1..=75 => Cmd::Push(len as u8, data),
+            76..=0xff => Cmd::Push(OP_PUSHDATA1, data),
+            0x100..=0xffff => Cmd::Push(OP_PUSHDATA2, data),
+            _ => Cmd::Push(OP_PUSHDATA4, data),
+        }
+    }
+    pub fn data(&self) -> Option<&Vec<u8>> {
+        match self {
+            Cmd::Push(_, data) => Some(data),
+            Cmd::Op(_) => None,
+        }
+    }
+    /// Whether a push uses the shortest encoding for its data (MINIMALDATA).
+    pub fn is_minimal_push(&self) -> bool {
+        match self {
+            Cmd::Op(_) => true,
+            Cmd::Push(op_code, data) => match data.len() {
+                // OP_0, OP_1..OP_16 and OP_1NEGATE should have been used
+                0 => false,
+                1 if (1..=16).contains(&data[0]) || data[0] == 0x81 => false,
+                len @ 1..=75 => *op_code as usize == len,
+                76..=0xff => *op_code == OP_PUSHDATA1,
+                0x100..=0xffff => *op_code == OP_PUSHDATA2,
+                _ => true,
+            },
+        }
+    }
+    /// OP_IF..=OP_ENDIF are processed even inside a branch not taken.
+    pub fn is_conditional(&self) -> bool {
+        matches!(self, Cmd::Op(op_code) if (OP_IF..=OP_ENDIF).contains(op_code))
+    }
+    /// Opcodes disabled since 2010, they fail a script wherever they appear.
+    pub fn is_disabled(&self) -> bool {
+        matches!(self, Cmd::Op(
+            OP_CAT | OP_SUBSTR | OP_LEFT | OP_RIGHT | OP_INVERT | OP_AND | OP_OR | OP_XOR
+            | OP_2MUL | OP_2DIV | OP_MUL | OP_DIV | OP_MOD | OP_LSHIFT | OP_RSHIFT
+        ))
+    }
+}
+
+/// The leaf being spent by a taproot script path, and what its signatures commit to (BIP342).
+struct TapscriptSpend {
+    leaf_hash: [u8; 32],
+    annex: Option<Vec<u8>>,
+    // op index of the first tapscript command, OP_CODESEPARATOR positions count from it
+    start: usize,
+    codesep_pos: u32,
+}
+
+/// Interpreter state carried from one command to the next.
+struct ExecState<'a> {
+    stack: Vec<Vec<u8>>,
+    altstack: Vec<Vec<u8>>,
+    // one entry per open OP_IF/OP_NOTIF, true when that branch is taken
+    exec_stack: Vec<bool>,
+    phase: TracePhase,
+    // non-push opcodes seen in the current script
+    op_count: usize,
+    // position of the current command, counted across all the scripts run
+    op_index: usize,
+    // what signatures commit to: the running script from its last executed
+    // OP_CODESEPARATOR
+    script_code: Vec<Cmd>,
+    tapscript: Option<TapscriptSpend>,
+    // signature checks left to a tapscript, the budget grows with the witness size
+    validation_weight: i64,
+    flags: VerifyFlags,
+    context: Option<&'a TxContext<'a>>,
+}
+impl<'a> ExecState<'a> {
+    fn new(flags: VerifyFlags, context: Option<&'a TxContext<'a>>) -> Self {
+        Self { stack: vec![], altstack: vec![], exec_stack: vec![], phase: TracePhase::Script, op_count: 0, op_index: 0, script_code: vec![], tapscript: None, validation_weight: 0, flags, context }
+    }
+    fn executing(&self) -> bool {
+        !self.exec_stack.contains(&false)
+    }
+    // the script succeeds if the top element is true
+    fn check_top(&self) -> Result<(), ScriptError> {
+        match self.stack.last() {
+            Some(element) if cast_to_bool(element) => Ok(()),
+            _ => Err(ScriptError::new(ScriptErrorKind::EvalFalse, None)),
+        }
+    }
+    // runs one script on the current stack, the altstack and the opcode count
+    // start over and its conditionals must be closed by its own OP_ENDIFs
+    fn execute(&mut self, cmds: &[Cmd], phase: TracePhase, z: &BigUint, trace: &mut Option<&mut Vec<TraceStep>>) -> Result<(), ScriptError> {
+        // tapscripts have no size limit
+        if phase != TracePhase::Tapscript && Script::new(cmds.to_vec()).raw_serialize().len() > MAX_SCRIPT_SIZE {
+            return Err(ScriptError::new(ScriptErrorKind::ScriptSize, None));
+        }
+        self.phase = phase;
+        self.altstack.clear();
+        self.op_count = 0;
+        self.script_code = cmds.to_vec();
+        for (position, cmd) in cmds.iter().enumerate() {
+            let op_index = self.op_index;
+            let executing = self.executing();
+            let mut result = self.step(cmd, &cmds[position + 1..], z, op_index);
+            if result.is_ok() && self.stack.len() + self.altstack.len() > MAX_STACK_SIZE {
+                result = Err(ScriptError::new(ScriptErrorKind::StackSize, Some(op_index)));
+            }
+            if let Some(steps) = trace.as_mut() {
+                let (op, data) = match cmd {
+                    Cmd::Op(op_code) => (op_code_name(*op_code), None),
+                    Cmd::Push(op_code, data) if *op_code <= 75 => (op_code_pushdata_name(*op_code), Some(data.clone())),
+                    Cmd::Push(op_code, data) => (op_code_name(*op_code), Some(data.clone())),
+                };
+                steps.push(TraceStep {
+                    op_index,
+                    phase,
+                    op,
+                    data,
+                    executed: executing || cmd.is_conditional(),
+                    stack: self.stack.clone(),
+                    altstack: self.altstack.clone(),
+                    exec_stack: self.exec_stack.clone(),
+                });
+            }
+            result?;
+            self.op_index += 1;
+        }
+        // every OP_IF/OP_NOTIF must be closed by an OP_ENDIF
+        if !self.exec_stack.is_empty() {
+            return Err(ScriptError::new(ScriptErrorKind::UnbalancedConditional, None));
+        }
+        Ok(())
+    }
+    // runs the witness program of an output (BIP141), or of the redeem script of a
+    // p2sh output, on the witness. The stack it was found on is left with one element.
+    fn execute_witness_program(&mut self, version: u8, program: &[u8], mut witness: Vec<Vec<u8>>, is_p2sh: bool, z: &BigUint, trace: &mut Option<&mut Vec<TraceStep>>) -> Result<(), ScriptError> {
+        let fail = |kind: ScriptErrorKind| ScriptError::new(kind, None);
+        let mut outer_stack = std::mem::take(&mut self.stack);
+        outer_stack.truncate(1);
+        match (version, program.len()) {
+            // 0 <20 byte hash> is p2wpkh, the witness is a signature and a pubkey
+            (0, 20) => {
+                if witness.is_empty() {
+                    return Err(fail(ScriptErrorKind::WitnessProgramWitnessEmpty));
+                }
+                if witness.len() != 2 {
+                    return Err(fail(ScriptErrorKind::WitnessProgramMismatch));
+                }
+                // witness items are stack elements, not script to be parsed
+                self.stack = witness;
+                // the script code of p2wpkh is the p2pkh script
+                self.execute(&Script::p2pkh_script(program.to_vec()).cmds, TracePhase::WitnessProgram, z, trace)?;
+            }
+            // 0 <32 byte hash> is p2wsh, the last witness item is the script
+            (0, 32) => {
+                let witness_script = witness.pop().ok_or(fail(ScriptErrorKind::WitnessProgramWitnessEmpty))?;
+                if witness_script.len() > MAX_SCRIPT_SIZE {
+                    return Err(fail(ScriptErrorKind::ScriptSize));
+                }
+                if witness.iter().any(|element| element.len() > MAX_SCRIPT_ELEMENT_SIZE) {
+                    return Err(fail(ScriptErrorKind::PushSize));
+                }
+                if Sha256::digest(&witness_script).as_slice() != program {
+                    return Err(fail(ScriptErrorKind::WitnessProgramMismatch));
+                }
+                let witness_script = Script::parse_raw(&witness_script).map_err(|_| fail(ScriptErrorKind::BadOpcode))?;
+                self.stack = witness;
+                self.execute(&witness_script.cmds, TracePhase::WitnessScript, z, trace)?;
+            }
+            // 1 <32 byte key> is p2tr, only when not nested in p2sh
+            (1, 32) if self.flags.contains(VerifyFlags::TAPROOT) && !is_p2sh => {
+                let witness_size = serialized_witness_size(&witness);
+                if witness.is_empty() {
+                    return Err(fail(ScriptErrorKind::WitnessProgramWitnessEmpty));
+                }
+                // with two or more elements the last one is the annex if it starts with 0x50
+                let annex = match witness.len() >= 2 && witness[witness.len() - 1].first() == Some(&ANNEX_TAG) {
+                    true => witness.pop(),
+                    false => None,
+                };
+                // key path: a signature of the output key
+                if witness.len() == 1 {
+                    let context = self.context;
+                    let taproot_sig_hash = |hash_type: u8| match context {
+                        Some(context) => context.sig_hash_taproot(hash_type, annex.as_deref(), None),
+                        None => int_to_big_endian(z, 32).try_into().ok(),
+                    };
+                    check_schnorr_sig(program, &witness[0], &taproot_sig_hash).map_err(fail)?;
+                    self.stack = outer_stack;
+                    return Ok(());
+                }
+                // script path: the script inputs, the leaf script and the control block
+                let control_block = witness.pop().unwrap();
+                let leaf_script = witness.pop().unwrap();
+                if !is_valid_control_block_size(&control_block) {
+                    return Err(fail(ScriptErrorKind::TaprootWrongControlSize));
+                }
+                let leaf_version = control_block[0] & 0xfe;
+                let leaf_hash = tap_leaf_hash(leaf_version, &leaf_script);
+                if !verify_taproot_commitment(program, &control_block, &leaf_hash) {
+                    return Err(fail(ScriptErrorKind::WitnessProgramMismatch));
+                }
+                // unknown leaf versions are left for future upgrades and succeed
+                if leaf_version != TAPROOT_LEAF_TAPSCRIPT {
+                    if self.flags.contains(VerifyFlags::DISCOURAGE_UPGRADABLE_TAPROOT_VERSION) {
+                        return Err(fail(ScriptErrorKind::DiscourageUpgradableTaprootVersion));
+                    }
+                    self.stack = outer_stack;
+                    return Ok(());
+                }
+                let leaf_script = Script::parse_raw(&leaf_script).map_err(|_| fail(ScriptErrorKind::BadOpcode))?;
+                // an OP_SUCCESSx anywhere makes the spend valid without running the script
+                if leaf_script.cmds.iter().any(|cmd| matches!(cmd, Cmd::Op(op_code) if is_op_success(*op_code))) {
+                    if self.flags.contains(VerifyFlags::DISCOURAGE_OP_SUCCESS) {
+                        return Err(fail(ScriptErrorKind::DiscourageOpSuccess));
+                    }
+                    self.stack = outer_stack;
+                    return Ok(());
+                }
+                if witness.iter().any(|element| element.len() > MAX_SCRIPT_ELEMENT_SIZE) {
+                    return Err(fail(ScriptErrorKind::PushSize));
+                }
+                self.stack = witness;
+                self.tapscript = Some(TapscriptSpend { leaf_hash, annex, start: self.op_index, codesep_pos: 0xffffffff });
+                self.validation_weight = VALIDATION_WEIGHT_OFFSET + witness_size as i64;
+                self.execute(&leaf_script.cmds, TracePhase::Tapscript, z, trace)?;
+            }
+            (0, _) => return Err(fail(ScriptErrorKind::WitnessProgramWrongLength)),
+            // taproot outputs before its activation
+            (1, 32) if !is_p2sh => {
+                self.stack = outer_stack;
+                return Ok(());
+            }
+            // other versions and lengths, and v1 nested in p2sh, are left for
+            // future soft forks and succeed as they are
+            _ => {
+                if self.flags.contains(VerifyFlags::DISCOURAGE_UPGRADABLE_WITNESS_PROGRAM) {
+                    return Err(fail(ScriptErrorKind::DiscourageUpgradableWitnessProgram));
+                }
+                self.stack = outer_stack;
+                return Ok(());
+            }
+        }
+        // witness scripts must leave exactly one true element
+        if self.stack.len() != 1 {
+            return Err(fail(ScriptErrorKind::CleanStack));
+        }
+        self.check_top()?;
+        self.stack = outer_stack;
+        Ok(())
+    }
+    fn step(&mut self, cmd: &Cmd, cmds: &[Cmd], z: &BigUint, index: usize) -> Result<(), ScriptError> {
+        let fail = |kind: ScriptErrorKind| ScriptError::new(kind, Some(index));
+        let executing = self.executing();
+        let ExecState { stack, altstack, exec_stack, phase, op_count, script_code, tapscript, validation_weight, flags, context, .. } = self;
+        let flags = *flags;
+        let context = *context;
+        let witness_v0 = *phase == TracePhase::WitnessProgram || *phase == TracePhase::WitnessScript;
+        let sig_hash = |sig: &[u8], signatures: &[Vec<u8>]| match context {
+            Some(context) => {
+                let mut script_code = Script::new(script_code.clone());
+                // a legacy script can't sign its own signatures (FindAndDelete)
+                if !witness_v0 {
+                    for signature in signatures {
+                        script_code = script_code.find_and_delete(signature);
+                    }
+                }
+                context.sig_hash(&script_code, sig[sig.len() - 1], witness_v0)
+            }
+            None => z.clone(),
+        };
+        let tapscript_sig_hash = |hash_type: u8| match (context, tapscript.as_ref()) {
+            (Some(context), Some(spend)) => context.sig_hash_taproot(hash_type, spend.annex.as_deref(), Some((spend.leaf_hash, spend.codesep_pos))),
+            _ => int_to_big_endian(z, 32).try_into().ok(),
+        };
+        if executing && flags.contains(VerifyFlags::MINIMALDATA) {
+            if !cmd.is_minimal_push() {
+                return Err(fail(ScriptErrorKind::MinimalData));
+            }
+            if let Cmd::Op(op_code) = cmd {
+                let operands = numeric_operands(*op_code).min(stack.len());
+                if !stack[stack.len() - operands..].iter().all(|element| is_minimal_num(element)) {
+                    return Err(fail(ScriptErrorKind::NonMinimalNumber));
+                }
+            }
+        }
+        match cmd {
+            Cmd::Op(op_code) => {
+                // every non-push opcode counts, even inside a branch not taken,
+                // tapscripts have no opcode limit
+                if *op_code > OP_16 && tapscript.is_none() {
+                    *op_count += 1;
+                    // and multisig adds its keys
+                    if executing && (*op_code == OP_CHECKMULTISIG || *op_code == OP_CHECKMULTISIGVERIFY) {
+                        let keys = stack.last().filter(|n| n.len() <= 4).map(|n| decode_num(n)).unwrap_or(0);
+                        if (0..=20).contains(&keys) {
+                            *op_count += keys as usize;
+                        }
+                    }
+                    if *op_count > MAX_OPS_PER_SCRIPT {
+                        return Err(fail(ScriptErrorKind::OpCount));
+                    }
+                }
+                if cmd.is_disabled() {
+                    return Err(fail(ScriptErrorKind::DisabledOpcode));
+                }
+                let legacy = !witness_v0 && tapscript.is_none();
+                if *op_code == OP_CODESEPARATOR && legacy && flags.contains(VerifyFlags::CONST_SCRIPTCODE) {
+                    return Err(fail(ScriptErrorKind::OpCodeSeparator));
+                }
+                // conditionals are always processed to keep track of nesting,
+                // everything else is skipped inside a branch not taken
+                if !executing && !cmd.is_conditional() {
+                    return Ok(());
+                }
+                // with CONST_SCRIPTCODE a legacy script can't contain the signatures
+                // it checks, FindAndDelete would have to change it
+                if legacy && flags.contains(VerifyFlags::CONST_SCRIPTCODE) {
+                    let signatures = match *op_code {
+                        OP_CHECKSIG | OP_CHECKSIGVERIFY if stack.len() >= 2 => vec![stack[stack.len() - 2].clone()],
+                        OP_CHECKMULTISIG | OP_CHECKMULTISIGVERIFY => multisig_signatures(stack).unwrap_or_default(),
+                        _ => vec![],
+                    };
+                    let script = Script::new(script_code.clone());
+                    if signatures.iter().any(|signature| script.find_and_delete(signature).cmds.len() != script_code.len()) {
+                        return Err(fail(ScriptErrorKind::SigFindAndDelete));
+                    }
+                }
+                // compressed pubkeys are only required in witness v0 scripts
+                let sig_flags = match witness_v0 {
+                    true => flags,
+                    false => flags.without(VerifyFlags::WITNESS_PUBKEYTYPE),
+                };
+                let result = match *op_code {
+                    OP_0 => op_0(stack),
+                    OP_1NEGATE => op_1negate(stack),
+
+                    OP_1 => op_1(stack),
+                    OP_2 => op_2(stack),
+                    OP_3 => op_3(stack),
+                    OP_4 => op_4(stack),
+                    OP_5 => op_5(stack),
+                    OP_6 => op_6(stack),
+                    OP_7 => op_7(stack),
+                    OP_8 => op_8(stack),
+                    OP_9 => op_9(stack),
+                    OP_10 => op_10(stack),
+                    OP_11 => op_11(stack),
+                    OP_12 => op_12(stack),
+                    OP_13 => op_13(stack),
+                    OP_14 => op_14(stack),
+                    OP_15 => op_15(stack),
+                    OP_16 => op_16(stack),
+                    OP_NOP => op_nop(stack),
+                    // tapscript conditions are empty or 0x01, witness v0 ones too with MINIMALIF
+                    OP_IF | OP_NOTIF if executing && (tapscript.is_some() || witness_v0 && flags.contains(VerifyFlags::MINIMALIF))
+                        && stack.last().is_some_and(|element| element.len() > 1 || element.first().is_some_and(|byte| *byte != 1)) => {
+                        match tapscript.is_some() {
+                            true => Err(ScriptErrorKind::TapscriptMinimalIf),
+                            false => Err(ScriptErrorKind::MinimalIf),
+                        }
+                    }
+                    OP_IF => op_if(stack, exec_stack),
+                    OP_NOTIF => op_notif(stack, exec_stack),
+                    OP_ELSE => op_else(exec_stack),
+                    OP_ENDIF => op_endif(exec_stack),
+                    OP_VERIFY => op_verify(stack),
+                    OP_RETURN => op_return(stack),
+                    OP_TOALTSTACK => op_toaltstack(stack, altstack),
+                    OP_FROMALTSTACK => op_fromaltstack(stack, altstack),
+                    OP_2DROP => op_2drop(stack),
+                    OP_2DUP => op_2dup(stack),
+                    OP_3DUP => op_3dup(stack),
+                    OP_2OVER => op_2over(stack),
+                    OP_2ROT => op_2rot(stack),
+                    OP_2SWAP => op_2swap(stack),
+                    OP_IFDUP => op_ifdup(stack),
+                    OP_DEPTH => op_depth(stack),
+                    OP_DROP => op_drop(stack),
+                    OP_DUP => op_dup(stack),
+                    OP_NIP => op_nip(stack),
+                    OP_OVER => op_over(stack),
+                    OP_PICK => op_pick(stack),
+                    OP_ROLL => op_roll(stack),
+                    OP_ROT => op_rot(stack),
+                    OP_SWAP => op_swap(stack),
+                    OP_TUCK => op_tuck(stack),
+                    OP_SIZE => op_size(stack),
+                    OP_EQUAL => op_equal(stack),
+                    OP_EQUALVERIFY => op_equalverify(stack),
+                    OP_1ADD => op_1add(stack),
+                    OP_1SUB => op_1sub(stack),
+                    OP_NEGATE => op_negate(stack),
+                    OP_ABS => op_abs(stack),
+                    OP_NOT => op_not(stack),
+                    OP_0NOTEQUAL => op_0notequal(stack),
+                    OP_ADD => op_add(stack),
+                    OP_SUB => op_sub(stack),
+                    OP_BOOLAND => op_booland(stack),
+                    OP_BOOLOR => op_boolor(stack),
+                    OP_NUMEQUAL => op_numequal(stack),
+                    OP_NUMEQUALVERIFY => op_numequalverify(stack),
+                    OP_NUMNOTEQUAL => op_numnotequal(stack),
+                    OP_LESSTHAN => op_lessthan(stack),
+                    OP_GREATERTHAN => op_greaterthan(stack),
+                    OP_LESSTHANOREQUAL => op_lessthanorequal(stack),
+                    OP_GREATERTHANOREQUAL => op_greaterthanorequal(stack),
+                    OP_MIN => op_min(stack),
+                    OP_MAX => op_max(stack),
+                    OP_WITHIN => op_within(stack),
+                    OP_RIPEMD160 => op_ripemd160(stack),
+                    OP_SHA1 => op_sha1(stack),
+                    OP_SHA256 => op_sha256(stack),
+                    OP_HASH160 => op_hash160(stack),
+                    OP_HASH256 => op_hash256(stack),
+                    OP_CODESEPARATOR => {
+                        match tapscript.as_mut() {
+                            Some(spend) => spend.codesep_pos = (index - spend.start) as u32,
+                            None => *script_code = cmds.to_vec(),
+                        }
+                        op_codeseparator(stack)
+                    }
+                    OP_CHECKSIG if tapscript.is_some() => op_checksig_tapscript(stack, &tapscript_sig_hash, validation_weight, flags),
+                    OP_CHECKSIGVERIFY if tapscript.is_some() => op_checksigverify_tapscript(stack, &tapscript_sig_hash, validation_weight, flags),
+                    OP_CHECKSIGADD if tapscript.is_some() => op_checksigadd(stack, &tapscript_sig_hash, validation_weight, flags),
+                    OP_CHECKMULTISIG | OP_CHECKMULTISIGVERIFY if tapscript.is_some() => Err(ScriptErrorKind::TapscriptCheckMultiSig),
+                    OP_CHECKSIG => op_checksig(stack, &sig_hash, sig_flags),
+                    OP_CHECKSIGVERIFY => op_checksigverify(stack, &sig_hash, sig_flags),
+                    OP_CHECKMULTISIG => op_checkmultisig(stack, &sig_hash, sig_flags),
+                    OP_CHECKMULTISIGVERIFY => op_checkmultisigverify(stack, &sig_hash, sig_flags),
+                    // reserved for soft forks, like CLTV and CSV were
+                    OP_NOOP1 | OP_NOP4..=OP_NOP10 if flags.contains(VerifyFlags::DISCOURAGE_UPGRADABLE_NOPS) => {
+                        Err(ScriptErrorKind::DiscourageUpgradableNops)
+                    }
+                    OP_NOOP1 => op_noop1(stack),
+                    OP_NOP4..=OP_NOP10 => op_nop(stack),
+                    // without their flags they behave as the NOPs they replaced
+                    OP_CHECKLOCKTIMEVERIFY if flags.contains(VerifyFlags::CHECKLOCKTIMEVERIFY) => op_checklocktimeverify(stack, context),
+                    OP_CHECKSEQUENCEVERIFY if flags.contains(VerifyFlags::CHECKSEQUENCEVERIFY) => op_checksequenceverify(stack, context),
+                    OP_CHECKLOCKTIMEVERIFY | OP_CHECKSEQUENCEVERIFY => op_nop(stack),
+
+                    _ => Err(ScriptErrorKind::BadOpcode),
+                };
+                result.map_err(fail)?;
+            }
+            Cmd::Push(_, cmd) => {
+                if cmd.len() > MAX_SCRIPT_ELEMENT_SIZE {
+                    return Err(fail(ScriptErrorKind::PushSize));
+                }
+                // pushes inside a branch not taken are skipped
+                if !executing {
+                    return Ok(());
+                }
+                stack.push(cmd.clone());
+            }
+        }
+        Ok(())
+    }
+}
 
 #[derive(Debug, Eq, PartialEq, Clone)]
 pub struct Script {
-    pub cmds: Vec<Vec<u8>>,
+    pub cmds: Vec<Cmd>,
     pub(crate) script_json: serde_json::Value,
 }
 impl Script {
-    pub fn new(cmds: Vec<Vec<u8>>) -> Self {
+    pub fn new(cmds: Vec<Cmd>) -> Self {
         Self { cmds: cmds, script_json: json!({}) }
     }
 }
@@ -26,97 +509,179 @@ impl Script {
     pub fn get_json(&self) -> serde_json::Value {
         self.script_json.clone()
     }
-    pub fn parse(stream: &mut Cursor<Vec<u8>>) -> Result<Script, Error> {
+    pub fn parse(stream: &mut Cursor<Vec<u8>>) -> Result<Script, DecodeError> {
         let mut cmds = vec![];
         let mut cmd_list_json: Vec<String> = vec![];
         let mut script_length: u32 = 0;
 
         let mut count = 0;
-        let length = read_varint(stream)?; // length of entire script
+        let length_offset = stream.position() as usize;
+        let length = read_length(stream)?; // length of entire script
         script_length += length.bytes as u32 + length.value as u32;
+        // push lengths are checked against the declared length before reading,
+        // a corrupt length can't make us allocate more than the script itself
+        let oversized_push = |offset: u64, length: u64| DecodeError::OversizedVector { offset: offset as usize, length };
         while count < length.value {
-            let mut current = [0u8; 1];
-            stream.read(&mut current)?;
+            let op_offset = stream.position();
+            let [current_byte] = read_array::<1>(stream)?;
             count += 1;
-            let current_byte = current[0];
 
             match current_byte {
                 _len @ 1..=75 => {
                     let n = current_byte;
-                    let mut cmd = vec![0u8; n as usize];
-                    stream.read(&mut cmd)?;
-                    cmds.push(cmd.clone());
+                    if count + n as u64 > length.value {
+                        return Err(oversized_push(op_offset, n as u64));
+                    }
+                    let cmd = read_bytes(stream, n as usize)?;
                     cmd_list_json.push(op_code_pushdata_name(n));
-                    cmd_list_json.push(hex::encode(cmd));
+                    cmd_list_json.push(hex::encode(&cmd));
+                    cmds.push(Cmd::Push(n, cmd));
                     count += n as u64;
                 }
                 OP_PUSHDATA1 => {
-                    let mut buffer = [0; 1];
-                    stream.read(&mut buffer)?;
+                    let buffer = read_array::<1>(stream)?;
                     let ln = little_endian_to_int(buffer.as_slice()).to_u16().unwrap();
-                    let mut cmd = vec![0; ln.to_usize().unwrap()];
-                    stream.read(&mut cmd)?;
-                    cmds.push(cmd.clone());
+                    if count + 1 + ln as u64 > length.value {
+                        return Err(oversized_push(op_offset, ln as u64));
+                    }
+                    let cmd = read_bytes(stream, ln.to_usize().unwrap())?;
                     cmd_list_json.push("OP_PUSHDATA1".to_string());
-                    cmd_list_json.push(hex::encode(cmd));
+                    cmd_list_json.push(hex::encode(&cmd));
+                    cmds.push(Cmd::Push(OP_PUSHDATA1, cmd));
                     count += ln as u64 + 1;
                 }
                 OP_PUSHDATA2 => {
-                    let mut buffer = [0; 2];
-                    stream.read(&mut buffer)?;
+                    let buffer = read_array::<2>(stream)?;
                     let ln = little_endian_to_int(buffer.as_slice()).to_u16().unwrap();
-                    let mut cmd = vec![0; ln.to_usize().unwrap()];
-                    stream.read(&mut cmd)?;
-                    cmds.push(cmd.clone());
+                    if count + 2 + ln as u64 > length.value {
+                        return Err(oversized_push(op_offset, ln as u64));
+                    }
+                    let cmd = read_bytes(stream, ln.to_usize().unwrap())?;
                     cmd_list_json.push("OP_PUSHDATA2".to_string());
-                    cmd_list_json.push(hex::encode(cmd));
+                    cmd_list_json.push(hex::encode(&cmd));
+                    cmds.push(Cmd::Push(OP_PUSHDATA2, cmd));
                     count += ln as u64 + 2;
 
                 }
+                OP_PUSHDATA4 => {
+                    let buffer = read_array::<4>(stream)?;
+                    let ln = little_endian_to_int(buffer.as_slice()).to_u32().unwrap();
+                    if count + 4 + ln as u64 > length.value {
+                        return Err(oversized_push(op_offset, ln as u64));
+                    }
+                    let cmd = read_bytes(stream, ln.to_usize().unwrap())?;
+                    cmd_list_json.push("OP_PUSHDATA4".to_string());
+                    cmd_list_json.push(hex::encode(&cmd));
+                    cmds.push(Cmd::Push(OP_PUSHDATA4, cmd));
+                    count += ln as u64 + 4;
+                }
                 _ => {
                     let op_code = current_byte;
-                    cmds.push(vec![op_code]);
-                    if is_op(&vec![op_code]) {
-                        let op_code_names = op_code_names();
-                        cmd_list_json.push(op_code_names[&op_code].to_string());
-                    } else {
-                        panic!("OP not handled: {:?}", op_code);
-                    }
+                    cmds.push(Cmd::Op(op_code));
+                    // unknown opcodes are kept, they only fail when executed
+                    cmd_list_json.push(op_code_name(op_code));
                 }
             }
         }
         if count != length.value {
-            return Err(Error::new(
-                std::io::ErrorKind::InvalidData,
-                "parsing script failed",
-            ));
+            return Err(DecodeError::OversizedVector { offset: length_offset, length: length.value });
         }
         let script_json = json!( {"script_length": script_length, "cmd_list_json": cmd_list_json});
         Ok(Script { cmds, script_json })
     }
-    fn raw_serialize(&self) -> Vec<u8> {
+    /// Parses a script serialized without its length prefix.
+    pub fn parse_raw(raw: &[u8]) -> Result<Script, DecodeError> {
+        let mut script = encode_varint(raw.len() as u64).unwrap();
+        script.extend(raw);
+        Script::parse(&mut Cursor::new(script))
+    }
+    /// Builds a script from ASM text: opcode names (the OP_ prefix is optional),
+    /// decimal numbers, data pushes as `<hex>`, `0x<hex>` or bare hex, and explicit
+    /// `OP_PUSHBYTES_n`/`OP_PUSHDATA1/2/4` pushes followed by their data.
+    /// Tokens made only of digits are read as numbers, not hex.
+    pub fn from_asm(asm: &str) -> Result<Script, Error> {
+        let invalid = |message: String| Error::new(std::io::ErrorKind::InvalidData, message);
+        let mut cmds = vec![];
+        let mut tokens = asm.split_whitespace();
+        while let Some(token) = tokens.next() {
+            let upper = token.to_uppercase();
+            // explicit pushes keep the opcode they were written with
+            let push_op = match upper.strip_prefix("OP_PUSHBYTES_") {
+                Some(n) => match n.parse::<u8>() {
+                    Ok(n @ 1..=75) => Some(n),
+                    _ => return Err(invalid(format!("invalid push {}", token))),
+                },
+                None => op_code_from_name(&upper).filter(|op_code| (OP_PUSHDATA1..=OP_PUSHDATA4).contains(op_code)),
+            };
+            if let Some(op_code) = push_op {
+                let data = tokens.next()
+                    .and_then(asm_data)
+                    .ok_or_else(|| invalid(format!("{} must be followed by hex data", token)))?;
+                let fits = match op_code {
+                    OP_PUSHDATA1 => data.len() <= 0xff,
+                    OP_PUSHDATA2 => data.len() <= 0xffff,
+                    OP_PUSHDATA4 => data.len() <= 0xffffffff,
+                    n => data.len() == n as usize,
+                };
+                if !fits {
+                    return Err(invalid(format!("{} can't push {} bytes", token, data.len())));
+                }
+                cmds.push(Cmd::Push(op_code, data));
+            } else if let Some(op_code) = op_code_from_name(&upper) {
+                cmds.push(Cmd::Op(op_code));
+            } else if let Ok(num) = token.parse::<i64>() {
+                if num.abs() > 0xffffffff {
+                    return Err(invalid(format!("number out of range {}", token)));
+                }
+                cmds.push(match num {
+                    -1 => Cmd::Op(OP_1NEGATE),
+                    0 => Cmd::Op(OP_0),
+                    1..=16 => Cmd::Op(OP_1 + num as u8 - 1),
+                    _ => Cmd::push(encode_num(num)),
+                });
+            } else if let Some(data) = asm_data(token) {
+                cmds.push(Cmd::push(data));
+            } else {
+                return Err(invalid(format!("unknown token {}", token)));
+            }
+        }
+        // parsing the serialization fills in script_json
+        Ok(Script::parse(&mut Cursor::new(Script::new(cmds).serialize()))?)
+    }
+    pub fn raw_serialize(&self) -> Vec<u8> {
         let mut result = vec![];
         for cmd in &self.cmds {
-            if cmd.len() == 1 {
-                if !is_op(&cmd) {
-                    panic!("OP no handled: {:?}", cmd);
+            match cmd {
+                Cmd::Op(op_code) => {
+                    result.push(*op_code);
+                }
+                Cmd::Push(op_code, data) => {
+                    let length = data.len();
+                    let fits = match *op_code {
+                        OP_PUSHDATA1 => length <= 0xff,
+                        OP_PUSHDATA2 => length <= 0xffff,
+                        OP_PUSHDATA4 => length as u64 <= 0xffffffff,
+                        n => n as usize == length,
+                    };
+                    // a push built with an opcode that can't hold its data
+                    // is written with the smallest one that can
+                    let op_code = match (fits, Cmd::push(data.clone())) {
+                        (true, _) => *op_code,
+                        (false, Cmd::Push(op_code, _)) => op_code,
+                        (false, Cmd::Op(op_code)) => {
+                            result.push(op_code);
+                            continue;
+                        }
+                    };
+                    result.push(op_code);
+                    match op_code {
+                        OP_PUSHDATA1 => result.extend(int_to_little_endian(BigUint::from(length), 1)),
+                        OP_PUSHDATA2 => result.extend(int_to_little_endian(BigUint::from(length), 2)),
+                        OP_PUSHDATA4 => result.extend(int_to_little_endian(BigUint::from(length), 4)),
+                        _ => {}
+                    }
+                    result.extend(data)
                 }
-                let op_code = cmd[0];
-                result.extend(int_to_little_endian(BigUint::from(op_code), 1));
-            } else {
-                let length = cmd.len();
-                if length < 75 {
-                    result.extend(int_to_little_endian(BigUint::from(length.to_u64().unwrap()), 1));
-                } else if length < 256 {
-                    result.extend(int_to_little_endian(BigUint::from(76u64), 1));
-                    result.extend(int_to_little_endian(BigUint::from(length), 1));
-                } else if length < 520 {
-                    result.extend(int_to_little_endian(BigUint::from(77u64), 1));
-                    result.extend(int_to_little_endian(BigUint::from(length), 2));
-                } else {
-                    panic!("too long a cmd");
-                }
-                result.extend(cmd)
             }
         }
         result
@@ -130,202 +695,161 @@ impl Script {
         result.extend(raw_result);
         result
     }
-    pub fn evaluate(&self, z: &BigUint, witness: &Option<Vec<Vec<u8>>>) -> bool {
-        let mut cmds = self.cmds.clone();
-        let mut stack: Vec<Vec<u8>> = vec![];
-        let mut altstack: Vec<Vec<u8>> = vec![];
-        while cmds.len() > 0 {
-
-            let cmd = cmds.remove(0);
-            if cmd.len() == 1 {
-                if !is_op(&cmd) {
-                    panic!("OP not handled {:?}", cmd);
-                }
-                let op_code = cmd[0];
-                match op_code {
-                    OP_0 => { if !op_0(&mut stack) { return false; } }
-                    OP_1NEGATE => { if !op_1negate(&mut stack) { return false; } }
-
-                    OP_1 => { if !op_1(&mut stack) { return false; } }
-                    OP_2 => { if !op_2(&mut stack) { return false; } }
-                    OP_3 => { if !op_3(&mut stack) { return false; } }
-                    OP_4 => { if !op_4(&mut stack) { return false; } }
-                    OP_5 => { if !op_5(&mut stack) { return false; } }
-                    OP_6 => { if !op_6(&mut stack) { return false; } }
-                    OP_7 => { if !op_7(&mut stack) { return false; } }
-                    OP_8 => { if !op_8(&mut stack) { return false; } }
-                    OP_9 => { if !op_9(&mut stack) { return false; } }
-                    OP_10 => { if !op_10(&mut stack) { return false; } }
-                    OP_11 => { if !op_11(&mut stack) { return false; } }
-                    OP_12 => { if !op_12(&mut stack) { return false; } }
-                    OP_13 => { if !op_13(&mut stack) { return false; } }
-                    OP_14 => { if !op_14(&mut stack) { return false; } }
-                    OP_15 => { if !op_15(&mut stack) { return false; } }
-                    OP_16 => { if !op_16(&mut stack) { return false; } }
-                    OP_NOP => { if !op_nop(&mut stack) { return false; } }
-                    // OP_IF => {}
-                    // OP_NOTIF => {}
-                    // OP_ELSE => {}
-                    // OP_ENDIF => {}
-                    OP_VERIFY => { if !op_verify(&mut stack) { return false; } }
-                    OP_RETURN => { if !op_return(&mut stack) { return false; } }
-                    OP_TOALTSTACK => { if !op_toaltstack(&mut stack, &mut altstack) { return false; } }
-                    OP_FROMALTSTACK => { if !op_fromaltstack(&mut stack, &mut altstack) { return false; } }
-                    OP_2DROP => { if !op_2drop(&mut stack) { return false; } }
-                    OP_2DUP => { if !op_2dup(&mut stack) { return false; } }
-                    OP_3DUP => { if !op_3dup(&mut stack) { return false; } }
-                    OP_2OVER => { if !op_2over(&mut stack) { return false; } }
-                    OP_2ROT => { if !op_2rot(&mut stack) { return false; } }
-                    OP_2SWAP => { if !op_2swap(&mut stack) { return false; } }
-                    OP_IFDUP => { if !op_ifdup(&mut stack) { return false; } }
-                    OP_DEPTH => { if !op_depth(&mut stack) { return false; } }
-                    OP_DROP => { if !op_drop(&mut stack) { return false; } }
-                    OP_DUP => { if !op_dup(&mut stack) { return false; } }
-                    OP_NIP => { if !op_nip(&mut stack) { return false; } }
-                    OP_OVER => { if !op_over(&mut stack) { return false; } }
-                    OP_PICK => { if !op_pick(&mut stack) { return false; } }
-                    OP_ROLL => { if !op_roll(&mut stack) { return false; } }
-                    OP_ROT => { if !op_rot(&mut stack) { return false; } }
-                    OP_SWAP => { if !op_swap(&mut stack) { return false; } }
-                    OP_TUCK => { if !op_tuck(&mut stack) { return false; } }
-                    OP_SIZE => { if !op_size(&mut stack) { return false; } }
-                    OP_EQUAL => { if !op_equal(&mut stack) { return false; } }
-                    OP_EQUALVERIFY => { if !op_equalverify(&mut stack) { return false; } }
-                    OP_1ADD => { if !op_1add(&mut stack) { return false; } }
-                    OP_1SUB => { if !op_1sub(&mut stack) { return false; } }
-                    OP_NEGATE => { if !op_negate(&mut stack) { return false; } }
-                    OP_ABS => { if !op_abs(&mut stack) { return false; } }
-                    OP_NOT => { if !op_not(&mut stack) { return false; } }
-                    OP_0NOTEQUAL => { if !op_0notequal(&mut stack) { return false; } }
-                    OP_ADD => { if !op_add(&mut stack) { return false; } }
-                    OP_SUB => { if !op_sub(&mut stack) { return false; } }
-                    OP_MUL => { if !op_dup(&mut stack) { return false; } }
-                    OP_BOOLAND => { if !op_booland(&mut stack) { return false; } }
-                    OP_BOOLOR => { if !op_boolor(&mut stack) { return false; } }
-                    OP_NUMEQUAL => { if !op_numequal(&mut stack) { return false; } }
-                    OP_NUMEQUALVERIFY => { if !op_numequalverify(&mut stack) { return false; } }
-                    OP_NUMNOTEQUAL => { if !op_numnotequal(&mut stack) { return false; } }
-                    OP_LESSTHAN => { if !op_lessthan(&mut stack) { return false; } }
-                    OP_GREATERTHAN => { if !op_greaterthan(&mut stack) { return false; } }
-                    OP_LESSTHANOREQUAL => { if !op_lessthanorequal(&mut stack) { return false; } }
-                    OP_GREATERTHANOREQUAL => { if !op_greaterthanorequal(&mut stack) { return false; } }
-                    OP_MIN => { if !op_min(&mut stack) { return false; } }
-                    OP_MAX => { if !op_max(&mut stack) { return false; } }
-                    OP_WITHIN => { if !op_within(&mut stack) { return false; } }
-                    OP_RIPEMD160 => { if !op_ripemd160(&mut stack) { return false; } }
-                    OP_SHA1 => { if !op_sha1(&mut stack) { return false; } }
-                    OP_SHA256 => { if !op_sha256(&mut stack) { return false; } }
-                    OP_HASH160 => { if !op_hash160(&mut stack) { return false; } }
-                    OP_HASH256 => { if !op_hash256(&mut stack) { return false; } }
-                    OP_CODESEPARATOR => { if !op_codeseparator(&mut stack) { return false; } }
-                    OP_CHECKSIG => { if !op_checksig(&mut stack, z) { return false; } }
-                    OP_CHECKSIGVERIFY => { if !op_checksigverify(&mut stack, z) { return false; } }
-                    OP_CHECKMULTISIG => { if !op_checkmultisig(&mut stack, z) { return false; } }
-                    OP_CHECKMULTISIGVERIFY => { if !op_checkmultisigverify(&mut stack, z) { return false; } }
-
-                    _ => {
-                        panic!("UNKNOWN OP CODE {}", op_code);
-                    }
+    /// Evaluates the script for the input described by `context`, as the scriptPubKey
+    /// of an output spent with an empty scriptSig. Signatures are checked against the
+    /// digest of their hash type. Without a context they are checked against `z` and
+    /// the timelock opcodes fail when their flags are set.
+    pub fn evaluate(&self, z: &BigUint, witness: &Option<Vec<Vec<u8>>>, flags: VerifyFlags, context: Option<&TxContext>) -> Result<(), ScriptError> {
+        self.run(&Script::new(vec![]), z, witness, flags, context, None)
+    }
+    /// Evaluates `script_sig` and then this scriptPubKey on the stack it leaves.
+    pub fn evaluate_spend(&self, script_sig: &Script, z: &BigUint, witness: &Option<Vec<Vec<u8>>>, flags: VerifyFlags, context: Option<&TxContext>) -> Result<(), ScriptError> {
+        self.run(script_sig, z, witness, flags, context, None)
+    }
+    /// Evaluates the script recording the stacks after every command.
+    pub fn trace(&self, z: &BigUint, witness: &Option<Vec<Vec<u8>>>, flags: VerifyFlags, context: Option<&TxContext>) -> ScriptTrace {
+        self.trace_spend(&Script::new(vec![]), z, witness, flags, context)
+    }
+    /// Evaluates `script_sig` and this scriptPubKey recording the stacks after every command.
+    pub fn trace_spend(&self, script_sig: &Script, z: &BigUint, witness: &Option<Vec<Vec<u8>>>, flags: VerifyFlags, context: Option<&TxContext>) -> ScriptTrace {
+        let mut steps: Vec<TraceStep> = vec![];
+        let result = self.run(script_sig, z, witness, flags, context, Some(&mut steps));
+        ScriptTrace::new(steps, result)
+    }
+    // the scriptSig and the scriptPubKey run one after the other, only the stack
+    // is carried over, then the redeem script of a p2sh output and the witness
+    fn run(&self, script_sig: &Script, z: &BigUint, witness: &Option<Vec<Vec<u8>>>, flags: VerifyFlags, context: Option<&TxContext>, mut trace: Option<&mut Vec<TraceStep>>) -> Result<(), ScriptError> {
+        let witness = witness.clone().unwrap_or_default();
+        if flags.contains(VerifyFlags::SIGPUSHONLY) && !script_sig.is_push_only() {
+            return Err(ScriptError::new(ScriptErrorKind::SigPushOnly, None));
+        }
+        let mut state = ExecState::new(flags, context);
+        state.execute(&script_sig.cmds, TracePhase::Script, z, &mut trace)?;
+        // the redeem script runs on the stack left by the scriptSig
+        let stack_copy = state.stack.clone();
+        state.execute(&self.cmds, TracePhase::Script, z, &mut trace)?;
+        state.check_top()?;
+        let mut had_witness = false;
+        if flags.contains(VerifyFlags::WITNESS) {
+            if let Some((version, program)) = self.witness_program() {
+                had_witness = true;
+                // the witness is all a witness output is spent with
+                if !script_sig.cmds.is_empty() {
+                    return Err(ScriptError::new(ScriptErrorKind::WitnessMalleated, None));
                 }
-            } else {
-                stack.push(cmd.clone());
-                // p2sh form here. Previous row in p2sh is the push of the RedeemScript
-                if cmds.len() == 3 && self.is_p2sh(&cmds) {
-                    cmds.pop();
-                    let h160 = cmds.pop();
-                    cmds.pop();
-                    if !op_hash160(&mut stack) {
-                        return false
-                    }
-                    stack.push(h160.unwrap());
-                    if !op_equal(&mut stack) {
-                        return false
-                    }
-                    if !op_verify(&mut stack) {
-                        println!("bad p2sh h160");
-                        return false
-                    }
-                    let mut redeem_script: Vec<u8> = vec![];
-                    redeem_script.extend(encode_varint(cmd.len() as u64).unwrap());
-                    redeem_script.extend(cmd);
-                    let mut cursor = Cursor::new(redeem_script);
-                    cmds.extend(Script::parse(&mut cursor).unwrap().cmds);
-                }
-                // witness program version 0 rule. if stack cmds are:
-                // 0 <20 byte hash> this is p2wpkh
-                if stack.len() == 2 && stack[0] == b"" && stack[1].len() == 20 {
-                    // is b"" correct ?
-                    let h160 = stack.pop();
-                    stack.pop();
-                    cmds.extend(witness.clone().unwrap());
-                    cmds.extend(Script::p2pkh_script(h160.unwrap()).cmds);
-
-                }
-                // witness program version 0 rule. if stack cmds are:
-                // 0 <32 byte hash> this is p2wsh
-                if stack.len() == 2 && stack[0] == b"" && stack[1].len() == 32 {
-
-                    let s256 = stack.pop();
-                    stack.pop();
-                    let mut w = witness.clone().unwrap();
-                    w.pop();
-                    cmds.extend(w);
-                    let witness_script = witness.clone().unwrap().pop().unwrap();
-                    let digest = Sha256::digest(witness_script.clone()).to_vec();
-                    if s256.clone().unwrap() != digest {
-                        println!("bad sha256 script digest: {}, s256: {}", hex::encode(digest), hex::encode(s256.clone().unwrap()));
-                        return false
+                state.execute_witness_program(version, &program, witness.clone(), false, z, &mut trace)?;
+            }
+        }
+        if flags.contains(VerifyFlags::P2SH) && self.is_p2sh_script_pubkey() {
+            if !script_sig.is_push_only() {
+                return Err(ScriptError::new(ScriptErrorKind::SigPushOnly, None));
+            }
+            state.stack = stack_copy;
+            let raw_redeem_script = state.stack.pop().ok_or(ScriptError::new(ScriptErrorKind::EvalFalse, None))?;
+            let redeem_script = Script::parse_raw(&raw_redeem_script).map_err(|_| ScriptError::new(ScriptErrorKind::BadOpcode, None))?;
+            state.execute(&redeem_script.cmds, TracePhase::RedeemScript, z, &mut trace)?;
+            state.check_top()?;
+            if flags.contains(VerifyFlags::WITNESS) {
+                if let Some((version, program)) = redeem_script.witness_program() {
+                    had_witness = true;
+                    // and a p2sh one only needs the push of its redeem script
+                    if script_sig.cmds != [Cmd::push(raw_redeem_script)] {
+                        return Err(ScriptError::new(ScriptErrorKind::WitnessMalleatedP2sh, None));
                     }
-                    let mut w_script: Vec<u8> = vec![];
-                    w_script.extend(encode_varint(witness_script.clone().len() as u64).unwrap());
-                    w_script.extend(witness_script);
-                    let mut stream = Cursor::new(w_script);
-                    let witness_script_cmds = Script::parse(stream.by_ref()).unwrap();
-                    cmds.extend(witness_script_cmds.cmds)
+                    state.execute_witness_program(version, &program, witness.clone(), true, z, &mut trace)?;
                 }
             }
         }
-        if stack.len() == 0 {
-            return false;
+        if flags.contains(VerifyFlags::CLEANSTACK) && state.stack.len() != 1 {
+            return Err(ScriptError::new(ScriptErrorKind::CleanStack, None));
         }
-        if stack.remove(0) == b"" {
-            return false;
+        if flags.contains(VerifyFlags::WITNESS) && !had_witness && !witness.is_empty() {
+            return Err(ScriptError::new(ScriptErrorKind::WitnessUnexpected, None));
         }
-        true
+        Ok(())
     }
-    fn is_p2sh(&self, cmds: &Vec<Vec<u8>>) -> bool {
-        cmds[0] == [0xa9] && cmds[1].len() == 20 && cmds[2] == [0x87]
+    /// The script without the pushes of `data` in their shortest encoding,
+    /// how legacy signature hashes leave out the signatures (FindAndDelete).
+    pub fn find_and_delete(&self, data: &[u8]) -> Script {
+        let push = Cmd::push(data.to_vec());
+        Script::new(self.cmds.iter().filter(|cmd| **cmd != push).cloned().collect())
+    }
+    fn is_p2sh(&self, cmds: &[Cmd]) -> bool {
+        cmds[0] == Cmd::Op(OP_HASH160) && cmds[1].data().is_some_and(|h| h.len() == 20) && cmds[2] == Cmd::Op(OP_EQUAL)
     }
     pub fn p2pkh_script(h160: Vec<u8>) -> Self {
-        let mut cmds: Vec<Vec<u8>> = Vec::new();
-        cmds.push(vec![0x76]); // OP_DUP
-        cmds.push(vec![0xa9]); // OP_HASH160
-        cmds.push(h160);
-        cmds.push(vec![0x88]); // OP_EQUALVERIFY
-        cmds.push(vec![0xac]); // OP_CHECKSIG
+        let cmds = vec![
+            Cmd::Op(OP_DUP),
+            Cmd::Op(OP_HASH160),
+            Cmd::push(h160),
+            Cmd::Op(OP_EQUALVERIFY),
+            Cmd::Op(OP_CHECKSIG),
+        ];
         let script_json = json!({});
         Script{cmds:cmds, script_json}
     }
+    pub fn p2sh_script(h160: Vec<u8>) -> Self {
+        Script::new(vec![Cmd::Op(OP_HASH160), Cmd::push(h160), Cmd::Op(OP_EQUAL)])
+    }
+    pub fn witness_program_script(version: u8, program: Vec<u8>) -> Self {
+        let version = match version {
+            0 => OP_0,
+            _ => OP_1 + version - 1,
+        };
+        Script::new(vec![Cmd::Op(version), Cmd::push(program)])
+    }
+    /// Version and program of a segwit output script (BIP141), the program
+    /// is a direct push.
+    pub fn witness_program(&self) -> Option<(u8, Vec<u8>)> {
+        let [Cmd::Op(version), Cmd::Push(op_code, program)] = self.cmds.as_slice() else {
+            return None;
+        };
+        let version = match *version {
+            OP_0 => 0,
+            OP_1..=OP_16 => version - OP_1 + 1,
+            _ => return None,
+        };
+        match (2..=40).contains(&program.len()) && *op_code as usize == program.len() {
+            true => Some((version, program.clone())),
+            false => None,
+        }
+    }
+    /// The redeem script this scriptSig gives a p2sh output, the element on top
+    /// of the stack once it has run. None when it fails or the element doesn't
+    /// parse as a script.
+    pub fn redeem_script(&self, flags: VerifyFlags) -> Option<Script> {
+        let mut state = ExecState::new(flags, None);
+        state.execute(&self.cmds, TracePhase::Script, &BigUint::from(0u32), &mut None).ok()?;
+        Script::parse_raw(state.stack.last()?).ok()
+    }
+    /// The output script paying to a base58 or bech32 address of the network.
+    pub fn from_address(address: &str, network: Network) -> Option<Self> {
+        Address::parse_for_network(address, network).ok().map(|address| address.script_pubkey())
+    }
     pub fn is_p2pkh_script_pubkey(&self) -> bool {
-        self.cmds.len() == 5 && self.cmds[0] == [0x76] && self.cmds[1] == [0xa9] && self.cmds[2].len() == 20 && self.cmds[3] == [0x88] && self.cmds[4] == [0xac]
+        self.cmds.len() == 5 && self.cmds[0] == Cmd::Op(OP_DUP) && self.cmds[1] == Cmd::Op(OP_HASH160) && self.cmds[2].data().is_some_and(|h| h.len() == 20) && self.cmds[3] == Cmd::Op(OP_EQUALVERIFY) && self.cmds[4] == Cmd::Op(OP_CHECKSIG)
+    }
+    /// True when the script only pushes data, OP_1NEGATE and OP_1..OP_16 included.
+    pub fn is_push_only(&self) -> bool {
+        self.cmds.iter().all(|cmd| matches!(cmd, Cmd::Push(_, _) | Cmd::Op(0..=OP_16)))
     }
     pub fn is_p2sh_script_pubkey(&self) -> bool {
-        self.cmds.len() == 3 && self.cmds[0] == [0xa9] && self.cmds[1].len() == 20 && self.cmds[2] == [0x87]
+        self.cmds.len() == 3 && self.is_p2sh(&self.cmds)
     }
     pub fn is_p2wpkh_script_pubkey(&self) -> bool {
-        self.cmds.len() == 2 && self.cmds[0] == [0x00] && self.cmds[1].len() == 20
+        self.cmds.len() == 2 && self.cmds[0] == Cmd::Op(OP_0) && self.cmds[1].data().is_some_and(|h| h.len() == 20)
     }
     pub fn is_p2wsh_script_pubkey(&self) -> bool {
-        self.cmds.len() == 2 && self.cmds[0] == [0x00] && self.cmds[1].len() == 32
+        self.cmds.len() == 2 && self.cmds[0] == Cmd::Op(OP_0) && self.cmds[1].data().is_some_and(|h| h.len() == 32)
     }
     fn is_p2pk(&self) -> bool {
-        self.cmds.len() == 2 && self.cmds[1] == [0xac]
+        self.cmds.len() == 2 && self.cmds[1] == Cmd::Op(OP_CHECKSIG)
     }
     fn is_op_return(&self) -> bool {
-        self.cmds[0] == [0x6a]
+        self.cmds.first() == Some(&Cmd::Op(OP_RETURN))
     }
-    fn is_p2tr(&self) -> bool {
-        self.cmds.len() == 2 && self.cmds[0] == [0x51] && self.cmds[1].len() == 32
+    pub fn is_p2tr_script_pubkey(&self) -> bool {
+        self.cmds.len() == 2 && self.cmds[0] == Cmd::Op(OP_1) && self.cmds[1].data().is_some_and(|h| h.len() == 32)
     }
     pub fn get_output_type(&self) -> OutputType {
         if self.is_p2pk() == true {
@@ -338,22 +862,22 @@ impl Script {
             return OutputType::P2wpkh
         } else if self.is_p2wsh_script_pubkey() {
             return OutputType::P2wsh
-        } else if self.is_p2tr() {
+        } else if self.is_p2tr_script_pubkey() {
             return OutputType::P2tr
         } else if self.is_op_return() {
             return OutputType::OpReturn
         }
         OutputType::Unknown
     }
-    pub fn get_address(&self, testnet: bool) -> String {
+    pub fn get_address(&self, network: Network) -> String {
         if self.is_p2pkh_script_pubkey() == true {
-            return String::from_utf8(h160_to_p2pkh_address(self.cmds[2].clone(), testnet)).unwrap_or(String::new());
+            return String::from_utf8(h160_to_p2pkh_address(self.cmds[2].data().unwrap().clone(), network)).unwrap_or(String::new());
         } else if self.is_p2sh_script_pubkey() == true {
-            return String::from_utf8(h160_to_p2sh_address(self.cmds[1].clone(), testnet)).unwrap_or(String::new());
+            return String::from_utf8(h160_to_p2sh_address(self.cmds[1].data().unwrap().clone(), network)).unwrap_or(String::new());
         } else if self.is_p2wpkh_script_pubkey() == true || self.is_p2wsh_script_pubkey() == true {
-            return bech32_segwit_encode(self.cmds[1].clone(), SegwitVersion::version_0)
-        } else if self.is_p2tr() {
-            return bech32_segwit_encode(self.cmds[1].clone(), SegwitVersion::version_1)
+            return bech32_segwit_encode(self.cmds[1].data().unwrap().clone(), SegwitVersion::version_0, network)
+        } else if self.is_p2tr_script_pubkey() {
+            return bech32_segwit_encode(self.cmds[1].data().unwrap().clone(), SegwitVersion::version_1, network)
         }
         "".to_string()
     }
@@ -367,24 +891,35 @@ impl Add for Script {
         Script::new(combined)
     }
 }
+// size of the witness stack as serialized in the transaction
+fn serialized_witness_size(witness: &[Vec<u8>]) -> usize {
+    let items = encode_varint(witness.len() as u64).unwrap().len();
+    items + witness.iter().map(|item| encode_varint(item.len() as u64).unwrap().len() + item.len()).sum::<usize>()
+}
+// data in ASM: <hex>, 0x<hex> or bare hex
+fn asm_data(token: &str) -> Option<Vec<u8>> {
+    let hex = match token.strip_prefix('<').and_then(|token| token.strip_suffix('>')) {
+        Some(hex) => hex,
+        None => token.strip_prefix("0x").unwrap_or(token),
+    };
+    hex::decode(hex).ok()
+}
 impl fmt::Display for Script {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let op_code_names = op_code_names();
         let mut result = String::new();
 
         for cmd in &self.cmds {
-            if cmd.len() == 1 {
-                if !is_op(&cmd) {
-                    panic!("OP not handled {:?}", cmd);
+            match cmd {
+                Cmd::Op(op_code) => {
+                    result.push_str(&op_code_name(*op_code));
+                }
+                Cmd::Push(_, data) => {
+                    result.push_str(
+                        &data.iter()
+                            .map(|byte| format!("{:02x}", byte))
+                            .collect::<String>(),
+                    );
                 }
-                let op_code = cmd[0];
-                result.push_str(&op_code_names[&op_code]);
-            } else {
-                result.push_str(
-                    &cmd.iter()
-                        .map(|byte| format!("{:02x}", byte))
-                        .collect::<String>(),
-                );
             }
             result.push(' ');
         }
@@ -395,6 +930,14 @@ impl fmt::Display for Script {
 mod tests {
     use super::*;
     use num::Num;
+    use crate::helpers::hash256::hash256;
+    use crate::helpers::hash160::hash160;
+    use crate::helpers::sig_hash::{SIGHASH_ALL, SIGHASH_ANYONECANPAY, SIGHASH_DEFAULT, SIGHASH_NONE, SIGHASH_SINGLE};
+    use crate::helpers::taproot::{tap_branch_hash, tweak_public_key};
+    use crate::private_key::PrivateKey;
+    use crate::tx::Tx;
+    use crate::tx_input::TxInput;
+    use crate::tx_output::TxOutput;
     #[test]
     fn test_parse() {
 
@@ -403,9 +946,9 @@ mod tests {
         let script = Script::parse(stream.by_ref()).unwrap();
         println!("{}", script);
         let required = hex::decode("304402207899531a52d59a6de200179928ca900254a36b8dff8bb75f5f5d71b1cdc26125022008b422690b8461cb52c3cc30330b23d574351872b7c361e9aae3649071c1a71601").unwrap();
-        assert_eq!(script.cmds[0], required);
+        assert_eq!(script.cmds[0].data().unwrap(), &required);
         let required = hex::decode("035d5c93d9ac96881f19ba1f686f15f009ded7c62efe85a872e6a19b43c15a2937").unwrap();
-        assert_eq!(script.cmds[1], required);
+        assert_eq!(script.cmds[1].data().unwrap(), &required);
 
         // fake test OP_PUSHDATA2
         let script_pubkey = hex::decode("FD03014d0001aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa").unwrap();
@@ -413,15 +956,15 @@ mod tests {
         let script = Script::parse(stream.by_ref()).unwrap();
         println!("{}", script);
         let required = hex::decode("aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa").unwrap();
-        assert_eq!(script.cmds[0], required);
+        assert_eq!(script.cmds[0].data().unwrap(), &required);
 
         // fake test OP_PUSHDATA1
-        let script_pubkey = hex::decode("4F4c4caaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa").unwrap();
+        let script_pubkey = hex::decode("4E4c4caaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa").unwrap();
         let mut stream = Cursor::new(script_pubkey.clone());
         let script = Script::parse(stream.by_ref()).unwrap();
         println!("{}", script);
         let required = hex::decode("aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa").unwrap();
-        assert_eq!(script.cmds[0], required);
+        assert_eq!(script.cmds[0].data().unwrap(), &required);
     }
     #[test]
     fn test_serialize() {
@@ -529,9 +1072,10 @@ mod tests {
 
         let combined_script =  script_sig + script_pubkey;
         println!("COMBINED: {}", combined_script);
-        let eval = combined_script.evaluate(&BigUint::from(0u32), &None);
+        let eval = combined_script.evaluate(&BigUint::from(0u32), &None, VerifyFlags::CONSENSUS, None);
         println!("EVAL: {:?}", eval);
-        assert_eq!(eval, true);
+        // OP_MUL is disabled
+        assert_eq!(eval.unwrap_err().kind(), ScriptErrorKind::DisabledOpcode);
     }
     #[test]
     fn test_eval_2() {
@@ -555,9 +1099,9 @@ mod tests {
 
         let combined_script =  script_sig + script_pubkey;
         println!("COMBINED: {}", combined_script);
-        let eval = combined_script.evaluate(&BigUint::from(0u32), &None);
+        let eval = combined_script.evaluate(&BigUint::from(0u32), &None, VerifyFlags::CONSENSUS, None);
         println!("EVAL: {:?}", eval);
-        assert_eq!(eval, true);
+        assert!(eval.is_ok());
     }
     #[test]
     fn test_p2pk() {
@@ -566,22 +1110,16 @@ mod tests {
         let sig = "3045022000eff69ef2b1bd93a66ed5219add4fb51e11a840f404876325a1e8ffe0529a2c022100c7207fee197d27c618aea621406f6bf5ef6fca38681d82b2f06fddbdce6feab601";
 
         let sec = hex::decode(sec).unwrap();
-        let mut sec_v:Vec<Vec<u8>> = vec![];
-        let check = hex::decode("ac").unwrap();
-        sec_v.push(sec);
-        sec_v.push(check);
-        let script_pubkey = Script::new(sec_v);
+        let script_pubkey = Script::new(vec![Cmd::push(sec), Cmd::Op(OP_CHECKSIG)]);
 
         let sig = hex::decode(sig).unwrap();
-        let mut sig_v:Vec<Vec<u8>> = vec![];
-        sig_v.push(sig);
-        let script_sig = Script::new(sig_v);
+        let script_sig = Script::new(vec![Cmd::push(sig)]);
 
         let combined_script =  script_sig + script_pubkey;
         println!("COMBINED: {}", combined_script);
-        let eval = combined_script.evaluate(&z, &None);
+        let eval = combined_script.evaluate(&z, &None, VerifyFlags::CONSENSUS, None);
         println!("EVAL: {:?}", eval);
-        assert_eq!(eval, true);
+        assert!(eval.is_ok());
     }
     #[test]
     fn test_asm_4() {
@@ -602,4 +1140,603 @@ mod tests {
         println!("{}", script);
 
     }
-}
\ No newline at end of file
+    fn script_from_hex(hex: &str) -> Script {
+        let script = hex::decode(hex).unwrap();
+        let mut full_script = encode_varint(script.len() as u64).unwrap();
+        full_script.extend(script);
+        let mut stream = Cursor::new(full_script);
+        Script::parse(&mut stream).unwrap()
+    }
+    #[test]
+    fn test_eval_if_else() {
+        // OP_IF OP_2 OP_ELSE OP_3 OP_ENDIF OP_3 OP_EQUAL
+        let script_pubkey = script_from_hex("63526753685387");
+        let taken = Script::new(vec![Cmd::Op(OP_1)]) + script_pubkey.clone();
+        assert!(taken.evaluate(&BigUint::from(0u32), &None, VerifyFlags::CONSENSUS, None).is_err());
+        let not_taken = Script::new(vec![Cmd::Op(OP_0)]) + script_pubkey.clone();
+        assert!(not_taken.evaluate(&BigUint::from(0u32), &None, VerifyFlags::CONSENSUS, None).is_ok());
+
+        // OP_NOTIF inverts the condition
+        let script_pubkey = script_from_hex("64526753685387");
+        let taken = Script::new(vec![Cmd::Op(OP_0)]) + script_pubkey;
+        assert!(taken.evaluate(&BigUint::from(0u32), &None, VerifyFlags::CONSENSUS, None).is_err());
+    }
+    #[test]
+    fn test_eval_nested_if() {
+        // OP_IF OP_IF OP_2 OP_ELSE OP_3 OP_ENDIF OP_ELSE OP_4 OP_ENDIF
+        let script_pubkey = script_from_hex("636352675368675468");
+        let expected = [(OP_1, OP_1, 2), (OP_0, OP_1, 3), (OP_1, OP_0, 4), (OP_0, OP_0, 4)];
+        for (inner, outer, want) in expected {
+            let script_sig = Script::new(vec![Cmd::Op(inner), Cmd::Op(outer)]);
+            let combined = script_sig + script_pubkey.clone() + Script::new(vec![Cmd::push(encode_num(want)), Cmd::Op(OP_EQUAL)]);
+            assert!(combined.evaluate(&BigUint::from(0u32), &None, VerifyFlags::CONSENSUS, None).is_ok(), "inner {} outer {}", inner, outer);
+        }
+    }
+    #[test]
+    fn test_eval_unbalanced_if() {
+        // OP_1 OP_IF OP_1
+        assert_eq!(script_from_hex("516351").evaluate(&BigUint::from(0u32), &None, VerifyFlags::CONSENSUS, None).unwrap_err().kind(), ScriptErrorKind::UnbalancedConditional);
+        // OP_1 OP_ENDIF
+        assert_eq!(script_from_hex("5168").evaluate(&BigUint::from(0u32), &None, VerifyFlags::CONSENSUS, None).unwrap_err().kind(), ScriptErrorKind::UnbalancedConditional);
+        // OP_1 OP_ELSE OP_1
+        assert_eq!(script_from_hex("516751").evaluate(&BigUint::from(0u32), &None, VerifyFlags::CONSENSUS, None).unwrap_err().kind(), ScriptErrorKind::UnbalancedConditional);
+        // OP_IF with an empty stack
+        assert_eq!(script_from_hex("635168").evaluate(&BigUint::from(0u32), &None, VerifyFlags::CONSENSUS, None).unwrap_err().kind(), ScriptErrorKind::UnbalancedConditional);
+        // OP_1 OP_IF in the scriptSig can't be closed by OP_1 OP_ENDIF in the scriptPubKey
+        let error = script_from_hex("5168").evaluate_spend(&script_from_hex("5163"), &BigUint::from(0u32), &None, VerifyFlags::CONSENSUS, None).unwrap_err();
+        assert_eq!(error.kind(), ScriptErrorKind::UnbalancedConditional);
+        assert_eq!(error.op_index(), None);
+    }
+    #[test]
+    fn test_eval_skip_not_executed() {
+        // OP_0 OP_IF <01> OP_RETURN OP_ENDIF OP_1: the push and OP_RETURN are skipped
+        let script = script_from_hex("006301016a6851");
+        assert_eq!(script.cmds[2], Cmd::Push(1, vec![0x01]));
+        assert!(script.evaluate(&BigUint::from(0u32), &None, VerifyFlags::CONSENSUS, None).is_ok());
+        // OP_1 OP_IF OP_RETURN OP_ENDIF OP_1: OP_RETURN is executed
+        let error = script_from_hex("51636a6851").evaluate(&BigUint::from(0u32), &None, VerifyFlags::CONSENSUS, None).unwrap_err();
+        assert_eq!(error.kind(), ScriptErrorKind::OpReturn);
+        assert_eq!(error.op_index(), Some(2));
+    }
+    #[test]
+    fn test_eval_p2wsh_htlc() {
+        // OP_IF OP_SHA256 <h> OP_EQUALVERIFY <pubkey a> OP_ELSE <pubkey b> OP_ENDIF OP_CHECKSIG
+        let z = BigUint::from_bytes_be(&hash256(b"htlc spend"));
+        let key_a = PrivateKey::new(&BigUint::from_bytes_be(&hash256(b"htlc key a")));
+        let key_b = PrivateKey::new(&BigUint::from_bytes_be(&hash256(b"htlc key b")));
+        let preimage = b"preimage".to_vec();
+        let witness_script = Script::new(vec![
+            Cmd::Op(OP_IF),
+            Cmd::Op(OP_SHA256),
+            Cmd::push(Sha256::digest(&preimage).to_vec()),
+            Cmd::Op(OP_EQUALVERIFY),
+            Cmd::push(key_a.point().sec(true)),
+            Cmd::Op(OP_ELSE),
+            Cmd::push(key_b.point().sec(true)),
+            Cmd::Op(OP_ENDIF),
+            Cmd::Op(OP_CHECKSIG),
+        ]).raw_serialize();
+        let script_pubkey = Script::new(vec![Cmd::Op(OP_0), Cmd::push(Sha256::digest(&witness_script).to_vec())]);
+
+        let mut sig_a = key_a.sign(&z).der();
+        sig_a.push(SIGHASH_ALL);
+        let witness = vec![sig_a.clone(), preimage.clone(), vec![0x01], witness_script.clone()];
+        assert!(script_pubkey.evaluate(&z, &Some(witness), VerifyFlags::CONSENSUS, None).is_ok());
+
+        let mut sig_b = key_b.sign(&z).der();
+        sig_b.push(SIGHASH_ALL);
+        let witness = vec![sig_b, vec![], witness_script.clone()];
+        assert!(script_pubkey.evaluate(&z, &Some(witness), VerifyFlags::CONSENSUS, None).is_ok());
+
+        // key a can't take the refund branch
+        let witness = vec![sig_a, vec![], witness_script];
+        assert!(script_pubkey.evaluate(&z, &Some(witness), VerifyFlags::CONSENSUS, None).is_err());
+    }
+    #[test]
+    fn test_push_round_trip() {
+        // <20> OP_SIZE, and OP_PUSHDATA1 with a short element is kept as encoded
+        let hex = "0120824c0201020000";
+        let script = script_from_hex(hex);
+        assert_eq!(script.cmds[0], Cmd::Push(1, vec![0x20]));
+        assert_eq!(script.cmds[1], Cmd::Op(OP_SIZE));
+        assert_eq!(script.cmds[2], Cmd::Push(OP_PUSHDATA1, vec![0x01, 0x02]));
+        assert_eq!(hex::encode(script.raw_serialize()), hex);
+    }
+    #[test]
+    fn test_eval_witness_program_errors() {
+        let z = BigUint::from(0u32);
+        let p2wpkh = Script::new(vec![Cmd::Op(OP_0), Cmd::push(vec![1u8; 20])]);
+        assert_eq!(p2wpkh.evaluate(&z, &None, VerifyFlags::CONSENSUS, None).unwrap_err().kind(), ScriptErrorKind::WitnessProgramWitnessEmpty);
+        let error = p2wpkh.evaluate(&z, &Some(vec![vec![0x01]]), VerifyFlags::CONSENSUS, None).unwrap_err();
+        assert_eq!(error.kind(), ScriptErrorKind::WitnessProgramMismatch);
+        assert_eq!(error.op_index(), None);
+
+        let p2wsh = Script::new(vec![Cmd::Op(OP_0), Cmd::push(Sha256::digest([OP_1]).to_vec())]);
+        assert!(p2wsh.evaluate(&z, &Some(vec![vec![OP_1]]), VerifyFlags::CONSENSUS, None).is_ok());
+        assert_eq!(p2wsh.evaluate(&z, &Some(vec![vec![OP_2]]), VerifyFlags::CONSENSUS, None).unwrap_err().kind(), ScriptErrorKind::WitnessProgramMismatch);
+        // the witness is all a witness output is spent with
+        let script_sig = Script::new(vec![Cmd::Op(OP_1)]);
+        assert_eq!(p2wsh.evaluate_spend(&script_sig, &z, &Some(vec![vec![OP_1]]), VerifyFlags::CONSENSUS, None).unwrap_err().kind(), ScriptErrorKind::WitnessMalleated);
+        // version 0 programs are 20 or 32 bytes
+        let wrong_length = Script::witness_program_script(0, vec![1u8; 21]);
+        assert_eq!(wrong_length.evaluate(&z, &Some(vec![vec![OP_1]]), VerifyFlags::CONSENSUS, None).unwrap_err().kind(), ScriptErrorKind::WitnessProgramWrongLength);
+    }
+    #[test]
+    fn test_eval_future_witness_programs() {
+        let z = BigUint::from(0u32);
+        let witness = Some(vec![vec![0x01; 64]]);
+        // versions 2 to 16, and version 1 programs that are not 32 bytes, relay
+        // policy doesn't spend them
+        for (version, len) in [(2, 32), (16, 40), (1, 20), (1, 33)] {
+            let script_pubkey = Script::witness_program_script(version, vec![1u8; len]);
+            assert!(script_pubkey.evaluate(&z, &witness, VerifyFlags::CONSENSUS, None).is_ok(), "v{} {} bytes", version, len);
+            let error = script_pubkey.evaluate(&z, &witness, VerifyFlags::STANDARD, None).unwrap_err();
+            assert_eq!(error.kind(), ScriptErrorKind::DiscourageUpgradableWitnessProgram);
+        }
+        // taproot outputs before taproot, and nested in p2sh
+        let p2tr = Script::witness_program_script(1, vec![1u8; 32]);
+        assert!(p2tr.evaluate(&z, &witness, VerifyFlags::STANDARD.without(VerifyFlags::TAPROOT), None).is_ok());
+        assert_eq!(p2tr.evaluate(&z, &witness, VerifyFlags::STANDARD, None).unwrap_err().kind(), ScriptErrorKind::SchnorrSig);
+        let script_pubkey = Script::p2sh_script(hash160(&p2tr.raw_serialize()).to_vec());
+        let script_sig = Script::new(vec![Cmd::push(p2tr.raw_serialize())]);
+        assert!(script_pubkey.evaluate_spend(&script_sig, &z, &witness, VerifyFlags::CONSENSUS, None).is_ok());
+        let error = script_pubkey.evaluate_spend(&script_sig, &z, &witness, VerifyFlags::STANDARD, None).unwrap_err();
+        assert_eq!(error.kind(), ScriptErrorKind::DiscourageUpgradableWitnessProgram);
+        // with nothing but the push of the redeem script
+        let script_sig = Script::new(vec![Cmd::Op(OP_1), Cmd::push(p2tr.raw_serialize())]);
+        assert_eq!(script_pubkey.evaluate_spend(&script_sig, &z, &witness, VerifyFlags::STANDARD, None).unwrap_err().kind(), ScriptErrorKind::WitnessMalleatedP2sh);
+    }
+    #[test]
+    fn test_trace() {
+        // OP_0 OP_IF OP_2 OP_ENDIF OP_3 OP_DUP OP_TOALTSTACK
+        let script = script_from_hex("0063526853766b");
+        let trace = script.trace(&BigUint::from(0u32), &None, VerifyFlags::CONSENSUS, None);
+        assert!(trace.result.is_ok());
+        let ops: Vec<&str> = trace.steps.iter().map(|step| step.op.as_str()).collect();
+        assert_eq!(ops, vec!["OP_0", "OP_IF", "OP_2", "OP_ENDIF", "OP_3", "OP_DUP", "OP_TOALTSTACK"]);
+        assert_eq!(trace.steps[1].exec_stack, vec![false]);
+        assert!(!trace.steps[2].executed);
+        assert_eq!(trace.steps[5].stack, vec![vec![3], vec![3]]);
+        assert_eq!(trace.steps[6].stack, vec![vec![3]]);
+        assert_eq!(trace.steps[6].altstack, vec![vec![3]]);
+
+        // the failing command is the last step
+        let trace = script_from_hex("0069").trace(&BigUint::from(0u32), &None, VerifyFlags::CONSENSUS, None);
+        assert_eq!(trace.steps.len(), 2);
+        assert_eq!(trace.result.clone().unwrap_err().op_index(), Some(1));
+        assert_eq!(trace.get_json()["script_error"]["error"], "VerifyFailed");
+    }
+    #[test]
+    fn test_trace_phases() {
+        // p2sh wrapping p2wsh, the witness script is OP_1
+        let witness_script = vec![OP_1];
+        let redeem_script = Script::new(vec![Cmd::Op(OP_0), Cmd::push(Sha256::digest(&witness_script).to_vec())]).raw_serialize();
+        let script_pubkey = Script::new(vec![Cmd::Op(OP_HASH160), Cmd::push(hash160(&redeem_script).to_vec()), Cmd::Op(OP_EQUAL)]);
+        let script_sig = Script::new(vec![Cmd::push(redeem_script)]);
+        let trace = script_pubkey.trace_spend(&script_sig, &BigUint::from(0u32), &Some(vec![witness_script]), VerifyFlags::CONSENSUS, None);
+        assert!(trace.result.is_ok());
+        let phases: Vec<TracePhase> = trace.steps.iter().map(|step| step.phase).collect();
+        assert_eq!(phases, [vec![TracePhase::Script; 4], vec![TracePhase::RedeemScript; 2], vec![TracePhase::WitnessScript]].concat());
+        assert_eq!(trace.steps[6].op, "OP_1");
+    }
+    #[test]
+    fn test_eval_flags() {
+        let z = BigUint::from(0u32);
+        // OP_PUSHDATA1 <01> OP_1 OP_EQUAL: a number pushed without OP_1
+        let script = script_from_hex("4c01015187");
+        assert!(script.evaluate(&z, &None, VerifyFlags::CONSENSUS, None).is_ok());
+        let error = script.evaluate(&z, &None, VerifyFlags::STANDARD, None).unwrap_err();
+        assert_eq!(error.kind(), ScriptErrorKind::MinimalData);
+        assert_eq!(error.op_index(), Some(0));
+        assert_eq!(error.kind().flag(), Some(VerifyFlags::MINIMALDATA));
+        // <0100> OP_1ADD: a number with a trailing zero byte
+        let script = script_from_hex("0201008b");
+        assert!(script.evaluate(&z, &None, VerifyFlags::CONSENSUS, None).is_ok());
+        assert_eq!(script.evaluate(&z, &None, VerifyFlags::MINIMALDATA, None).unwrap_err().kind(), ScriptErrorKind::NonMinimalNumber);
+
+        // OP_1 OP_1: true but two elements left
+        let script = script_from_hex("5151");
+        assert!(script.evaluate(&z, &None, VerifyFlags::CONSENSUS, None).is_ok());
+        assert_eq!(script.evaluate(&z, &None, VerifyFlags::STANDARD, None).unwrap_err().kind(), ScriptErrorKind::CleanStack);
+
+        // a witness for a script that is not a witness program
+        let script = script_from_hex("51");
+        assert!(script.evaluate(&z, &Some(vec![vec![0x01]]), VerifyFlags::P2SH, None).is_ok());
+        assert_eq!(script.evaluate(&z, &Some(vec![vec![0x01]]), VerifyFlags::CONSENSUS, None).unwrap_err().kind(), ScriptErrorKind::WitnessUnexpected);
+
+        // without P2SH the redeem script (OP_0) is not evaluated
+        let redeem_script = vec![OP_0];
+        let script_pubkey = Script::new(vec![Cmd::Op(OP_HASH160), Cmd::push(hash160(&redeem_script).to_vec()), Cmd::Op(OP_EQUAL)]);
+        let script_sig = Script::new(vec![Cmd::push(redeem_script.clone())]);
+        assert!(script_pubkey.evaluate_spend(&script_sig, &z, &None, VerifyFlags::NONE, None).is_ok());
+        assert_eq!(script_pubkey.evaluate_spend(&script_sig, &z, &None, VerifyFlags::P2SH, None).unwrap_err().kind(), ScriptErrorKind::EvalFalse);
+        // and with P2SH the scriptSig can only push data
+        let script_sig = Script::new(vec![Cmd::Op(OP_NOP), Cmd::push(redeem_script)]);
+        assert!(script_pubkey.evaluate_spend(&script_sig, &z, &None, VerifyFlags::NONE, None).is_ok());
+        assert_eq!(script_pubkey.evaluate_spend(&script_sig, &z, &None, VerifyFlags::P2SH, None).unwrap_err().kind(), ScriptErrorKind::SigPushOnly);
+
+        // witness scripts must leave exactly one element
+        let witness_script = vec![OP_1, OP_1];
+        let p2wsh = Script::new(vec![Cmd::Op(OP_0), Cmd::push(Sha256::digest(&witness_script).to_vec())]);
+        assert_eq!(p2wsh.evaluate(&z, &Some(vec![witness_script]), VerifyFlags::CONSENSUS, None).unwrap_err().kind(), ScriptErrorKind::CleanStack);
+    }
+    #[test]
+    fn test_eval_timelocks() {
+        let z = BigUint::from(0u32);
+        let tx_in = TxInput::new(vec![0u8; 32], 0, Script::new(vec![]), 0xfffffffe);
+        let tx = Tx::new(2, vec![tx_in], vec![], 600_000, Network::Mainnet, false);
+        let context = TxContext::new(&tx, 0, TxOutput::new(0, Script::new(vec![])));
+        // <600000> OP_CHECKLOCKTIMEVERIFY OP_DROP OP_1
+        let script = Script::new(vec![Cmd::push(encode_num(600_000)), Cmd::Op(OP_CHECKLOCKTIMEVERIFY), Cmd::Op(OP_DROP), Cmd::Op(OP_1)]);
+        assert!(script.evaluate(&z, &None, VerifyFlags::CONSENSUS, Some(&context)).is_ok());
+        let error = script.evaluate(&z, &None, VerifyFlags::CONSENSUS, None).unwrap_err();
+        assert_eq!(error.kind(), ScriptErrorKind::UnsatisfiedLocktime);
+        assert_eq!(error.op_index(), Some(1));
+        // without the flag it is a NOP
+        assert!(script.evaluate(&z, &None, VerifyFlags::P2SH, None).is_ok());
+        // <600001> OP_CHECKLOCKTIMEVERIFY OP_DROP OP_1
+        let script = Script::new(vec![Cmd::push(encode_num(600_001)), Cmd::Op(OP_CHECKLOCKTIMEVERIFY), Cmd::Op(OP_DROP), Cmd::Op(OP_1)]);
+        assert_eq!(script.evaluate(&z, &None, VerifyFlags::CONSENSUS, Some(&context)).unwrap_err().kind(), ScriptErrorKind::UnsatisfiedLocktime);
+        // OP_0 OP_CHECKSEQUENCEVERIFY: the input sequence 0xfffffffe has the disable flag set
+        let script = script_from_hex("00b2");
+        assert_eq!(script.evaluate(&z, &None, VerifyFlags::CONSENSUS, Some(&context)).unwrap_err().kind(), ScriptErrorKind::UnsatisfiedLocktime);
+    }
+    #[test]
+    fn test_from_asm() {
+        // p2pkh round-trips through Display
+        let hex = "76a914ab68025513c3dbd2f7b92a94e0581f5d50f654e788ac";
+        let script = Script::from_asm("OP_DUP OP_HASH160 <ab68025513c3dbd2f7b92a94e0581f5d50f654e7> OP_EQUALVERIFY OP_CHECKSIG").unwrap();
+        assert_eq!(hex::encode(script.raw_serialize()), hex);
+        assert_eq!(Script::from_asm(&script.to_string()).unwrap().serialize(), script.serialize());
+        assert_eq!(script.get_json()["cmd_list_json"], script_from_hex(hex).get_json()["cmd_list_json"]);
+        // names without the OP_ prefix, 0x data and the Display form of unknown opcodes
+        let script = Script::from_asm("dup hash160 0xab68025513c3dbd2f7b92a94e0581f5d50f654e7 equalverify checksig OP_UNKNOWN_0xba").unwrap();
+        assert_eq!(hex::encode(script.raw_serialize()), format!("{}ba", hex));
+        // numbers use the small integer opcodes when they can
+        let script = Script::from_asm("-1 0 1 16 17 -17 1000 <>").unwrap();
+        assert_eq!(hex::encode(script.raw_serialize()), "4f00516001110191 02e803 00".replace(' ', ""));
+        // explicit push forms are kept
+        let script = Script::from_asm("OP_PUSHDATA1 0x01 OP_PUSHDATA2 0x01 OP_PUSHDATA4 0x01 OP_PUSHBYTES_1 01").unwrap();
+        assert_eq!(hex::encode(script.raw_serialize()), "4c01014d0100014e01000000010101");
+        assert_eq!(script.get_json()["cmd_list_json"][6], "OP_PUSHBYTES_1");
+        // the cmd list of a parsed script can be assembled back
+        let hex = "4c0101ab";
+        let asm = script_from_hex(hex).get_json()["cmd_list_json"].as_array().unwrap().iter()
+            .map(|cmd| cmd.as_str().unwrap()).collect::<Vec<&str>>().join(" ");
+        assert_eq!(hex::encode(Script::from_asm(&asm).unwrap().raw_serialize()), hex);
+        // and evaluated
+        assert!(Script::from_asm("2 3 OP_ADD 5 OP_EQUAL").unwrap().evaluate(&BigUint::from(0u32), &None, VerifyFlags::CONSENSUS, None).is_ok());
+
+        assert!(Script::from_asm("OP_FOO").is_err());
+        assert!(Script::from_asm("abc").is_err());
+        assert!(Script::from_asm("OP_PUSHBYTES_2 01").is_err());
+        assert!(Script::from_asm("OP_PUSHBYTES_76 01").is_err());
+        assert!(Script::from_asm("OP_PUSHDATA1").is_err());
+        assert!(Script::from_asm("4294967296").is_err());
+    }
+    #[test]
+    fn test_eval_sighash_types() {
+        let key = PrivateKey::new(&BigUint::from_bytes_be(&hash256(b"sighash key")));
+        let sec = key.point().sec(true);
+        let h160 = hash160(&sec).to_vec();
+        let inputs = vec![
+            TxInput::new(vec![1u8; 32], 0, Script::new(vec![]), 0xffffffff),
+            TxInput::new(vec![2u8; 32], 1, Script::new(vec![]), 0xffffffff),
+        ];
+        let outputs = vec![TxOutput::new(20_000, Script::new(vec![Cmd::Op(OP_1)])), TxOutput::new(20_000, Script::new(vec![Cmd::Op(OP_2)]))];
+        let tx = Tx::new(1, inputs.clone(), outputs.clone(), 0, Network::Mainnet, false);
+        // the second output is not signed by SIGHASH_SINGLE or SIGHASH_NONE
+        let changed_tx = Tx::new(1, inputs, vec![outputs[0].clone()], 0, Network::Mainnet, false);
+        let verify = |tx: &Tx, script_sig: Script, script_pubkey: Script, witness: Option<Vec<Vec<u8>>>| {
+            let context = TxContext::new(tx, 0, TxOutput::new(50_000, script_pubkey.clone()));
+            script_pubkey.evaluate_spend(&script_sig, &BigUint::from(0u32), &witness, VerifyFlags::STANDARD, Some(&context)).is_ok()
+        };
+
+        // legacy p2pkh signed with SIGHASH_SINGLE
+        let script_pubkey = Script::p2pkh_script(h160.clone());
+        let mut sig = key.sign(&tx.sig_hash_legacy(0, &script_pubkey, SIGHASH_SINGLE)).der();
+        sig.push(SIGHASH_SINGLE);
+        let script_sig = |sig: &[u8]| Script::new(vec![Cmd::push(sig.to_vec()), Cmd::push(sec.clone())]);
+        assert!(verify(&tx, script_sig(&sig), script_pubkey.clone(), None));
+        assert!(verify(&changed_tx, script_sig(&sig), script_pubkey.clone(), None));
+        // the hash type is part of the digest
+        let last = sig.len() - 1;
+        sig[last] = SIGHASH_ALL;
+        assert!(!verify(&tx, script_sig(&sig), script_pubkey, None));
+
+        // p2wpkh signed with SIGHASH_NONE | SIGHASH_ANYONECANPAY
+        let hash_type = SIGHASH_NONE | SIGHASH_ANYONECANPAY;
+        let script_pubkey = Script::new(vec![Cmd::Op(OP_0), Cmd::push(h160.clone())]);
+        let mut sig = key.sign(&tx.sig_hash_segwit(0, &Script::p2pkh_script(h160), 50_000, hash_type)).der();
+        sig.push(hash_type);
+        assert!(verify(&tx, Script::new(vec![]), script_pubkey.clone(), Some(vec![sig.clone(), sec.clone()])));
+        assert!(verify(&changed_tx, Script::new(vec![]), script_pubkey.clone(), Some(vec![sig.clone(), sec.clone()])));
+        let last = sig.len() - 1;
+        sig[last] = SIGHASH_NONE;
+        assert!(!verify(&tx, Script::new(vec![]), script_pubkey, Some(vec![sig, sec])));
+    }
+    #[test]
+    fn test_eval_find_and_delete_and_codeseparator() {
+        let key = PrivateKey::new(&BigUint::from_bytes_be(&hash256(b"codeseparator key")));
+        let sec = key.point().sec(true);
+        let tx_in = TxInput::new(vec![1u8; 32], 0, Script::new(vec![]), 0xffffffff);
+        let tx = Tx::new(1, vec![tx_in], vec![TxOutput::new(1000, Script::new(vec![]))], 0, Network::Mainnet, false);
+        let sign = |script_code: &Script| {
+            let mut sig = key.sign(&tx.sig_hash_legacy(0, script_code, SIGHASH_ALL)).der();
+            sig.push(SIGHASH_ALL);
+            sig
+        };
+        let verify = |script_pubkey: Script| {
+            let context = TxContext::new(&tx, 0, TxOutput::new(2000, script_pubkey.clone()));
+            script_pubkey.evaluate(&BigUint::from(0u32), &None, VerifyFlags::CONSENSUS, Some(&context)).is_ok()
+        };
+        let checksig = Script::new(vec![Cmd::push(sec.clone()), Cmd::Op(OP_CHECKSIG)]);
+
+        // the signature is removed from the script code it signs
+        let sig = sign(&checksig);
+        assert!(verify(Script::new(vec![Cmd::push(sig.clone())]) + checksig.clone()));
+        assert_eq!(Script::new(vec![Cmd::push(sig.clone())]).find_and_delete(&sig).cmds, vec![]);
+        // only when pushed in its shortest encoding
+        let long_push = Script::new(vec![Cmd::Push(OP_PUSHDATA1, sig.clone())]);
+        assert_eq!(long_push.find_and_delete(&sig), long_push);
+
+        // only the script after the last executed OP_CODESEPARATOR is signed
+        let script_pubkey = Script::new(vec![Cmd::Op(OP_NOP), Cmd::Op(OP_CODESEPARATOR)]) + checksig.clone();
+        let sig = sign(&checksig);
+        assert!(verify(Script::new(vec![Cmd::push(sig.clone())]) + script_pubkey.clone()));
+        let sig = sign(&script_pubkey);
+        assert!(!verify(Script::new(vec![Cmd::push(sig)]) + script_pubkey));
+        // an OP_CODESEPARATOR in a branch not taken changes nothing
+        let script_pubkey = Script::new(vec![Cmd::Op(OP_0), Cmd::Op(OP_IF), Cmd::Op(OP_CODESEPARATOR), Cmd::Op(OP_ENDIF)]) + checksig.clone();
+        let sig = sign(&script_pubkey);
+        assert!(verify(Script::new(vec![Cmd::push(sig)]) + script_pubkey));
+    }
+    #[test]
+    fn test_eval_taproot_key_path() {
+        let key = PrivateKey::new(&BigUint::from_bytes_be(&hash256(b"taproot key")));
+        let script_pubkey = Script::new(vec![Cmd::Op(OP_1), Cmd::push(key.point().xonly())]);
+        let inputs = vec![
+            TxInput::new(vec![1u8; 32], 0, Script::new(vec![]), 0xffffffff),
+            TxInput::new(vec![2u8; 32], 0, Script::new(vec![]), 0xffffffff),
+        ];
+        let tx = Tx::new(2, inputs, vec![TxOutput::new(50_000, Script::new(vec![]))], 0, Network::Mainnet, true);
+        let spent_outputs = vec![TxOutput::new(30_000, script_pubkey.clone()), TxOutput::new(30_000, Script::p2pkh_script(vec![0u8; 20]))];
+        let context = TxContext::new(&tx, 0, spent_outputs[0].clone()).with_spent_outputs(spent_outputs.clone());
+        let eval = |witness: Vec<Vec<u8>>| script_pubkey.evaluate(&BigUint::from(0u32), &Some(witness), VerifyFlags::STANDARD, Some(&context)).map_err(|e| e.kind());
+        let sign = |hash_type, annex: Option<&[u8]>| key.sign_schnorr(&tx.sig_hash_taproot(0, &spent_outputs, hash_type, annex, None).unwrap(), &[0u8; 32]);
+
+        let sig = sign(SIGHASH_DEFAULT, None);
+        assert_eq!(eval(vec![sig.clone()]), Ok(()));
+        // the signature commits to the hash type
+        let mut explicit = sig.clone();
+        explicit.push(SIGHASH_ALL);
+        assert_eq!(eval(vec![explicit]), Err(ScriptErrorKind::SchnorrSig));
+        let mut explicit = sign(SIGHASH_ALL | SIGHASH_ANYONECANPAY, None);
+        explicit.push(SIGHASH_ALL | SIGHASH_ANYONECANPAY);
+        assert_eq!(eval(vec![explicit]), Ok(()));
+        // and to the annex
+        let annex = vec![ANNEX_TAG, 0x01];
+        assert_eq!(eval(vec![sig.clone(), annex.clone()]), Err(ScriptErrorKind::SchnorrSig));
+        assert_eq!(eval(vec![sign(SIGHASH_DEFAULT, Some(&annex)), annex]), Ok(()));
+
+        let mut zero_hash_type = sig.clone();
+        zero_hash_type.push(SIGHASH_DEFAULT);
+        assert_eq!(eval(vec![zero_hash_type]), Err(ScriptErrorKind::SchnorrSigHashType));
+        let mut undefined_hash_type = sig.clone();
+        undefined_hash_type.push(0x04);
+        assert_eq!(eval(vec![undefined_hash_type]), Err(ScriptErrorKind::SchnorrSigHashType));
+        assert_eq!(eval(vec![sig[..63].to_vec()]), Err(ScriptErrorKind::SchnorrSigSize));
+        assert_eq!(eval(vec![]), Err(ScriptErrorKind::WitnessProgramWitnessEmpty));
+        // with more elements it's a script path spend
+        assert_eq!(eval(vec![sig.clone(), vec![0x01]]), Err(ScriptErrorKind::TaprootWrongControlSize));
+    }
+    #[test]
+    fn test_eval_tapscript() {
+        let internal_key = PrivateKey::new(&BigUint::from_bytes_be(&hash256(b"internal key"))).point();
+        let key_b = PrivateKey::new(&BigUint::from_bytes_be(&hash256(b"tapscript key b")));
+        let key_c = PrivateKey::new(&BigUint::from_bytes_be(&hash256(b"tapscript key c")));
+        // a single signature leaf and a 2-of-2 leaf
+        let leaf1 = Script::new(vec![Cmd::push(key_b.point().xonly()), Cmd::Op(OP_CHECKSIG)]);
+        let leaf2 = Script::new(vec![
+            Cmd::push(key_b.point().xonly()), Cmd::Op(OP_CHECKSIG),
+            Cmd::push(key_c.point().xonly()), Cmd::Op(OP_CHECKSIGADD), Cmd::Op(OP_2), Cmd::Op(OP_NUMEQUAL),
+        ]);
+        let (hash1, hash2) = (tap_leaf_hash(TAPROOT_LEAF_TAPSCRIPT, &leaf1.raw_serialize()), tap_leaf_hash(TAPROOT_LEAF_TAPSCRIPT, &leaf2.raw_serialize()));
+        let output_key = tweak_public_key(&internal_key.xonly(), Some(&tap_branch_hash(&hash1, &hash2))).unwrap();
+        let script_pubkey = Script::new(vec![Cmd::Op(OP_1), Cmd::push(output_key.xonly())]);
+        let control_block = |sibling: &[u8]| {
+            let mut control_block = vec![TAPROOT_LEAF_TAPSCRIPT | if output_key.has_even_y() { 0 } else { 1 }];
+            control_block.extend(internal_key.xonly());
+            control_block.extend(sibling);
+            control_block
+        };
+
+        let tx_in = TxInput::new(vec![1u8; 32], 0, Script::new(vec![]), 0xffffffff);
+        let tx = Tx::new(2, vec![tx_in], vec![TxOutput::new(50_000, Script::new(vec![]))], 0, Network::Mainnet, true);
+        let spent_outputs = vec![TxOutput::new(60_000, script_pubkey.clone())];
+        let context = TxContext::new(&tx, 0, spent_outputs[0].clone()).with_spent_outputs(spent_outputs.clone());
+        let eval = |witness: Vec<Vec<u8>>| script_pubkey.evaluate(&BigUint::from(0u32), &Some(witness), VerifyFlags::STANDARD, Some(&context)).map_err(|e| e.kind());
+        let sign = |key: &PrivateKey, leaf_hash| key.sign_schnorr(&tx.sig_hash_taproot(0, &spent_outputs, SIGHASH_DEFAULT, None, Some((leaf_hash, 0xffffffff))).unwrap(), &[0u8; 32]);
+
+        let sig_b = sign(&key_b, hash1);
+        assert_eq!(eval(vec![sig_b.clone(), leaf1.raw_serialize(), control_block(&hash2)]), Ok(()));
+        // the control block must prove the leaf is in the tree
+        let mut wrong_parity = control_block(&hash2);
+        wrong_parity[0] ^= 1;
+        assert_eq!(eval(vec![sig_b.clone(), leaf1.raw_serialize(), wrong_parity]), Err(ScriptErrorKind::WitnessProgramMismatch));
+        assert_eq!(eval(vec![sig_b.clone(), leaf1.raw_serialize(), control_block(&hash1)]), Err(ScriptErrorKind::WitnessProgramMismatch));
+        assert_eq!(eval(vec![sig_b.clone(), leaf1.raw_serialize(), control_block(&hash2[..31])]), Err(ScriptErrorKind::TaprootWrongControlSize));
+        // signatures commit to the leaf, a non-empty invalid one fails the script
+        let sig_b2 = sign(&key_b, hash2);
+        assert_eq!(eval(vec![sig_b2.clone(), leaf1.raw_serialize(), control_block(&hash2)]), Err(ScriptErrorKind::SchnorrSig));
+        // OP_CHECKSIGADD counts the valid signatures, an empty one counts zero
+        let sig_c2 = sign(&key_c, hash2);
+        assert_eq!(eval(vec![sig_c2, sig_b2.clone(), leaf2.raw_serialize(), control_block(&hash1)]), Ok(()));
+        assert_eq!(eval(vec![vec![], sig_b2, leaf2.raw_serialize(), control_block(&hash1)]), Err(ScriptErrorKind::EvalFalse));
+
+        // single leaf trees, without signatures
+        let eval_leaf_with = |leaf: Script, inputs: Vec<Vec<u8>>, leaf_version: u8, flags: VerifyFlags| {
+            let output_key = tweak_public_key(&internal_key.xonly(), Some(&tap_leaf_hash(leaf_version, &leaf.raw_serialize()))).unwrap();
+            let script_pubkey = Script::new(vec![Cmd::Op(OP_1), Cmd::push(output_key.xonly())]);
+            let mut control_block = vec![leaf_version | if output_key.has_even_y() { 0 } else { 1 }];
+            control_block.extend(internal_key.xonly());
+            let mut witness = inputs;
+            witness.extend([leaf.raw_serialize(), control_block]);
+            script_pubkey.evaluate(&BigUint::from(0u32), &Some(witness), flags, None).map_err(|e| e.kind())
+        };
+        let eval_leaf = |leaf: Script, inputs: Vec<Vec<u8>>, leaf_version: u8| eval_leaf_with(leaf, inputs, leaf_version, VerifyFlags::CONSENSUS);
+        // OP_SUCCESSx succeeds before anything is executed, so do unknown leaf versions
+        assert_eq!(eval_leaf(Script::new(vec![Cmd::Op(OP_RETURN), Cmd::Op(OP_CAT)]), vec![], TAPROOT_LEAF_TAPSCRIPT), Ok(()));
+        assert_eq!(eval_leaf(Script::new(vec![Cmd::Op(OP_RETURN)]), vec![], 0xc2), Ok(()));
+        // relay policy keeps them for upgrades
+        let op_success = eval_leaf_with(Script::new(vec![Cmd::Op(OP_RETURN), Cmd::Op(OP_CAT)]), vec![], TAPROOT_LEAF_TAPSCRIPT, VerifyFlags::STANDARD);
+        assert_eq!(op_success, Err(ScriptErrorKind::DiscourageOpSuccess));
+        let leaf_version = eval_leaf_with(Script::new(vec![Cmd::Op(OP_RETURN)]), vec![], 0xc2, VerifyFlags::STANDARD);
+        assert_eq!(leaf_version, Err(ScriptErrorKind::DiscourageUpgradableTaprootVersion));
+        let multisig = Script::new(vec![Cmd::Op(OP_0), Cmd::Op(OP_0), Cmd::Op(OP_0), Cmd::Op(OP_CHECKMULTISIG)]);
+        assert_eq!(eval_leaf(multisig, vec![], TAPROOT_LEAF_TAPSCRIPT), Err(ScriptErrorKind::TapscriptCheckMultiSig));
+        let if_script = Script::new(vec![Cmd::Op(OP_IF), Cmd::Op(OP_1), Cmd::Op(OP_ENDIF)]);
+        assert_eq!(eval_leaf(if_script.clone(), vec![vec![0x01]], TAPROOT_LEAF_TAPSCRIPT), Ok(()));
+        assert_eq!(eval_leaf(if_script, vec![vec![0x02]], TAPROOT_LEAF_TAPSCRIPT), Err(ScriptErrorKind::TapscriptMinimalIf));
+        // keys that are not 32 bytes are not checked, but their signatures use up the validation weight
+        let checks = |n: usize| {
+            let mut cmds = vec![];
+            for _ in 0..n {
+                cmds.extend([Cmd::push(vec![0x02; 33]), Cmd::Op(OP_CHECKSIGVERIFY)]);
+            }
+            cmds.push(Cmd::Op(OP_1));
+            eval_leaf(Script::new(cmds), vec![vec![0x01]; n], TAPROOT_LEAF_TAPSCRIPT)
+        };
+        assert_eq!(checks(2), Ok(()));
+        let unknown_key = Script::new(vec![Cmd::Op(OP_0), Cmd::push(vec![0x02; 33]), Cmd::Op(OP_CHECKSIG), Cmd::Op(OP_NOT)]);
+        assert_eq!(eval_leaf(unknown_key.clone(), vec![], TAPROOT_LEAF_TAPSCRIPT), Ok(()));
+        assert_eq!(eval_leaf_with(unknown_key, vec![], TAPROOT_LEAF_TAPSCRIPT, VerifyFlags::STANDARD), Err(ScriptErrorKind::DiscourageUpgradablePubKeyType));
+        assert_eq!(checks(20), Err(ScriptErrorKind::TapscriptValidationWeight));
+        // OP_CHECKSIGADD is only defined in tapscript
+        let legacy = Script::new(vec![Cmd::Op(OP_0), Cmd::Op(OP_0), Cmd::Op(OP_0), Cmd::Op(OP_CHECKSIGADD)]);
+        assert_eq!(legacy.evaluate(&BigUint::from(0u32), &None, VerifyFlags::STANDARD, None).unwrap_err().kind(), ScriptErrorKind::BadOpcode);
+    }
+    #[test]
+    fn test_eval_policy_flags() {
+        let z = BigUint::from(0u32);
+        let eval = |script_sig: Vec<Cmd>, script_pubkey: Vec<Cmd>, witness: Option<Vec<Vec<u8>>>, flags: VerifyFlags| {
+            Script::new(script_pubkey).evaluate_spend(&Script::new(script_sig), &z, &witness, flags, None).map_err(|error| error.kind())
+        };
+        let (bad_sig, key) = (vec![0x30, 0x01, SIGHASH_ALL], vec![0x02; 33]);
+        // a failed check must have an empty signature, multisig checks it before the dummy
+        let checksig = vec![Cmd::push(key.clone()), Cmd::Op(OP_CHECKSIG), Cmd::Op(OP_NOT)];
+        assert_eq!(eval(vec![Cmd::push(bad_sig.clone())], checksig.clone(), None, VerifyFlags::NONE), Ok(()));
+        assert_eq!(eval(vec![Cmd::push(bad_sig.clone())], checksig.clone(), None, VerifyFlags::NULLFAIL), Err(ScriptErrorKind::SigNullFail));
+        assert_eq!(eval(vec![Cmd::Op(OP_0)], checksig, None, VerifyFlags::NULLFAIL), Ok(()));
+        let multisig = vec![Cmd::Op(OP_1), Cmd::push(key.clone()), Cmd::Op(OP_1), Cmd::Op(OP_CHECKMULTISIG), Cmd::Op(OP_NOT)];
+        let flags = VerifyFlags::NULLFAIL | VerifyFlags::NULLDUMMY;
+        assert_eq!(eval(vec![Cmd::Op(OP_1), Cmd::push(bad_sig.clone())], multisig.clone(), None, flags), Err(ScriptErrorKind::SigNullFail));
+        assert_eq!(eval(vec![Cmd::Op(OP_1), Cmd::Op(OP_0)], multisig, None, flags), Err(ScriptErrorKind::SigNullDummy));
+
+        // legacy scripts can't use OP_CODESEPARATOR, even in a branch not taken,
+        // or contain their signatures
+        let codeseparator = vec![Cmd::Op(OP_0), Cmd::Op(OP_IF), Cmd::Op(OP_CODESEPARATOR), Cmd::Op(OP_ENDIF), Cmd::Op(OP_1)];
+        assert_eq!(eval(vec![], codeseparator.clone(), None, VerifyFlags::NONE), Ok(()));
+        assert_eq!(eval(vec![], codeseparator, None, VerifyFlags::CONST_SCRIPTCODE), Err(ScriptErrorKind::OpCodeSeparator));
+        let own_sig = vec![Cmd::push(bad_sig.clone()), Cmd::Op(OP_DROP), Cmd::push(key.clone()), Cmd::Op(OP_CHECKSIG), Cmd::Op(OP_NOT)];
+        assert_eq!(eval(vec![Cmd::push(bad_sig.clone())], own_sig.clone(), None, VerifyFlags::NONE), Ok(()));
+        assert_eq!(eval(vec![Cmd::push(bad_sig)], own_sig, None, VerifyFlags::CONST_SCRIPTCODE), Err(ScriptErrorKind::SigFindAndDelete));
+
+        // witness v0 scripts: minimal OP_IF arguments and compressed keys only
+        let p2wsh = |witness_script: &Script| vec![Cmd::Op(OP_0), Cmd::push(Sha256::digest(witness_script.raw_serialize()).to_vec())];
+        let if_script = Script::new(vec![Cmd::Op(OP_IF), Cmd::Op(OP_1), Cmd::Op(OP_ENDIF)]);
+        let witness = Some(vec![vec![0x02], if_script.raw_serialize()]);
+        assert_eq!(eval(vec![], p2wsh(&if_script), witness.clone(), VerifyFlags::CONSENSUS), Ok(()));
+        assert_eq!(eval(vec![], p2wsh(&if_script), witness, VerifyFlags::CONSENSUS | VerifyFlags::MINIMALIF), Err(ScriptErrorKind::MinimalIf));
+        let uncompressed = Script::new(vec![Cmd::Op(OP_0), Cmd::push([vec![0x04], vec![0x01; 64]].concat()), Cmd::Op(OP_CHECKSIG), Cmd::Op(OP_NOT)]);
+        let witness = Some(vec![uncompressed.raw_serialize()]);
+        let flags = VerifyFlags::CONSENSUS | VerifyFlags::WITNESS_PUBKEYTYPE;
+        assert_eq!(eval(vec![], p2wsh(&uncompressed), witness.clone(), VerifyFlags::CONSENSUS), Ok(()));
+        assert_eq!(eval(vec![], p2wsh(&uncompressed), witness, flags), Err(ScriptErrorKind::WitnessPubKeyType));
+        assert_eq!(eval(vec![], uncompressed.cmds, None, flags), Ok(()));
+
+        // scriptSigs that do more than push
+        assert_eq!(eval(vec![Cmd::Op(OP_NOP), Cmd::Op(OP_1)], vec![Cmd::Op(OP_1)], None, VerifyFlags::NONE), Ok(()));
+        assert_eq!(eval(vec![Cmd::Op(OP_NOP), Cmd::Op(OP_1)], vec![Cmd::Op(OP_1)], None, VerifyFlags::SIGPUSHONLY), Err(ScriptErrorKind::SigPushOnly));
+    }
+    #[test]
+    fn test_nops_and_disabled_opcodes() {
+        let z = BigUint::from(0u32);
+        let eval = |hex: &str| script_from_hex(hex).evaluate(&z, &None, VerifyFlags::CONSENSUS, None).map_err(|error| error.kind());
+        // OP_1 OP_NOP1 OP_NOP4 .. OP_NOP10
+        assert_eq!(eval("51b0b3b4b5b6b7b8b9"), Ok(()));
+        let discouraged = script_from_hex("51b0").evaluate(&z, &None, VerifyFlags::STANDARD, None).unwrap_err();
+        assert_eq!((discouraged.kind(), discouraged.op_index()), (ScriptErrorKind::DiscourageUpgradableNops, Some(1)));
+        // OP_0 OP_IF OP_NOP10 OP_ENDIF OP_1, only executed NOPs are discouraged
+        assert!(script_from_hex("0063b96851").evaluate(&z, &None, VerifyFlags::STANDARD, None).is_ok());
+        // OP_0 OP_IF OP_CAT OP_ENDIF OP_1, not executed but still disabled
+        assert_eq!(eval("00637e6851"), Err(ScriptErrorKind::DisabledOpcode));
+        assert_eq!(eval("5252958d"), Err(ScriptErrorKind::DisabledOpcode));
+        // OP_VERIF is not an opcode at all
+        assert_eq!(eval("00636568"), Err(ScriptErrorKind::BadOpcode));
+    }
+    #[test]
+    fn test_limits() {
+        let z = BigUint::from(0u32);
+        let eval = |hex: String| script_from_hex(&hex).evaluate(&z, &None, VerifyFlags::CONSENSUS, None);
+        // stack elements
+        let script = Script::new(vec![Cmd::push(vec![1u8; MAX_SCRIPT_ELEMENT_SIZE + 1])]);
+        assert_eq!(script.evaluate(&z, &None, VerifyFlags::CONSENSUS, None).unwrap_err().kind(), ScriptErrorKind::PushSize);
+        // non-push opcodes, OP_1 followed by OP_NOPs
+        assert!(eval(format!("51{}", "61".repeat(201))).is_ok());
+        let error = eval(format!("51{}", "61".repeat(202))).unwrap_err();
+        assert_eq!(error.kind(), ScriptErrorKind::OpCount);
+        assert_eq!(error.op_index(), Some(202));
+        // opcodes in a branch not taken count too
+        assert_eq!(eval(format!("0063{}6851", "61".repeat(200))).unwrap_err().kind(), ScriptErrorKind::OpCount);
+        // OP_0 OP_0 <16 keys> OP_16 OP_CHECKMULTISIG counts 17
+        let multisig = format!("0000{}60ae", format!("21{}", "02".repeat(33)).repeat(16));
+        assert!(eval(format!("{}{}", "61".repeat(184), multisig)).is_ok());
+        assert_eq!(eval(format!("{}{}", "61".repeat(185), multisig)).unwrap_err().kind(), ScriptErrorKind::OpCount);
+        // stack and altstack together
+        assert!(eval("51".repeat(1000)).is_ok());
+        assert_eq!(eval("51".repeat(1001)).unwrap_err().kind(), ScriptErrorKind::StackSize);
+        assert_eq!(eval(format!("{}6b51", "51".repeat(1000))).unwrap_err().kind(), ScriptErrorKind::StackSize);
+        // witness scripts
+        let witness_script = vec![OP_1; MAX_SCRIPT_SIZE + 1];
+        let p2wsh = Script::new(vec![Cmd::Op(OP_0), Cmd::push(Sha256::digest(&witness_script).to_vec())]);
+        assert_eq!(p2wsh.evaluate(&z, &Some(vec![witness_script]), VerifyFlags::CONSENSUS, None).unwrap_err().kind(), ScriptErrorKind::ScriptSize);
+    }
+    #[test]
+    fn test_parse_and_serialize_limits() {
+        // a push longer than the script fails before allocating
+        let mut stream = Cursor::new(hex::decode("064effffffff00").unwrap());
+        assert!(Script::parse(&mut stream).is_err());
+        let mut stream = Cursor::new(hex::decode("024c05").unwrap());
+        assert!(Script::parse(&mut stream).is_err());
+        // and so does a script shorter than its length
+        let mut stream = Cursor::new(hex::decode("0a51").unwrap());
+        assert!(Script::parse(&mut stream).is_err());
+        // large pushes serialize, a push opcode too small for its data is widened
+        let data = vec![0xaau8; 600];
+        let script = Script::new(vec![Cmd::Push(OP_PUSHDATA1, data.clone())]);
+        let raw = script.raw_serialize();
+        assert_eq!(hex::encode(&raw[..3]), "4d5802");
+        let parsed = Script::parse(&mut Cursor::new(script.serialize())).unwrap();
+        assert_eq!(parsed.cmds, vec![Cmd::Push(OP_PUSHDATA2, data)]);
+    }
+    #[test]
+    fn test_address_networks() {
+        let p2wpkh = Script::witness_program_script(0, hex::decode("60d6e1ace002fd8eaf32150bf57c85d44c16e16a").unwrap());
+        let p2pkh = Script::p2pkh_script(hex::decode("74d691da1574e6b3c192ecfb52cc8984ee7b6c56").unwrap());
+        let addresses = [
+            (Network::Mainnet, "bc1qvrtwrt8qqt7catejz59l2ly963xpdct2j370g3", "1BenRpVUFK65JFWcQSuHnJKzc4M8ZP8Eqa"),
+            (Network::Testnet4, "tb1qvrtwrt8qqt7catejz59l2ly963xpdct2ch9unz", "mrAjisaT4LXL5MzE81sfcDYKU3wqWSvf9q"),
+            (Network::Regtest, "bcrt1qvrtwrt8qqt7catejz59l2ly963xpdct267u3yt", "mrAjisaT4LXL5MzE81sfcDYKU3wqWSvf9q"),
+        ];
+        for (network, segwit_address, base58_address) in addresses {
+            assert_eq!(p2wpkh.get_address(network), segwit_address);
+            assert_eq!(p2pkh.get_address(network), base58_address);
+            assert_eq!(Script::from_address(segwit_address, network).map(|script| script.cmds), Some(p2wpkh.cmds.clone()));
+            assert_eq!(Script::from_address(base58_address, network).map(|script| script.cmds), Some(p2pkh.cmds.clone()));
+        }
+        // an address of another network pays nobody here
+        assert_eq!(Script::from_address("tb1qvrtwrt8qqt7catejz59l2ly963xpdct2ch9unz", Network::Regtest), None);
+        assert_eq!(Script::from_address("1BenRpVUFK65JFWcQSuHnJKzc4M8ZP8Eqa", Network::Signet), None);
+    }
+}
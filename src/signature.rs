@@ -1,5 +1,5 @@
 use core::fmt;
-use std::io::{Cursor, Error};
+use std::io::{Cursor, Error, ErrorKind};
 use num::{BigUint};
 
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -23,51 +23,51 @@ impl Signature {
     }
 
     pub fn parse(der: &Vec<u8>) -> Result<Signature, Error> {
+        let invalid = |message: &str| Error::new(ErrorKind::InvalidData, message.to_string());
 
         let mut buffer = [0u8; 1];
         let mut stream = &mut Cursor::new(der);
-        //stream.read(&mut buffer)?;
-        std::io::Read::read(&mut stream, &mut buffer)?;
+        std::io::Read::read_exact(&mut stream, &mut buffer)?;
         let compound = buffer[0];
         if compound != 0x30 {
-            panic!("Invalid signature compound");
+            return Err(invalid("Invalid signature compound"));
         }
         let mut buffer = [0u8; 1];
-        std::io::Read::read(&mut stream, &mut buffer)?;
+        std::io::Read::read_exact(&mut stream, &mut buffer)?;
         let length = buffer[0];
         if length as usize + 2usize != der.len() {
-            panic!("Invalid signature length");
+            return Err(invalid("Invalid signature length"));
         }
         let mut buffer = [0u8; 1];
-        std::io::Read::read(&mut stream, &mut buffer)?;
+        std::io::Read::read_exact(&mut stream, &mut buffer)?;
         let marker = buffer[0];
         if marker != 0x02 {
-            panic!("Invalid signature marker");
+            return Err(invalid("Invalid signature marker"));
         }
         let mut buffer = [0u8; 1];
-        std::io::Read::read(&mut stream, &mut buffer)?;
+        std::io::Read::read_exact(&mut stream, &mut buffer)?;
         let rlength = buffer[0];
 
         let mut buffer = vec![0u8; rlength as usize];
 
-        std::io::Read::read(&mut stream, &mut buffer)?;
+        std::io::Read::read_exact(&mut stream, &mut buffer)?;
         let r = BigUint::from_bytes_be(buffer.as_slice());
 
         let mut buffer = [0u8; 1];
-        std::io::Read::read(&mut stream, &mut buffer)?;
+        std::io::Read::read_exact(&mut stream, &mut buffer)?;
         let marker = buffer[0];
         if marker != 0x02 {
-            panic!("Invalid signature marker 2");
+            return Err(invalid("Invalid signature marker 2"));
         }
-        std::io::Read::read(&mut stream, &mut buffer)?;
+        std::io::Read::read_exact(&mut stream, &mut buffer)?;
         let slength = buffer[0];
         let mut buffer = vec![0u8; slength as usize];
 
-        std::io::Read::read(&mut stream, &mut buffer)?;
+        std::io::Read::read_exact(&mut stream, &mut buffer)?;
         let s = BigUint::from_bytes_be(buffer.as_slice());
 
         if der.len() != 6usize + rlength as usize  + slength as usize {
-            panic!("Signature too long");
+            return Err(invalid("Signature too long"));
         }
         Ok(Signature::new(&r, &s))
     }
@@ -1,20 +1,29 @@
-use std::{fmt, io::{Cursor, Read}, vec};
-use std::io::{Seek, SeekFrom};
+use std::{fmt, io::Cursor, vec};
 use std::ptr::write;
 use log::info;
 use num::{BigUint, ToPrimitive, Zero};
 use crate::helpers::endianness::{int_to_little_endian, little_endian_to_int};
+use crate::network::Network;
 use crate::tx_input::TxInput;
 use crate::tx_output::TxOutput;
-use crate::helpers::varint::{encode_varint, read_varint};
+use crate::helpers::varint::{encode_varint, read_length};
+use crate::helpers::decode_error::{check_end, read_array, read_bytes, DecodeError};
 use crate::helpers::hash256::hash256;
-use crate::helpers::sig_hash::SIGHASH_ALL;
+use crate::helpers::sig_hash::{SIGHASH_ANYONECANPAY, SIGHASH_NONE, SIGHASH_SINGLE};
+use crate::helpers::op_codes::{OP_0, OP_CODESEPARATOR};
+use crate::helpers::hash160::hash160;
 use crate::private_key::PrivateKey;
-use crate::script::Script;
+use crate::script::{Cmd, Script};
 use serde_json::json;
 use crate::helpers::out_type::OutputType;
 use crate::rpc_api::RpcApi;
+use crate::prevout_provider::{PrevoutProvider, RpcPrevoutProvider};
 use crate::helpers::verify_input_res::VerifyInputRes;
+use crate::helpers::script_error::{ScriptError, ScriptErrorKind};
+use crate::helpers::verify_flags::VerifyFlags;
+use crate::helpers::tx_context::TxContext;
+use crate::helpers::taproot::tagged_hash;
+use sha2::{Digest, Sha256};
 
 #[derive(Debug, Eq, PartialEq, Clone)]
 pub struct Tx {
@@ -22,32 +31,26 @@ pub struct Tx {
     inputs: Vec<TxInput>,
     outputs: Vec<TxOutput>,
     locktime: u32,
-    testnet: bool,
+    network: Network,
     segwit: bool,
-    hash_prevouts: Option<Vec<u8>>,
-    hash_sequence: Option<Vec<u8>>,
-    hash_outputs: Option<Vec<u8>>,
     pub(crate) tx_json: serde_json::Value,
 }
 
 impl Tx {
-    pub fn new(version: u32, inputs: Vec<TxInput>, outputs: Vec<TxOutput>, locktime: u32, testnet: bool, segwit: bool) -> Self {
+    pub fn new(version: u32, inputs: Vec<TxInput>, outputs: Vec<TxOutput>, locktime: u32, network: Network, segwit: bool) -> Self {
         Tx {
             version: version,
             inputs: inputs,
             outputs: outputs,
             locktime: locktime,
-            testnet: testnet,
+            network: network,
             segwit: segwit,
-            hash_prevouts: None,
-            hash_sequence: None,
-            hash_outputs: None,
             tx_json: json!(null),
         }
     }
-    pub async fn new_from_id(testnet: bool, tx_id_str: String) -> Option<Tx>  {
+    pub async fn new_from_id(network: Network, tx_id_str: String) -> Option<Tx>  {
         let tx_id = tx_id_str.as_str();
-        let tf = RpcApi::new(testnet);
+        let tf = RpcApi::new(network);
         let mut tx_wrap = tf.get_tx(tx_id).await;
 
         let mut tx: Option<Tx> = None;
@@ -61,12 +64,22 @@ impl Tx {
             }
         }
 
-        let mut tx = tx.unwrap();
+        let tx = tx.unwrap();
+        Some(tx.with_verification_json(&RpcPrevoutProvider::new(network)).await)
+    }
+    /// Completes the tx json with the fee, the verification of every input and
+    /// the output addresses, the outputs spent come from `provider`.
+    pub async fn with_verification_json(self, provider: &impl PrevoutProvider) -> Tx {
+        let mut tx = self;
+        let network = tx.network;
+        if !tx.is_coinbase() {
+            provider.prefetch(&tx).await;
+        }
         let mut tx_json = json!({});
         tx_json = tx.tx_json();
 
         if tx.is_coinbase() == false {
-            if let fee = tx.fee().await {
+            if let Some(fee) = tx.fee(provider).await {
                 log::info!("fee is available");
                 tx_json["fee"] = json!(fee);
             } else {
@@ -77,6 +90,11 @@ impl Tx {
             tx_json["coinbase_height"] = json!(tx.coinbase_height().unwrap().to_string());
         }
         tx_json["is_coinbase"] = json!(tx.is_coinbase());
+        if let serde_json::Value::Object(size_json) = tx.size_json(tx_json["fee"].as_i64()) {
+            for (key, value) in size_json {
+                tx_json[key] = value;
+            }
+        }
 
         tx_json["tx_id"] = json!(tx.tx_id());
         tx_json["hash"] = json!(hex::encode(tx.hash(false)));
@@ -87,12 +105,28 @@ impl Tx {
             let input = tx.tx_ins()[i].clone();
             let mut tx_in_json = json!({});
             tx_in_json = input.get_json();
+            let (weight, witness_size) = tx.input_weight(i);
+            tx_in_json["weight"] = json!(weight);
+            tx_in_json["witness_size"] = json!(witness_size);
+            tx_in_json["witness_discount"] = json!(witness_size * 3);
 
             if tx.is_coinbase() == false {
-                let res = tx.verify_input(i).await;
+                let mut res = tx.verify_input(i, VerifyFlags::STANDARD, provider).await;
+                tx_in_json["is_standard"] = json!(res.is_valid);
+                if !res.is_valid {
+                    // tell a policy violation from an input that is not valid at all
+                    if let Some(error) = &res.error {
+                        tx_in_json["standard_error"] = error.get_json();
+                    }
+                    res = tx.verify_input(i, VerifyFlags::CONSENSUS, provider).await;
+                }
                 if res.is_valid == false {
                     log::info!("----------> input is invalid {}/{}", i, tx.tx_ins().len());
                 }
+                tx_in_json["is_valid"] = json!(res.is_valid);
+                if let Some(error) = &res.error {
+                    tx_in_json["script_error"] = error.get_json();
+                }
                 match res.script_pubkey {
                     Some(prev_out_script_pub_key) => {
                         log::info!("Prev Output ScriptPubKey: {} {:?}", i, prev_out_script_pub_key.script_json);
@@ -131,14 +165,13 @@ impl Tx {
         for output in tx.tx_outs() {
             let mut tx_out_json = json!({});
             tx_out_json = output.get_json();
-            let address = output.script_pubkey().get_address(testnet);
+            let address = output.script_pubkey().get_address(network);
             tx_out_json["address"] = json!(address);
             outputs_json_list.push(tx_out_json);
         }
         tx_json["outputs"] = json!(outputs_json_list);
         tx.tx_json = tx_json.clone();
-
-        Some(tx)
+        tx
     }
     pub fn version(&self) -> u32 {
         self.version
@@ -149,13 +182,24 @@ impl Tx {
     pub fn tx_outs(&self) -> Vec<TxOutput> {
         self.outputs.clone()
     }
+    /// Supplies the output spent by an input, so it can be verified offline.
+    pub fn set_prev_output(&mut self, input_index: usize, prev_output: TxOutput) {
+        self.inputs[input_index].set_prev_output(prev_output);
+    }
     pub fn locktime(&self) -> u32 {
         self.locktime
     }
     pub fn tx_json(&self) -> serde_json::Value {
         self.tx_json.clone()
     }
-    pub fn parse(stream: &mut Cursor<Vec<u8>>, testnet: bool) -> Result<Self, std::io::Error> {
+    /// Parses a whole serialized tx, any data after it is an error.
+    pub fn from_bytes(raw: &[u8], network: Network) -> Result<Self, DecodeError> {
+        let mut stream = Cursor::new(raw.to_vec());
+        let tx = Tx::parse(&mut stream, network)?;
+        check_end(&stream)?;
+        Ok(tx)
+    }
+    pub fn parse(stream: &mut Cursor<Vec<u8>>, network: Network) -> Result<Self, DecodeError> {
 
         info!("parse tx");
 
@@ -163,28 +207,19 @@ impl Tx {
         let mut length_w_b = 0u32;
 
         length_non_w_b += 4;
-        let mut buffer = [0; 4];
-        stream.read(&mut buffer)?;
-        let mut buffer = [0; 1];
-        stream.read(&mut buffer)?;
-        stream.seek(SeekFrom::Start(0))?;
-        let mut is_segwit = false;
-        if buffer[0] == 0x00 { // segwit marker
-            is_segwit = true;
-        }
         // start parse
-        let mut buffer = [0; 4];
-        stream.read(&mut buffer)?;
+        let buffer = read_array::<4>(stream)?;
         let version = little_endian_to_int(buffer.as_slice()).to_u32().unwrap();
         let version_hex = hex::encode(buffer.as_slice());
         let mut marker_hex = "";
         let mut marker_flag = "";
+        // a segwit marker where the input count would be
+        let is_segwit = stream.get_ref().get(stream.position() as usize) == Some(&0x00);
         if is_segwit {
             length_w_b += 2;
-            let mut buffer = [0; 2];
-            stream.read(&mut buffer)?;
-            if buffer != [0x00,0x01] { // segwit marker
-                panic!("invalid segwit marker");
+            let [_, flag] = read_array::<2>(stream)?;
+            if flag != 0x01 {
+                return Err(DecodeError::BadSegwitFlag { offset: stream.position() as usize - 1, flag });
             }
             marker_hex = "00";
             marker_flag = "01";
@@ -193,65 +228,54 @@ impl Tx {
         let mut outputs: Vec<TxOutput> = Vec::new();
 
         let mut is_rbf = false;
-        if let Ok(num_inputs) = read_varint(stream) {
-            length_non_w_b += num_inputs.bytes as u32;
-            for _ in 0..num_inputs.value {
-                let input = TxInput::parse(stream).unwrap();
-                let x = input.clone().tx_in_json;
-                let val = x.get("length").unwrap().as_u64().unwrap();
-                if input.sequence() < ( 0xffffffff - 1) {
-                    is_rbf = true;
-                }
-                length_non_w_b += val as u32;
-                inputs.push(input.clone());
+        let num_inputs = read_length(stream)?;
+        length_non_w_b += num_inputs.bytes as u32;
+        for _ in 0..num_inputs.value {
+            let input = TxInput::parse(stream)?;
+            let x = input.clone().tx_in_json;
+            let val = x.get("length").unwrap().as_u64().unwrap();
+            if input.sequence() < ( 0xffffffff - 1) {
+                is_rbf = true;
             }
+            length_non_w_b += val as u32;
+            inputs.push(input.clone());
         }
         //let mut outputs = vec![];
-        if let Ok(num_outputs) = read_varint(stream) {
-            length_non_w_b += num_outputs.bytes as u32;
-            for _ in 0..num_outputs.value {
-                let output = TxOutput::parse(stream).unwrap();
-                let x = output.clone().tx_out_json;
-                let val = x.get("length").unwrap().as_u64().unwrap();
-
-                length_non_w_b += val as u32;
-                outputs.push(output.clone());
-            }
+        let num_outputs = read_length(stream)?;
+        length_non_w_b += num_outputs.bytes as u32;
+        for _ in 0..num_outputs.value {
+            let output = TxOutput::parse(stream)?;
+            let x = output.clone().tx_out_json;
+            let val = x.get("length").unwrap().as_u64().unwrap();
+
+            length_non_w_b += val as u32;
+            outputs.push(output.clone());
         }
 
         if is_segwit {
             for tx_in in inputs.iter_mut() {
                 let mut items_json: Vec<String> = vec![];
-                if let Ok(num_items) = read_varint(stream) {
-                    length_w_b += num_items.bytes as u32;
-
-                    let mut items: Vec<Vec<u8>> = vec![];
-                    for _ in 0..num_items.value {
-                        if let Ok(item_len) = read_varint(stream) {
-                            length_w_b += item_len.bytes as u32;
-                            length_w_b += item_len.value as u32;
-                            if item_len.value == 0 {
-                                items.push(vec![0]);
-                                items_json.push(hex::encode(vec![0]));
-                            } else {
-                                let mut buffer: Vec<u8> = vec![0;item_len.value as usize];
-                                stream.read(&mut buffer)?;
-                                items.push(buffer.clone());
-                                items_json.push(hex::encode(buffer));
-                            }
-                        }
-                    }
-                    tx_in.witness = Some(items);
-                    let mut tx_in_json = tx_in.get_json();
-                    tx_in_json["witness"] = json!(items_json);
-                    tx_in.tx_in_json = tx_in_json;
-                    //tx_in.witness_json = json!(cmd_list_json);
+                let num_items = read_length(stream)?;
+                length_w_b += num_items.bytes as u32;
+
+                let mut items: Vec<Vec<u8>> = vec![];
+                for _ in 0..num_items.value {
+                    let item_len = read_length(stream)?;
+                    length_w_b += item_len.bytes as u32;
+                    length_w_b += item_len.value as u32;
+                    let buffer = read_bytes(stream, item_len.value as usize)?;
+                    items.push(buffer.clone());
+                    items_json.push(hex::encode(buffer));
                 }
+                tx_in.witness = Some(items);
+                let mut tx_in_json = tx_in.get_json();
+                tx_in_json["witness"] = json!(items_json);
+                tx_in.tx_in_json = tx_in_json;
+                //tx_in.witness_json = json!(cmd_list_json);
             }
         }
         length_non_w_b += 4u32; // locktime
-        let mut buffer = vec![0; 4];
-        stream.read(&mut buffer).unwrap();
+        let buffer = read_array::<4>(stream)?;
         let locktime = little_endian_to_int(buffer.as_slice()).to_u32().unwrap();
         let locktime_hex = hex::encode(buffer.as_slice());
 
@@ -275,11 +299,8 @@ impl Tx {
             inputs,
             outputs,
             locktime,
-            testnet,
+            network,
             segwit: is_segwit,
-            hash_prevouts: None,
-            hash_sequence: None,
-            hash_outputs: None,
             tx_json: tx_json.clone(),
         })
     }
@@ -306,20 +327,7 @@ impl Tx {
         }
         if skip_witness == false {
             for tx_in in self.tx_ins() {
-                match tx_in.witness {
-                    Some(witness) => {
-                        result.extend(int_to_little_endian(BigUint::from(witness.len()), 1));
-                        for item in witness {
-                            if item.len() == 1 {
-                                result.extend(int_to_little_endian(BigUint::from(item[0]), 1))
-                            } else {
-                                result.extend(encode_varint(item.len() as u64).unwrap());
-                                result.extend(item);
-                            }
-                        }
-                    }
-                    None => {}
-                }
+                result.extend(tx_in.serialize_witness());
             }
         }
         result.extend(int_to_little_endian(BigUint::from(self.locktime), 4));
@@ -339,43 +347,68 @@ impl Tx {
         result.extend(int_to_little_endian(BigUint::from(self.locktime), 4));
         result
     }
+    /// Size without the witness data, as seen by nodes before segwit.
+    pub fn base_size(&self) -> usize {
+        self.serialize_legacy().len()
+    }
+    /// Size including the witness data.
+    pub fn total_size(&self) -> usize {
+        self.serialize(false).len()
+    }
+    /// BIP141 weight: witness bytes count one unit, all the others four.
+    pub fn weight(&self) -> usize {
+        self.base_size() * 3 + self.total_size()
+    }
+    pub fn vsize(&self) -> usize {
+        self.weight().div_ceil(4)
+    }
+    /// Fee rate in sat/vB of the tx paying `fee`.
+    pub fn fee_rate(&self, fee: i64) -> f64 {
+        fee as f64 / self.vsize() as f64
+    }
+    /// Weight and witness size of an input, each witness byte saves three units.
+    pub fn input_weight(&self, input_index: usize) -> (usize, usize) {
+        let tx_in = &self.inputs[input_index];
+        let witness_size = if self.segwit { tx_in.serialize_witness().len() } else { 0 };
+        (tx_in.serialize().len() * 4 + witness_size, witness_size)
+    }
+    /// Size metrics of the tx, with the fee rate when the fee is known.
+    pub fn size_json(&self, fee: Option<i64>) -> serde_json::Value {
+        json!({
+            "size": self.total_size(),
+            "base_size": self.base_size(),
+            "weight": self.weight(),
+            "vsize": self.vsize(),
+            "fee_rate": fee.map(|fee| self.fee_rate(fee)),
+        })
+    }
     pub fn tx_id(&self) -> String {
         hex::encode(self.hash(true))
     }
-    pub fn hash_prevouts(&mut self) -> Option<Vec<u8>> {
+    // BIP143 digests, computed per call so they follow changes to the inputs and outputs
+    pub fn hash_prevouts(&self) -> Option<Vec<u8>> {
         let mut all_prevouts: Vec<u8> = vec![];
-        let mut all_sequence: Vec<u8> = vec![];
-        if self.hash_prevouts.is_none() {
-            for tx_in in self.tx_ins() {
-                let mut p_outs = tx_in.prev_tx().clone();
-                p_outs.reverse();
-                all_prevouts.extend(p_outs);
-                all_prevouts.extend(int_to_little_endian(BigUint::from(tx_in.prev_index()), 4));
-                all_sequence.extend(int_to_little_endian(BigUint::from(tx_in.sequence()), 4));
-            }
-            self.hash_prevouts = Some(hash256(all_prevouts.as_slice()).to_vec());
-            self.hash_sequence = Some(hash256(all_sequence.as_slice()).to_vec());
+        for tx_in in &self.inputs {
+            let mut p_outs = tx_in.prev_tx();
+            p_outs.reverse();
+            all_prevouts.extend(p_outs);
+            all_prevouts.extend(int_to_little_endian(BigUint::from(tx_in.prev_index()), 4));
         }
-        self.hash_prevouts.clone()
+        Some(hash256(all_prevouts.as_slice()).to_vec())
     }
-
-
-
-    pub fn hash_sequence(&mut self) -> Option<Vec<u8>> {
-        if self.hash_prevouts.is_none() {
-            self.hash_prevouts();
+    pub fn hash_sequence(&self) -> Option<Vec<u8>> {
+        let mut all_sequence: Vec<u8> = vec![];
+        for tx_in in &self.inputs {
+            all_sequence.extend(int_to_little_endian(BigUint::from(tx_in.sequence()), 4));
         }
-        self.hash_sequence.clone()
+        Some(hash256(all_sequence.as_slice()).to_vec())
     }
-    pub fn hash_outputs(&mut self) -> Option<Vec<u8>> {
+    pub fn hash_outputs(&self) -> Option<Vec<u8>> {
         let mut all_outputs: Vec<u8> = vec![];
-        if self.hash_outputs.is_none() {
-            for tx_out in self.tx_outs() {
-                all_outputs.extend(tx_out.serialize());
-            }
-            self.hash_outputs = Some(hash256(all_outputs.as_slice()).to_vec());
+        for tx_out in &self.outputs {
+            all_outputs.extend(tx_out.serialize());
         }
-        self.hash_outputs.clone()
+        Some(hash256(all_outputs.as_slice()).to_vec())
     }
     fn hash(&self, skip_witness: bool) -> Vec<u8> {
         let mut bytes: Vec<u8> = vec![];
@@ -384,190 +417,266 @@ impl Tx {
         hash.reverse();
         hash.to_vec()
     }
-    pub async fn fee(&self) -> i64 {
+    /// None if an output spent is not known.
+    pub async fn fee(&self, provider: &impl PrevoutProvider) -> Option<i64> {
 
         let mut sum_tx_ins: u64 = 0;
         let mut sum_tx_outs: u64 = 0;
         for tx_in in self.tx_ins() {
-            sum_tx_ins += tx_in.value(self.testnet).await.to_u64().unwrap();
+            sum_tx_ins += tx_in.value(provider).await?;
         }
         for tx_out in self.tx_outs() {
             sum_tx_outs += tx_out.amount()
         }
-        sum_tx_ins as i64 - sum_tx_outs as i64
+        Some(sum_tx_ins as i64 - sum_tx_outs as i64)
     }
-    pub async fn sig_hash(&self, input_index: usize, redeem_script: Option<Script>) -> BigUint {
+    /// Legacy signature hash of an input. `script_code` is the script spent
+    /// (the RedeemScript for p2sh) with the signatures already removed.
+    pub fn sig_hash_legacy(&self, input_index: usize, script_code: &Script, hash_type: u8) -> BigUint {
+        let base_type = hash_type & 0x1f;
+        let anyone_can_pay = hash_type & SIGHASH_ANYONECANPAY != 0;
+        // SIGHASH_SINGLE without a matching output signs the number one,
+        // a bug of the original client that became consensus
+        if base_type == SIGHASH_SINGLE && input_index >= self.outputs.len() {
+            let mut one = [0u8; 32];
+            one[0] = 1;
+            return BigUint::from_bytes_be(&one);
+        }
+        let script_code = Script::new(script_code.cmds.iter().filter(|cmd| **cmd != Cmd::Op(OP_CODESEPARATOR)).cloned().collect());
+
         let mut result = Vec::new();
         result.extend(int_to_little_endian(BigUint::from(self.version), 4));
-        let num_ins = encode_varint(self.inputs.len() as u64).unwrap();
-        result.extend(num_ins);
-
-        for (idx, tx_in) in self.inputs.iter().enumerate() {
+        // with ANYONECANPAY only the input being signed is committed to
+        let inputs: Vec<(usize, &TxInput)> = match anyone_can_pay {
+            true => vec![(input_index, &self.inputs[input_index])],
+            false => self.inputs.iter().enumerate().collect(),
+        };
+        result.extend(encode_varint(inputs.len() as u64).unwrap());
+        for (idx, tx_in) in inputs {
             if idx == input_index {
-                // if the RedeemScript was passed in, that's the ScriptSig
-                // otherwise the previous tx's ScriptPubkey is the ScriptSig
-                match &redeem_script {
-                    Some(script) => {
-                        println!("WITH REDEEM");
-                        let tx_input = TxInput::new(tx_in.prev_tx(), tx_in.prev_index(), script.clone(), tx_in.sequence());
-                        result.extend(tx_input.serialize());
-                    }
-
-                    None => {
-                        println!("NO REDEEM");
-                        let tx_input = TxInput::new(tx_in.prev_tx(), tx_in.prev_index(), tx_in.script_pubkey(self.testnet).await, tx_in.sequence());
-                        result.extend(tx_input.serialize());
-                    }
-                }
+                let tx_input = TxInput::new(tx_in.prev_tx(), tx_in.prev_index(), script_code.clone(), tx_in.sequence());
+                result.extend(tx_input.serialize());
             } else {
-                let tx_input = TxInput::new(tx_in.prev_tx(), tx_in.prev_index(), Script::new(vec![]), tx_in.sequence());
+                // NONE and SINGLE let the other inputs change their sequence
+                let sequence = if base_type == SIGHASH_NONE || base_type == SIGHASH_SINGLE { 0 } else { tx_in.sequence() };
+                let tx_input = TxInput::new(tx_in.prev_tx(), tx_in.prev_index(), Script::new(vec![]), sequence);
                 result.extend(tx_input.serialize());
             }
         }
-        result.extend(encode_varint(self.outputs.len() as u64).unwrap());
-        for tx_out in self.tx_outs() {
+        // NONE signs no output, SINGLE the one with the same index as the input,
+        // the outputs before it are left empty with an amount of -1
+        let outputs: Vec<TxOutput> = match base_type {
+            SIGHASH_NONE => vec![],
+            SIGHASH_SINGLE => (0..=input_index)
+                .map(|idx| if idx == input_index { self.outputs[idx].clone() } else { TxOutput::new(u64::MAX, Script::new(vec![])) })
+                .collect(),
+            _ => self.outputs.clone(),
+        };
+        result.extend(encode_varint(outputs.len() as u64).unwrap());
+        for tx_out in outputs {
             result.extend(tx_out.serialize());
         }
         result.extend(int_to_little_endian(BigUint::from(self.locktime), 4));
-        result.extend(int_to_little_endian(BigUint::from(SIGHASH_ALL), 4));
+        result.extend(int_to_little_endian(BigUint::from(hash_type), 4));
         let hash = hash256(&result);
-        let z = BigUint::from_bytes_be(hash.as_slice());
-        z
+        BigUint::from_bytes_be(hash.as_slice())
     }
-    pub async fn sig_hash_bip143(&mut self, input_index: usize, redeem_script: Option<Script>, witness_script: Option<Script>) -> BigUint {
-
-        let pr = self.hash_prevouts().unwrap();
-        let se = self.hash_sequence().unwrap();
+    /// BIP143 signature hash of a witness v0 input spending `amount`.
+    pub fn sig_hash_segwit(&self, input_index: usize, script_code: &Script, amount: u64, hash_type: u8) -> BigUint {
+        let base_type = hash_type & 0x1f;
+        let anyone_can_pay = hash_type & SIGHASH_ANYONECANPAY != 0;
+        let zero = vec![0u8; 32];
+        let hash_prevouts = match anyone_can_pay {
+            true => zero.clone(),
+            false => self.hash_prevouts().unwrap(),
+        };
+        let hash_sequence = match anyone_can_pay || base_type == SIGHASH_NONE || base_type == SIGHASH_SINGLE {
+            true => zero.clone(),
+            false => self.hash_sequence().unwrap(),
+        };
+        let hash_outputs = match base_type {
+            SIGHASH_NONE => zero,
+            SIGHASH_SINGLE if input_index < self.outputs.len() => hash256(&self.outputs[input_index].serialize()).to_vec(),
+            SIGHASH_SINGLE => zero,
+            _ => self.hash_outputs().unwrap(),
+        };
 
         let tx_in = &self.inputs[input_index];
         let mut s: Vec<u8> = Vec::new();
         // per BIP143 spec
         s.extend(int_to_little_endian(BigUint::from(self.version), 4));
-
-        println!("pr: {}", hex::encode(pr.clone()));
-        s.extend(pr);
-        s.extend(se);
+        s.extend(hash_prevouts);
+        s.extend(hash_sequence);
 
         let mut prev = tx_in.prev_tx();
         prev.reverse();
         s.extend(prev);
         s.extend(int_to_little_endian(BigUint::from(tx_in.prev_index()), 4));
-
-        let mut script_code = Vec::new();
-        if witness_script.is_some() {
-            script_code = witness_script.unwrap().serialize()
-        } else if redeem_script.is_some() {
-            let script = redeem_script.unwrap();
-            let h160 = script.cmds[1].clone();
-            script_code = Script::p2pkh_script(h160).serialize();
-        } else {
-            let script = tx_in.script_pubkey(self.testnet).await;
-            let h160 = script.cmds[1].clone();
-            script_code = Script::p2pkh_script(h160).serialize();
-        }
-        s.extend(script_code.clone());
-
-        s.extend(int_to_little_endian(BigUint::from(tx_in.value(self.testnet).await), 8));
-
+        s.extend(script_code.serialize());
+        s.extend(int_to_little_endian(BigUint::from(amount), 8));
         s.extend(int_to_little_endian(BigUint::from(tx_in.sequence()), 4));
-        s.extend(self.hash_outputs().unwrap());
+        s.extend(hash_outputs);
         s.extend(int_to_little_endian(BigUint::from(self.locktime), 4));
-        s.extend(int_to_little_endian(BigUint::from(SIGHASH_ALL), 4));
+        s.extend(int_to_little_endian(BigUint::from(hash_type), 4));
 
-        println!("sc: {:?}", script_code);
         let hash = hash256(s.as_slice());
         BigUint::from_bytes_be(hash.as_slice())
     }
+    /// BIP341 signature hash of a taproot spend, `spent_outputs` are the outputs
+    /// spent by all the inputs. Script path spends pass the leaf hash and the
+    /// position of the last executed OP_CODESEPARATOR (0xffffffff if none), BIP342.
+    /// None for an undefined hash type or a SIGHASH_SINGLE without a matching output.
+    pub fn sig_hash_taproot(&self, input_index: usize, spent_outputs: &[TxOutput], hash_type: u8, annex: Option<&[u8]>, script_path: Option<([u8; 32], u32)>) -> Option<[u8; 32]> {
+        if !matches!(hash_type, 0x00..=0x03 | 0x81..=0x83) || spent_outputs.len() != self.inputs.len() {
+            return None;
+        }
+        let base_type = hash_type & 0x03;
+        let anyone_can_pay = hash_type & SIGHASH_ANYONECANPAY != 0;
+        let sha256 = |data: &[u8]| Sha256::digest(data).to_vec();
+        let outpoint = |tx_in: &TxInput| {
+            let mut outpoint = tx_in.prev_tx();
+            outpoint.reverse();
+            outpoint.extend(int_to_little_endian(BigUint::from(tx_in.prev_index()), 4));
+            outpoint
+        };
+
+        // epoch 0 and the hash type
+        let mut m: Vec<u8> = vec![0x00, hash_type];
+        m.extend(int_to_little_endian(BigUint::from(self.version), 4));
+        m.extend(int_to_little_endian(BigUint::from(self.locktime), 4));
+        if !anyone_can_pay {
+            let (mut prevouts, mut amounts, mut script_pubkeys, mut sequences) = (vec![], vec![], vec![], vec![]);
+            for (tx_in, spent_output) in self.inputs.iter().zip(spent_outputs) {
+                prevouts.extend(outpoint(tx_in));
+                amounts.extend(int_to_little_endian(BigUint::from(spent_output.amount()), 8));
+                script_pubkeys.extend(spent_output.script_pubkey().serialize());
+                sequences.extend(int_to_little_endian(BigUint::from(tx_in.sequence()), 4));
+            }
+            m.extend(sha256(&prevouts));
+            m.extend(sha256(&amounts));
+            m.extend(sha256(&script_pubkeys));
+            m.extend(sha256(&sequences));
+        }
+        if base_type != SIGHASH_NONE && base_type != SIGHASH_SINGLE {
+            let outputs: Vec<u8> = self.outputs.iter().flat_map(|tx_out| tx_out.serialize()).collect();
+            m.extend(sha256(&outputs));
+        }
+        // spend type: key or script path, with or without annex
+        let ext_flag = if script_path.is_some() { 1 } else { 0 };
+        m.push(ext_flag * 2 + if annex.is_some() { 1 } else { 0 });
+        if anyone_can_pay {
+            let tx_in = &self.inputs[input_index];
+            m.extend(outpoint(tx_in));
+            m.extend(int_to_little_endian(BigUint::from(spent_outputs[input_index].amount()), 8));
+            m.extend(spent_outputs[input_index].script_pubkey().serialize());
+            m.extend(int_to_little_endian(BigUint::from(tx_in.sequence()), 4));
+        } else {
+            m.extend(int_to_little_endian(BigUint::from(input_index as u32), 4));
+        }
+        if let Some(annex) = annex {
+            let mut serialized = encode_varint(annex.len() as u64).unwrap();
+            serialized.extend(annex);
+            m.extend(sha256(&serialized));
+        }
+        if base_type == SIGHASH_SINGLE {
+            m.extend(sha256(&self.outputs.get(input_index)?.serialize()));
+        }
+        if let Some((leaf_hash, codesep_pos)) = script_path {
+            // key version 0
+            m.extend(leaf_hash);
+            m.push(0x00);
+            m.extend(int_to_little_endian(BigUint::from(codesep_pos), 4));
+        }
+        Some(tagged_hash("TapSighash", &m))
+    }
+    pub async fn sig_hash(&self, input_index: usize, redeem_script: Option<Script>, hash_type: u8, provider: &impl PrevoutProvider) -> Option<BigUint> {
+        // if the RedeemScript was passed in, that's the ScriptSig
+        // otherwise the previous tx's ScriptPubkey is the ScriptSig
+        let script_code = match redeem_script {
+            Some(script) => script,
+            None => self.inputs[input_index].script_pubkey(provider).await?,
+        };
+        Some(self.sig_hash_legacy(input_index, &script_code, hash_type))
+    }
+    pub async fn sig_hash_bip143(&self, input_index: usize, redeem_script: Option<Script>, witness_script: Option<Script>, hash_type: u8, provider: &impl PrevoutProvider) -> Option<BigUint> {
+        let tx_in = &self.inputs[input_index];
+        let script_code = if let Some(witness_script) = witness_script {
+            witness_script
+        } else {
+            let script = match redeem_script {
+                Some(script) => script,
+                None => tx_in.script_pubkey(provider).await?,
+            };
+            if !script.is_p2wpkh_script_pubkey() {
+                return None;
+            }
+            let h160 = script.cmds[1].data()?.clone();
+            Script::p2pkh_script(h160)
+        };
+        let amount = tx_in.value(provider).await?;
+        Some(self.sig_hash_segwit(input_index, &script_code, amount, hash_type))
+    }
 
-    pub async fn verify_input(&mut self, input_index: usize) -> VerifyInputRes {
+    /// Verifies an input against the output it spends, from `provider` unless set with `set_prev_output`.
+    pub async fn verify_input(&mut self, input_index: usize, flags: VerifyFlags, provider: &impl PrevoutProvider) -> VerifyInputRes {
+        self.check_input(input_index, flags, false, provider).await
+    }
+    /// Like verify_input, also recording the execution trace of the input scripts.
+    pub async fn trace_input(&mut self, input_index: usize, flags: VerifyFlags, provider: &impl PrevoutProvider) -> VerifyInputRes {
+        self.check_input(input_index, flags, true, provider).await
+    }
+    async fn check_input(&mut self, input_index: usize, flags: VerifyFlags, trace: bool, provider: &impl PrevoutProvider) -> VerifyInputRes {
 
         log::info!("verify_input");
 
         let tx_ins = self.tx_ins(); //[input_index];
         let tx_in = &tx_ins[input_index];
-        let prev_script_pubkey = tx_in.script_pubkey(self.testnet).await;
-
-        let mut z: BigUint = BigUint::zero();
-        let mut witness: Option<Vec<Vec<u8>>> = None;
-        let mut redeem_script: Option<Script> = None;
-
-        if prev_script_pubkey.is_p2sh_script_pubkey() {
-            // the last cmd in a p2sh is the RedeemScript
-            let mut script_sig = tx_in.script_sig.clone();
-            let cmd = script_sig.cmds.pop().unwrap();
-            let mut raw_redeem: Vec<u8> = vec![];
-            let len_raw_redeem = encode_varint(cmd.len() as u64).unwrap();
-            raw_redeem.extend(len_raw_redeem);
-            raw_redeem.extend(cmd);
-            let mut stream = Cursor::new(raw_redeem);  //ScriptSig
-            match Script::parse(&mut stream) {
-                Ok(script) => {
-                    redeem_script = Some(script.clone());
-                    if script.is_p2wpkh_script_pubkey() {
-                        // nested p2wpkh in p2sh
-                        // p2sh-p2wpkh
-                        z = self.sig_hash_bip143(input_index, redeem_script.clone(), None).await;
-                        witness = tx_in.witness.clone();
-                    } else if redeem_script.clone().unwrap().is_p2wsh_script_pubkey() {
-                        let mut raw_witness: Vec<u8> = vec![];
-                        let mut part = tx_in.witness.clone().unwrap();
-                        let cmd: Vec<u8> = part.pop().unwrap();
-                        raw_witness.extend(encode_varint(cmd.len() as u64).unwrap());
-                        raw_witness.extend(cmd);
-                        let mut w_stream = Cursor::new(raw_witness);
-                        let witness_script = Script::parse(&mut w_stream).unwrap();
-                        z = self.sig_hash_bip143(input_index, None, Some(witness_script)).await;
-                        witness = tx_in.clone().witness;
-                    } else {
-                        z = self.sig_hash(input_index, redeem_script.clone()).await;
-                        witness = None;
+        let Some(prev_output) = tx_in.prev_output(provider).await else {
+            let error = ScriptError::new(ScriptErrorKind::UnknownPrevout, None);
+            return VerifyInputRes::new(Err(error), None, None);
+        };
+        let prev_script_pubkey = prev_output.script_pubkey();
+
+        // the interpreter decides whether the witness is expected
+        let witness = tx_in.witness.clone();
+        // only shown, the interpreter takes it from the stack itself
+        let redeem_script = match prev_script_pubkey.is_p2sh_script_pubkey() {
+            true => tx_in.script_sig.redeem_script(flags),
+            false => None,
+        };
+        // taproot signatures commit to the outputs spent by all the inputs
+        let mut spent_outputs = None;
+        if prev_script_pubkey.is_p2tr_script_pubkey() {
+            let mut outputs = vec![];
+            for tx_in in &tx_ins {
+                match tx_in.prev_output(provider).await {
+                    Some(prev_output) => outputs.push(prev_output),
+                    None => {
+                        let error = ScriptError::new(ScriptErrorKind::UnknownPrevout, None);
+                        return VerifyInputRes::new(Err(error), Some(prev_script_pubkey), None);
                     }
                 }
-                Err(e) => {
-                    println!("{:?}", e);
-                    println!("{:?} {:?} {:?}", redeem_script, z, witness);
-                    panic!("Can't parse redeem script");
-                }
-            }
-        } else {
-
-            if prev_script_pubkey.is_p2wpkh_script_pubkey() {
-
-                z = self.sig_hash_bip143(input_index, None, None).await;
-                witness = tx_in.clone().witness;
-
-            } else if prev_script_pubkey.is_p2wsh_script_pubkey() {
-
-                let mut raw_witness: Vec<u8> = Vec::new();
-                let mut part = tx_in.witness.clone().unwrap();
-                let cmd: Vec<u8> = part.pop().unwrap();
-                raw_witness.extend(encode_varint(cmd.len() as u64).unwrap());
-                raw_witness.extend(cmd);
-                let mut w_stream = Cursor::new(raw_witness);
-                let witness_script = Script::parse(&mut w_stream).unwrap();
-                z = self.sig_hash_bip143(input_index, None, Some(witness_script)).await;
-                witness = tx_in.clone().witness;
-            } else {
-
-                z = self.sig_hash(input_index, None).await;
-                witness = None;
             }
+            spent_outputs = Some(outputs);
         }
 
         let ss = tx_in.script_sig();
         let pp = prev_script_pubkey;
-        // println!("z: {}", z);
-        // let w1 = &witness.clone().unwrap()[0];
-        // let w2 = &witness.clone().unwrap()[1];
-        // println!("witness 0 : {:?}", hex::encode(w1));
-        // println!("witness 1 : {:?}", hex::encode(w2));
-        // println!("ss: {}", ss.clone());
-        // println!("pp: {}", pp.clone());
-        //log::info!("prev output scriptPubKey: {}", pp.clone());
-        let combined_script = ss + pp.clone();
-        let is_valid = combined_script.evaluate(&z.clone(), &witness.clone());
-        log::info!("is_valid: {:?}", is_valid);
-        VerifyInputRes::new(is_valid, Option::from(pp), redeem_script)
+        // each signature is checked against the digest the context computes for its hash type
+        let z = BigUint::zero();
+        let mut context = TxContext::new(self, input_index, prev_output);
+        if let Some(spent_outputs) = spent_outputs {
+            context = context.with_spent_outputs(spent_outputs);
+        }
+        if trace {
+            let script_trace = pp.trace_spend(&ss, &z, &witness, flags, Some(&context));
+            log::info!("is_valid: {:?}", script_trace.result);
+            let result = script_trace.result.clone();
+            return VerifyInputRes::new(result, Option::from(pp), redeem_script).with_trace(script_trace);
+        }
+        let result = pp.evaluate_spend(&ss, &z, &witness, flags, Some(&context));
+        log::info!("is_valid: {:?}", result);
+        VerifyInputRes::new(result, Option::from(pp), redeem_script)
     }
 
     // pub async fn verify_async(&mut self) -> bool {
@@ -586,23 +695,65 @@ impl Tx {
     //     }
     //     true
     // }
-    pub fn sign_input(&mut self, input_index: usize, private_key: &PrivateKey) -> bool {
-        /*
-        let z = self.sig_hash(input_index, None);
-        let der = private_key.sign(&z).der();
-        let mut sig: Vec<u8> = vec![];
-        sig.extend(der);
-        sig.extend(SIGHASH_ALL.to_le_bytes());
-        let sec = private_key.point().sec(false);
-        let mut cmds: Vec<Vec<u8>> = vec![];
-        cmds.push(sig);
-        cmds.push(sec);
-        let combined_script = Script::new(cmds);
-        self.inputs[input_index].script_sig = combined_script;
-        self.verify_input(input_index)
-        TODO: make verify_input async
-         */
-        false
+    /// Signs a P2PKH, P2WPKH or P2SH-P2WPKH input with `hash_type`, setting its
+    /// scriptSig and witness. False if the output is of another type, is not
+    /// locked to the key, or the signed input doesn't verify.
+    pub async fn sign_input(&mut self, input_index: usize, private_key: &PrivateKey, hash_type: u8, provider: &impl PrevoutProvider) -> bool {
+        let Some(script_pubkey) = self.inputs[input_index].script_pubkey(provider).await else {
+            return false;
+        };
+        let point = private_key.point();
+        let sec = point.sec(true);
+        let h160 = hash160(&sec).to_vec();
+        let sign = |z: BigUint| {
+            let mut sig = private_key.sign(&z).der();
+            sig.push(hash_type);
+            sig
+        };
+        if script_pubkey.is_p2pkh_script_pubkey() {
+            // legacy outputs may be locked to the uncompressed key
+            let target = script_pubkey.cmds[2].data().unwrap();
+            let sec = match *target == h160 {
+                true => sec,
+                false => point.sec(false),
+            };
+            if *target != hash160(&sec).to_vec() {
+                return false;
+            }
+            let Some(z) = self.sig_hash(input_index, None, hash_type, provider).await else {
+                return false;
+            };
+            let sig = sign(z);
+            self.inputs[input_index].script_sig = Script::new(vec![Cmd::push(sig), Cmd::push(sec)]);
+            self.inputs[input_index].witness = None;
+        } else if script_pubkey.is_p2wpkh_script_pubkey() {
+            if *script_pubkey.cmds[1].data().unwrap() != h160 {
+                return false;
+            }
+            let Some(z) = self.sig_hash_bip143(input_index, None, None, hash_type, provider).await else {
+                return false;
+            };
+            let sig = sign(z);
+            self.inputs[input_index].script_sig = Script::new(vec![]);
+            self.inputs[input_index].witness = Some(vec![sig, sec]);
+            self.segwit = true;
+        } else if script_pubkey.is_p2sh_script_pubkey() {
+            // the redeem script is the p2wpkh program of the key
+            let redeem_script = Script::new(vec![Cmd::Op(OP_0), Cmd::push(h160)]);
+            if *script_pubkey.cmds[1].data().unwrap() != hash160(&redeem_script.raw_serialize()).to_vec() {
+                return false;
+            }
+            let Some(z) = self.sig_hash_bip143(input_index, Some(redeem_script.clone()), None, hash_type, provider).await else {
+                return false;
+            };
+            let sig = sign(z);
+            self.inputs[input_index].script_sig = Script::new(vec![Cmd::push(redeem_script.raw_serialize())]);
+            self.inputs[input_index].witness = Some(vec![sig, sec]);
+            self.segwit = true;
+        } else {
+            return false;
+        }
+        self.verify_input(input_index, VerifyFlags::STANDARD, provider).await.is_valid
     }
     pub fn is_coinbase(&self) -> bool {
         if self.tx_ins().len() != 1 || self.tx_ins().len() == 0 {
@@ -620,7 +771,7 @@ impl Tx {
     pub fn coinbase_height(&self) -> Option<BigUint> {
         if self.is_coinbase() {
             let first = &self.tx_ins()[0];
-            let cmd = &first.script_sig.cmds[0];
+            let cmd = first.script_sig.cmds[0].data()?;
             return Some(little_endian_to_int(cmd));
         }
         None
@@ -778,12 +929,12 @@ mod tests {
         let satoshi = 100_000_000u64;
         // target
         let target_amount: u64 = (0.1f64 * satoshi as f64) as u64;
-        let target_h160 = decode_base58("mnrVtF8DWjMu839VW3rBfgYaAfKk8983Xf".as_bytes().to_vec());
+        let target_h160 = decode_base58(b"mnrVtF8DWjMu839VW3rBfgYaAfKk8983Xf").unwrap();
         let target_script = Script::p2pkh_script(target_h160);
         let target_output = TxOutput::new(target_amount, target_script);
         // change
         let change_amount: u64 = (0.33f64 * satoshi as f64) as u64;
-        let change_h160 = decode_base58("mzx5YhAH9kNHtcN481u6WkjeHjYtVeKVh2".as_bytes().to_vec());
+        let change_h160 = decode_base58(b"mzx5YhAH9kNHtcN481u6WkjeHjYtVeKVh2").unwrap();
         let change_script = Script::p2pkh_script(change_h160);
         let change_output = TxOutput::new(change_amount, change_script);
 
@@ -1051,4 +1202,224 @@ flase        assert_eq!(raw_tx, ser);
         }
     }
 */
+    use num::Num;
+    use crate::helpers::sig_hash::{SIGHASH_ALL, SIGHASH_ANYONECANPAY, SIGHASH_DEFAULT, SIGHASH_NONE, SIGHASH_SINGLE};
+    use crate::helpers::op_codes::{OP_1, OP_NOP};
+    use crate::prevout_provider::MemoryPrevoutProvider;
+    use crate::helpers::block_on::block_on;
+    use super::*;
+    #[test]
+    fn test_sig_hash_bip143() {
+        // native p2wpkh example of BIP143
+        let raw_tx = hex::decode("0100000002fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf433541db4e4ad969f0000000000eeffffffef51e1b804cc89d182d279655c3aa89e815b1b309fe287d9b2b55d57b90ec68a0100000000ffffffff02202cb206000000001976a9148280b37df378db99f66f85c95a783a76ac7a6d5988ac9093510d000000001976a9143bde42dbee7e4dbe6a21b2d50ce2f0167faa815988ac11000000").unwrap();
+        let tx = Tx::parse(&mut Cursor::new(raw_tx), Network::Mainnet).unwrap();
+        let script_code = Script::p2pkh_script(hex::decode("1d0f172a0ecb48aee1be1f2687d2963ae33f71a1").unwrap());
+        let z = BigUint::from_str_radix("c37af31116d1b27caf68aae9e3ac82f1477929014d5b917657d0eb49478cb670", 16).unwrap();
+        assert_eq!(tx.sig_hash_segwit(1, &script_code, 600_000_000, SIGHASH_ALL), z);
+        // computing a digest leaves the tx as parsed
+        let raw_tx = tx.serialize(false);
+        assert_eq!(Tx::parse(&mut Cursor::new(raw_tx), Network::Mainnet).unwrap(), tx);
+    }
+    #[test]
+    fn test_weight() {
+        let mut tx_in = TxInput::new(vec![1u8; 32], 0, Script::new(vec![]), 0xffffffff);
+        tx_in.witness = Some(vec![vec![1u8; 2]]);
+        let mut tx = Tx::new(2, vec![tx_in], vec![TxOutput::new(1000, Script::new(vec![]))], 0, Network::Mainnet, true);
+        // 4 version, 1 + 41 input, 1 + 9 output, 4 locktime, plus 2 marker and flag and 4 witness
+        assert_eq!((tx.base_size(), tx.total_size()), (60, 66));
+        assert_eq!((tx.weight(), tx.vsize()), (246, 62));
+        assert_eq!(tx.fee_rate(620), 10.0);
+        assert_eq!(tx.input_weight(0), (41 * 4 + 4, 4));
+        // without witness data every byte weighs four units
+        tx.inputs[0].witness = None;
+        tx.segwit = false;
+        assert_eq!((tx.weight(), tx.vsize()), (240, 60));
+        assert_eq!(tx.input_weight(0), (41 * 4, 0));
+        assert_eq!(tx.size_json(None)["fee_rate"], json!(null));
+    }
+    #[test]
+    fn test_sign_input() {
+        let key = PrivateKey::new(&BigUint::from_bytes_be(&hash256(b"sign input key")));
+        let sec = key.point().sec(true);
+        let p2wpkh = Script::new(vec![Cmd::Op(OP_0), Cmd::push(hash160(&sec).to_vec())]);
+        let prev_outputs = vec![
+            // locked to the uncompressed key
+            TxOutput::new(10_000, Script::p2pkh_script(hash160(&key.point().sec(false)).to_vec())),
+            TxOutput::new(20_000, p2wpkh.clone()),
+            TxOutput::new(30_000, Script::p2sh_script(hash160(&p2wpkh.raw_serialize()).to_vec())),
+        ];
+        let inputs = (0..3).map(|i| TxInput::new(vec![i as u8 + 1; 32], 0, Script::new(vec![]), 0xffffffff)).collect();
+        let mut tx = Tx::new(2, inputs, vec![TxOutput::new(55_000, Script::new(vec![]))], 0, Network::Mainnet, false);
+        let provider = MemoryPrevoutProvider::for_tx(&tx, prev_outputs);
+
+        assert!(block_on(tx.sign_input(0, &key, SIGHASH_SINGLE | SIGHASH_ANYONECANPAY, &provider)));
+        assert!(block_on(tx.sign_input(1, &key, SIGHASH_ALL, &provider)));
+        assert!(block_on(tx.sign_input(2, &key, SIGHASH_NONE, &provider)));
+        assert!(tx.tx_ins()[1].script_sig.cmds.is_empty());
+        assert_eq!(tx.tx_ins()[2].script_sig.cmds, vec![Cmd::push(p2wpkh.raw_serialize())]);
+        // the legacy input keeps an empty witness once the tx is segwit
+        let raw_tx = tx.serialize(false);
+        assert_eq!(Tx::parse(&mut Cursor::new(raw_tx.clone()), Network::Mainnet).unwrap().serialize(false), raw_tx);
+        assert_eq!(block_on(tx.fee(&provider)), Some(5_000));
+        assert!((0..3).all(|input_index| block_on(tx.verify_input(input_index, VerifyFlags::STANDARD, &provider)).is_valid));
+        // a witness on the legacy input is not left out
+        let mut with_witness = tx.clone();
+        with_witness.inputs[0].witness = Some(vec![vec![1u8]]);
+        let res = block_on(with_witness.verify_input(0, VerifyFlags::STANDARD, &provider));
+        assert_eq!(res.error.map(|error| error.kind()), Some(ScriptErrorKind::WitnessUnexpected));
+        // a key the output is not locked to
+        let other_key = PrivateKey::new(&BigUint::from_bytes_be(&hash256(b"other key")));
+        assert!(!block_on(tx.sign_input(1, &other_key, SIGHASH_ALL, &provider)));
+        // a bip143 digest needs a p2wpkh script to build the script code from
+        assert_eq!(block_on(tx.sig_hash_bip143(0, None, None, SIGHASH_ALL, &provider)), None);
+        assert_eq!(block_on(tx.sig_hash_bip143(1, Some(Script::p2pkh_script(vec![0; 20])), None, SIGHASH_ALL, &provider)), None);
+        assert!(block_on(tx.sig_hash_bip143(1, None, None, SIGHASH_ALL, &provider)).is_some());
+        // without the outputs spent nothing can be checked
+        let provider = MemoryPrevoutProvider::new();
+        assert_eq!(block_on(tx.fee(&provider)), None);
+        let res = block_on(tx.verify_input(1, VerifyFlags::STANDARD, &provider));
+        assert_eq!(res.error.map(|error| error.kind()), Some(ScriptErrorKind::UnknownPrevout));
+    }
+    #[test]
+    fn test_verify_p2sh_script_sig() {
+        let verify = |script_sig: Vec<Cmd>, redeem_script: &[u8]| {
+            let tx_in = TxInput::new(vec![1u8; 32], 0, Script::new(script_sig), 0xffffffff);
+            let mut tx = Tx::new(2, vec![tx_in], vec![TxOutput::new(1_000, Script::new(vec![]))], 0, Network::Mainnet, false);
+            let prev_output = TxOutput::new(2_000, Script::p2sh_script(hash160(redeem_script).to_vec()));
+            let provider = MemoryPrevoutProvider::for_tx(&tx, vec![prev_output]);
+            block_on(tx.verify_input(0, VerifyFlags::STANDARD, &provider))
+        };
+        // the redeem script is the top of the stack, OP_0 gives an empty one
+        let res = verify(vec![Cmd::Op(OP_0)], &[]);
+        assert_eq!(res.redeem_script.map(|script| script.cmds), Some(vec![]));
+        assert_eq!(res.error.map(|error| error.kind()), Some(ScriptErrorKind::EvalFalse));
+        // OP_1 gives 0x01, a push cut short
+        let res = verify(vec![Cmd::Op(OP_1)], &[0x01]);
+        assert_eq!(res.redeem_script.map(|script| script.cmds), None);
+        assert_eq!(res.error.map(|error| error.kind()), Some(ScriptErrorKind::BadOpcode));
+        let res = verify(vec![Cmd::push(vec![OP_1])], &[OP_1]);
+        assert_eq!(res.redeem_script.map(|script| script.cmds), Some(vec![Cmd::Op(OP_1)]));
+        assert!(res.is_valid);
+        // a scriptSig spending a p2sh output may only push
+        let res = verify(vec![Cmd::Op(OP_NOP), Cmd::push(vec![OP_1])], &[OP_1]);
+        assert_eq!(res.error.map(|error| error.kind()), Some(ScriptErrorKind::SigPushOnly));
+    }
+    // a copy of `tx` with its inputs and outputs changed
+    fn changed_tx(tx: &Tx, change: impl Fn(&mut Vec<TxInput>, &mut Vec<TxOutput>)) -> Tx {
+        let (mut inputs, mut outputs) = (tx.tx_ins(), tx.tx_outs());
+        change(&mut inputs, &mut outputs);
+        Tx::new(tx.version(), inputs, outputs, tx.locktime(), Network::Mainnet, false)
+    }
+    #[test]
+    fn test_sig_hash_types() {
+        let inputs = vec![
+            TxInput::new(vec![1u8; 32], 0, Script::new(vec![]), 0xffffffff),
+            TxInput::new(vec![2u8; 32], 1, Script::new(vec![]), 0xffffffff),
+        ];
+        let outputs = vec![TxOutput::new(20_000, Script::new(vec![])), TxOutput::new(20_000, Script::new(vec![]))];
+        let tx = Tx::new(1, inputs, outputs, 0, Network::Mainnet, false);
+        let changes = [
+            changed_tx(&tx, |inputs, _| inputs[1] = TxInput::new(vec![2u8; 32], 1, Script::new(vec![]), 0)),
+            changed_tx(&tx, |inputs, _| { inputs.pop(); }),
+            changed_tx(&tx, |_, outputs| outputs[1] = TxOutput::new(10_000, Script::new(vec![]))),
+            changed_tx(&tx, |_, outputs| outputs[0] = TxOutput::new(10_000, Script::new(vec![]))),
+        ];
+        // whether each hash type still signs the same digest after: another sequence
+        // in the other input, the other input removed, the other output changed, the
+        // output with the same index changed
+        let hash_types = [
+            (SIGHASH_ALL, [false, false, false, false]),
+            (SIGHASH_NONE, [true, false, true, true]),
+            (SIGHASH_SINGLE, [true, false, true, false]),
+            (SIGHASH_ALL | SIGHASH_ANYONECANPAY, [true, true, false, false]),
+            (SIGHASH_NONE | SIGHASH_ANYONECANPAY, [true, true, true, true]),
+            (SIGHASH_SINGLE | SIGHASH_ANYONECANPAY, [true, true, true, false]),
+        ];
+        let script_code = Script::p2pkh_script(vec![0u8; 20]);
+        for (hash_type, unchanged) in hash_types {
+            let legacy = |tx: &Tx| tx.sig_hash_legacy(0, &script_code, hash_type);
+            let segwit = |tx: &Tx| tx.sig_hash_segwit(0, &script_code, 50_000, hash_type);
+            for (changed, unchanged) in changes.iter().zip(unchanged) {
+                assert_eq!(legacy(changed) == legacy(&tx), unchanged, "legacy {:#x}", hash_type);
+                assert_eq!(segwit(changed) == segwit(&tx), unchanged, "segwit {:#x}", hash_type);
+            }
+        }
+        // OP_CODESEPARATOR is left out of the legacy script code only
+        let with_separator = Script::new(vec![Cmd::Op(OP_CODESEPARATOR)]) + script_code.clone();
+        assert_eq!(tx.sig_hash_legacy(0, &with_separator, SIGHASH_ALL), tx.sig_hash_legacy(0, &script_code, SIGHASH_ALL));
+        assert_ne!(tx.sig_hash_segwit(0, &with_separator, 50_000, SIGHASH_ALL), tx.sig_hash_segwit(0, &script_code, 50_000, SIGHASH_ALL));
+    }
+    #[test]
+    fn test_sig_hash_taproot() {
+        let inputs = vec![
+            TxInput::new(vec![1u8; 32], 0, Script::new(vec![]), 0xffffffff),
+            TxInput::new(vec![2u8; 32], 1, Script::new(vec![]), 0xffffffff),
+        ];
+        let tx = Tx::new(2, inputs, vec![TxOutput::new(20_000, Script::new(vec![]))], 0, Network::Mainnet, true);
+        let p2tr = Script::new(vec![Cmd::Op(OP_1), Cmd::push(vec![7u8; 32])]);
+        let spent_outputs = vec![TxOutput::new(30_000, p2tr.clone()), TxOutput::new(40_000, p2tr.clone())];
+        let other_amounts = vec![TxOutput::new(30_000, p2tr.clone()), TxOutput::new(45_000, p2tr)];
+        let digest = |input_index, spent_outputs: &[TxOutput], hash_type, annex| tx.sig_hash_taproot(input_index, spent_outputs, hash_type, annex, None);
+
+        // the amounts of all the inputs are signed, unless ANYONECANPAY
+        assert_ne!(digest(0, &spent_outputs, SIGHASH_DEFAULT, None), digest(0, &other_amounts, SIGHASH_DEFAULT, None));
+        let hash_type = SIGHASH_ALL | SIGHASH_ANYONECANPAY;
+        assert_eq!(digest(0, &spent_outputs, hash_type, None), digest(0, &other_amounts, hash_type, None));
+        // the default hash type signs like SIGHASH_ALL, but is a different digest
+        assert_ne!(digest(0, &spent_outputs, SIGHASH_DEFAULT, None), digest(0, &spent_outputs, SIGHASH_ALL, None));
+        assert_ne!(digest(0, &spent_outputs, SIGHASH_DEFAULT, None), digest(1, &spent_outputs, SIGHASH_DEFAULT, None));
+        assert_ne!(digest(0, &spent_outputs, SIGHASH_DEFAULT, None), digest(0, &spent_outputs, SIGHASH_DEFAULT, Some(&[0x50])));
+        // undefined hash types, SIGHASH_SINGLE without an output and missing spent outputs
+        assert_eq!(digest(0, &spent_outputs, 0x04, None), None);
+        assert_eq!(digest(0, &spent_outputs, 0x80, None), None);
+        assert!(digest(0, &spent_outputs, SIGHASH_SINGLE, None).is_some());
+        assert_eq!(digest(1, &spent_outputs, SIGHASH_SINGLE, None), None);
+        assert_eq!(digest(0, &spent_outputs[..1], SIGHASH_DEFAULT, None), None);
+        // script path spends sign the leaf and the OP_CODESEPARATOR position
+        let leaf = |leaf_hash, codesep_pos| tx.sig_hash_taproot(0, &spent_outputs, SIGHASH_DEFAULT, None, Some((leaf_hash, codesep_pos)));
+        assert_ne!(leaf([1u8; 32], 0xffffffff), digest(0, &spent_outputs, SIGHASH_DEFAULT, None));
+        assert_ne!(leaf([1u8; 32], 0xffffffff), leaf([2u8; 32], 0xffffffff));
+        assert_ne!(leaf([1u8; 32], 0xffffffff), leaf([1u8; 32], 0));
+    }
+    #[test]
+    fn test_sig_hash_single_bug() {
+        let inputs = vec![
+            TxInput::new(vec![1u8; 32], 0, Script::new(vec![]), 0xffffffff),
+            TxInput::new(vec![2u8; 32], 0, Script::new(vec![]), 0xffffffff),
+        ];
+        let tx = Tx::new(1, inputs, vec![TxOutput::new(1000, Script::new(vec![]))], 0, Network::Mainnet, false);
+        let script_code = Script::p2pkh_script(vec![0u8; 20]);
+        // the second input has no matching output, the digest is the number one
+        let one = BigUint::from(1u32) << 248;
+        assert_eq!(tx.sig_hash_legacy(1, &script_code, SIGHASH_SINGLE), one);
+        assert_ne!(tx.sig_hash_legacy(0, &script_code, SIGHASH_SINGLE), one);
+        // BIP143 fixed it
+        assert_ne!(tx.sig_hash_segwit(1, &script_code, 1000, SIGHASH_SINGLE), one);
+    }
+    #[test]
+    fn test_decode_errors() {
+        let mut tx_in = TxInput::new(vec![1u8; 32], 0, Script::new(vec![]), 0xffffffff);
+        tx_in.witness = Some(vec![vec![1u8; 2]]);
+        let raw_tx = Tx::new(2, vec![tx_in], vec![TxOutput::new(1000, Script::new(vec![]))], 0, Network::Mainnet, true).serialize(false);
+        assert!(Tx::from_bytes(&raw_tx, Network::Mainnet).is_ok());
+        // every truncation fails without panicking
+        for length in 0..raw_tx.len() {
+            assert!(Tx::from_bytes(&raw_tx[..length], Network::Mainnet).is_err());
+        }
+        assert_eq!(Tx::from_bytes(&raw_tx[..40], Network::Mainnet).unwrap_err(), DecodeError::UnexpectedEof { offset: 40 });
+        let mut bad_flag = raw_tx.clone();
+        bad_flag[5] = 0x02;
+        assert_eq!(Tx::from_bytes(&bad_flag, Network::Mainnet).unwrap_err(), DecodeError::BadSegwitFlag { offset: 5, flag: 0x02 });
+        let mut trailing = raw_tx.clone();
+        trailing.push(0);
+        assert_eq!(Tx::from_bytes(&trailing, Network::Mainnet).unwrap_err(), DecodeError::TrailingBytes { offset: raw_tx.len() });
+        // an input count of 0xfd written in 3 bytes where 1 would do
+        let mut non_minimal = raw_tx[..6].to_vec();
+        non_minimal.extend([0xfd, 0x01, 0x00]);
+        assert_eq!(Tx::from_bytes(&non_minimal, Network::Mainnet).unwrap_err(), DecodeError::NonMinimalVarint { offset: 6 });
+        // more inputs than there are bytes left
+        let mut oversized = raw_tx[..6].to_vec();
+        oversized.extend([0xfe, 0xff, 0xff, 0xff, 0x00]);
+        assert_eq!(Tx::from_bytes(&oversized, Network::Mainnet).unwrap_err(), DecodeError::OversizedVector { offset: 6, length: 0xffffff });
+    }
 }
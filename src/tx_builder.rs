@@ -0,0 +1,266 @@
+use std::fmt;
+use crate::script::Script;
+use crate::network::Network;
+use crate::tx::Tx;
+use crate::tx_input::TxInput;
+use crate::tx_output::TxOutput;
+
+// sequence numbers: final, locktime enabled, and signalling replace-by-fee (BIP125)
+pub const SEQUENCE_FINAL: u32 = 0xffffffff;
+pub const SEQUENCE_LOCKTIME: u32 = 0xfffffffe;
+pub const SEQUENCE_RBF: u32 = 0xfffffffd;
+
+/// An output the builder can spend.
+#[derive(Debug, Clone)]
+pub struct Utxo {
+    pub prev_tx: Vec<u8>,
+    pub prev_index: u32,
+    pub output: TxOutput,
+    satisfaction_weight: Option<usize>,
+}
+impl Utxo {
+    pub fn new(prev_tx: Vec<u8>, prev_index: u32, output: TxOutput) -> Self {
+        Self { prev_tx, prev_index, output, satisfaction_weight: None }
+    }
+    /// Weight of the scriptSig and witness spending the output, for scripts
+    /// the builder can't estimate, like p2wsh.
+    pub fn with_satisfaction_weight(mut self, satisfaction_weight: usize) -> Self {
+        self.satisfaction_weight = Some(satisfaction_weight);
+        self
+    }
+    /// Weight the scriptSig and witness will add once signed, and whether the
+    /// input has a witness. Signatures are counted at 72 bytes with compressed
+    /// keys, and p2sh outputs are taken as p2sh-p2wpkh.
+    pub fn satisfaction_weight(&self) -> Option<(usize, bool)> {
+        let script_pubkey = self.output.script_pubkey();
+        let is_witness = script_pubkey.witness_program().is_some() || script_pubkey.is_p2sh_script_pubkey();
+        if let Some(satisfaction_weight) = self.satisfaction_weight {
+            return Some((satisfaction_weight, is_witness));
+        }
+        // <sig> <pubkey> with its item count or push opcodes
+        let sig_and_key = 1 + 1 + 72 + 1 + 33;
+        if script_pubkey.is_p2pkh_script_pubkey() {
+            Some(((sig_and_key - 1) * 4, false))
+        } else if script_pubkey.is_p2wpkh_script_pubkey() {
+            Some((sig_and_key, true))
+        } else if script_pubkey.is_p2sh_script_pubkey() {
+            // the push of the 22 byte p2wpkh program
+            Some((23 * 4 + sig_and_key, true))
+        } else if script_pubkey.is_p2tr_script_pubkey() {
+            // a key path spend with a SIGHASH_DEFAULT signature
+            Some((1 + 1 + 64, true))
+        } else {
+            None
+        }
+    }
+    /// Amount left once the fee to spend it at `fee_rate` sat/vB is paid.
+    pub fn effective_value(&self, fee_rate: f64) -> Option<i64> {
+        let (satisfaction_weight, _) = self.satisfaction_weight()?;
+        // outpoint, empty scriptSig and sequence of the unsigned input
+        let input_weight = (32 + 4 + 1 + 4) * 4 + satisfaction_weight;
+        Some(self.output.amount() as i64 - (fee_rate * input_weight as f64 / 4.0).ceil() as i64)
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum TxBuilderError {
+    NoRecipients,
+    InvalidAddress(String),
+    DustOutput(usize),
+    UnknownInputWeight(usize),
+    InsufficientFunds { needed: u64, available: u64 },
+}
+impl fmt::Display for TxBuilderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TxBuilderError::NoRecipients => write!(f, "no recipients"),
+            TxBuilderError::InvalidAddress(address) => write!(f, "invalid address for the network: {}", address),
+            TxBuilderError::DustOutput(index) => write!(f, "output {} is below the dust threshold", index),
+            TxBuilderError::UnknownInputWeight(index) => write!(f, "can't estimate the weight of input {}", index),
+            TxBuilderError::InsufficientFunds { needed, available } => write!(f, "insufficient funds: {} sats needed, {} available", needed, available),
+        }
+    }
+}
+
+// where an output pays, addresses are decoded when building
+#[derive(Debug, Clone)]
+enum Destination {
+    Script(Script),
+    Address(String),
+}
+
+/// Builds an unsigned tx spending all its utxos to the recipients, paying the
+/// fee for the target rate and sending what is left to the change output.
+#[derive(Debug, Clone)]
+pub struct TxBuilder {
+    network: Network,
+    version: u32,
+    locktime: u32,
+    rbf: bool,
+    fee_rate: f64,
+    utxos: Vec<Utxo>,
+    recipients: Vec<(Destination, u64)>,
+    change: Option<Destination>,
+}
+impl TxBuilder {
+    pub fn new(network: Network) -> Self {
+        Self { network, version: 2, locktime: 0, rbf: true, fee_rate: 1.0, utxos: vec![], recipients: vec![], change: None }
+    }
+    pub fn add_utxo(mut self, utxo: Utxo) -> Self {
+        self.utxos.push(utxo);
+        self
+    }
+    pub fn add_recipient(mut self, script_pubkey: Script, amount: u64) -> Self {
+        self.recipients.push((Destination::Script(script_pubkey), amount));
+        self
+    }
+    pub fn add_recipient_address(mut self, address: &str, amount: u64) -> Self {
+        self.recipients.push((Destination::Address(address.to_string()), amount));
+        self
+    }
+    pub fn with_change_script(mut self, script_pubkey: Script) -> Self {
+        self.change = Some(Destination::Script(script_pubkey));
+        self
+    }
+    pub fn with_change_address(mut self, address: &str) -> Self {
+        self.change = Some(Destination::Address(address.to_string()));
+        self
+    }
+    /// Target fee rate in sat/vB.
+    pub fn with_fee_rate(mut self, fee_rate: f64) -> Self {
+        self.fee_rate = fee_rate;
+        self
+    }
+    /// Whether the inputs signal replace-by-fee, on by default.
+    pub fn with_rbf(mut self, rbf: bool) -> Self {
+        self.rbf = rbf;
+        self
+    }
+    pub fn with_locktime(mut self, locktime: u32) -> Self {
+        self.locktime = locktime;
+        self
+    }
+    pub fn with_version(mut self, version: u32) -> Self {
+        self.version = version;
+        self
+    }
+    fn script_pubkey(&self, destination: &Destination) -> Result<Script, TxBuilderError> {
+        match destination {
+            Destination::Script(script_pubkey) => Ok(script_pubkey.clone()),
+            Destination::Address(address) => Script::from_address(address, self.network).ok_or(TxBuilderError::InvalidAddress(address.clone())),
+        }
+    }
+    // fee for the unsigned tx once signed
+    fn fee(&self, tx: &Tx, satisfaction_weight: usize) -> u64 {
+        let vsize = (tx.weight() + satisfaction_weight).div_ceil(4);
+        (self.fee_rate * vsize as f64).ceil() as u64
+    }
+    /// The unsigned tx, each input knowing the output it spends so it can be
+    /// signed with `Tx::sign_input`. Change under the dust threshold is left
+    /// to the fee.
+    pub fn build(&self) -> Result<Tx, TxBuilderError> {
+        if self.recipients.is_empty() {
+            return Err(TxBuilderError::NoRecipients);
+        }
+        let mut outputs = vec![];
+        for (index, (destination, amount)) in self.recipients.iter().enumerate() {
+            let output = TxOutput::new(*amount, self.script_pubkey(destination)?);
+            if *amount < output.dust_threshold() {
+                return Err(TxBuilderError::DustOutput(index));
+            }
+            outputs.push(output);
+        }
+        let mut satisfaction_weight = 0;
+        let mut witness_inputs = 0;
+        for (index, utxo) in self.utxos.iter().enumerate() {
+            let (weight, is_witness) = utxo.satisfaction_weight().ok_or(TxBuilderError::UnknownInputWeight(index))?;
+            satisfaction_weight += weight;
+            witness_inputs += is_witness as usize;
+        }
+        // segwit marker and flag, and the empty witness of each other input
+        if witness_inputs > 0 {
+            satisfaction_weight += 2 + self.utxos.len() - witness_inputs;
+        }
+        let sequence = match (self.rbf, self.locktime) {
+            (true, _) => SEQUENCE_RBF,
+            (false, 0) => SEQUENCE_FINAL,
+            (false, _) => SEQUENCE_LOCKTIME,
+        };
+        let inputs: Vec<TxInput> = self.utxos.iter().map(|utxo| {
+            let mut tx_in = TxInput::new(utxo.prev_tx.clone(), utxo.prev_index, Script::new(vec![]), sequence);
+            tx_in.set_prev_output(utxo.output.clone());
+            tx_in
+        }).collect();
+        let available: u64 = self.utxos.iter().map(|utxo| utxo.output.amount()).sum();
+        let sent: u64 = outputs.iter().map(|output| output.amount()).sum();
+
+        let tx = Tx::new(self.version, inputs.clone(), outputs.clone(), self.locktime, self.network, false);
+        let needed = sent + self.fee(&tx, satisfaction_weight);
+        if available < needed {
+            return Err(TxBuilderError::InsufficientFunds { needed, available });
+        }
+        if let Some(change) = &self.change {
+            let mut with_change = outputs.clone();
+            with_change.push(TxOutput::new(0, self.script_pubkey(change)?));
+            let tx = Tx::new(self.version, inputs.clone(), with_change.clone(), self.locktime, self.network, false);
+            let change_amount = available.saturating_sub(sent + self.fee(&tx, satisfaction_weight));
+            let change_output = TxOutput::new(change_amount, self.script_pubkey(change)?);
+            if change_amount >= change_output.dust_threshold() {
+                with_change.pop();
+                with_change.push(change_output);
+                return Ok(Tx::new(self.version, inputs, with_change, self.locktime, self.network, false));
+            }
+        }
+        Ok(tx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::op_codes::OP_0;
+    use crate::script::Cmd;
+
+    fn p2wpkh(byte: u8) -> Script {
+        Script::new(vec![Cmd::Op(OP_0), Cmd::push(vec![byte; 20])])
+    }
+    #[test]
+    fn test_satisfaction_weight() {
+        let utxo = |script_pubkey: Script| Utxo::new(vec![0x01; 32], 0, TxOutput::new(10_000, script_pubkey));
+        assert_eq!(utxo(Script::p2pkh_script(vec![0x01; 20])).satisfaction_weight(), Some((428, false)));
+        assert_eq!(utxo(p2wpkh(0x01)).satisfaction_weight(), Some((108, true)));
+        assert_eq!(utxo(Script::p2sh_script(vec![0x01; 20])).satisfaction_weight(), Some((200, true)));
+        let p2wsh = Script::witness_program_script(0, vec![0x01; 32]);
+        assert_eq!(utxo(p2wsh.clone()).satisfaction_weight(), None);
+        assert_eq!(utxo(p2wsh).with_satisfaction_weight(250).satisfaction_weight(), Some((250, true)));
+        // a p2wpkh input is 68 vbytes
+        assert_eq!(utxo(p2wpkh(0x01)).effective_value(10.0), Some(10_000 - 680));
+    }
+    #[test]
+    fn test_build() {
+        let utxo = Utxo::new(vec![0x01; 32], 0, TxOutput::new(100_000, p2wpkh(0x01)));
+        let builder = TxBuilder::new(Network::Mainnet)
+            .add_utxo(utxo)
+            .add_recipient_address("bc1qvrtwrt8qqt7catejz59l2ly963xpdct2j370g3", 50_000)
+            .with_change_script(p2wpkh(0x02))
+            .with_fee_rate(2.0);
+        let tx = builder.build().unwrap();
+        assert_eq!(tx.tx_outs().len(), 2);
+        assert_eq!(tx.tx_ins()[0].sequence(), SEQUENCE_RBF);
+        // 1 p2wpkh input and 2 p2wpkh outputs: 141 vbytes once signed
+        assert_eq!(tx.tx_outs()[1].amount(), 100_000 - 50_000 - 282);
+        assert_eq!(tx.tx_outs()[0].script_pubkey().get_address(Network::Mainnet), "bc1qvrtwrt8qqt7catejz59l2ly963xpdct2j370g3");
+
+        // change below the dust threshold goes to the fee
+        let tx = builder.clone().add_recipient(p2wpkh(0x03), 49_500).build().unwrap();
+        assert_eq!(tx.tx_outs().len(), 2);
+        let tx = builder.clone().with_rbf(false).with_locktime(800_000).build().unwrap();
+        assert_eq!((tx.tx_ins()[0].sequence(), tx.locktime()), (SEQUENCE_LOCKTIME, 800_000));
+
+        assert_eq!(builder.clone().add_recipient(p2wpkh(0x03), 100).build().unwrap_err(), TxBuilderError::DustOutput(1));
+        assert_eq!(builder.clone().add_recipient(p2wpkh(0x03), 60_000).build().unwrap_err(), TxBuilderError::InsufficientFunds { needed: 110_282, available: 100_000 });
+        let invalid = builder.clone().add_recipient_address("tb1qvrtwrt8qqt7catejz59l2ly963xpdct2j370g3", 1000);
+        assert!(matches!(invalid.build(), Err(TxBuilderError::InvalidAddress(_))));
+        assert_eq!(TxBuilder::new(Network::Mainnet).build().unwrap_err(), TxBuilderError::NoRecipients);
+    }
+}
@@ -1,9 +1,14 @@
 use crate::script::Script;
-use std::{fmt, io::{Cursor, Read, Error}};
+use crate::network::Network;
+use std::{fmt, io::Cursor};
 use num::{BigUint, ToPrimitive};
 use crate::helpers::endianness::{int_to_little_endian, little_endian_to_int};
 use crate::rpc_api::RpcApi;
 use crate::tx::Tx;
+use crate::tx_output::TxOutput;
+use crate::helpers::varint::encode_varint;
+use crate::helpers::decode_error::{read_bytes, DecodeError};
+use crate::prevout_provider::PrevoutProvider;
 use serde_json::json;
 
 #[derive(Debug, Eq, PartialEq, Clone)]
@@ -13,6 +18,8 @@ pub struct TxInput {
     pub script_sig: Script,
     sequence: u32,
     pub witness: Option<Vec<Vec<u8>>>,
+    // the output being spent, when known it is used instead of fetching the previous tx
+    prev_output: Option<TxOutput>,
     pub tx_in_json: serde_json::Value,
 }
 impl TxInput {
@@ -23,23 +30,22 @@ impl TxInput {
             script_sig: script_sig,
             sequence: sequence,
             witness: None,
+            prev_output: None,
             tx_in_json: json!(null),
         }
     }
     pub fn get_json(&self) -> serde_json::Value {
         self.tx_in_json.clone()
     }
-    pub fn parse(stream: &mut Cursor<Vec<u8>>) -> Result<Self, Error> {
+    pub fn parse(stream: &mut Cursor<Vec<u8>>) -> Result<Self, DecodeError> {
         let mut length: u32 = 0;
         length += 32;
-        let mut buffer = vec![0; 32];
-        stream.read(&mut buffer)?;
+        let mut buffer = read_bytes(stream, 32)?;
         buffer.reverse();
         let prev_tx = buffer.clone();
 
         length += 4;
-        let mut buffer = vec![0; 4];
-        stream.read(&mut buffer)?;
+        let buffer = read_bytes(stream, 4)?;
         let prev_index_bytes = buffer.as_slice();
         let prev_index = little_endian_to_int(prev_index_bytes).to_u32().unwrap();
         let prev_index_hex = hex::encode(prev_index_bytes);
@@ -49,8 +55,7 @@ impl TxInput {
         length += val as u32; // scriptsig length
 
         length += 4; //sequence
-        let mut buffer = vec![0; 4];
-        stream.read(&mut buffer)?;
+        let mut buffer = read_bytes(stream, 4)?;
         let sequence = little_endian_to_int(buffer.as_slice()).to_u32().unwrap();
 
         buffer.reverse();
@@ -69,6 +74,7 @@ impl TxInput {
             script_sig: script_sig,
             witness: None,
             sequence,
+            prev_output: None,
             tx_in_json: tx_in_json.clone(),
         })
     }
@@ -83,6 +89,16 @@ impl TxInput {
         result.extend(int_to_little_endian(BigUint::from(self.sequence), 4u32));
         result
     }
+    /// The witness stack as serialized in a segwit tx, inputs without a witness have an empty one.
+    pub fn serialize_witness(&self) -> Vec<u8> {
+        let witness = self.witness.clone().unwrap_or_default();
+        let mut result = encode_varint(witness.len() as u64).unwrap();
+        for item in witness {
+            result.extend(encode_varint(item.len() as u64).unwrap());
+            result.extend(item);
+        }
+        result
+    }
     pub fn prev_tx(&self) -> Vec<u8> {
         self.prev_tx.to_vec()
     }
@@ -95,22 +111,30 @@ impl TxInput {
     pub fn script_sig(&self) -> Script {
         self.script_sig.clone()
     }
-    pub async fn fetch_tx_async(&self, testnet: bool) -> Result<Tx, reqwest::Error> {
+    pub async fn fetch_tx_async(&self, network: Network) -> Result<Tx, reqwest::Error> {
         let tx_id = hex::encode(self.prev_tx().to_vec());
-        let tf = RpcApi::new(testnet);
+        let tf = RpcApi::new(network);
         let result = tf.get_tx(tx_id.as_str()).await;
         match result {
             Ok(tx) => Ok(tx),
             Err(e) => Err(e)
         }
     }
-    pub async fn value(&self, testnet: bool) -> u64 {
-        let tx = self.fetch_tx_async(testnet).await.unwrap();
-        tx.tx_outs()[self.prev_index as usize].amount()
+    pub fn set_prev_output(&mut self, prev_output: TxOutput) {
+        self.prev_output = Some(prev_output);
+    }
+    /// The output being spent, asked to `provider` when it is not known.
+    pub async fn prev_output(&self, provider: &impl PrevoutProvider) -> Option<TxOutput> {
+        if let Some(prev_output) = &self.prev_output {
+            return Some(prev_output.clone());
+        }
+        provider.prevout(&self.prev_tx, self.prev_index).await
+    }
+    pub async fn value(&self, provider: &impl PrevoutProvider) -> Option<u64> {
+        Some(self.prev_output(provider).await?.amount())
     }
-    pub async fn script_pubkey(&self, testnet: bool) -> Script {
-        let tx = self.fetch_tx_async(testnet).await.unwrap();
-        tx.tx_outs()[self.prev_index as usize].script_pubkey()
+    pub async fn script_pubkey(&self, provider: &impl PrevoutProvider) -> Option<Script> {
+        Some(self.prev_output(provider).await?.script_pubkey())
     }
 }
 
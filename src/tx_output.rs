@@ -1,10 +1,14 @@
 
 use crate::script::Script;
-use std::{fmt, io::{Cursor, Read}};
+use std::{fmt, io::Cursor};
 use num::{BigUint, ToPrimitive};
 use crate::helpers::endianness::{int_to_little_endian, little_endian_to_int};
 use serde_json::json;
 use crate::helpers::out_type::OutputType;
+use crate::helpers::decode_error::{read_array, DecodeError};
+
+// fee rate in sat/vB nodes use to tell dust outputs, which cost more to spend than they are worth
+pub const DUST_RELAY_FEE_RATE: u64 = 3;
 
 #[derive(Debug, Eq, PartialEq, Clone)]
 pub struct TxOutput {
@@ -23,11 +27,10 @@ impl TxOutput {
     pub fn get_json(&self) -> serde_json::Value {
         self.tx_out_json.clone()
     }
-    pub fn parse(stream: &mut Cursor<Vec<u8>>) -> Result<Self, std::io::Error> {
+    pub fn parse(stream: &mut Cursor<Vec<u8>>) -> Result<Self, DecodeError> {
         let mut length: u32 = 0;
         length += 8;
-        let mut buffer = [0; 8];
-        stream.read(&mut buffer)?;
+        let buffer = read_array::<8>(stream)?;
 
         let script_pubkey = Script::parse(stream)?;
 
@@ -59,6 +62,19 @@ impl TxOutput {
     pub fn script_pubkey(&self) -> Script {
         self.script_pubkey.clone()
     }
+    /// Smallest amount nodes relay for this output: the cost of creating
+    /// and then spending it at DUST_RELAY_FEE_RATE.
+    pub fn dust_threshold(&self) -> u64 {
+        if matches!(self.script_pubkey.get_output_type(), OutputType::OpReturn) {
+            return 0;
+        }
+        // outpoint, scriptSig length and sequence, plus a signature and pubkey, discounted if in the witness
+        let spend_size = match self.script_pubkey.witness_program() {
+            Some(_) => 32 + 4 + 1 + 4 + 107 / 4,
+            None => 32 + 4 + 1 + 4 + 107,
+        };
+        (self.serialize().len() + spend_size) as u64 * DUST_RELAY_FEE_RATE
+    }
 }
 impl fmt::Display for TxOutput {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -0,0 +1,381 @@
+//! Runs Bitcoin Core's script_tests.json, tx_valid.json and tx_invalid.json
+//! through `Script::evaluate_spend` and `Tx::verify_input`, printing pass/fail
+//! coverage per opcode. Run with `--nocapture` to see it. The files are copied
+//! unchanged from Core's src/test/data into tests/data, a test whose file is
+//! missing says so and is skipped. Errors are compared by Core's name for them,
+//! vectors using a flag or error the interpreter doesn't know are skipped and
+//! counted, every other vector must pass.
+
+use std::collections::BTreeMap;
+use std::io::Cursor;
+use num::BigUint;
+use serde_json::Value;
+use btc_inspect::helpers::block_on::block_on;
+use btc_inspect::helpers::op_codes::{encode_num, op_code_from_name, op_code_name, OP_0, OP_1, OP_1NEGATE};
+use btc_inspect::helpers::script_error::ScriptErrorKind;
+use btc_inspect::helpers::tx_context::TxContext;
+use btc_inspect::helpers::varint::encode_varint;
+use btc_inspect::helpers::verify_flags::VerifyFlags;
+use btc_inspect::network::Network;
+use btc_inspect::prevout_provider::MemoryPrevoutProvider;
+use btc_inspect::script::{Cmd, Script};
+use btc_inspect::tx::Tx;
+use btc_inspect::tx_input::TxInput;
+use btc_inspect::tx_output::TxOutput;
+
+// None, with a note, if the file hasn't been copied in
+fn load(name: &str) -> Option<Vec<Value>> {
+    let path = format!("{}/tests/data/{}", env!("CARGO_MANIFEST_DIR"), name);
+    let Ok(json) = std::fs::read_to_string(&path) else {
+        eprintln!("skipping {}: {} is missing, copy it unchanged from Bitcoin Core's src/test/data", name, path);
+        return None;
+    };
+    Some(serde_json::from_str::<Value>(&json).unwrap().as_array().unwrap().clone())
+}
+
+// Core's test script syntax: decimal numbers, 0x raw bytes (not pushes),
+// 'quoted' pushes and opcode names with or without OP_
+fn parse_core_script(asm: &str) -> Option<Script> {
+    let mut raw: Vec<u8> = vec![];
+    for token in asm.split_whitespace() {
+        let is_number = token.strip_prefix('-').unwrap_or(token).chars().all(|c| c.is_ascii_digit());
+        if is_number {
+            let num: i64 = token.parse().ok()?;
+            match num {
+                -1 => raw.push(OP_1NEGATE),
+                0 => raw.push(OP_0),
+                1..=16 => raw.push(OP_1 + num as u8 - 1),
+                _ => raw.extend(Script::new(vec![Cmd::push(encode_num(num))]).raw_serialize()),
+            }
+        } else if let Some(hex) = token.strip_prefix("0x") {
+            raw.extend(hex::decode(hex).ok()?);
+        } else if token.len() >= 2 && token.starts_with('\'') && token.ends_with('\'') {
+            let data = token[1..token.len() - 1].as_bytes().to_vec();
+            raw.extend(Script::new(vec![Cmd::push(data)]).raw_serialize());
+        } else {
+            raw.push(op_code_from_name(token)?);
+        }
+    }
+    let mut script = encode_varint(raw.len() as u64).unwrap();
+    script.extend(raw);
+    Script::parse(&mut Cursor::new(script)).ok()
+}
+
+// Core's name for an error, several of ours are UNKNOWN_ERROR there
+fn core_name(kind: ScriptErrorKind) -> &'static str {
+    match kind {
+        ScriptErrorKind::EvalFalse => "EVAL_FALSE",
+        ScriptErrorKind::OpReturn => "OP_RETURN",
+        ScriptErrorKind::BadOpcode => "BAD_OPCODE",
+        ScriptErrorKind::DisabledOpcode => "DISABLED_OPCODE",
+        ScriptErrorKind::PushSize => "PUSH_SIZE",
+        ScriptErrorKind::ScriptSize => "SCRIPT_SIZE",
+        ScriptErrorKind::OpCount => "OP_COUNT",
+        ScriptErrorKind::StackSize => "STACK_SIZE",
+        ScriptErrorKind::InvalidNumber => "UNKNOWN_ERROR",
+        ScriptErrorKind::NonMinimalNumber => "UNKNOWN_ERROR",
+        ScriptErrorKind::UnknownPrevout => "UNKNOWN_ERROR",
+        ScriptErrorKind::StackUnderflow => "INVALID_STACK_OPERATION",
+        ScriptErrorKind::AltStackUnderflow => "INVALID_ALTSTACK_OPERATION",
+        ScriptErrorKind::UnbalancedConditional => "UNBALANCED_CONDITIONAL",
+        ScriptErrorKind::VerifyFailed => "VERIFY",
+        ScriptErrorKind::EqualVerifyFailed => "EQUALVERIFY",
+        ScriptErrorKind::NumEqualVerifyFailed => "NUMEQUALVERIFY",
+        ScriptErrorKind::CheckSigVerifyFailed => "CHECKSIGVERIFY",
+        ScriptErrorKind::CheckMultiSigVerifyFailed => "CHECKMULTISIGVERIFY",
+        ScriptErrorKind::SigInvalid => "SIG_DER",
+        ScriptErrorKind::SigHighS => "SIG_HIGH_S",
+        ScriptErrorKind::SigHashType => "SIG_HASHTYPE",
+        ScriptErrorKind::SigNullDummy => "SIG_NULLDUMMY",
+        ScriptErrorKind::SigPushOnly => "SIG_PUSHONLY",
+        ScriptErrorKind::SigNullFail => "NULLFAIL",
+        ScriptErrorKind::SigFindAndDelete => "SIG_FINDANDDELETE",
+        ScriptErrorKind::PubKeyType => "PUBKEYTYPE",
+        ScriptErrorKind::WitnessPubKeyType => "WITNESS_PUBKEYTYPE",
+        ScriptErrorKind::MinimalData => "MINIMALDATA",
+        ScriptErrorKind::CleanStack => "CLEANSTACK",
+        ScriptErrorKind::MinimalIf => "MINIMALIF",
+        ScriptErrorKind::OpCodeSeparator => "OP_CODESEPARATOR",
+        ScriptErrorKind::DiscourageUpgradableNops => "DISCOURAGE_UPGRADABLE_NOPS",
+        ScriptErrorKind::DiscourageUpgradableWitnessProgram => "DISCOURAGE_UPGRADABLE_WITNESS_PROGRAM",
+        ScriptErrorKind::DiscourageUpgradableTaprootVersion => "DISCOURAGE_UPGRADABLE_TAPROOT_VERSION",
+        ScriptErrorKind::DiscourageOpSuccess => "DISCOURAGE_OP_SUCCESS",
+        ScriptErrorKind::DiscourageUpgradablePubKeyType => "DISCOURAGE_UPGRADABLE_PUBKEYTYPE",
+        ScriptErrorKind::SigCount => "SIG_COUNT",
+        ScriptErrorKind::PubKeyCount => "PUBKEY_COUNT",
+        ScriptErrorKind::NegativeLocktime => "NEGATIVE_LOCKTIME",
+        ScriptErrorKind::UnsatisfiedLocktime => "UNSATISFIED_LOCKTIME",
+        ScriptErrorKind::WitnessProgramWrongLength => "WITNESS_PROGRAM_WRONG_LENGTH",
+        ScriptErrorKind::WitnessProgramMismatch => "WITNESS_PROGRAM_MISMATCH",
+        ScriptErrorKind::WitnessProgramWitnessEmpty => "WITNESS_PROGRAM_WITNESS_EMPTY",
+        ScriptErrorKind::WitnessMalleated => "WITNESS_MALLEATED",
+        ScriptErrorKind::WitnessMalleatedP2sh => "WITNESS_MALLEATED_P2SH",
+        ScriptErrorKind::WitnessUnexpected => "WITNESS_UNEXPECTED",
+        ScriptErrorKind::SchnorrSigSize => "SCHNORR_SIG_SIZE",
+        ScriptErrorKind::SchnorrSigHashType => "SCHNORR_SIG_HASHTYPE",
+        ScriptErrorKind::SchnorrSig => "SCHNORR_SIG",
+        ScriptErrorKind::TaprootWrongControlSize => "TAPROOT_WRONG_CONTROL_SIZE",
+        ScriptErrorKind::TapscriptValidationWeight => "TAPSCRIPT_VALIDATION_WEIGHT",
+        ScriptErrorKind::TapscriptCheckMultiSig => "TAPSCRIPT_CHECKMULTISIG",
+        ScriptErrorKind::TapscriptMinimalIf => "TAPSCRIPT_MINIMALIF",
+    }
+}
+
+// "OK" or the Core name of an error the interpreter can return
+fn is_known_error(name: &str) -> bool {
+    const KNOWN: [&str; 52] = [
+        "EVAL_FALSE",
+        "OP_RETURN",
+        "BAD_OPCODE",
+        "DISABLED_OPCODE",
+        "PUSH_SIZE",
+        "SCRIPT_SIZE",
+        "OP_COUNT",
+        "STACK_SIZE",
+        "UNKNOWN_ERROR",
+        "INVALID_STACK_OPERATION",
+        "INVALID_ALTSTACK_OPERATION",
+        "UNBALANCED_CONDITIONAL",
+        "VERIFY",
+        "EQUALVERIFY",
+        "NUMEQUALVERIFY",
+        "CHECKSIGVERIFY",
+        "CHECKMULTISIGVERIFY",
+        "SIG_DER",
+        "SIG_HIGH_S",
+        "SIG_HASHTYPE",
+        "SIG_NULLDUMMY",
+        "SIG_PUSHONLY",
+        "NULLFAIL",
+        "SIG_FINDANDDELETE",
+        "PUBKEYTYPE",
+        "WITNESS_PUBKEYTYPE",
+        "MINIMALDATA",
+        "CLEANSTACK",
+        "MINIMALIF",
+        "OP_CODESEPARATOR",
+        "DISCOURAGE_UPGRADABLE_NOPS",
+        "DISCOURAGE_UPGRADABLE_WITNESS_PROGRAM",
+        "DISCOURAGE_UPGRADABLE_TAPROOT_VERSION",
+        "DISCOURAGE_OP_SUCCESS",
+        "DISCOURAGE_UPGRADABLE_PUBKEYTYPE",
+        "SIG_COUNT",
+        "PUBKEY_COUNT",
+        "NEGATIVE_LOCKTIME",
+        "UNSATISFIED_LOCKTIME",
+        "WITNESS_PROGRAM_WRONG_LENGTH",
+        "WITNESS_PROGRAM_MISMATCH",
+        "WITNESS_PROGRAM_WITNESS_EMPTY",
+        "WITNESS_MALLEATED",
+        "WITNESS_MALLEATED_P2SH",
+        "WITNESS_UNEXPECTED",
+        "SCHNORR_SIG_SIZE",
+        "SCHNORR_SIG_HASHTYPE",
+        "SCHNORR_SIG",
+        "TAPROOT_WRONG_CONTROL_SIZE",
+        "TAPSCRIPT_VALIDATION_WEIGHT",
+        "TAPSCRIPT_CHECKMULTISIG",
+        "TAPSCRIPT_MINIMALIF",
+    ];
+    name == "OK" || KNOWN.contains(&name)
+}
+
+/// Pass/fail counts per opcode, plus the vectors that failed or were skipped.
+#[derive(Default)]
+struct Report {
+    opcodes: BTreeMap<String, (usize, usize)>,
+    passed: usize,
+    failed: Vec<String>,
+    skipped: Vec<String>,
+}
+impl Report {
+    fn record(&mut self, scripts: &[&Script], passed: bool, description: String) {
+        let mut names: Vec<String> = scripts.iter()
+            .flat_map(|script| script.cmds.iter())
+            .filter_map(|cmd| match cmd {
+                Cmd::Op(op_code) => Some(op_code_name(*op_code)),
+                Cmd::Push(_, _) => None,
+            })
+            .collect();
+        names.sort();
+        names.dedup();
+        for name in names {
+            let counts = self.opcodes.entry(name).or_default();
+            counts.1 += 1;
+            if passed {
+                counts.0 += 1;
+            }
+        }
+        if passed {
+            self.passed += 1;
+        } else {
+            self.failed.push(description);
+        }
+    }
+    fn skip(&mut self, description: String) {
+        self.skipped.push(description);
+    }
+    fn print(&self, title: &str) {
+        println!("{}: {} passed, {} failed, {} skipped", title, self.passed, self.failed.len(), self.skipped.len());
+        for (name, (passed, total)) in &self.opcodes {
+            println!("  {:<24} {:>4}/{:<4}", name, passed, total);
+        }
+        for skipped in &self.skipped {
+            println!("  SKIPPED {}", skipped);
+        }
+        for failed in &self.failed {
+            println!("  FAILED {}", failed);
+        }
+    }
+}
+
+// the transactions Core signs the vectors with: one creating an output with the
+// scriptPubKey, and one spending it with the scriptSig to an empty output
+fn core_spending_tx(script_sig: &Script, script_pubkey: &Script, amount: u64) -> (Tx, TxOutput) {
+    let coinbase_script = Script::new(vec![Cmd::Op(OP_0), Cmd::Op(OP_0)]);
+    let credit_in = TxInput::new(vec![0u8; 32], 0xffffffff, coinbase_script, 0xffffffff);
+    let prev_output = TxOutput::new(amount, script_pubkey.clone());
+    let credit_tx = Tx::new(1, vec![credit_in], vec![prev_output.clone()], 0, Network::Mainnet, false);
+    let spend_in = TxInput::new(hex::decode(credit_tx.tx_id()).unwrap(), 0, script_sig.clone(), 0xffffffff);
+    let spend_tx = Tx::new(1, vec![spend_in], vec![TxOutput::new(amount, Script::new(vec![]))], 0, Network::Mainnet, false);
+    (spend_tx, prev_output)
+}
+
+
+// the context free checks of Core's CheckTransaction, tx_invalid marks the
+// transactions failing them with BADTX
+fn check_transaction(tx: &Tx) -> bool {
+    const MAX_MONEY: u64 = 21_000_000 * 100_000_000;
+    let (tx_ins, tx_outs) = (tx.tx_ins(), tx.tx_outs());
+    if tx_ins.is_empty() || tx_outs.is_empty() || tx.serialize(true).len() * 4 > 4_000_000 {
+        return false;
+    }
+    let mut total: u64 = 0;
+    for tx_out in &tx_outs {
+        total = total.saturating_add(tx_out.amount());
+        if tx_out.amount() > MAX_MONEY || total > MAX_MONEY {
+            return false;
+        }
+    }
+    let mut outpoints: Vec<(Vec<u8>, u32)> = tx_ins.iter().map(|tx_in| (tx_in.prev_tx(), tx_in.prev_index())).collect();
+    outpoints.sort();
+    outpoints.dedup();
+    if outpoints.len() != tx_ins.len() {
+        return false;
+    }
+    let is_null = |tx_in: &TxInput| tx_in.prev_tx() == [0u8; 32] && tx_in.prev_index() == 0xffffffff;
+    match tx.is_coinbase() {
+        true => (2..=100).contains(&tx_ins[0].script_sig().raw_serialize().len()),
+        false => !tx_ins.iter().any(is_null),
+    }
+}
+
+#[test]
+fn script_tests() {
+    let Some(tests) = load("script_tests.json") else {
+        return;
+    };
+    let mut report = Report::default();
+    for test in tests {
+        let mut test = test.as_array().unwrap().clone();
+        // [[wit..., amount]?, scriptSig, scriptPubKey, flags, error, comment?]
+        let (witness, amount) = match test.first() {
+            Some(Value::Array(items)) => {
+                let witness = items[..items.len() - 1].iter().map(|item| hex::decode(item.as_str().unwrap()).unwrap()).collect();
+                // the amount is in BTC
+                let amount = (items[items.len() - 1].as_f64().unwrap() * 100_000_000.0).round() as u64;
+                test.remove(0);
+                (Some(witness), amount)
+            }
+            _ => (None, 0),
+        };
+        if test.len() < 4 {
+            // a comment
+            continue;
+        }
+        let field = |i: usize| test.get(i).and_then(|value| value.as_str()).unwrap_or("").to_string();
+        let (script_sig, script_pubkey, flags, error, comment) = (field(0), field(1), field(2), field(3), field(4));
+        let description = format!("[{:?}, {:?}, {:?}, {:?}] {}", script_sig, script_pubkey, flags, error, comment);
+        let Some(flags) = VerifyFlags::from_names(&flags).filter(|_| is_known_error(&error)) else {
+            report.skip(format!("{} has an unknown flag or error", description));
+            continue;
+        };
+        let (result, scripts) = match (parse_core_script(&script_sig), parse_core_script(&script_pubkey)) {
+            (Some(sig), Some(pubkey)) => {
+                let (spending_tx, prev_output) = core_spending_tx(&sig, &pubkey, amount);
+                let context = TxContext::new(&spending_tx, 0, prev_output);
+                let result = pubkey.evaluate_spend(&sig, &BigUint::from(0u32), &witness, flags, Some(&context)).map_err(|error| error.kind());
+                (result, vec![sig, pubkey])
+            }
+            // a script that doesn't parse fails as soon as it is executed
+            _ => (Err(ScriptErrorKind::BadOpcode), vec![]),
+        };
+        let passed = match result {
+            Ok(()) => error == "OK",
+            Err(kind) => core_name(kind) == error,
+        };
+        report.record(&scripts.iter().collect::<Vec<&Script>>(), passed, format!("{} got {:?}", description, result));
+    }
+    report.print("script_tests.json");
+    assert!(report.failed.is_empty(), "{} vectors failed", report.failed.len());
+}
+
+// [[[prevout hash, prevout index, prevout scriptPubKey, amount?], ...], serializedTransaction, flags]
+fn run_tx_tests(name: &str, valid: bool) {
+    let Some(tests) = load(name) else {
+        return;
+    };
+    let mut report = Report::default();
+    let mut comments: Vec<String> = vec![];
+    for test in tests {
+        let test = test.as_array().unwrap();
+        if test.len() == 1 {
+            comments.push(test[0].as_str().unwrap().to_string());
+            continue;
+        }
+        let description = format!("{} {:?}", comments.join(" "), test[2]);
+        comments.clear();
+        let flag_names = test[2].as_str().unwrap();
+        let raw_tx = hex::decode(test[1].as_str().unwrap()).unwrap();
+        let tx = Tx::parse(&mut Cursor::new(raw_tx), Network::Mainnet).ok();
+        // a transaction failing CheckTransaction is only expected with BADTX
+        let Some(mut tx) = tx.filter(check_transaction) else {
+            report.record(&[], !valid && flag_names == "BADTX", description);
+            continue;
+        };
+        let flags = VerifyFlags::from_names(flag_names);
+        // tx_valid lists the flags to leave out, tx_invalid the ones to apply
+        let Some(flags) = flags.map(|flags| if valid { VerifyFlags::ALL.without(flags) } else { flags }) else {
+            report.skip(format!("{} has an unknown flag", description));
+            continue;
+        };
+        let mut provider = MemoryPrevoutProvider::new();
+        let mut prevout_scripts = vec![];
+        for prevout in test[0].as_array().unwrap().iter().map(|prevout| prevout.as_array().unwrap()) {
+            let prev_tx = hex::decode(prevout[0].as_str().unwrap()).unwrap();
+            // -1 is the index of a null prevout
+            let prev_index = prevout[1].as_i64().unwrap() as u32;
+            let script_pubkey = parse_core_script(prevout[2].as_str().unwrap()).unwrap();
+            let amount = prevout.get(3).and_then(|amount| amount.as_u64()).unwrap_or(0);
+            provider.insert(prev_tx, prev_index, TxOutput::new(amount, script_pubkey.clone()));
+            prevout_scripts.push(script_pubkey);
+        }
+        let results: Vec<bool> = (0..tx.tx_ins().len())
+            .map(|input_index| block_on(tx.verify_input(input_index, flags, &provider)).is_valid)
+            .collect();
+        let passed = results.iter().all(|is_valid| *is_valid) == valid;
+        report.record(&prevout_scripts.iter().collect::<Vec<&Script>>(), passed, description);
+    }
+    report.print(name);
+    assert!(report.failed.is_empty(), "{} vectors failed", report.failed.len());
+}
+
+#[test]
+fn tx_valid() {
+    run_tx_tests("tx_valid.json", true);
+}
+
+#[test]
+fn tx_invalid() {
+    run_tx_tests("tx_invalid.json", false);
+}
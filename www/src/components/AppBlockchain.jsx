@@ -11,42 +11,42 @@ import {
 } from "btc-inspect";
 
 function AppBlockchain() {
-    // TODO move testnetValue to parent
-    const [testnetValue, setTestnetValue] = useState(true);
+    // TODO move networkValue to parent
+    const [networkValue, setNetworkValue] = useState("testnet3");
     const [jsonTree, setJsonTree] = useState(null)
 
     function getBlockCount() {
-        get_block_count(testnetValue).then(res => {
+        get_block_count(networkValue).then(res => {
             setJsonTree(JSON.parse(res))
             console.log(res)
         })
     }
     function getBlockchainInfo() {
-        get_blockchain_info(testnetValue).then(res => {
+        get_blockchain_info(networkValue).then(res => {
             setJsonTree(JSON.parse(res))
             console.log(res)
         })
     }
     function getMempoolInfo() {
-        get_mempool_info(testnetValue).then(res => {
+        get_mempool_info(networkValue).then(res => {
             setJsonTree(JSON.parse(res))
             console.log(res)
         })
     }
     function getMiningInfo() {
-        get_mining_info(testnetValue).then(res => {
+        get_mining_info(networkValue).then(res => {
             setJsonTree(JSON.parse(res))
             console.log(res)
         })
     }
     function getNetTotals() {
-        get_nettotals(testnetValue).then(res => {
+        get_nettotals(networkValue).then(res => {
             setJsonTree(JSON.parse(res))
             console.log(res)
         })
     }
     function getNetworkInfo() {
-        get_network_info(testnetValue).then(res => {
+        get_network_info(networkValue).then(res => {
             setJsonTree(JSON.parse(res))
             console.log(res)
         })
@@ -10,8 +10,8 @@ function AppExplorer() {
     const [blockJson, setBlockJson] = useState(null)
     const [errLbl, setErrLbl] = useState(null)
     const [inputValue, setInputValue] = useState('');
-    // TODO move testnetValue to parent
-    const [testnetValue, setTestnetValue] = useState(true);
+    // TODO move networkValue to parent
+    const [networkValue, setNetworkValue] = useState("testnet3");
     const [blockTxs, setBlockTxs] = useState([])
     function handleFetch(input) {
 
@@ -20,9 +20,9 @@ function AppExplorer() {
         setBlockJson(null)
         setErrLbl(null)
         // wasm: can't aquire multiple mutex
-        get_tx_json(testnetValue, input).then(tx_json_str => {
+        get_tx_json(networkValue, input).then(tx_json_str => {
                 if (tx_json_str === "") {
-                    get_block_json(testnetValue, input).then(block_json_str => {
+                    get_block_json(networkValue, input).then(block_json_str => {
                             if (block_json_str === "") {
                                 setErrLbl("Invalid hash")
                             } else {
@@ -39,7 +39,7 @@ function AppExplorer() {
         )
     }
     function handleBlockTxs(blockId) {
-        get_block_txs_json(testnetValue, blockId).then(res => {
+        get_block_txs_json(networkValue, blockId).then(res => {
             let block_txs_json = JSON.parse(res);
             setBlockTxs(block_txs_json)
         })
@@ -128,14 +128,14 @@ function AppExplorer() {
                 <Col>
                     <h3>Explorer</h3>
                     {
-                        testnetValue === true &&
+                        networkValue !== "mainnet" &&
                         <NavDropdown title="Samples" id="basic-nav-dropdown">
                             <NavDropdown.Item href=""
                                               onClick={() => handleTestSample(1)}>t1</NavDropdown.Item>
                         </NavDropdown>
                     }
                     {
-                        testnetValue === false &&
+                        networkValue === "mainnet" &&
 
                     <NavDropdown title="Samples" id="basic-nav-dropdown">
                         <NavDropdown.Item href=""
@@ -8,24 +8,24 @@ import {
 } from "btc-inspect";
 
 function AppWallet() {
-    // TODO move testnetValue to parent
-    const [testnetValue, setTestnetValue] = useState(true);
+    // TODO move networkValue to parent
+    const [networkValue, setNetworkValue] = useState("testnet3");
     const [jsonTree, setJsonTree] = useState(null)
 
     function getWalletInfo() {
-        get_wallet_info(testnetValue).then(res => {
+        get_wallet_info(networkValue).then(res => {
             setJsonTree(JSON.parse(res))
             console.log(res)
         })
     }
     function listTransactions() {
-        list_transactions(testnetValue).then(res => {
+        list_transactions(networkValue).then(res => {
             setJsonTree(JSON.parse(res))
             console.log(res)
         })
     }
     function listUnspent() {
-        list_unspent(testnetValue).then(res => {
+        list_unspent(networkValue).then(res => {
             setJsonTree(JSON.parse(res))
             console.log(res)
         })